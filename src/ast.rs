@@ -0,0 +1,109 @@
+use std::sync::Arc;
+
+use crate::{
+    expr::{Expr, ExprId},
+    resolver::{Local, Resolution},
+    stmt::{Stmt, StmtId},
+};
+
+// Arena holding every node of a parsed program. Nodes refer to their
+// children by index instead of owning them through a `Box`, so a whole
+// program lives in two contiguous vectors.
+#[derive(Debug, Default)]
+pub struct Ast {
+    exprs: Vec<Expr>,
+    stmts: Vec<Stmt>,
+    // Where the program's local variables live, once `resolver` has
+    // looked.
+    resolution: Resolution,
+}
+
+impl Ast {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_expr(&mut self, expr: Expr) -> ExprId {
+        self.exprs.push(expr);
+        ExprId(self.exprs.len() - 1)
+    }
+
+    pub fn add_stmt(&mut self, stmt: Stmt) -> StmtId {
+        self.stmts.push(stmt);
+        StmtId(self.stmts.len() - 1)
+    }
+
+    pub fn expr(&self, id: ExprId) -> &Expr {
+        &self.exprs[id.0]
+    }
+
+    pub fn stmt(&self, id: StmtId) -> &Stmt {
+        &self.stmts[id.0]
+    }
+
+    // How many statements there are, with ids from zero up.
+    pub fn len(&self) -> usize {
+        self.stmts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stmts.is_empty()
+    }
+
+    // The local variable a variable or assignment expression refers to,
+    // or none if it is global or the program was not resolved.
+    pub fn local(&self, expr: ExprId) -> Option<Local> {
+        self.resolution.locals.get(expr.0).copied().flatten()
+    }
+
+    // The slot the variable a `var` or `fun` declares goes in, or none if
+    // it is global or the program was not resolved.
+    pub fn slot(&self, stmt: StmtId) -> Option<usize> {
+        let slot = self.resolution.slots.get(stmt.0).copied().flatten()?;
+        Some(slot as usize)
+    }
+
+    // The names of the variables a block or function declares, by slot,
+    // with a function's parameters first, or none if the program was not
+    // resolved. A block that declares none has no scope of its own.
+    pub fn scope(&self, stmt: StmtId) -> Option<&Arc<[String]>> {
+        self.resolution.scopes.get(stmt.0)?.as_ref()
+    }
+
+    // The constant a string literal's value is, or none if the program was
+    // not resolved.
+    pub fn constant(&self, expr: ExprId) -> Option<usize> {
+        let index = self.resolution.literals.get(expr.0).copied().flatten()?;
+        Some(index as usize)
+    }
+
+    // The texts of the program's string literals, each once, by constant.
+    pub fn constants(&self) -> &[String] {
+        &self.resolution.constants
+    }
+
+    pub(crate) fn resolved(&mut self, resolution: Resolution) {
+        self.resolution = resolution;
+    }
+}
+
+// A parsed program: the node arena plus its top-level statements in order.
+// The arena is shared with the functions the program declares.
+//
+// Running a program never changes it: what a run declares and computes
+// lives in the interpreter. So a program parsed once can be put behind an
+// `Arc` and run by any number of interpreters, on as many threads, each
+// with globals of its own; a server can parse its script at startup and
+// run it for every request without scanning or parsing again.
+#[derive(Debug, Default)]
+pub struct Program {
+    pub ast: Arc<Ast>,
+    pub statements: Vec<StmtId>,
+}
+
+// Keeps anything shared with a single thread, such as a `LiteralValue`,
+// from finding its way into the tree.
+const _: fn() = || {
+    fn shareable<T: Send + Sync>() {}
+    shareable::<Program>();
+};
@@ -0,0 +1,66 @@
+use std::fs;
+use std::time::{Duration, Instant};
+
+use interprete::{diagnostic::Format, interpreter::Setup, natives};
+
+// Times scripts for `interprete bench SCRIPT [--iterations N]`, to measure
+// changes to the interpreter by. The script runs the given number of times,
+// ten by default, each time in a new interpreter, and each run is timed
+// from parsing to the end, leaving out setting up the interpreter. What the
+// script prints is kept rather than shown.
+
+// Gives the status to exit with.
+pub fn run(path: &str, iterations: usize, setup: &Setup, format: Format) -> i32 {
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(error) => {
+            eprintln!("Error reading file '{}': {}", path, error);
+            return 66;
+        }
+    };
+    let mut times = Vec::with_capacity(iterations);
+    let mut steps = 0;
+    for _ in 0..iterations {
+        let mut engine = match setup.engine() {
+            Ok(engine) => engine,
+            Err(error) => {
+                eprintln!("{}", error);
+                return 70;
+            }
+        };
+        engine.interpreter_mut().capture_output();
+        engine.define_global("args", natives::list(Vec::<String>::new()));
+        // The prelude runs statements too.
+        let before = engine.interpreter().steps();
+        let start = Instant::now();
+        let result = interprete::run_in(engine.as_mut(), source.clone());
+        times.push(start.elapsed());
+        if let Err(error) = result {
+            format.report(&error.diagnostic(), path, &source);
+            return error.exit_code();
+        }
+        steps += engine.interpreter().steps() - before;
+    }
+
+    let total: Duration = times.iter().sum();
+    times.sort();
+    let median = if iterations.is_multiple_of(2) {
+        (times[iterations / 2 - 1] + times[iterations / 2]) / 2
+    } else {
+        times[iterations / 2]
+    };
+    println!("{}: {} iterations", path, iterations);
+    println!("  min     {}", milliseconds(times[0]));
+    println!("  median  {}", milliseconds(median));
+    println!("  mean    {}", milliseconds(total / iterations as u32));
+    // The VM does not count statements.
+    if steps > 0 {
+        let rate = steps as f64 / total.as_secs_f64();
+        println!("  {:.0} statements per second", rate);
+    }
+    0
+}
+
+fn milliseconds(duration: Duration) -> String {
+    format!("{:.3} ms", duration.as_secs_f64() * 1000.0)
+}
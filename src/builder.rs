@@ -0,0 +1,141 @@
+use std::io::Write;
+
+use crate::{
+    capability::{Capabilities, Capability},
+    hooks::Hooks,
+    interpreter::{Interpreter, Limits, Setup, MAX_DEPTH},
+};
+
+// Sets up an interpreter for a program embedding it, with the options the
+// command line and config files give the interpreter, and those only a
+// host has, in one place:
+//
+//     let mut interpreter = Interpreter::builder()
+//         .sandbox(true)
+//         .max_steps(100_000)
+//         .stdout(log_file)
+//         .strict_types(true)
+//         .build()?;
+//
+// Anything not set is as `Setup::default` has it: the prelude declared,
+// the default capabilities, no limits but on how deep calls nest, and
+// output to standard output.
+pub struct InterpreterBuilder {
+    setup: Setup,
+    max_steps: Option<u64>,
+    max_depth: Option<usize>,
+    max_memory: Option<usize>,
+    output: Option<Box<dyn Write>>,
+    hooks: Option<Box<dyn Hooks>>,
+}
+
+impl Interpreter {
+    pub fn builder() -> InterpreterBuilder {
+        InterpreterBuilder {
+            setup: Setup::default(),
+            max_steps: None,
+            max_depth: None,
+            max_memory: None,
+            output: None,
+            hooks: None,
+        }
+    }
+}
+
+impl InterpreterBuilder {
+    pub fn allow(mut self, capability: Capability) -> Self {
+        self.setup.capabilities.allow(capability);
+        self
+    }
+
+    pub fn deny(mut self, capability: Capability) -> Self {
+        self.setup.capabilities.deny(capability);
+        self
+    }
+
+    // Denies every capability, leaving scripts with pure computation and
+    // printing, or, given false, goes back to the default ones.
+    pub fn sandbox(mut self, sandbox: bool) -> Self {
+        self.setup.capabilities = Capabilities::default();
+        if sandbox {
+            for capability in Capability::ALL {
+                self.setup.capabilities.deny(capability);
+            }
+        }
+        self
+    }
+
+    pub fn prelude(mut self, prelude: bool) -> Self {
+        self.setup.prelude = prelude;
+        self
+    }
+
+    // Loads the natives of a plugin; see `plugin`.
+    pub fn plugin(mut self, path: &str) -> Self {
+        self.setup.plugins.push(path.to_string());
+        self
+    }
+
+    pub fn trace(mut self, trace: bool) -> Self {
+        self.setup.trace = trace;
+        self
+    }
+
+    // See `Setup::strict_types`.
+    pub fn strict_types(mut self, strict: bool) -> Self {
+        self.setup.strict_types = strict;
+        self
+    }
+
+    // The most statements scripts may run, in all, before they run out of
+    // fuel; see `Interpreter::set_fuel`.
+    pub fn max_steps(mut self, steps: u64) -> Self {
+        self.max_steps = Some(steps);
+        self
+    }
+
+    // The most calls to script functions that may be running at once,
+    // `MAX_DEPTH` if not set.
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    // Roughly the most bytes the values scripts hold may take; see
+    // `memory`.
+    pub fn max_memory(mut self, bytes: usize) -> Self {
+        self.max_memory = Some(bytes);
+        self
+    }
+
+    // Where what scripts print goes; see `Interpreter::set_output`.
+    pub fn stdout(mut self, writer: impl Write + 'static) -> Self {
+        self.output = Some(Box::new(writer));
+        self
+    }
+
+    // What to watch scripts with; see `Hooks`.
+    pub fn hooks(mut self, hooks: impl Hooks + 'static) -> Self {
+        self.hooks = Some(Box::new(hooks));
+        self
+    }
+
+    // Fails only if a plugin does not load.
+    pub fn build(self) -> Result<Interpreter, String> {
+        let mut interpreter = self.setup.interpreter()?;
+        if self.max_steps.is_some() || self.max_depth.is_some() || self.max_memory.is_some() {
+            interpreter.limit(Limits {
+                steps: self.max_steps.unwrap_or(u64::MAX),
+                depth: self.max_depth.unwrap_or(MAX_DEPTH),
+                memory: self.max_memory.unwrap_or(usize::MAX),
+            });
+        }
+        if let Some(writer) = self.output {
+            interpreter.set_output(writer);
+        }
+        if let Some(hooks) = self.hooks {
+            interpreter.set_hooks(hooks);
+        }
+        Ok(interpreter)
+    }
+}
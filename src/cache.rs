@@ -0,0 +1,89 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use interprete::{ast::Program, diagnostic::Format, encoding, hash, log, serialize};
+
+// Scripts compiled ahead of time, by `interprete compile SCRIPT [-o PATH]`,
+// so that a large program need not be scanned and parsed on every run. A
+// compiled script, `script.loxc` beside the script by default, holds its
+// AST as `--emit-ast` prints it, after a line with the version of the
+// format and the SHA-256 of the source it was compiled from:
+//
+//     loxc 1 9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08
+//     [{"type":"print",...}]
+//
+// Running `script.lox` loads `script.loxc` instead of parsing the script
+// if it was compiled from the same source. One that is stale, or in
+// another version of the format, is ignored.
+
+const HEADER: &str = "loxc 1";
+
+// Gives the status to exit with.
+pub fn compile(path: &str, output: Option<&str>, format: Format) -> i32 {
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(error) => {
+            eprintln!("Error reading file '{}': {}", path, error);
+            return 66;
+        }
+    };
+    let program = match interprete::parse(source.clone()) {
+        Ok(program) => program,
+        Err(error) => {
+            format.report(&error.diagnostic(), path, &source);
+            return 65;
+        }
+    };
+    let output = output.map_or_else(|| cache_path(path), PathBuf::from);
+    let text = format!(
+        "{} {}\n{}\n",
+        HEADER,
+        digest(&source),
+        serialize::serialize_program(&program)
+    );
+    if let Err(error) = fs::write(&output, text) {
+        eprintln!("Error writing file '{}': {}", output.display(), error);
+        return 74;
+    }
+    log::info(format_args!(
+        "Compiled '{}' to '{}'",
+        path,
+        output.display()
+    ));
+    0
+}
+
+// The program compiled beside the script at `path`, if there is one and
+// it was compiled from `source`.
+pub fn load(path: &str, source: &str) -> Option<Program> {
+    let cache = cache_path(path);
+    let text = fs::read_to_string(&cache).ok()?;
+    let fresh = text
+        .split_once('\n')
+        .filter(|(header, _)| *header == format!("{} {}", HEADER, digest(source)));
+    let Some((_, json)) = fresh else {
+        log::info(format_args!(
+            "Ignoring '{}', which is stale",
+            cache.display()
+        ));
+        return None;
+    };
+    match serialize::deserialize_program(json) {
+        Ok(program) => {
+            log::info(format_args!("Loading '{}'", cache.display()));
+            Some(program)
+        }
+        Err(error) => {
+            log::info(format_args!("Ignoring '{}': {}", cache.display(), error));
+            None
+        }
+    }
+}
+
+fn cache_path(path: &str) -> PathBuf {
+    Path::new(path).with_extension("loxc")
+}
+
+fn digest(source: &str) -> String {
+    encoding::hex_encode(&hash::sha256(source.as_bytes()))
+}
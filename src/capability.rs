@@ -0,0 +1,94 @@
+use std::fmt;
+
+// The groups of natives that reach outside the interpreter. An embedder or
+// the command line can deny any of them, so that an untrusted script is
+// left with pure computation and printing to standard output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    // Reading and writing files and directories.
+    Fs,
+    // HTTP requests.
+    Net,
+    // Starting threads.
+    Process,
+    // Reading and setting environment variables.
+    Env,
+    // Reading the clock.
+    Time,
+    // Calling C functions in shared libraries, which can do anything.
+    Ffi,
+    // Reading standard input, writing to standard error, and pausing in the
+    // debugger, which does both.
+    Console,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 7] = [
+        Capability::Fs,
+        Capability::Net,
+        Capability::Process,
+        Capability::Env,
+        Capability::Time,
+        Capability::Ffi,
+        Capability::Console,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Capability::Fs => "fs",
+            Capability::Net => "net",
+            Capability::Process => "process",
+            Capability::Env => "env",
+            Capability::Time => "time",
+            Capability::Ffi => "ffi",
+            Capability::Console => "console",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Capability> {
+        Capability::ALL
+            .into_iter()
+            .find(|capability| capability.name() == name)
+    }
+
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+// The capabilities granted to an interpreter. By default that is all of
+// them but `net` and `ffi`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities(u8);
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        let mut capabilities = Capabilities(0);
+        for capability in Capability::ALL {
+            capabilities.allow(capability);
+        }
+        capabilities.deny(Capability::Net);
+        capabilities.deny(Capability::Ffi);
+        capabilities
+    }
+}
+
+impl Capabilities {
+    pub fn allow(&mut self, capability: Capability) {
+        self.0 |= capability.bit();
+    }
+
+    pub fn deny(&mut self, capability: Capability) {
+        self.0 &= !capability.bit();
+    }
+
+    pub fn allows(self, capability: Capability) -> bool {
+        self.0 & capability.bit() != 0
+    }
+}
@@ -0,0 +1,138 @@
+use std::collections::HashSet;
+
+use crate::{
+    ast::{Ast, Program},
+    diagnostic::{Diagnostic, Span},
+    expr::{Expr, ExprId},
+    scanner::Token,
+    stack,
+    stmt::{Stmt, StmtId},
+};
+
+// Looks for problems in parsed programs without running them, for
+// `--check`. Without running a program, the checker cannot tell which code
+// will be reached, so what it finds are warnings: a name that nothing
+// declares fails only if the code using it runs.
+
+// The names a program declares with `var`, `fun`, `class` or as
+// parameters, in any scope.
+pub fn declarations(program: &Program) -> HashSet<String> {
+    walk(program).declared
+}
+
+// Warns about each use of a variable whose name `is_declared` does not
+// know. Any declaration should count, whatever its scope, so that this
+// never warns about a variable that might exist when the use is reached.
+pub fn check(program: &Program, is_declared: impl Fn(&str) -> bool) -> Vec<Diagnostic> {
+    walk(program)
+        .uses
+        .into_iter()
+        .filter(|name| !is_declared(&name.lexeme))
+        .map(|name| {
+            Diagnostic::warning(
+                "E1003",
+                format!("Undefined variable '{}'", name.lexeme),
+                Some(Span::of(name)),
+            )
+            .with_label("nothing declares this")
+            .with_help("Declare it with 'var' before using it.")
+        })
+        .collect()
+}
+
+fn walk(program: &Program) -> Checker<'_> {
+    let mut checker = Checker {
+        ast: &program.ast,
+        declared: HashSet::new(),
+        uses: Vec::new(),
+    };
+    for &stmt in &program.statements {
+        checker.stmt(stmt);
+    }
+    checker
+}
+
+struct Checker<'a> {
+    ast: &'a Ast,
+    declared: HashSet<String>,
+    uses: Vec<&'a Token>,
+}
+
+impl<'a> Checker<'a> {
+    fn stmt(&mut self, id: StmtId) {
+        let ast = self.ast;
+        match ast.stmt(id) {
+            Stmt::Expression(expr) | Stmt::Print(expr) => self.expr(*expr),
+            Stmt::Var(name, initializer) => {
+                self.declared.insert(name.lexeme.clone());
+                if let Some(initializer) = initializer {
+                    self.expr(*initializer);
+                }
+            }
+            Stmt::Block(statements) => self.stmts(statements),
+            Stmt::If(condition, then_branch, else_branch) => {
+                self.expr(*condition);
+                self.stmt(*then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.stmt(*else_branch);
+                }
+            }
+            Stmt::While(condition, body) => {
+                self.expr(*condition);
+                self.stmt(*body);
+            }
+            Stmt::Function(name, params, body) => {
+                self.declared.insert(name.lexeme.clone());
+                for param in params {
+                    self.declared.insert(param.lexeme.clone());
+                }
+                self.stmts(body);
+            }
+            Stmt::Return(_, value) => {
+                if let Some(value) = value {
+                    self.expr(*value);
+                }
+            }
+            Stmt::Class(name, _, _) => {
+                self.declared.insert(name.lexeme.clone());
+            }
+        }
+    }
+
+    fn stmts(&mut self, statements: &[StmtId]) {
+        for &stmt in statements {
+            self.stmt(stmt);
+        }
+    }
+
+    fn expr(&mut self, id: ExprId) {
+        stack::descend(|| self.expr_node(id))
+    }
+
+    fn expr_node(&mut self, id: ExprId) {
+        let ast = self.ast;
+        match ast.expr(id) {
+            Expr::Binary(left, _, right) | Expr::Logical(left, _, right) => {
+                self.expr(*left);
+                self.expr(*right);
+            }
+            Expr::Unary(_, expr) | Expr::Grouping(expr) | Expr::Get(expr, _) => self.expr(*expr),
+            Expr::Literal(_) | Expr::This(_) | Expr::Super(..) => {}
+            Expr::Variable(name) => self.uses.push(name),
+            Expr::Assignment(name, value) => {
+                self.uses.push(name);
+                self.expr(*value);
+            }
+            Expr::Call(callee, _, arguments) => {
+                self.expr(*callee);
+                for &argument in arguments {
+                    self.expr(argument);
+                }
+            }
+            Expr::Set(object, _, value) => {
+                self.expr(*object);
+                self.expr(*value);
+            }
+        }
+    }
+}
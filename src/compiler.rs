@@ -0,0 +1,662 @@
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::{
+    ast::{Ast, Program},
+    expr::{Expr, ExprId},
+    scanner::{Literal, LiteralValue, ParseError, Token, TokenType},
+    stack,
+    stmt::{Stmt, StmtId},
+};
+
+// Lowers a parsed program to bytecode for the stack machine in `vm`, for
+// `--backend=vm`. Each function, and the script itself, becomes a
+// `Function` whose chunk holds its instructions and the constants, nested
+// functions and tokens they refer to by index.
+//
+// Variables are resolved as the code is compiled. Locals live in slots on
+// the VM's stack, except those that a nested function refers to, which are
+// found beforehand by `Captures` and live in cells of their own instead,
+// so that closures can share them and they outlive the call that declared
+// them. Names that are not declared in an enclosing function are globals,
+// looked up when the code runs, as the tree-walker does.
+
+pub struct Function {
+    pub name: String,
+    pub arity: usize,
+    pub chunk: Chunk,
+    // How many cells a call of the function needs for its captured locals.
+    pub cells: usize,
+    // What the function's closures capture, in the order its
+    // `GetUpvalue` and `SetUpvalue` instructions number them.
+    pub captures: Vec<Capture>,
+}
+
+#[derive(Default)]
+pub struct Chunk {
+    pub code: Vec<Op>,
+    pub constants: Vec<LiteralValue>,
+    pub functions: Vec<Rc<Function>>,
+    // The tokens of names, and of operators and calls that may fail, which
+    // errors are reported at.
+    pub tokens: Vec<Token>,
+}
+
+// Where a closure finds a variable it captures when it is made: a cell of
+// the function making it, or one that function has captured itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capture {
+    Cell(u32),
+    Upvalue(u32),
+}
+
+// One instruction. Operands index the chunk's tables, the current call's
+// stack slots or cells, or the code itself for jumps.
+#[derive(Debug, Clone, Copy)]
+pub enum Op {
+    Constant(u32),
+    Nil,
+    True,
+    False,
+    Pop,
+    GetLocal(u32),
+    SetLocal(u32),
+    GetCell(u32),
+    SetCell(u32),
+    // Moves the value on top of the stack into a new cell.
+    MakeCell(u32),
+    GetUpvalue(u32),
+    SetUpvalue(u32),
+    GetGlobal(u32),
+    DefineGlobal(u32),
+    SetGlobal(u32),
+    GetProperty(u32),
+    SetProperty(u32),
+    // The common operators have instructions of their own, which are quick
+    // with numbers; the others, and these with other operands, defer to
+    // the interpreter so that both backends give the same results.
+    Add(u32),
+    Subtract(u32),
+    Multiply(u32),
+    Less(u32),
+    Greater(u32),
+    Binary(u32),
+    Negate(u32),
+    Not,
+    Print,
+    Jump(u32),
+    // Jump if the value on top of the stack is false, or true, leaving it
+    // there.
+    JumpIfFalse(u32),
+    JumpIfTrue(u32),
+    Call { token: u32, arguments: u32 },
+    Closure(u32),
+    Return,
+}
+
+pub fn compile(program: &Program) -> Result<Rc<Function>, ParseError> {
+    let ast = &program.ast;
+    let mut captures = Captures::default();
+    captures.statements(ast, &program.statements);
+    let mut compiler = Compiler {
+        ast,
+        captured: captures.captured,
+        functions: vec![FunctionCompiler::new("<script>", 0)],
+    };
+    for &statement in &program.statements {
+        compiler.statement(statement)?;
+    }
+    Ok(Rc::new(compiler.finish()))
+}
+
+// Compiles a lone expression, as the REPL evaluates, to a function that
+// returns its value.
+pub fn compile_expression(ast: &Arc<Ast>, expression: ExprId) -> Result<Rc<Function>, ParseError> {
+    // An expression declares no functions, so captures nothing.
+    let mut compiler = Compiler {
+        ast,
+        captured: HashSet::new(),
+        functions: vec![FunctionCompiler::new("<script>", 0)],
+    };
+    compiler.expression(expression)?;
+    compiler.emit(Op::Return);
+    Ok(Rc::new(compiler.finish()))
+}
+
+// Where a declaration is, which tells it apart from others of the same
+// name.
+type Place = (usize, usize);
+
+fn place(name: &Token) -> Place {
+    (name.line, name.column)
+}
+
+// Finds the local variables that functions declared inside their scope
+// refer to.
+#[derive(Default)]
+struct Captures {
+    // The innermost scope last, with the depth of the function it is in.
+    scopes: Vec<(HashMap<String, Place>, usize)>,
+    depth: usize,
+    captured: HashSet<Place>,
+}
+
+impl Captures {
+    fn statements(&mut self, ast: &Ast, statements: &[StmtId]) {
+        for &statement in statements {
+            self.statement(ast, statement);
+        }
+    }
+
+    fn statement(&mut self, ast: &Ast, statement: StmtId) {
+        match ast.stmt(statement) {
+            Stmt::Expression(expr) | Stmt::Print(expr) => self.expression(ast, *expr),
+            Stmt::Var(name, initializer) => {
+                if let Some(initializer) = initializer {
+                    self.expression(ast, *initializer);
+                }
+                self.declare(name);
+            }
+            Stmt::Block(statements) => {
+                self.scopes.push((HashMap::new(), self.depth));
+                self.statements(ast, statements);
+                self.scopes.pop();
+            }
+            Stmt::If(condition, then_branch, else_branch) => {
+                self.expression(ast, *condition);
+                self.statement(ast, *then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.statement(ast, *else_branch);
+                }
+            }
+            Stmt::While(condition, body) => {
+                self.expression(ast, *condition);
+                self.statement(ast, *body);
+            }
+            Stmt::Function(name, params, body) => {
+                self.declare(name);
+                self.depth += 1;
+                self.scopes.push((HashMap::new(), self.depth));
+                for param in params {
+                    self.declare(param);
+                }
+                self.statements(ast, body);
+                self.scopes.pop();
+                self.depth -= 1;
+            }
+            Stmt::Return(_, value) => {
+                if let Some(value) = value {
+                    self.expression(ast, *value);
+                }
+            }
+            Stmt::Class(..) => {}
+        }
+    }
+
+    fn expression(&mut self, ast: &Ast, expression: ExprId) {
+        stack::descend(|| self.expression_node(ast, expression))
+    }
+
+    fn expression_node(&mut self, ast: &Ast, expression: ExprId) {
+        match ast.expr(expression) {
+            Expr::Binary(left, _, right) | Expr::Logical(left, _, right) => {
+                self.expression(ast, *left);
+                self.expression(ast, *right);
+            }
+            Expr::Unary(_, expr) | Expr::Grouping(expr) | Expr::Get(expr, _) => {
+                self.expression(ast, *expr)
+            }
+            Expr::Variable(name) => self.refer(name),
+            Expr::Assignment(name, value) => {
+                self.expression(ast, *value);
+                self.refer(name);
+            }
+            Expr::Call(callee, _, arguments) => {
+                self.expression(ast, *callee);
+                for &argument in arguments {
+                    self.expression(ast, argument);
+                }
+            }
+            Expr::Set(object, _, value) => {
+                self.expression(ast, *object);
+                self.expression(ast, *value);
+            }
+            Expr::Literal(_) | Expr::This(_) | Expr::Super(..) => {}
+        }
+    }
+
+    // Names declared outside any scope are globals, which are not captured.
+    fn declare(&mut self, name: &Token) {
+        if let Some((names, _)) = self.scopes.last_mut() {
+            names.insert(name.lexeme.clone(), place(name));
+        }
+    }
+
+    fn refer(&mut self, name: &Token) {
+        let found = self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|(names, depth)| Some((*names.get(&name.lexeme)?, *depth)));
+        if let Some((place, depth)) = found {
+            if depth < self.depth {
+                self.captured.insert(place);
+            }
+        }
+    }
+}
+
+struct Compiler<'a> {
+    ast: &'a Arc<Ast>,
+    captured: HashSet<Place>,
+    // The function being compiled last, inside the ones before it.
+    functions: Vec<FunctionCompiler>,
+}
+
+struct FunctionCompiler {
+    function: Function,
+    locals: Vec<Local>,
+    scope_depth: usize,
+    // How many locals are in stack slots.
+    slots: u32,
+}
+
+struct Local {
+    name: String,
+    depth: usize,
+    storage: Storage,
+}
+
+#[derive(Clone, Copy)]
+enum Storage {
+    Slot(u32),
+    Cell(u32),
+}
+
+impl FunctionCompiler {
+    fn new(name: &str, arity: usize) -> FunctionCompiler {
+        FunctionCompiler {
+            function: Function {
+                name: name.to_string(),
+                arity,
+                chunk: Chunk::default(),
+                cells: 0,
+                captures: Vec::new(),
+            },
+            locals: Vec::new(),
+            scope_depth: 0,
+            slots: 0,
+        }
+    }
+
+    fn local(&self, name: &str) -> Option<Storage> {
+        let local = self.locals.iter().rev().find(|local| local.name == name)?;
+        Some(local.storage)
+    }
+
+    fn capture(&mut self, capture: Capture) -> u32 {
+        let captures = &mut self.function.captures;
+        let index = captures.iter().position(|&existing| existing == capture);
+        index.unwrap_or_else(|| {
+            captures.push(capture);
+            captures.len() - 1
+        }) as u32
+    }
+}
+
+// How to read and write a variable.
+enum Variable {
+    Local(Storage),
+    Upvalue(u32),
+    Global(u32),
+}
+
+impl Compiler<'_> {
+    fn current(&mut self) -> &mut FunctionCompiler {
+        self.functions
+            .last_mut()
+            .expect("a function is being compiled")
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        let code = &mut self.current().function.chunk.code;
+        code.push(op);
+        code.len() - 1
+    }
+
+    fn here(&mut self) -> u32 {
+        self.current().function.chunk.code.len() as u32
+    }
+
+    // Points the jump at `index` to the next instruction.
+    fn patch(&mut self, index: usize) {
+        let target = self.here();
+        match &mut self.current().function.chunk.code[index] {
+            Op::Jump(to) | Op::JumpIfFalse(to) | Op::JumpIfTrue(to) => *to = target,
+            _ => unreachable!("only jumps are patched"),
+        }
+    }
+
+    fn token(&mut self, token: &Token) -> u32 {
+        let tokens = &mut self.current().function.chunk.tokens;
+        tokens.push(token.clone());
+        (tokens.len() - 1) as u32
+    }
+
+    fn constant(&mut self, value: LiteralValue) {
+        let constants = &mut self.current().function.chunk.constants;
+        constants.push(value);
+        let index = (constants.len() - 1) as u32;
+        self.emit(Op::Constant(index));
+    }
+
+    fn finish(&mut self) -> Function {
+        self.emit(Op::Nil);
+        self.emit(Op::Return);
+        self.functions
+            .pop()
+            .expect("a function is being compiled")
+            .function
+    }
+
+    fn statement(&mut self, statement: StmtId) -> Result<(), ParseError> {
+        let ast = self.ast;
+        match ast.stmt(statement) {
+            Stmt::Expression(expr) => {
+                self.expression(*expr)?;
+                self.emit(Op::Pop);
+            }
+            Stmt::Print(expr) => {
+                self.expression(*expr)?;
+                self.emit(Op::Print);
+            }
+            Stmt::Var(name, initializer) => {
+                match initializer {
+                    Some(initializer) => self.expression(*initializer)?,
+                    None => {
+                        self.emit(Op::Nil);
+                    }
+                }
+                self.define(name);
+            }
+            Stmt::Block(statements) => {
+                self.current().scope_depth += 1;
+                for &statement in statements {
+                    self.statement(statement)?;
+                }
+                self.end_scope();
+            }
+            Stmt::If(condition, then_branch, else_branch) => {
+                self.expression(*condition)?;
+                let to_else = self.emit(Op::JumpIfFalse(0));
+                self.emit(Op::Pop);
+                self.statement(*then_branch)?;
+                let to_end = self.emit(Op::Jump(0));
+                self.patch(to_else);
+                self.emit(Op::Pop);
+                if let Some(else_branch) = else_branch {
+                    self.statement(*else_branch)?;
+                }
+                self.patch(to_end);
+            }
+            Stmt::While(condition, body) => {
+                let start = self.here();
+                self.expression(*condition)?;
+                let to_exit = self.emit(Op::JumpIfFalse(0));
+                self.emit(Op::Pop);
+                self.statement(*body)?;
+                self.emit(Op::Jump(start));
+                self.patch(to_exit);
+                self.emit(Op::Pop);
+            }
+            Stmt::Function(name, params, body) => self.function(name, params, body)?,
+            Stmt::Return(_, value) => {
+                match value {
+                    Some(value) => self.expression(*value)?,
+                    None => {
+                        self.emit(Op::Nil);
+                    }
+                }
+                self.emit(Op::Return);
+            }
+            Stmt::Class(name, _, _) => return Err(unsupported(name, "Classes")),
+        }
+        Ok(())
+    }
+
+    // Declares a variable with the value on top of the stack.
+    fn define(&mut self, name: &Token) {
+        if self.current().scope_depth == 0 && self.functions.len() == 1 {
+            let token = self.token(name);
+            self.emit(Op::DefineGlobal(token));
+            return;
+        }
+        let storage = if self.captured.contains(&place(name)) {
+            let current = self.current();
+            let cell = current.function.cells as u32;
+            current.function.cells += 1;
+            self.emit(Op::MakeCell(cell));
+            Storage::Cell(cell)
+        } else {
+            // The value is already where the slot is.
+            let current = self.current();
+            current.slots += 1;
+            Storage::Slot(current.slots - 1)
+        };
+        let current = self.current();
+        current.locals.push(Local {
+            name: name.lexeme.clone(),
+            depth: current.scope_depth,
+            storage,
+        });
+    }
+
+    fn end_scope(&mut self) {
+        let current = self.current();
+        current.scope_depth -= 1;
+        let depth = current.scope_depth;
+        let mut pops = 0;
+        while let Some(local) = current.locals.last() {
+            if local.depth <= depth {
+                break;
+            }
+            if let Storage::Slot(_) = local.storage {
+                pops += 1;
+            }
+            current.locals.pop();
+        }
+        current.slots -= pops;
+        for _ in 0..pops {
+            self.emit(Op::Pop);
+        }
+    }
+
+    fn function(
+        &mut self,
+        name: &Token,
+        params: &[Token],
+        body: &[StmtId],
+    ) -> Result<(), ParseError> {
+        let global = self.current().scope_depth == 0 && self.functions.len() == 1;
+        // A function can call itself, so a local one is declared first.
+        let captured = !global && self.captured.contains(&place(name));
+        if captured {
+            self.emit(Op::Nil);
+            self.define(name);
+        }
+
+        self.functions
+            .push(FunctionCompiler::new(&name.lexeme, params.len()));
+        self.current().scope_depth = 1;
+        for param in params {
+            let current = self.current();
+            current.slots += 1;
+            let slot = current.slots - 1;
+            current.locals.push(Local {
+                name: param.lexeme.clone(),
+                depth: 1,
+                storage: Storage::Slot(slot),
+            });
+            // A captured parameter moves into a cell as the call starts.
+            if self.captured.contains(&place(param)) {
+                self.emit(Op::GetLocal(slot));
+                self.current().locals.pop();
+                self.define(param);
+            }
+        }
+        for &statement in body {
+            self.statement(statement)?;
+        }
+        let function = self.finish();
+
+        let functions = &mut self.current().function.chunk.functions;
+        functions.push(Rc::new(function));
+        let index = (functions.len() - 1) as u32;
+        self.emit(Op::Closure(index));
+        if global {
+            let token = self.token(name);
+            self.emit(Op::DefineGlobal(token));
+        } else if captured {
+            self.set_variable(name);
+            self.emit(Op::Pop);
+        } else {
+            self.define(name);
+        }
+        Ok(())
+    }
+
+    fn expression(&mut self, expression: ExprId) -> Result<(), ParseError> {
+        stack::descend(|| self.expression_node(expression))
+    }
+
+    fn expression_node(&mut self, expression: ExprId) -> Result<(), ParseError> {
+        let ast = self.ast;
+        match ast.expr(expression) {
+            Expr::Literal(value) => match value {
+                None | Some(Literal::Nil) => {
+                    self.emit(Op::Nil);
+                }
+                Some(Literal::Boolean(true)) => {
+                    self.emit(Op::True);
+                }
+                Some(Literal::Boolean(false)) => {
+                    self.emit(Op::False);
+                }
+                Some(value) => self.constant(value.value()),
+            },
+            Expr::Grouping(expr) => self.expression(*expr)?,
+            Expr::Unary(operator, right) => {
+                self.expression(*right)?;
+                if operator.token_type == TokenType::Bang {
+                    self.emit(Op::Not);
+                } else {
+                    let token = self.token(operator);
+                    self.emit(Op::Negate(token));
+                }
+            }
+            Expr::Binary(left, operator, right) => {
+                self.expression(*left)?;
+                self.expression(*right)?;
+                let token = self.token(operator);
+                self.emit(match operator.token_type {
+                    TokenType::Plus => Op::Add(token),
+                    TokenType::Minus => Op::Subtract(token),
+                    TokenType::Star => Op::Multiply(token),
+                    TokenType::Less => Op::Less(token),
+                    TokenType::Greater => Op::Greater(token),
+                    _ => Op::Binary(token),
+                });
+            }
+            Expr::Logical(left, operator, right) => {
+                self.expression(*left)?;
+                let to_end = if operator.token_type == TokenType::Or {
+                    self.emit(Op::JumpIfTrue(0))
+                } else {
+                    self.emit(Op::JumpIfFalse(0))
+                };
+                self.emit(Op::Pop);
+                self.expression(*right)?;
+                self.patch(to_end);
+            }
+            Expr::Variable(name) => {
+                let op = match self.variable(name) {
+                    Variable::Local(Storage::Slot(slot)) => Op::GetLocal(slot),
+                    Variable::Local(Storage::Cell(cell)) => Op::GetCell(cell),
+                    Variable::Upvalue(index) => Op::GetUpvalue(index),
+                    Variable::Global(token) => Op::GetGlobal(token),
+                };
+                self.emit(op);
+            }
+            Expr::Assignment(name, value) => {
+                self.expression(*value)?;
+                self.set_variable(name);
+            }
+            Expr::Call(callee, paren, arguments) => {
+                self.expression(*callee)?;
+                for &argument in arguments {
+                    self.expression(argument)?;
+                }
+                let token = self.token(paren);
+                self.emit(Op::Call {
+                    token,
+                    arguments: arguments.len() as u32,
+                });
+            }
+            Expr::Get(object, name) => {
+                self.expression(*object)?;
+                let token = self.token(name);
+                self.emit(Op::GetProperty(token));
+            }
+            Expr::Set(object, name, value) => {
+                self.expression(*object)?;
+                self.expression(*value)?;
+                let token = self.token(name);
+                self.emit(Op::SetProperty(token));
+            }
+            Expr::This(keyword) | Expr::Super(keyword, _) => {
+                return Err(unsupported(keyword, "Classes"))
+            }
+        }
+        Ok(())
+    }
+
+    // Sets a variable to the value on top of the stack, leaving it there.
+    fn set_variable(&mut self, name: &Token) {
+        let op = match self.variable(name) {
+            Variable::Local(Storage::Slot(slot)) => Op::SetLocal(slot),
+            Variable::Local(Storage::Cell(cell)) => Op::SetCell(cell),
+            Variable::Upvalue(index) => Op::SetUpvalue(index),
+            Variable::Global(token) => Op::SetGlobal(token),
+        };
+        self.emit(op);
+    }
+
+    fn variable(&mut self, name: &Token) -> Variable {
+        let level = self.functions.len() - 1;
+        if let Some(storage) = self.functions[level].local(&name.lexeme) {
+            return Variable::Local(storage);
+        }
+        match self.upvalue(level, &name.lexeme) {
+            Some(index) => Variable::Upvalue(index),
+            None => Variable::Global(self.token(name)),
+        }
+    }
+
+    // The index of the upvalue through which the function at `level`
+    // reaches a local of a function around it, if there is one.
+    fn upvalue(&mut self, level: usize, name: &str) -> Option<u32> {
+        let enclosing = level.checked_sub(1)?;
+        let capture = match self.functions[enclosing].local(name) {
+            Some(Storage::Cell(cell)) => Capture::Cell(cell),
+            Some(Storage::Slot(_)) => unreachable!("`Captures` puts captured locals in cells"),
+            None => Capture::Upvalue(self.upvalue(enclosing, name)?),
+        };
+        Some(self.functions[level].capture(capture))
+    }
+}
+
+fn unsupported(token: &Token, what: &str) -> ParseError {
+    let message = format!("{} are not supported by the VM backend yet.", what);
+    ParseError::UnexpectedToken(token.clone(), message)
+}
@@ -0,0 +1,226 @@
+use std::{fs, io, path::Path};
+
+use interprete::{
+    capability::{Capabilities, Capability},
+    interpreter::{Backend, Setup},
+    log,
+};
+
+// Defaults for the scripts in a directory, read from `interprete.toml` or,
+// if there is none, `.loxrc` beside them, so that a project does not need
+// the same options on every command line. Both hold settings one per line,
+// in a small part of TOML: `true` or `false`, strings in double quotes and
+// lists of strings on one line.
+//
+//     # The scripts here never touch the disk, and compare like with like.
+//     deny = ["fs"]
+//     strict = true
+//     prelude = false
+//
+// `allow` and `deny` also take "all", and `strict` is `Setup::strict_types`.
+// Options on the command line are applied after these settings, so they
+// win.
+//
+// A config file comes with the scripts, so whoever wrote them could have
+// written it too. It may take capabilities away, but giving any beyond the
+// defaults, or loading plugins, which can do anything, is an error unless
+// the command line says to trust it with `--trust-config`:
+//
+//     allow = ["net"]
+//     plugins = ["lib/libgeometry.so"]
+//
+// Plugin paths are relative to the directory of the file. There are no
+// modules yet, so no search path for them either.
+
+const FILE_NAMES: [&str; 2] = ["interprete.toml", ".loxrc"];
+
+// One change to how an interpreter is set up, made by a line of a config
+// file or an option on the command line.
+pub enum Setting {
+    Allow(Capability),
+    Deny(Capability),
+    Prelude(bool),
+    Strict(bool),
+    Plugin(String),
+    Trace,
+    // Counting which statements run, and writing the counts to an lcov
+    // file if there is a path.
+    Coverage(Option<String>),
+    Backend(Backend),
+}
+
+impl Setting {
+    pub fn apply(self, setup: &mut Setup) {
+        match self {
+            Setting::Allow(capability) => setup.capabilities.allow(capability),
+            Setting::Deny(capability) => setup.capabilities.deny(capability),
+            Setting::Prelude(prelude) => setup.prelude = prelude,
+            Setting::Strict(strict) => setup.strict_types = strict,
+            Setting::Plugin(path) => setup.plugins.push(path),
+            Setting::Trace => setup.trace = true,
+            Setting::Coverage(lcov) => {
+                setup.coverage = true;
+                setup.lcov = lcov;
+            }
+            Setting::Backend(backend) => setup.backend = backend,
+        }
+    }
+}
+
+// The settings in the config file in `dir`, or none if it has no config
+// file. Unless `trusted`, settings that give scripts more than the defaults
+// are errors. The error names the file and line of the setting at fault.
+pub fn load(dir: &Path, trusted: bool) -> Result<Vec<Setting>, String> {
+    for name in FILE_NAMES {
+        let path = dir.join(name);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => continue,
+            Err(error) => return Err(format!("Error reading '{}': {}", path.display(), error)),
+        };
+        log::info(format_args!("Reading settings from '{}'", path.display()));
+        let mut settings = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            parse_line(line, dir, trusted, &mut settings)
+                .map_err(|error| format!("{}:{}: {}", path.display(), number + 1, error))?;
+        }
+        return Ok(settings);
+    }
+    Ok(Vec::new())
+}
+
+enum Value {
+    Boolean(bool),
+    String(String),
+    List(Vec<String>),
+}
+
+fn parse_line(
+    line: &str,
+    dir: &Path,
+    trusted: bool,
+    settings: &mut Vec<Setting>,
+) -> Result<(), String> {
+    let line = without_comment(line).trim();
+    if line.is_empty() {
+        return Ok(());
+    }
+    let (key, value) = line
+        .split_once('=')
+        .ok_or_else(|| "Expected 'key = value'.".to_string())?;
+    let key = key.trim();
+    match (key, value_of(value.trim())?) {
+        ("prelude", Value::Boolean(prelude)) => settings.push(Setting::Prelude(prelude)),
+        ("strict", Value::Boolean(strict)) => settings.push(Setting::Strict(strict)),
+        ("allow" | "deny", value) => {
+            for capability in capabilities(strings(key, value)?)? {
+                if key == "deny" {
+                    settings.push(Setting::Deny(capability));
+                    continue;
+                }
+                if !trusted && !Capabilities::default().allows(capability) {
+                    return Err(format!(
+                        "Only the command line can allow '{}', unless it has --trust-config.",
+                        capability
+                    ));
+                }
+                settings.push(Setting::Allow(capability));
+            }
+        }
+        ("plugins", value) => {
+            let paths = strings(key, value)?;
+            if !trusted && !paths.is_empty() {
+                return Err(
+                    "Only the command line can load plugins, unless it has --trust-config."
+                        .to_string(),
+                );
+            }
+            for path in paths {
+                let path = dir.join(path).to_string_lossy().into_owned();
+                settings.push(Setting::Plugin(path));
+            }
+        }
+        ("prelude" | "strict", _) => return Err(format!("'{}' must be true or false.", key)),
+        _ => {
+            return Err(format!(
+                "Unknown setting '{}'; the settings are allow, deny, strict, prelude and plugins.",
+                key
+            ))
+        }
+    }
+    Ok(())
+}
+
+// Cuts a line off at a `#` that is not in a string.
+fn without_comment(line: &str) -> &str {
+    let mut in_string = false;
+    for (index, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..index],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn value_of(text: &str) -> Result<Value, String> {
+    match text {
+        "true" => return Ok(Value::Boolean(true)),
+        "false" => return Ok(Value::Boolean(false)),
+        _ => {}
+    }
+    if let Some(items) = text.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+        let items = items.trim().trim_end_matches(',');
+        if items.trim().is_empty() {
+            return Ok(Value::List(Vec::new()));
+        }
+        return items
+            .split(',')
+            .map(|item| string_of(item.trim()))
+            .collect::<Result<_, _>>()
+            .map(Value::List);
+    }
+    string_of(text).map(Value::String)
+}
+
+fn string_of(text: &str) -> Result<String, String> {
+    text.strip_prefix('"')
+        .and_then(|t| t.strip_suffix('"'))
+        .filter(|t| !t.contains('"'))
+        .map(str::to_string)
+        .ok_or_else(|| {
+            format!(
+                "Expected true, false, a string or a list, found '{}'.",
+                text
+            )
+        })
+}
+
+// A string or a list of strings, as one list.
+fn strings(key: &str, value: Value) -> Result<Vec<String>, String> {
+    match value {
+        Value::String(s) => Ok(vec![s]),
+        Value::List(items) => Ok(items),
+        Value::Boolean(_) => Err(format!("'{}' must be a string or a list of strings.", key)),
+    }
+}
+
+fn capabilities(names: Vec<String>) -> Result<Vec<Capability>, String> {
+    let mut capabilities = Vec::new();
+    for name in names {
+        if name == "all" {
+            capabilities.extend(Capability::ALL);
+            continue;
+        }
+        let capability = Capability::from_name(&name).ok_or_else(|| {
+            let known = Capability::ALL.map(Capability::name).join(", ");
+            format!(
+                "Unknown capability '{}'; the capabilities are {}.",
+                name, known
+            )
+        })?;
+        capabilities.push(capability);
+    }
+    Ok(capabilities)
+}
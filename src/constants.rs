@@ -0,0 +1,58 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Weak};
+
+use crate::{ast::Ast, intern::Interner, scanner::LiteralValue};
+
+// The values of the string literals of each program an interpreter runs,
+// made from the constants `resolver` gathered the first time the program
+// runs one, so that a literal in a loop is a look up and another reference
+// to the same string rather than a new one each time round. Numbers and
+// the other literals cost nothing to make, and are read from the tree.
+//
+// Programs are told apart by address. A table holds its program weakly,
+// which keeps the address from going to another program while the table
+// is kept, and tables of programs that have been dropped go once another
+// program runs a literal for the first time.
+pub struct Constants {
+    tables: HashMap<*const Ast, Table>,
+}
+
+struct Table {
+    ast: Weak<Ast>,
+    values: Vec<LiteralValue>,
+}
+
+impl Constants {
+    pub fn new() -> Self {
+        Constants {
+            tables: HashMap::new(),
+        }
+    }
+
+    // The value of constant `index` of `ast`.
+    pub fn get(&mut self, ast: &Arc<Ast>, index: usize, strings: &mut Interner) -> LiteralValue {
+        let key = Arc::as_ptr(ast);
+        if let Some(table) = self.tables.get(&key) {
+            return table.values[index].clone();
+        }
+        self.tables.retain(|_, table| table.ast.strong_count() > 0);
+        let values: Vec<_> = ast
+            .constants()
+            .iter()
+            .map(|text| strings.literal(text))
+            .collect();
+        let value = values[index].clone();
+        let table = Table {
+            ast: Arc::downgrade(ast),
+            values,
+        };
+        self.tables.insert(key, table);
+        value
+    }
+}
+
+impl Default for Constants {
+    fn default() -> Self {
+        Constants::new()
+    }
+}
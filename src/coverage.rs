@@ -0,0 +1,133 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Arc;
+
+use crate::{
+    ast::Ast,
+    stmt::{Stmt, StmtId},
+    trace,
+};
+
+// Which statements of a run have run, and how often, for `--coverage`.
+// Once the scripts finish, a summary of each goes to standard error:
+//
+//     Coverage:
+//       sort.lox: 18 of 21 statements run (85.7%); not run: lines 12-13, 30
+//
+// and with `--coverage=PATH`, the counts for each line go to PATH in the
+// lcov format, for tools such as `genhtml`. A block is not counted itself,
+// since the statements in it are, and a statement on a line only counts
+// toward the lines if the line can be told; see `trace::line`. What runs on
+// other threads is not counted.
+#[derive(Default)]
+pub struct Coverage {
+    // Each AST that statements have run from, with how many times each of
+    // its statements has run. The ASTs are few, so a list does.
+    counts: Vec<(Arc<Ast>, Vec<u64>)>,
+}
+
+impl Coverage {
+    pub fn record(&mut self, ast: &Arc<Ast>, statement: StmtId) {
+        let found = self
+            .counts
+            .iter()
+            .position(|(seen, _)| Arc::ptr_eq(seen, ast));
+        let index = found.unwrap_or_else(|| {
+            self.counts.push((ast.clone(), vec![0; ast.len()]));
+            self.counts.len() - 1
+        });
+        self.counts[index].1[statement.0] += 1;
+    }
+
+    // How many times each statement of a script has run, by id.
+    fn counts<'a>(&'a self, ast: &Arc<Ast>) -> impl Fn(StmtId) -> u64 + 'a {
+        let counts = self
+            .counts
+            .iter()
+            .find(|(seen, _)| Arc::ptr_eq(seen, ast))
+            .map(|(_, counts)| counts.as_slice());
+        move |statement| counts.map_or(0, |counts| counts[statement.0])
+    }
+
+    // One line of the summary for a script named `name`.
+    pub fn summary(&self, name: &str, ast: &Arc<Ast>) -> String {
+        let counts = self.counts(ast);
+        let statements: Vec<StmtId> = statements(ast).collect();
+        let run = statements.iter().filter(|&&id| counts(id) > 0).count();
+        let percent = if statements.is_empty() {
+            100.0
+        } else {
+            run as f64 * 100.0 / statements.len() as f64
+        };
+        let mut summary = format!(
+            "{}: {} of {} statements run ({:.1}%)",
+            name,
+            run,
+            statements.len(),
+            percent
+        );
+        let missed: Vec<usize> = lines(ast, &counts)
+            .into_iter()
+            .filter(|&(_, count)| count == 0)
+            .map(|(line, _)| line)
+            .collect();
+        if !missed.is_empty() {
+            write!(summary, "; not run: lines {}", ranges(&missed)).ok();
+        }
+        summary
+    }
+
+    // The record of a script at `path` in an lcov file.
+    pub fn lcov(&self, path: &str, ast: &Arc<Ast>) -> String {
+        let lines = lines(ast, &self.counts(ast));
+        let mut record = format!("TN:\nSF:{}\n", path);
+        for (line, count) in &lines {
+            writeln!(record, "DA:{},{}", line, count).ok();
+        }
+        let hit = lines.values().filter(|&&count| count > 0).count();
+        write!(record, "LF:{}\nLH:{}\nend_of_record\n", lines.len(), hit).ok();
+        record
+    }
+}
+
+// Every statement in a script that counts, leaving out blocks.
+fn statements(ast: &Ast) -> impl Iterator<Item = StmtId> + '_ {
+    (0..ast.len())
+        .map(StmtId)
+        .filter(|&id| !matches!(ast.stmt(id), Stmt::Block(_)))
+}
+
+// Each line that statements start on, with the most times any of them has
+// run.
+fn lines(ast: &Ast, counts: &impl Fn(StmtId) -> u64) -> BTreeMap<usize, u64> {
+    let mut lines = BTreeMap::new();
+    for id in statements(ast) {
+        if let Some(line) = trace::line(ast, id) {
+            let count = lines.entry(line).or_insert(0);
+            *count = (*count).max(counts(id));
+        }
+    }
+    lines
+}
+
+// Sorted line numbers as short ranges, such as `3-5, 9`.
+fn ranges(lines: &[usize]) -> String {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for &line in lines {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == line => *end = line,
+            _ => ranges.push((line, line)),
+        }
+    }
+    let ranges: Vec<String> = ranges
+        .into_iter()
+        .map(|(start, end)| {
+            if start == end {
+                start.to_string()
+            } else {
+                format!("{}-{}", start, end)
+            }
+        })
+        .collect();
+    ranges.join(", ")
+}
@@ -0,0 +1,54 @@
+use interprete::InterruptHandle;
+
+// Makes Control-C stop the script running rather than the interpreter: the
+// script fails with `RuntimeError::Interrupted` at its next statement, so
+// the REPL goes back to its prompt and a script run from a file is reported
+// as stopped. A script can only be stopped between statements, so pressing
+// Control-C again before it has been, as when it is waiting for input,
+// ends the interpreter as usual.
+//
+// Only the first engine made is stopped, which is the only one the command
+// line makes. At the REPL's prompt the line editor reads Control-C itself.
+
+#[cfg(target_os = "linux")]
+mod signal {
+    use std::ffi::c_int;
+
+    pub const SIGINT: c_int = 2;
+
+    extern "C" {
+        pub fn signal(signal: c_int, handler: extern "C" fn(c_int)) -> usize;
+        pub fn _exit(status: c_int) -> !;
+    }
+}
+
+#[cfg(target_os = "linux")]
+static INTERRUPT: std::sync::OnceLock<InterruptHandle> = std::sync::OnceLock::new();
+
+// Handles Control-C from now on by cancelling `interrupt`.
+#[cfg(target_os = "linux")]
+pub fn install(interrupt: InterruptHandle) {
+    if INTERRUPT.set(interrupt).is_err() {
+        return;
+    }
+    // SAFETY: `interrupted` only does what is safe in a signal handler:
+    // atomic loads and stores, and `_exit`.
+    unsafe {
+        signal::signal(signal::SIGINT, interrupted);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn install(_interrupt: InterruptHandle) {}
+
+#[cfg(target_os = "linux")]
+extern "C" fn interrupted(_signal: std::ffi::c_int) {
+    let Some(interrupt) = INTERRUPT.get() else {
+        return;
+    };
+    if interrupt.is_cancelled() {
+        // SAFETY: `_exit` ends the process without running anything else.
+        unsafe { signal::_exit(130) }
+    }
+    interrupt.cancel();
+}
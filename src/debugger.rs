@@ -0,0 +1,23 @@
+use std::io;
+
+use crate::{diagnostic::Format, interpreter::Interpreter, line_editor::LineEditor};
+
+// Pauses a script where it calls `breakpoint()` and reads lines from the
+// user. They run like lines in the REPL, a bare expression having its value
+// printed, but in the scope of the call, so they see the variables the
+// script sees there and can change them. An error is reported and leaves
+// the script paused; `continue`, or the end of input, resumes it.
+pub fn pause(interpreter: &mut Interpreter, line: usize) -> io::Result<()> {
+    eprintln!("Paused at line {}; enter 'continue' to resume.", line);
+    // The debugger keeps no history, to leave the REPL's alone.
+    let mut editor = LineEditor::new(None);
+    while let Some(line) = editor.read_line("debug> ")? {
+        if line.trim() == "continue" {
+            break;
+        }
+        if let Err(e) = crate::run_line(interpreter, line.clone()) {
+            Format::Human.report(&e.diagnostic(), "<debug>", &line);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,345 @@
+use crate::{json::JsonValue, scanner::Token};
+
+// What the interpreter reports about a script, in a form that can be
+// rendered for people or read by tools. Parse and runtime errors both turn
+// into one with `diagnostic()`. Rendered, it shows the line of source it
+// points at:
+//
+//     error[E1003]: Undefined variable 'x'
+//      --> script.lox:3:7
+//       |
+//     3 | print x + 1;
+//       |       ^
+//       |
+//       = help: Declare it with 'var' before using it.
+//
+// Tokens do not record which source they came from, so a token from another
+// file, such as the prelude, could be shown against the wrong one. A span
+// therefore carries the text it covers, and the snippet is only drawn if
+// the source has that text there.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    // A stable code for the kind of problem, such as `E1003`: `E0xxx` for
+    // errors found parsing and `E1xxx` for ones found running. `--explain`
+    // describes each one.
+    pub code: &'static str,
+    pub message: String,
+    // Where the problem is, if it is anywhere in particular, and a note to
+    // show beside it.
+    pub span: Option<Span>,
+    pub label: Option<String>,
+    // Other places that bear on the problem.
+    pub labels: Vec<Label>,
+    pub help: Option<String>,
+    // For an error while running, the calls to script functions that were
+    // being made, innermost first.
+    pub trace: Vec<Frame>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    // Something that may be a problem, but does not stop a script.
+    Warning,
+}
+
+impl Severity {
+    pub fn name(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+// A call to a script function: the function called, and where it was
+// called from. A call made by the host has a line of zero.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub function: String,
+    pub span: Span,
+}
+
+// A place in the source: the line and column where something starts, both
+// counted from one, and the text there. A column of zero means only the
+// line is known.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    pub text: String,
+}
+
+impl Span {
+    pub fn new(line: usize, column: usize, text: &str) -> Span {
+        Span {
+            line,
+            column,
+            text: text.to_string(),
+        }
+    }
+
+    // The span of a token, which for one spanning lines covers only its
+    // first line.
+    pub fn of(token: &Token) -> Span {
+        let text = token.lexeme.lines().next().unwrap_or("");
+        Span::new(token.start_line(), token.column, text)
+    }
+}
+
+impl Diagnostic {
+    pub fn error(code: &'static str, message: String, span: Option<Span>) -> Diagnostic {
+        Diagnostic::new(Severity::Error, code, message, span)
+    }
+
+    pub fn warning(code: &'static str, message: String, span: Option<Span>) -> Diagnostic {
+        Diagnostic::new(Severity::Warning, code, message, span)
+    }
+
+    fn new(
+        severity: Severity,
+        code: &'static str,
+        message: String,
+        span: Option<Span>,
+    ) -> Diagnostic {
+        Diagnostic {
+            severity,
+            code,
+            message,
+            span,
+            label: None,
+            labels: Vec::new(),
+            help: None,
+            trace: Vec::new(),
+        }
+    }
+
+    pub fn with_label(mut self, label: &str) -> Diagnostic {
+        self.label = Some(label.to_string());
+        self
+    }
+
+    pub fn with_help(mut self, help: &str) -> Diagnostic {
+        self.help = Some(help.to_string());
+        self
+    }
+
+    pub fn with_trace(mut self, trace: Vec<Frame>) -> Diagnostic {
+        self.trace = trace;
+        self
+    }
+
+    // Renders the diagnostic for `source`, which was read from `file`.
+    pub fn render(&self, file: &str, source: &str) -> String {
+        let mut out = format!("{}[{}]: {}", self.severity.name(), self.code, self.message);
+        let lines: Vec<&str> = source
+            .split('\n')
+            .map(|line| line.strip_suffix('\r').unwrap_or(line))
+            .collect();
+        // The gutter is as wide as the widest line number shown.
+        let width = self
+            .labels
+            .iter()
+            .map(|label| label.span.line)
+            .chain(self.span.as_ref().map(|span| span.line))
+            .max()
+            .map_or(0, |line| line.to_string().len());
+        let gutter = " ".repeat(width);
+        if let Some(span) = &self.span {
+            match snippet(&lines, span) {
+                Some(line) => {
+                    out.push_str(&format!(
+                        "\n{gutter}--> {file}:{}:{}\n{gutter} |",
+                        span.line, span.column
+                    ));
+                    out.push_str(&underline(width, line, span, '^', self.label.as_deref()));
+                    for label in &self.labels {
+                        if let Some(line) = snippet(&lines, &label.span) {
+                            out.push_str(&underline(
+                                width,
+                                line,
+                                &label.span,
+                                '-',
+                                Some(&label.message),
+                            ));
+                        }
+                    }
+                }
+                None => out.push_str(&format!("\n{gutter}--> line {}", span.line)),
+            }
+            if self.help.is_some() || !self.trace.is_empty() {
+                out.push_str(&format!("\n{gutter} |"));
+            }
+        }
+        for line in trace(&self.trace, Some(file)) {
+            out.push_str(&format!("\n{gutter} = note: {}", line));
+        }
+        if let Some(help) = &self.help {
+            out.push_str(&format!("\n{gutter} = help: {}", help));
+        }
+        out
+    }
+}
+
+// How the command line reports diagnostics: rendered for people, or, with
+// `--diagnostics=json`, as one JSON object per line for editors and other
+// tools. Either way they go to standard error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Human,
+    Json,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name {
+            "human" => Some(Format::Human),
+            "json" => Some(Format::Json),
+            _ => None,
+        }
+    }
+
+    pub fn report(self, diagnostic: &Diagnostic, file: &str, source: &str) {
+        match self {
+            Format::Human => eprintln!("{}", diagnostic.render(file, source)),
+            Format::Json => eprintln!("{}", diagnostic.to_json(file)),
+        }
+    }
+}
+
+impl Diagnostic {
+    // The diagnostic as a JSON object, such as
+    //
+    //     {"severity":"error","code":"E1003","message":"Undefined variable 'x'",
+    //      "file":"script.lox","span":{"line":3,"column":7,"length":1},
+    //      "label":null,"labels":[],"help":"Declare it with 'var' ...",
+    //      "trace":[]}
+    //
+    // Lines and columns count from one, and lengths are in characters.
+    pub fn to_json(&self, file: &str) -> JsonValue {
+        let string = |s: &str| JsonValue::String(s.to_string());
+        let optional = |s: &Option<String>| s.as_deref().map_or(JsonValue::Null, string);
+        let labels = self
+            .labels
+            .iter()
+            .map(|label| {
+                JsonValue::Object(vec![
+                    ("span".to_string(), label.span.to_json()),
+                    ("message".to_string(), string(&label.message)),
+                ])
+            })
+            .collect();
+        let trace = self
+            .trace
+            .iter()
+            .map(|frame| {
+                JsonValue::Object(vec![
+                    ("function".to_string(), string(&frame.function)),
+                    ("span".to_string(), frame.span.to_json()),
+                ])
+            })
+            .collect();
+        JsonValue::Object(vec![
+            ("severity".to_string(), string(self.severity.name())),
+            ("code".to_string(), string(self.code)),
+            ("message".to_string(), string(&self.message)),
+            ("file".to_string(), string(file)),
+            (
+                "span".to_string(),
+                self.span.as_ref().map_or(JsonValue::Null, Span::to_json),
+            ),
+            ("label".to_string(), optional(&self.label)),
+            ("labels".to_string(), JsonValue::Array(labels)),
+            ("help".to_string(), optional(&self.help)),
+            ("trace".to_string(), JsonValue::Array(trace)),
+        ])
+    }
+}
+
+impl Span {
+    fn to_json(&self) -> JsonValue {
+        let number = |n: usize| JsonValue::Number(n as f64);
+        JsonValue::Object(vec![
+            ("line".to_string(), number(self.line)),
+            ("column".to_string(), number(self.column)),
+            ("length".to_string(), number(self.text.chars().count())),
+        ])
+    }
+}
+
+// The most lines a trace is rendered in; the outermost calls beyond them
+// are only counted.
+const TRACE_LINES: usize = 10;
+
+// A trace rendered a line to a call, with a call repeated one after another,
+// as a recursive function's are, on one line. Without a `file`, places are
+// given by line.
+pub fn trace(frames: &[Frame], file: Option<&str>) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut rest = frames;
+    while let Some(frame) = rest.first() {
+        if lines.len() == TRACE_LINES {
+            lines.push(format!("and {} more calls", rest.len()));
+            break;
+        }
+        let repeats = rest.iter().take_while(|other| *other == frame).count();
+        let place = match (frame.span.line, file) {
+            (0, _) => "by the host".to_string(),
+            (line, Some(file)) => format!("at {}:{}:{}", file, line, frame.span.column),
+            (line, None) => format!("at line {}", line),
+        };
+        let mut line = format!("in {}(), called {}", frame.function, place);
+        if repeats > 1 {
+            line.push_str(&format!(", {} times", repeats));
+        }
+        lines.push(line);
+        rest = &rest[repeats..];
+    }
+    lines
+}
+
+// The line a span points at, if the source has the span's text there. An
+// empty span, such as the end of the file, fits anywhere up to just past
+// the end of its line.
+fn snippet<'a>(lines: &[&'a str], span: &Span) -> Option<&'a str> {
+    let line = *lines.get(span.line.checked_sub(1)?)?;
+    if span.column == 0 {
+        return None;
+    }
+    let fits = if span.text.is_empty() {
+        line.chars().count() + 1 >= span.column
+    } else {
+        let rest: String = line.chars().skip(span.column - 1).collect();
+        rest.starts_with(&span.text)
+    };
+    fits.then_some(line)
+}
+
+// A line of source with the span underlined beneath it, the first
+// character marked with `mark` and the rest with tildes or dashes.
+fn underline(width: usize, line: &str, span: &Span, mark: char, label: Option<&str>) -> String {
+    let rest = if mark == '^' { '~' } else { '-' };
+    // Keep any tabs before the span, so the underline lines up however
+    // wide they are shown.
+    let indent: String = line
+        .chars()
+        .take(span.column - 1)
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    let length = span.text.chars().count().max(1);
+    let marks: String = std::iter::once(mark)
+        .chain(std::iter::repeat_n(rest, length - 1))
+        .collect();
+    let label = label.map(|label| format!(" {}", label)).unwrap_or_default();
+    format!(
+        "\n{:>width$} | {line}\n{:width$} | {indent}{marks}{label}",
+        span.line, ""
+    )
+}
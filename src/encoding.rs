@@ -0,0 +1,106 @@
+// Text encodings of binary data: standard Base64 (RFC 4648, with padding)
+// and lowercase hexadecimal. `encode` and `decode` convert between strings
+// and bytes by the name of an encoding, as the `bytes` natives do.
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn base64_encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let group = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                let sextet = (group >> (18 - 6 * i)) & 0x3F;
+                out.push(BASE64_ALPHABET[sextet as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+pub fn base64_decode(text: &str) -> Result<Vec<u8>, String> {
+    let text = text.trim_end_matches('=');
+    if text.len() % 4 == 1 {
+        return Err("Invalid Base64 length.".to_string());
+    }
+    let mut out = Vec::with_capacity(text.len() * 3 / 4);
+    let mut group = 0u32;
+    let mut bits = 0;
+    for c in text.chars() {
+        let sextet = BASE64_ALPHABET
+            .iter()
+            .position(|&a| a as char == c)
+            .ok_or_else(|| format!("Invalid Base64 character '{}'.", c))?;
+        group = group << 6 | sextet as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((group >> bits) as u8);
+            group &= (1 << bits) - 1;
+        }
+    }
+    Ok(out)
+}
+
+pub fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn hex_decode(text: &str) -> Result<Vec<u8>, String> {
+    let digits: Vec<u32> = text
+        .chars()
+        .map(|c| {
+            c.to_digit(16)
+                .ok_or_else(|| format!("Invalid hexadecimal digit '{}'.", c))
+        })
+        .collect::<Result<_, _>>()?;
+    if !digits.len().is_multiple_of(2) {
+        return Err("Hexadecimal text must have an even number of digits.".to_string());
+    }
+    Ok(digits
+        .chunks(2)
+        .map(|pair| (pair[0] * 16 + pair[1]) as u8)
+        .collect())
+}
+
+// Converts text to bytes: "utf8" and "latin1" encode its characters, while
+// "hex" and "base64" read the bytes it spells out.
+pub fn encode(text: &str, encoding: &str) -> Result<Vec<u8>, String> {
+    match encoding {
+        "utf8" => Ok(text.as_bytes().to_vec()),
+        "latin1" => text
+            .chars()
+            .map(|c| u8::try_from(c).map_err(|_| format!("'{}' is not a Latin-1 character.", c)))
+            .collect(),
+        "hex" => hex_decode(text),
+        "base64" => base64_decode(text),
+        _ => Err(unknown_encoding(encoding)),
+    }
+}
+
+// The inverse of `encode`.
+pub fn decode(bytes: &[u8], encoding: &str) -> Result<String, String> {
+    match encoding {
+        "utf8" => String::from_utf8(bytes.to_vec())
+            .map_err(|_| "Bytes are not valid UTF-8 text.".to_string()),
+        "latin1" => Ok(bytes.iter().map(|&b| b as char).collect()),
+        "hex" => Ok(hex_encode(bytes)),
+        "base64" => Ok(base64_encode(bytes)),
+        _ => Err(unknown_encoding(encoding)),
+    }
+}
+
+fn unknown_encoding(encoding: &str) -> String {
+    format!(
+        "Unknown encoding '{}'; expected 'utf8', 'latin1', 'hex' or 'base64'.",
+        encoding
+    )
+}
@@ -0,0 +1,76 @@
+use std::sync::Arc;
+
+use crate::{
+    ast::{Ast, Program},
+    expr::ExprId,
+    interpreter::{Interpreter, RuntimeError},
+    scanner::{LiteralValue, Token},
+    InterpreterError,
+};
+
+// What runs scripts, whichever backend does it, so that the command line,
+// the REPL and the servers that embed the interpreter need not know which
+// was chosen; see `Setup::engine`. Every engine keeps its globals, natives
+// and what scripts print in an `Interpreter`, which is there for whatever
+// else a caller needs, such as printing values or taking the output.
+pub trait Engine {
+    // Runs a program, keeping what it declares for later ones.
+    fn interpret(&mut self, program: &Program) -> Result<(), InterpreterError>;
+
+    // Evaluates a lone expression among the globals.
+    fn evaluate(
+        &mut self,
+        ast: &Arc<Ast>,
+        expression: ExprId,
+    ) -> Result<LiteralValue, InterpreterError>;
+
+    fn define_global(&mut self, name: &str, value: LiteralValue);
+
+    // Calls a function value, as `Interpreter::call_value` does.
+    fn call(
+        &mut self,
+        callee: LiteralValue,
+        paren: &Token,
+        arguments: Vec<LiteralValue>,
+    ) -> Result<LiteralValue, RuntimeError>;
+
+    fn interpreter(&self) -> &Interpreter;
+
+    fn interpreter_mut(&mut self) -> &mut Interpreter;
+}
+
+// The tree-walker, which is its own engine.
+impl Engine for Interpreter {
+    fn interpret(&mut self, program: &Program) -> Result<(), InterpreterError> {
+        Ok(Interpreter::interpret(self, program)?)
+    }
+
+    fn evaluate(
+        &mut self,
+        ast: &Arc<Ast>,
+        expression: ExprId,
+    ) -> Result<LiteralValue, InterpreterError> {
+        Ok(self.evaluate_expression(ast, expression)?)
+    }
+
+    fn define_global(&mut self, name: &str, value: LiteralValue) {
+        Interpreter::define_global(self, name, value);
+    }
+
+    fn call(
+        &mut self,
+        callee: LiteralValue,
+        paren: &Token,
+        arguments: Vec<LiteralValue>,
+    ) -> Result<LiteralValue, RuntimeError> {
+        self.call_value(callee, paren, arguments)
+    }
+
+    fn interpreter(&self) -> &Interpreter {
+        self
+    }
+
+    fn interpreter_mut(&mut self) -> &mut Interpreter {
+        self
+    }
+}
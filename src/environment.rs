@@ -2,10 +2,20 @@ use crate::{
     interpreter::RuntimeError,
     scanner::{LiteralValue, Token},
 };
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{cell::RefCell, collections::HashMap, rc::Rc, sync::Arc};
 
+// A scope of variables. Those `resolver` found declared in a block or
+// function are kept by slot, and read by where it found them rather than
+// by name; globals, and variables declared by code that was not resolved
+// with the scope, as the debugger's, are kept by name, as the interpreter
+// interned it; see `intern`.
 pub struct Environment {
-    values: HashMap<String, LiteralValue>,
+    values: HashMap<Rc<str>, LiteralValue>,
+    // The variables declared so far of those with slots, which are
+    // declared in the order of their slots.
+    slots: Vec<LiteralValue>,
+    // The names of the variables with slots, by slot.
+    names: Option<Arc<[String]>>,
     enclosing: Option<Rc<RefCell<Environment>>>,
 }
 
@@ -13,19 +23,68 @@ impl Environment {
     pub fn new(enclosing: Option<Rc<RefCell<Environment>>>) -> Self {
         Environment {
             values: HashMap::new(),
+            slots: Vec::new(),
+            names: None,
             enclosing,
         }
     }
 
-    
+    // The variables defined in this scope itself, not the ones around it.
+    pub fn entries(&self) -> Vec<(String, LiteralValue)> {
+        self.values()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect()
+    }
+
+    // The variables defined in this scope itself, without copying them.
+    pub fn values(&self) -> impl Iterator<Item = (&str, &LiteralValue)> {
+        let names = self.names.iter().flat_map(|names| names.iter());
+        let slots = names.map(String::as_str).zip(&self.slots);
+        slots.chain(self.values.iter().map(|(name, value)| (&**name, value)))
+    }
+
+    // Drops the variables in this scope, for the collector to free a cycle
+    // it is in; see `gc`.
+    pub fn clear(&mut self) {
+        self.values.clear();
+        self.slots.clear();
+    }
+
+    pub fn enclosing(&self) -> Option<&Rc<RefCell<Environment>>> {
+        self.enclosing.as_ref()
+    }
+
+    // A variable defined in this scope itself, by name.
+    pub fn lookup(&self, name: &str) -> Option<LiteralValue> {
+        match self.slot(name) {
+            Some(slot) => Some(self.slots[slot].clone()),
+            None => self.values.get(name).cloned(),
+        }
+    }
 
-    pub fn define(&mut self, name: String, value: LiteralValue) {
-        self.values.insert(name, value);
+    pub fn define(&mut self, name: Rc<str>, value: LiteralValue) {
+        match self.slot(&name) {
+            Some(slot) => self.slots[slot] = value,
+            None => {
+                self.values.insert(name, value);
+            }
+        }
+    }
+
+    // Defines the variable `resolver` gave `slot`.
+    pub fn define_slot(&mut self, slot: usize, value: LiteralValue) {
+        if slot < self.slots.len() {
+            self.slots[slot] = value;
+        } else {
+            // Only a program that was not parsed declares out of order.
+            self.slots.resize(slot, LiteralValue::Nil);
+            self.slots.push(value);
+        }
     }
 
     pub fn get(&self, name: &Token) -> Result<LiteralValue, RuntimeError> {
-        if let Some(value) = self.values.get(&name.lexeme) {
-            Ok(value.clone())
+        if let Some(value) = self.lookup(&name.lexeme) {
+            Ok(value)
         } else if let Some(enclosing) = &self.enclosing {
             enclosing.borrow().get(name)
         } else {
@@ -33,9 +92,21 @@ impl Environment {
         }
     }
 
+    // The variable in `slot` of the scope `depth` scopes out from this
+    // one, or none if it has not been declared yet.
+    pub fn get_at(&self, depth: usize, slot: usize) -> Option<LiteralValue> {
+        match depth {
+            0 => self.slots.get(slot).cloned(),
+            _ => self.enclosing.as_ref()?.borrow().get_at(depth - 1, slot),
+        }
+    }
+
     pub fn assign(&mut self, name: &Token, value: LiteralValue) -> Result<(), RuntimeError> {
-        if self.values.contains_key(&name.lexeme) {
-            self.values.insert(name.lexeme.clone(), value);
+        if let Some(slot) = self.slot(&name.lexeme) {
+            self.slots[slot] = value;
+            Ok(())
+        } else if let Some(variable) = self.values.get_mut(name.lexeme.as_str()) {
+            *variable = value;
             Ok(())
         } else if let Some(enclosing) = &mut self.enclosing {
             enclosing.borrow_mut().assign(name, value)
@@ -43,4 +114,95 @@ impl Environment {
             Err(RuntimeError::UndefinedVariable(name.clone()))
         }
     }
+
+    // Sets the variable in `slot` of the scope `depth` scopes out, giving
+    // whether it has been declared.
+    pub fn assign_at(&mut self, depth: usize, slot: usize, value: LiteralValue) -> bool {
+        match depth {
+            0 => match self.slots.get_mut(slot) {
+                Some(variable) => {
+                    *variable = value;
+                    true
+                }
+                None => false,
+            },
+            _ => match &self.enclosing {
+                Some(enclosing) => enclosing.borrow_mut().assign_at(depth - 1, slot, value),
+                None => false,
+            },
+        }
+    }
+
+    // The slot of a variable with a slot that has been declared, by name.
+    // A name given to more than one, as parameters may be, is the last.
+    fn slot(&self, name: &str) -> Option<usize> {
+        let names = self.names.as_ref()?;
+        names.iter().take(self.slots.len()).rposition(|n| n == name)
+    }
+}
+
+// How many scopes a pool keeps for reuse at most.
+const POOL_SIZE: usize = 64;
+
+// Scopes that blocks and calls have finished with, to open the next ones
+// in rather than allocate each anew, as a loop whose body declares
+// variables opens a scope each time round. A scope a function declared in
+// it still holds is left to the function, and not reused.
+pub struct Pool {
+    free: Vec<Rc<RefCell<Environment>>>,
+}
+
+impl Pool {
+    pub fn new() -> Self {
+        Pool { free: Vec::new() }
+    }
+
+    // A scope for a block or call in `enclosing`, whose variables are
+    // `names` by slot if `resolver` found them.
+    pub fn open(
+        &mut self,
+        enclosing: Rc<RefCell<Environment>>,
+        names: Option<Arc<[String]>>,
+    ) -> Rc<RefCell<Environment>> {
+        let Some(scope) = self.free.pop() else {
+            let mut environment = Environment::new(Some(enclosing));
+            if let Some(names) = names {
+                environment.slots.reserve(names.len());
+                environment.names = Some(names);
+            }
+            return Rc::new(RefCell::new(environment));
+        };
+        {
+            let mut environment = scope.borrow_mut();
+            if let Some(names) = &names {
+                environment.slots.reserve(names.len());
+            }
+            environment.names = names;
+            environment.enclosing = Some(enclosing);
+        }
+        scope
+    }
+
+    // Takes back a scope once its block or call has finished, dropping
+    // what it holds.
+    pub fn close(&mut self, mut scope: Rc<RefCell<Environment>>) {
+        if self.free.len() >= POOL_SIZE {
+            return;
+        }
+        let Some(environment) = Rc::get_mut(&mut scope) else {
+            return;
+        };
+        let environment = environment.get_mut();
+        environment.values.clear();
+        environment.slots.clear();
+        environment.names = None;
+        environment.enclosing = None;
+        self.free.push(scope);
+    }
+}
+
+impl Default for Pool {
+    fn default() -> Self {
+        Pool::new()
+    }
 }
@@ -0,0 +1,247 @@
+// Longer descriptions of the error codes, printed by `--explain CODE`. Codes
+// starting `E0` are for errors found parsing a script, before any of it
+// runs; codes starting `E1` are for errors found while running it.
+
+const EXPLANATIONS: &[(&str, &str)] = &[
+    (
+        "E0001",
+        "A string was opened with '\"' but never closed.
+
+Strings run from one double quote to the next, and may span lines, so a
+missing closing quote swallows the rest of the file:
+
+    var greeting = \"hello;
+    print greeting;
+
+Add the closing quote:
+
+    var greeting = \"hello\";
+
+Strings have no escapes, so they cannot contain a double quote.",
+    ),
+    (
+        "E0002",
+        "The script contains a character that is not part of the language.
+
+    print 1 @ 2;
+
+Outside strings and comments, only letters, digits, underscores, whitespace
+and the operators and punctuation of the language may appear.",
+    ),
+    (
+        "E0003",
+        "A token appeared where it cannot start or continue what came before it.
+
+    print (1 + );
+
+Here an expression was expected after '+'. The message says what the parser
+was looking for.",
+    ),
+    (
+        "E0004",
+        "A particular token was required, such as a ';' at the end of a
+statement or a ')' closing a call, but something else was found.
+
+    print 1
+    print 2;
+
+Add the missing token:
+
+    print 1;
+    print 2;",
+    ),
+    (
+        "E0005",
+        "Expressions or blocks are nested more deeply than the parser allows.
+
+The limit keeps deeply nested code from overflowing the stack. Code that
+reaches it is usually generated; split it into smaller functions, or give
+the intermediate values names with 'var'.",
+    ),
+    (
+        "E0006",
+        "The script ended in the middle of a declaration, statement or
+expression. Finish what was started, such as a block missing its closing
+'}'.",
+    ),
+    (
+        "E0007",
+        "An AST given to '--run-ast' is not one that '--emit-ast' produces.
+
+The file must hold the JSON written by '--emit-ast', unchanged. Emit it
+again from the script.",
+    ),
+    (
+        "E0008",
+        "An edit to a document being parsed incrementally names a range of
+bytes that is not in the document, or does not begin and end between
+characters.",
+    ),
+    (
+        "E0009",
+        "A number in the script could not be read as one.
+
+Numbers are digits, with an optional '.' and more digits after it, such as
+'42' or '3.25'. Numbers too large to hold become infinity rather than
+failing, so this points to a bug in the scanner; please report it.",
+    ),
+    (
+        "E1001",
+        "A number was divided by zero.
+
+    var n = 0;
+    print 10 / n;
+
+Check the divisor first:
+
+    if (n != 0) print 10 / n;",
+    ),
+    (
+        "E1002",
+        "'+' was given operands it cannot add. It adds two numbers or joins two
+strings, but does not mix them:
+
+    print \"total: \" + 3;
+
+Turn the number into a string first:
+
+    print \"total: \" + toString(3);",
+    ),
+    (
+        "E1003",
+        "A variable was used that has not been declared.
+
+    print count;
+
+Declare it with 'var' first, in the same scope or an enclosing one:
+
+    var count = 0;
+    print count;
+
+The name may also be misspelt, or declared in a block that has ended.",
+    ),
+    (
+        "E1004",
+        "An operator or property access was used on a value of the wrong type.
+
+Arithmetic and comparison operators other than '+' and '==' take numbers
+only:
+
+    print \"3\" > 2;
+
+Only maps and namespaces have properties, and only maps have ones that can
+be assigned:
+
+    var n = 1;
+    n.size = 2;",
+    ),
+    (
+        "E1005",
+        "A value that is not a function was called.
+
+    var x = 1;
+    x();
+
+Only functions, including the built-in ones, can be called.",
+    ),
+    (
+        "E1006",
+        "A property was read from a map or namespace that does not have it.
+
+    var point = jsonParse(\"{}\");
+    print point.x;
+
+Set the property first, or check that a map has it with 'has':
+
+    point.x = 1;
+    print point.x;",
+    ),
+    (
+        "E1007",
+        "A function was called with the wrong number of arguments.
+
+    fun add(a, b) { return a + b; }
+    print add(1);
+
+Pass exactly as many arguments as the function has parameters.",
+    ),
+    (
+        "E1008",
+        "A built-in function failed. The message says why; for example, a file
+could not be read, an argument had the wrong type, or a capability the
+function needs was denied:
+
+    print readFile(\"missing.txt\");
+
+Capabilities are allowed and denied with '--allow' and '--deny'.",
+    ),
+    (
+        "E1009",
+        "A script ran more statements than the limit set on how long it may
+run. Limits are set where scripts that are not trusted are run, as by
+'serve', so that none can run forever:
+
+    while (true) {}
+
+Make the script do less work.",
+    ),
+    (
+        "E1010",
+        "The script was stopped before it finished: by Control-C, or by the
+program embedding the interpreter, as one may when a script runs for too
+long or its result is no longer wanted.
+
+Nothing in the script itself is at fault, though one that loops forever
+will always be stopped:
+
+    while (true) {}",
+    ),
+    (
+        "E1011",
+        "Calls to functions nested more deeply than the interpreter allows,
+most often because a recursive function never reaches the case that ends
+it:
+
+    fun count(n) { return count(n + 1); }
+
+Check that the recursion ends, or turn it into a loop:
+
+    fun sum(n) {
+      var total = 0;
+      for (var i = 1; i <= n; i = i + 1) total = total + i;
+      return total;
+    }",
+    ),
+    (
+        "E1012",
+        "The script uses something the interpreter does not run yet, such as
+'this' or 'super', which belong to classes:
+
+    print this;
+
+The same is reported for a class, which the parser does not accept yet,
+in a program built by another tool and run with '--run-ast', or for an
+operator where none of its kind can be in one built by the program
+embedding the interpreter. Change the script, or the tool, to leave it
+out.",
+    ),
+    (
+        "E1013",
+        "A script's values took more memory than the limit set on them.
+Limits are set where scripts that are not trusted are run, as by 'serve',
+so that none can take all the memory there is:
+
+    var s = \"x\";
+    while (true) s = s + s;
+
+Keep less data at once, or let go of what is no longer needed.",
+    ),
+];
+
+// The description of an error code, if there is one.
+pub fn explanation(code: &str) -> Option<&'static str> {
+    EXPLANATIONS
+        .iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(code))
+        .map(|(_, text)| *text)
+}
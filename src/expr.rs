@@ -1,16 +1,21 @@
-use crate::scanner::{LiteralValue, Token};
+use crate::scanner::{Literal, Token};
+
+// Index of an expression node inside an `Ast` arena.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ExprId(pub usize);
+
 #[derive(Debug)]
 pub enum Expr {
-    Binary(Box<Expr>, Token, Box<Expr>),
-    Unary(Token, Box<Expr>),
-    Literal(Option<LiteralValue>),
-    Grouping(Box<Expr>),
+    Binary(ExprId, Token, ExprId),
+    Unary(Token, ExprId),
+    Literal(Option<Literal>),
+    Grouping(ExprId),
     Variable(Token),
-    Assignment(Token, Box<Expr>),
-    Logical(Box<Expr>, Token, Box<Expr>),
-    Call(Box<Expr>, Token, Vec<Expr>),
-    Get(Box<Expr>, Token),
-    Set(Box<Expr>, Token, Box<Expr>),
+    Assignment(Token, ExprId),
+    Logical(ExprId, Token, ExprId),
+    Call(ExprId, Token, Vec<ExprId>),
+    Get(ExprId, Token),
+    Set(ExprId, Token, ExprId),
     This(Token),
     Super(Token, Token),
 }
@@ -0,0 +1,191 @@
+use std::ffi::{c_char, c_void, CStr, CString};
+
+use crate::{library, scanner::LiteralValue};
+
+// Calls C functions in shared libraries by name, for `ffiCall`. There is no
+// libffi to build calls at run time, so every call goes through a function
+// pointer of one general type. On the supported platforms, 64-bit x86 and
+// ARM on Unix, integer and pointer arguments are passed in one set of
+// registers and floating-point arguments in another, each in order, so a
+// function taking up to six integers or pointers and up to eight doubles,
+// in any order, can be called by passing the two kinds separately. The
+// registers it does not take are simply ignored. Functions taking structs,
+// `float`s or variable arguments cannot be called this way.
+
+const INTEGER_REGISTERS: usize = 6;
+const FLOAT_REGISTERS: usize = 8;
+
+// The C types a signature can name, by letter: `i` is `int`, `l` is
+// `long`, `d` is `double`, `s` is a NUL-terminated `const char *`, and `v`,
+// for the result only, is `void`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Type {
+    Int,
+    Long,
+    Double,
+    String,
+    Void,
+}
+
+impl Type {
+    fn from_letter(letter: char) -> Option<Type> {
+        match letter {
+            'i' => Some(Type::Int),
+            'l' => Some(Type::Long),
+            'd' => Some(Type::Double),
+            's' => Some(Type::String),
+            'v' => Some(Type::Void),
+            _ => None,
+        }
+    }
+}
+
+// Parses a signature such as `(dd)d`: the parameter types in parentheses,
+// then the result type.
+fn parse_signature(signature: &str) -> Result<(Vec<Type>, Type), String> {
+    let invalid = || {
+        format!(
+            "'{}' is not a valid signature; expected one like '(dd)d', \
+             with the letters i, l, d, s and v.",
+            signature
+        )
+    };
+    let (params, result) = signature
+        .strip_prefix('(')
+        .and_then(|rest| rest.split_once(')'))
+        .ok_or_else(invalid)?;
+    let params = params
+        .chars()
+        .map(|letter| Type::from_letter(letter).filter(|&t| t != Type::Void))
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(invalid)?;
+    let mut result = result.chars();
+    let result = match (result.next().and_then(Type::from_letter), result.next()) {
+        (Some(result), None) => result,
+        _ => return Err(invalid()),
+    };
+    Ok((params, result))
+}
+
+pub fn call(
+    library: &str,
+    symbol: &str,
+    signature: &str,
+    arguments: &[LiteralValue],
+) -> Result<LiteralValue, String> {
+    let (params, result) = parse_signature(signature)?;
+    if params.len() != arguments.len() {
+        return Err(format!(
+            "'{}' takes {} arguments but {} were given.",
+            symbol,
+            params.len(),
+            arguments.len()
+        ));
+    }
+
+    let mut integers = Vec::new();
+    let mut floats = Vec::new();
+    // The strings must stay alive until the call returns.
+    let mut strings = Vec::new();
+    for (index, (param, argument)) in params.iter().zip(arguments).enumerate() {
+        let expected = match param {
+            Type::String => "a string",
+            Type::Double => "a number",
+            _ => "a whole number",
+        };
+        let mismatch = || {
+            format!(
+                "Argument {} to '{}' must be {}.",
+                index + 1,
+                symbol,
+                expected
+            )
+        };
+        match (param, argument) {
+            (Type::Double, LiteralValue::Number(n)) => floats.push(*n),
+            (Type::Int | Type::Long, LiteralValue::Number(n)) if n.fract() == 0.0 => {
+                integers.push(*n as i64)
+            }
+            (Type::String, LiteralValue::String(s)) => {
+                let s = CString::new(&**s).map_err(|_| mismatch())?;
+                integers.push(s.as_ptr() as i64);
+                strings.push(s);
+            }
+            _ => return Err(mismatch()),
+        }
+    }
+    if integers.len() > INTEGER_REGISTERS || floats.len() > FLOAT_REGISTERS {
+        return Err(format!(
+            "Functions can take at most {} integer or string arguments and {} number \
+             arguments.",
+            INTEGER_REGISTERS, FLOAT_REGISTERS
+        ));
+    }
+    integers.resize(INTEGER_REGISTERS, 0);
+    floats.resize(FLOAT_REGISTERS, 0.0);
+
+    let address = library::symbol(library, symbol)?;
+    // SAFETY: none; the script vouches for the signature.
+    unsafe { invoke(address, &integers, &floats, result) }
+}
+
+#[cfg(all(unix, any(target_arch = "x86_64", target_arch = "aarch64")))]
+unsafe fn invoke(
+    address: *mut c_void,
+    i: &[i64],
+    f: &[f64],
+    result: Type,
+) -> Result<LiteralValue, String> {
+    type General<R> = unsafe extern "C" fn(
+        i64,
+        i64,
+        i64,
+        i64,
+        i64,
+        i64,
+        f64,
+        f64,
+        f64,
+        f64,
+        f64,
+        f64,
+        f64,
+        f64,
+    ) -> R;
+
+    macro_rules! call_as {
+        ($r:ty) => {{
+            let function = unsafe { std::mem::transmute::<*mut c_void, General<$r>>(address) };
+            unsafe {
+                function(
+                    i[0], i[1], i[2], i[3], i[4], i[5], f[0], f[1], f[2], f[3], f[4], f[5], f[6],
+                    f[7],
+                )
+            }
+        }};
+    }
+    Ok(match result {
+        Type::Double => LiteralValue::Number(call_as!(f64)),
+        // Only the low 32 bits of the register hold an `int`.
+        Type::Int => LiteralValue::Number(call_as!(i64) as i32 as f64),
+        Type::Long => LiteralValue::Number(call_as!(i64) as f64),
+        Type::String => {
+            let pointer = call_as!(i64) as *const c_char;
+            if pointer.is_null() {
+                LiteralValue::Nil
+            } else {
+                let s = unsafe { CStr::from_ptr(pointer) };
+                LiteralValue::String(s.to_string_lossy().into())
+            }
+        }
+        Type::Void => {
+            call_as!(i64);
+            LiteralValue::Nil
+        }
+    })
+}
+
+#[cfg(not(all(unix, any(target_arch = "x86_64", target_arch = "aarch64"))))]
+unsafe fn invoke(_: *mut c_void, _: &[i64], _: &[f64], _: Type) -> Result<LiteralValue, String> {
+    Err("'ffiCall' is only supported on 64-bit x86 and ARM Unix systems.".to_string())
+}
@@ -0,0 +1,213 @@
+use crate::scanner::{Comment, Token, TokenType};
+
+// Reprints a program in canonical form: four-space indentation, one
+// statement per line, single spaces around binary operators and long lines
+// wrapped at spaces. Works on the token stream rather than the AST, so
+// constructs the parser desugars (such as `for`) and comments survive.
+
+const INDENT: &str = "    ";
+const MAX_WIDTH: usize = 100;
+// Wrapped lines are indented two levels past the statement they continue.
+const CONTINUATION: usize = 2;
+
+pub fn format_source(tokens: &[Token], comments: &[Comment]) -> String {
+    let mut formatter = Formatter {
+        out: String::new(),
+        comments,
+        next_comment: 0,
+        indent: 0,
+        paren_depth: 0,
+        column: 0,
+        pending: Separator::Nothing,
+        last_line: 0,
+        previous: None,
+        previous_unary: false,
+    };
+    for (i, token) in tokens.iter().enumerate() {
+        if token.token_type == TokenType::Eof {
+            break;
+        }
+        let next = tokens.get(i + 1).map(|t| t.token_type);
+        formatter.token(token, next);
+    }
+    formatter.comments_before(usize::MAX);
+    if formatter.column > 0 {
+        formatter.out.push('\n');
+    }
+    formatter.out
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Separator {
+    Nothing,
+    Space,
+    Newline,
+}
+
+struct Formatter<'a> {
+    out: String,
+    comments: &'a [Comment],
+    next_comment: usize,
+    indent: usize,
+    paren_depth: usize,
+    // Length of the line currently being written.
+    column: usize,
+    // What must be written before the next token.
+    pending: Separator,
+    // Source line of the last token or comment written.
+    last_line: usize,
+    previous: Option<TokenType>,
+    previous_unary: bool,
+}
+
+impl Formatter<'_> {
+    fn token(&mut self, token: &Token, next: Option<TokenType>) {
+        self.comments_before(token.line);
+
+        let token_type = token.token_type;
+        if token_type == TokenType::RightBrace {
+            self.indent = self.indent.saturating_sub(1);
+            // An empty block stays on one line as `{}`.
+            let empty_block =
+                self.previous == Some(TokenType::LeftBrace) && self.pending == Separator::Nothing;
+            if !empty_block {
+                self.pending = Separator::Newline;
+            }
+        } else if self.pending == Separator::Nothing && self.space_before(token_type) {
+            self.pending = Separator::Space;
+        }
+
+        match self.pending {
+            Separator::Newline => {
+                let blank_line = token.line > self.last_line + 1
+                    && self.previous != Some(TokenType::LeftBrace)
+                    && token_type != TokenType::RightBrace;
+                self.newline(blank_line, self.indent);
+            }
+            Separator::Space => {
+                if self.column + 1 + token.lexeme.len() > MAX_WIDTH {
+                    self.newline(false, self.indent + CONTINUATION);
+                } else {
+                    self.write(" ");
+                }
+            }
+            Separator::Nothing => {}
+        }
+        self.write(&token.lexeme);
+
+        self.previous_unary = self.is_unary(token_type);
+        self.previous = Some(token_type);
+        self.last_line = token.line;
+        self.pending = match token_type {
+            TokenType::LeftParen => {
+                self.paren_depth += 1;
+                Separator::Nothing
+            }
+            TokenType::RightParen => {
+                self.paren_depth = self.paren_depth.saturating_sub(1);
+                Separator::Nothing
+            }
+            TokenType::LeftBrace => {
+                self.indent += 1;
+                if next == Some(TokenType::RightBrace) {
+                    Separator::Nothing
+                } else {
+                    Separator::Newline
+                }
+            }
+            TokenType::RightBrace => match next {
+                Some(TokenType::Else) => Separator::Space,
+                Some(TokenType::Semicolon | TokenType::RightParen | TokenType::Comma) => {
+                    Separator::Nothing
+                }
+                _ => Separator::Newline,
+            },
+            // The clauses of a `for` header share a line.
+            TokenType::Semicolon if self.paren_depth > 0 => Separator::Space,
+            TokenType::Semicolon => Separator::Newline,
+            _ => Separator::Nothing,
+        };
+    }
+
+    // Writes every comment that appears before the given source line. A
+    // comment on the same line as the code just written stays at its end.
+    fn comments_before(&mut self, line: usize) {
+        while let Some(comment) = self.comments.get(self.next_comment) {
+            if comment.line >= line {
+                break;
+            }
+            self.next_comment += 1;
+            if comment.line == self.last_line && self.column > 0 {
+                self.write("  ");
+            } else {
+                if self.column > 0 || !self.out.is_empty() {
+                    let blank_line = comment.line > self.last_line + 1
+                        && self.previous != Some(TokenType::LeftBrace);
+                    self.newline(blank_line, self.indent);
+                }
+                if self.column == 0 {
+                    self.write(&INDENT.repeat(self.indent));
+                }
+            }
+            self.write(&comment.text);
+            self.last_line = comment.line;
+            self.pending = Separator::Newline;
+        }
+    }
+
+    fn space_before(&self, token_type: TokenType) -> bool {
+        let Some(previous) = self.previous else {
+            return false;
+        };
+        if self.previous_unary || matches!(previous, TokenType::LeftParen | TokenType::Dot) {
+            return false;
+        }
+        match token_type {
+            TokenType::RightParen | TokenType::Semicolon | TokenType::Comma | TokenType::Dot => {
+                false
+            }
+            // A call keeps its parenthesis attached to the callee.
+            TokenType::LeftParen => !matches!(
+                previous,
+                TokenType::Identifier | TokenType::RightParen | TokenType::This | TokenType::Super
+            ),
+            _ => true,
+        }
+    }
+
+    // Whether the token just written is a prefix operator: `!` always is,
+    // `-` is when it cannot be the end of an operand.
+    fn is_unary(&self, token_type: TokenType) -> bool {
+        match token_type {
+            TokenType::Bang => true,
+            TokenType::Minus => !matches!(
+                self.previous,
+                Some(
+                    TokenType::Identifier
+                        | TokenType::Number
+                        | TokenType::String
+                        | TokenType::RightParen
+                        | TokenType::True
+                        | TokenType::False
+                        | TokenType::Nil
+                        | TokenType::This
+                )
+            ),
+            _ => false,
+        }
+    }
+
+    fn newline(&mut self, blank_line: bool, indent: usize) {
+        self.out.push('\n');
+        if blank_line {
+            self.out.push('\n');
+        }
+        self.column = 0;
+        self.write(&INDENT.repeat(indent));
+    }
+
+    fn write(&mut self, text: &str) {
+        self.out.push_str(text);
+        self.column += text.chars().count();
+    }
+}
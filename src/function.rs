@@ -0,0 +1,33 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::{ast::Ast, environment::Environment, scanner::Token, stmt::StmtId};
+
+// A function declared in a script. It keeps the arena its body was parsed
+// into, so it can still be called after the program that declared it has
+// finished, as happens in the REPL, and the environment it was declared
+// in, which its body can see.
+pub struct LoxFunction {
+    pub name: Token,
+    pub params: Vec<Token>,
+    pub body: Vec<StmtId>,
+    pub ast: Arc<Ast>,
+    pub closure: Rc<RefCell<Environment>>,
+    // The names of its parameters and of the variables its body declares,
+    // by slot, if the program declaring it was resolved; see `resolver`.
+    pub locals: Option<Arc<[String]>>,
+}
+
+impl LoxFunction {
+    pub fn arity(&self) -> usize {
+        self.params.len()
+    }
+}
+
+impl fmt::Debug for LoxFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<fn {}>", self.name.lexeme)
+    }
+}
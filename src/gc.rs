@@ -0,0 +1,340 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::{Rc, Weak};
+
+use crate::{
+    environment::Environment, function::LoxFunction, map::Map, scanner::LiteralValue, vm::Closure,
+};
+
+// Frees values that refer to one another in a cycle, which counting
+// references alone never does: a function declared in a call is held by
+// the scope of the call, which the function holds in turn as its closure,
+// and a list can be pushed onto itself.
+//
+// The interpreter tracks the functions, compiled closures, lists and maps
+// scripts make, by weak reference, as every cycle runs through one of
+// them. A collection looks at what those tracked refer to, and at what
+// that refers to in turn, and counts for each list, map, scope, function
+// and cell the references the others hold to it. One referred to more
+// often than that is held from outside them, by a variable of a call that
+// is running, by a value the VM is working on or by the host, and it is
+// kept with everything it refers to. The rest are cycles that nothing can
+// reach any more, and are emptied so that their counts drop to zero.
+//
+// A collection so needs no list of what the interpreter holds, and may run
+// whenever no list, map or scope is being changed. What it cannot look
+// inside, such as a thread, counts as holding from outside, so it keeps
+// anything it is unsure of.
+
+// How many values may be tracked before the first collection.
+const THRESHOLD: usize = 10_000;
+
+pub struct Heap {
+    tracked: Vec<Tracked>,
+    // The lists and maps among them, so that one met again, as it is in
+    // every list made of it, is not walked again. The weak references keep
+    // each address from being reused while it is here.
+    collections: HashSet<*const ()>,
+    // How many values may be tracked before the next collection: twice as
+    // many as the last left, so collections take time in proportion to
+    // what scripts allocate.
+    threshold: usize,
+}
+
+enum Tracked {
+    Function(Weak<LoxFunction>),
+    Closure(Weak<Closure>),
+    List(Weak<RefCell<Vec<LiteralValue>>>),
+    Map(Weak<RefCell<Map>>),
+}
+
+// What a collection found.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Collection {
+    // The lists, maps, scopes, functions and cells it freed.
+    pub freed: usize,
+    // The values still tracked.
+    pub live: usize,
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Heap {
+            tracked: Vec::new(),
+            collections: HashSet::new(),
+            threshold: THRESHOLD,
+        }
+    }
+
+    // Tracks a function or a closure scripts have just made, or a list or
+    // map with the lists and maps in it that are not tracked yet, and
+    // collects if enough have been since the last collection.
+    pub fn track(&mut self, value: &LiteralValue) -> Option<Collection> {
+        let mut pending = vec![value.clone()];
+        while let Some(value) = pending.pop() {
+            let tracked = match &value {
+                LiteralValue::Function(function) => Tracked::Function(Rc::downgrade(function)),
+                // A closure that captured nothing cannot be in a cycle.
+                LiteralValue::Closure(closure) if closure.upvalues().is_empty() => continue,
+                LiteralValue::Closure(closure) => Tracked::Closure(Rc::downgrade(closure)),
+                LiteralValue::List(items) => {
+                    if !self.collections.insert(Rc::as_ptr(items) as *const ()) {
+                        continue;
+                    }
+                    pending.extend(items.borrow().iter().filter(|item| nested(item)).cloned());
+                    Tracked::List(Rc::downgrade(items))
+                }
+                LiteralValue::Map(map) => {
+                    if !self.collections.insert(Rc::as_ptr(map) as *const ()) {
+                        continue;
+                    }
+                    let values = map.borrow();
+                    pending.extend(values.iter().map(|(_, v)| v).filter(|v| nested(v)).cloned());
+                    Tracked::Map(Rc::downgrade(map))
+                }
+                _ => continue,
+            };
+            self.tracked.push(tracked);
+        }
+        (self.tracked.len() > self.threshold).then(|| self.collect())
+    }
+
+    pub fn collect(&mut self) -> Collection {
+        let mut graph = Graph::default();
+        for tracked in &self.tracked {
+            if let Some(object) = tracked.upgrade() {
+                graph.add(object);
+            }
+        }
+        let freed = graph.sweep();
+        self.tracked.retain(Tracked::is_alive);
+        self.collections = self
+            .tracked
+            .iter()
+            .filter_map(Tracked::collection)
+            .collect();
+        self.threshold = THRESHOLD.max(self.tracked.len() * 2);
+        Collection {
+            freed,
+            live: self.tracked.len(),
+        }
+    }
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Heap::new()
+    }
+}
+
+impl Tracked {
+    fn upgrade(&self) -> Option<Object> {
+        match self {
+            Tracked::Function(function) => function.upgrade().map(Object::Function),
+            Tracked::Closure(closure) => closure.upgrade().map(Object::Closure),
+            Tracked::List(items) => items.upgrade().map(Object::List),
+            Tracked::Map(map) => map.upgrade().map(Object::Map),
+        }
+    }
+
+    // The address of a list or map.
+    fn collection(&self) -> Option<*const ()> {
+        match self {
+            Tracked::Function(_) | Tracked::Closure(_) => None,
+            Tracked::List(items) => Some(items.as_ptr() as *const ()),
+            Tracked::Map(map) => Some(map.as_ptr() as *const ()),
+        }
+    }
+
+    fn is_alive(&self) -> bool {
+        match self {
+            Tracked::Function(function) => function.strong_count() > 0,
+            Tracked::Closure(closure) => closure.strong_count() > 0,
+            Tracked::List(items) => items.strong_count() > 0,
+            Tracked::Map(map) => map.strong_count() > 0,
+        }
+    }
+}
+
+// Whether a value in a new list or map is one to track with it.
+fn nested(value: &LiteralValue) -> bool {
+    matches!(value, LiteralValue::List(_) | LiteralValue::Map(_))
+}
+
+// What a collection looks at: anything that holds values, or holds what
+// does.
+enum Object {
+    Function(Rc<LoxFunction>),
+    Closure(Rc<Closure>),
+    List(Rc<RefCell<Vec<LiteralValue>>>),
+    Map(Rc<RefCell<Map>>),
+    Scope(Rc<RefCell<Environment>>),
+    Cell(Rc<RefCell<LiteralValue>>),
+}
+
+impl Object {
+    fn of(value: &LiteralValue) -> Option<Object> {
+        let object = match value {
+            LiteralValue::Function(function) => Object::Function(Rc::clone(function)),
+            LiteralValue::Closure(closure) => Object::Closure(Rc::clone(closure)),
+            LiteralValue::List(items) => Object::List(Rc::clone(items)),
+            LiteralValue::Map(map) => Object::Map(Rc::clone(map)),
+            _ => return None,
+        };
+        Some(object)
+    }
+
+    fn address(&self) -> *const () {
+        match self {
+            Object::Function(function) => Rc::as_ptr(function) as *const (),
+            Object::Closure(closure) => Rc::as_ptr(closure) as *const (),
+            Object::List(items) => Rc::as_ptr(items) as *const (),
+            Object::Map(map) => Rc::as_ptr(map) as *const (),
+            Object::Scope(scope) => Rc::as_ptr(scope) as *const (),
+            Object::Cell(cell) => Rc::as_ptr(cell) as *const (),
+        }
+    }
+
+    fn references(&self) -> usize {
+        match self {
+            Object::Function(function) => Rc::strong_count(function),
+            Object::Closure(closure) => Rc::strong_count(closure),
+            Object::List(items) => Rc::strong_count(items),
+            Object::Map(map) => Rc::strong_count(map),
+            Object::Scope(scope) => Rc::strong_count(scope),
+            Object::Cell(cell) => Rc::strong_count(cell),
+        }
+    }
+
+    // What the object refers to, or none if it is borrowed to be changed,
+    // as then what it holds cannot be seen.
+    fn children(&self) -> Option<Vec<Object>> {
+        let children = match self {
+            Object::Function(function) => vec![Object::Scope(Rc::clone(&function.closure))],
+            Object::Closure(closure) => closure
+                .upvalues()
+                .iter()
+                .map(|cell| Object::Cell(Rc::clone(cell)))
+                .collect(),
+            Object::List(items) => items
+                .try_borrow()
+                .ok()?
+                .iter()
+                .filter_map(Object::of)
+                .collect(),
+            Object::Map(map) => {
+                let map = map.try_borrow().ok()?;
+                map.iter()
+                    .filter_map(|(_, value)| Object::of(value))
+                    .collect()
+            }
+            Object::Scope(scope) => {
+                let scope = scope.try_borrow().ok()?;
+                let mut children: Vec<_> = scope
+                    .values()
+                    .filter_map(|(_, value)| Object::of(value))
+                    .collect();
+                if let Some(enclosing) = scope.enclosing() {
+                    children.push(Object::Scope(Rc::clone(enclosing)));
+                }
+                children
+            }
+            Object::Cell(cell) => Object::of(&*cell.try_borrow().ok()?).into_iter().collect(),
+        };
+        Some(children)
+    }
+
+    // Drops what the object holds, which frees it and what it refers to
+    // once the collection lets go of them. Functions and closures hold
+    // nothing that changes, and only break a cycle along with the scope or
+    // cells they refer to.
+    fn clear(&self) {
+        match self {
+            Object::Function(_) | Object::Closure(_) => {}
+            Object::List(items) => items.borrow_mut().clear(),
+            Object::Map(map) => map.borrow_mut().clear(),
+            Object::Scope(scope) => scope.borrow_mut().clear(),
+            Object::Cell(cell) => *cell.borrow_mut() = LiteralValue::Nil,
+        }
+    }
+}
+
+// The objects reachable from those tracked, each held once, with the
+// references among them.
+#[derive(Default)]
+struct Graph {
+    objects: Vec<Object>,
+    index: HashMap<*const (), usize>,
+    // What each object refers to, by index.
+    edges: Vec<Vec<usize>>,
+    // How many references each has from the others.
+    internal: Vec<usize>,
+    // The objects whose references could not be seen, to keep.
+    pinned: Vec<bool>,
+    // How many of the objects have had what they refer to added.
+    scanned: usize,
+}
+
+impl Graph {
+    // Adds an object and what it refers to, in turn, from a queue rather
+    // than by recursion.
+    fn add(&mut self, object: Object) {
+        self.insert(object);
+        while self.scanned < self.objects.len() {
+            let next = self.scanned;
+            match self.objects[next].children() {
+                Some(children) => {
+                    for child in children {
+                        let child = self.insert(child);
+                        self.internal[child] += 1;
+                        self.edges[next].push(child);
+                    }
+                }
+                None => self.pinned[next] = true,
+            }
+            self.scanned += 1;
+        }
+    }
+
+    // Gives the index of an object, adding it without what it refers to if
+    // it is new.
+    fn insert(&mut self, object: Object) -> usize {
+        let address = object.address();
+        if let Some(&index) = self.index.get(&address) {
+            return index;
+        }
+        let index = self.objects.len();
+        self.index.insert(address, index);
+        self.objects.push(object);
+        self.edges.push(Vec::new());
+        self.internal.push(0);
+        self.pinned.push(false);
+        index
+    }
+
+    // Keeps the objects held from outside and what they refer to, and
+    // empties the rest, giving how many those were.
+    fn sweep(self) -> usize {
+        let count = self.objects.len();
+        let mut live = vec![false; count];
+        // Each object is referred to once by the graph itself.
+        let mut pending: Vec<usize> = (0..count)
+            .filter(|&i| self.pinned[i] || self.objects[i].references() > self.internal[i] + 1)
+            .collect();
+        while let Some(i) = pending.pop() {
+            if live[i] {
+                continue;
+            }
+            live[i] = true;
+            pending.extend(self.edges[i].iter().filter(|&&j| !live[j]));
+        }
+        let mut freed = 0;
+        for (object, live) in self.objects.iter().zip(&live) {
+            if !live {
+                object.clear();
+                freed += 1;
+            }
+        }
+        freed
+    }
+}
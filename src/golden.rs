@@ -0,0 +1,268 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use interprete::{
+    ast::Program, diagnostic::Diagnostic, interpreter::Setup, log, parser::Parser, scanner::Scanner,
+};
+
+// A runner for suites of scripts that say what they should do, for
+// `interprete test PATH...`, in the style of the test suite of Crafting
+// Interpreters. Every `.lox` file under the paths runs in an interpreter of
+// its own, and comments in it give what it should print and how it should
+// fail:
+//
+//     print 1 + 2; // expect: 3
+//     print x;     // expect runtime error: Undefined variable 'x'
+//     var = 3;     // expect error: E0004
+//
+// The `expect:` lines must be printed in order, and nothing else. An error
+// is given by its message or its code, and must be reported on the line of
+// its comment. A script with `expect error:` comments must fail to parse
+// with exactly those errors; one with `expect runtime error:` must print
+// what it expects and then fail with that error.
+//
+// A script may also declare tests of its own with the `test` native, which
+// run after it, in order, if it runs to the end:
+//
+//     fun addition() {
+//         assertEqual(1 + 2, 3);
+//     }
+//     test("addition", addition);
+//
+// A test passes if it returns without a runtime error, such as one from an
+// assertion. What tests print counts as the script's output.
+
+// Runs the scripts and prints which failed and how, then how many scripts
+// and tests passed and failed; passes are listed too with `--verbose`.
+// Gives the status to exit with: 1 if anything failed.
+pub fn run(paths: &[String], setup: &Setup) -> i32 {
+    let mut files = Vec::new();
+    for path in paths {
+        if let Err(error) = collect(Path::new(path), &mut files) {
+            eprintln!("Error reading '{}': {}", path, error);
+            return 66;
+        }
+    }
+    let mut failed = 0;
+    let mut tally = Tally::default();
+    for file in &files {
+        let failures = match fs::read_to_string(file) {
+            Ok(source) => test(&source, setup, &mut tally),
+            Err(error) => vec![format!("It could not be read: {}", error)],
+        };
+        if failures.is_empty() {
+            log::info(format_args!("PASS {}", file.display()));
+            continue;
+        }
+        failed += 1;
+        println!("FAIL {}", file.display());
+        for failure in failures {
+            println!("  {}", failure);
+        }
+    }
+    println!("{} passed, {} failed", files.len() - failed, failed);
+    if tally.run > 0 {
+        let passed = tally.run - tally.failed;
+        println!(
+            "{} tests: {} passed, {} failed",
+            tally.run, passed, tally.failed
+        );
+    }
+    if failed > 0 {
+        1
+    } else {
+        0
+    }
+}
+
+// Adds the path if it is a file, or the `.lox` files under it, in order of
+// name, if it is a directory.
+fn collect(path: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    if !path.is_dir() {
+        fs::metadata(path)?;
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+    let mut entries = fs::read_dir(path)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+    for entry in entries {
+        if entry.is_dir()
+            || entry
+                .extension()
+                .is_some_and(|extension| extension == "lox")
+        {
+            collect(&entry, files)?;
+        }
+    }
+    Ok(())
+}
+
+// What a test's comments say it should do.
+#[derive(Default)]
+struct Expectations {
+    output: Vec<String>,
+    // The line and the message or code of each error, found parsing or
+    // running.
+    errors: Vec<(usize, String)>,
+    runtime_error: Option<(usize, String)>,
+}
+
+impl Expectations {
+    fn read(source: &str) -> Expectations {
+        let mut expected = Expectations::default();
+        for (index, line) in source.lines().enumerate() {
+            let after = |marker: &str| {
+                let start = line.find(marker)? + marker.len();
+                Some(line[start..].trim_end().to_string())
+            };
+            if let Some(text) = after("// expect: ") {
+                expected.output.push(text);
+            } else if let Some(text) = after("// expect error: ") {
+                expected.errors.push((index + 1, text));
+            } else if let Some(text) = after("// expect runtime error: ") {
+                expected.runtime_error = Some((index + 1, text));
+            }
+        }
+        expected
+    }
+}
+
+// How many of the tests declared with `test` have run, and failed.
+#[derive(Default)]
+struct Tally {
+    run: usize,
+    failed: usize,
+}
+
+// Runs a script, giving how it differs from what it expects.
+fn test(source: &str, setup: &Setup, tally: &mut Tally) -> Vec<String> {
+    let expected = Expectations::read(source);
+    let program = match parse(source) {
+        Ok(program) => program,
+        Err(errors) => return compare_errors(&expected.errors, &errors),
+    };
+    let mut failures: Vec<String> = expected
+        .errors
+        .iter()
+        .map(|(line, text)| format!("Expected error '{}' on line {}, but it parsed.", text, line))
+        .collect();
+
+    let mut engine = match setup.engine() {
+        Ok(engine) => engine,
+        Err(error) => return vec![error],
+    };
+    engine.interpreter_mut().capture_output();
+    let result = engine.interpret(&program);
+    if result.is_ok() {
+        for test in engine.interpreter_mut().take_tests() {
+            tally.run += 1;
+            match engine.call(test.function, &test.token, Vec::new()) {
+                Ok(_) => log::info(format_args!("ok '{}'", test.name)),
+                Err(error) => {
+                    tally.failed += 1;
+                    failures.push(format!(
+                        "Test '{}' failed with {}",
+                        test.name,
+                        describe(&error.diagnostic())
+                    ));
+                }
+            }
+        }
+    }
+    let output = engine.interpreter_mut().take_output();
+    let mut printed = output.lines();
+    for (index, line) in expected.output.iter().enumerate() {
+        match printed.next() {
+            Some(actual) if actual == line => {}
+            Some(actual) => failures.push(format!(
+                "Expected '{}' as line {} of the output, but got '{}'.",
+                line,
+                index + 1,
+                actual
+            )),
+            None => failures.push(format!(
+                "Expected '{}' as line {} of the output, but the output ended.",
+                line,
+                index + 1
+            )),
+        }
+    }
+    for line in printed {
+        failures.push(format!("Did not expect '{}' in the output.", line));
+    }
+
+    match (result, &expected.runtime_error) {
+        (Ok(()), None) => {}
+        (Ok(()), Some((line, text))) => failures.push(format!(
+            "Expected runtime error '{}' on line {}, but it ran to the end.",
+            text, line
+        )),
+        (Err(error), expected) => {
+            let diagnostic = error.diagnostic();
+            if !expected
+                .as_ref()
+                .is_some_and(|expected| matches(expected, &diagnostic))
+            {
+                failures.push(format!("Did not expect {}", describe(&diagnostic)));
+                if let Some((line, text)) = expected {
+                    failures.push(format!(
+                        "Expected runtime error '{}' on line {}.",
+                        text, line
+                    ));
+                }
+            }
+        }
+    }
+    failures
+}
+
+// Parses a test, giving every error if it does not parse. A test that does
+// not scan is not parsed, as in `--check`.
+fn parse(source: &str) -> Result<Program, Vec<Diagnostic>> {
+    let errors = match Scanner::new(source.to_string()).scan_all() {
+        (tokens, errors) if errors.is_empty() => match Parser::new(tokens).parse_all() {
+            Ok(program) => return Ok(program),
+            Err(errors) => errors,
+        },
+        (_, errors) => errors,
+    };
+    Err(errors.iter().map(|error| error.diagnostic()).collect())
+}
+
+fn compare_errors(expected: &[(usize, String)], errors: &[Diagnostic]) -> Vec<String> {
+    let mut unmatched = expected.to_vec();
+    let mut failures = Vec::new();
+    for error in errors {
+        match unmatched
+            .iter()
+            .position(|expected| matches(expected, error))
+        {
+            Some(index) => {
+                unmatched.remove(index);
+            }
+            None => failures.push(format!("Did not expect {}", describe(error))),
+        }
+    }
+    for (line, text) in unmatched {
+        failures.push(format!("Expected error '{}' on line {}.", text, line));
+    }
+    failures
+}
+
+fn matches((line, text): &(usize, String), diagnostic: &Diagnostic) -> bool {
+    diagnostic.span.as_ref().map(|span| span.line) == Some(*line)
+        && (diagnostic.code == text || diagnostic.message == *text)
+}
+
+fn describe(diagnostic: &Diagnostic) -> String {
+    match &diagnostic.span {
+        Some(span) => format!(
+            "error {} on line {}: {}",
+            diagnostic.code, span.line, diagnostic.message
+        ),
+        None => format!("error {}: {}", diagnostic.code, diagnostic.message),
+    }
+}
@@ -0,0 +1,54 @@
+use crate::{diagnostic::Span, value::Value};
+
+// Lets a program embedding the interpreter watch scripts as they run, to
+// build its own tracer, debugger or audit log, by implementing whichever
+// of these it needs and handing them to `Interpreter::set_hooks`:
+//
+//     struct Audit;
+//
+//     impl Hooks for Audit {
+//         fn assign(&mut self, name: &str, value: &Value, span: &Span) {
+//             eprintln!("{}: {} = {:?}", span.line, name, value);
+//         }
+//     }
+//
+//     interpreter.set_hooks(Audit);
+//
+// The values are copies, as `Value`s always are, so a hook cannot change
+// what scripts see. Only the tree-walker calls hooks, as only it traces,
+// and hooks that want to keep what they see share it with the host through
+// an `Rc<RefCell<_>>`. Where the place of something is not known, its span
+// has a line of zero.
+pub trait Hooks {
+    // Before a statement runs, with where it starts and its source as
+    // `--trace` shows it. A block is not reported, since the statements in
+    // it are.
+    fn statement(&mut self, _span: &Span) {}
+
+    // Before the body of a script function runs, with where it was called.
+    fn call(&mut self, _name: &str, _arguments: &[Value], _span: &Span) {}
+
+    // After a script function has returned, with what it returned.
+    fn returned(&mut self, _name: &str, _value: &Value) {}
+
+    // As a variable is given a value, by `var` or by assignment.
+    fn assign(&mut self, _name: &str, _value: &Value, _span: &Span) {}
+}
+
+impl<H: Hooks + ?Sized> Hooks for Box<H> {
+    fn statement(&mut self, span: &Span) {
+        (**self).statement(span)
+    }
+
+    fn call(&mut self, name: &str, arguments: &[Value], span: &Span) {
+        (**self).call(name, arguments, span)
+    }
+
+    fn returned(&mut self, name: &str, value: &Value) {
+        (**self).returned(name, value)
+    }
+
+    fn assign(&mut self, name: &str, value: &Value, span: &Span) {
+        (**self).assign(name, value, span)
+    }
+}
@@ -0,0 +1,211 @@
+// Keeping a script scanned as it is edited, for editor integrations such as
+// `interprete lsp`, which re-parse on every keystroke.
+
+use crate::{
+    ast::Program,
+    parser::Parser,
+    scanner::{self, ParseError, Scanner, Token, TokenType},
+};
+
+// Replaces the bytes in `start..end` of the source with `text`.
+#[derive(Debug, Clone)]
+pub struct TextEdit {
+    pub start: usize,
+    pub end: usize,
+    pub text: String,
+}
+
+// Where a token begins in the source, and on which line. A token's own
+// `line` is where it ends, which differs for multi-line strings.
+#[derive(Debug, Clone, Copy)]
+struct Span {
+    offset: usize,
+    line: usize,
+}
+
+// A source file kept scanned across edits. An edit only rescans the tokens
+// around the changed range: scanning resumes a little before the edit and
+// stops as soon as it lines up again with a token boundary from the
+// previous scan, whose remaining tokens are reused with shifted positions.
+pub struct Document {
+    source: String,
+    tokens: Vec<Token>,
+    spans: Vec<Span>,
+    // False when the current source does not scan, in which case the next
+    // edit falls back to scanning the whole file.
+    scanned: bool,
+}
+
+impl Document {
+    pub fn new(source: String) -> Self {
+        let mut document = Document {
+            source,
+            tokens: Vec::new(),
+            spans: Vec::new(),
+            scanned: false,
+        };
+        // A scan error is reported by the next call to `parse`.
+        let _ = document.rescan_all();
+        document
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    // The tokens of the source, which are only all of them if it scans.
+    pub fn tokens(&self) -> &[Token] {
+        &self.tokens
+    }
+
+    pub fn scans(&self) -> bool {
+        self.scanned
+    }
+
+    pub fn parse(&self) -> Result<Program, ParseError> {
+        if !self.scanned {
+            // Surface the scan error itself.
+            Scanner::new(self.source.clone()).scan_tokens()?;
+        }
+        Parser::new(self.tokens.clone()).parse()
+    }
+
+    // Applies an edit and returns the updated program.
+    pub fn apply_edit(&mut self, edit: &TextEdit) -> Result<Program, ParseError> {
+        self.edit(edit)?;
+        self.parse()
+    }
+
+    // Applies an edit to the source, updating the token stream. Returns how
+    // many tokens had to be rescanned.
+    pub fn edit(&mut self, edit: &TextEdit) -> Result<usize, ParseError> {
+        if edit.start > edit.end
+            || edit.end > self.source.len()
+            || !self.source.is_char_boundary(edit.start)
+            || !self.source.is_char_boundary(edit.end)
+        {
+            return Err(ParseError::InvalidEdit(edit.start, edit.end));
+        }
+        self.source.replace_range(edit.start..edit.end, &edit.text);
+        if !self.scanned {
+            return self.rescan_all();
+        }
+        self.rescan_around(edit)
+            .inspect_err(|_| self.scanned = false)
+    }
+
+    fn rescan_all(&mut self) -> Result<usize, ParseError> {
+        self.tokens.clear();
+        self.spans.clear();
+        self.scanned = false;
+        let mut scanner = Scanner::new(self.source.clone());
+        while let Some((token, offset, line)) = scanner.next_token()? {
+            self.tokens.push(token);
+            self.spans.push(Span { offset, line });
+        }
+        self.push_eof(scanner.line());
+        self.scanned = true;
+        Ok(self.tokens.len())
+    }
+
+    fn rescan_around(&mut self, edit: &TextEdit) -> Result<usize, ParseError> {
+        let delta = edit.text.len() as isize - (edit.end - edit.start) as isize;
+        let edit_end = edit.start + edit.text.len();
+
+        // Restart two tokens before the first one the edit can touch, since
+        // a number such as `1.5` is decided by looking two characters ahead,
+        // and from the end of the token before that, since the edit may be
+        // in the space or comment just before it.
+        let first_touched = self
+            .spans
+            .iter()
+            .zip(&self.tokens)
+            .position(|(span, token)| span.offset + token.lexeme.len() >= edit.start)
+            .unwrap_or(self.tokens.len());
+        let restart = first_touched.saturating_sub(2);
+        let (offset, line) = match restart.checked_sub(1) {
+            Some(previous) => (
+                self.spans[previous].offset + self.tokens[previous].lexeme.len(),
+                self.tokens[previous].line,
+            ),
+            None => (0, 1),
+        };
+
+        let mut scanner = Scanner::new_at(self.source.clone(), offset, line);
+        let mut tokens = Vec::new();
+        let mut spans = Vec::new();
+        // The first old token that lies wholly after the edit and so may
+        // still be reusable; the Eof marker is never reused.
+        let last_old = self.tokens.len() - 1;
+        let mut old = self.spans[..last_old]
+            .iter()
+            .position(|span| span.offset >= edit.end)
+            .unwrap_or(last_old);
+        let mut resync = None;
+        while let Some((token, offset, line)) = scanner.next_token()? {
+            if offset >= edit_end {
+                while old < last_old && shift(self.spans[old].offset, delta) < offset {
+                    old += 1;
+                }
+                if old < last_old && shift(self.spans[old].offset, delta) == offset {
+                    resync = Some((old, line as isize - self.spans[old].line as isize));
+                    break;
+                }
+            }
+            tokens.push(token);
+            spans.push(Span { offset, line });
+        }
+        let rescanned = tokens.len();
+
+        let (tail_start, line_delta) = match resync {
+            Some(point) => point,
+            None => (last_old, scanner.line() as isize - self.eof_line() as isize),
+        };
+        let eof_line = shift(self.eof_line(), line_delta);
+        let mut tail_tokens: Vec<Token> = self.tokens.drain(tail_start..last_old).collect();
+        let mut tail_spans: Vec<Span> = self.spans.drain(tail_start..last_old).collect();
+        let resync_line = tail_spans.first().map(|span| span.line);
+        for (token, span) in tail_tokens.iter_mut().zip(tail_spans.iter_mut()) {
+            // Tokens that share a line with the end of the edit may have
+            // moved along it.
+            if Some(span.line) == resync_line {
+                token.column = scanner::column_at(&self.source, shift(span.offset, delta));
+            }
+            token.line = shift(token.line, line_delta);
+            span.line = shift(span.line, line_delta);
+            span.offset = shift(span.offset, delta);
+        }
+
+        self.tokens.truncate(restart);
+        self.spans.truncate(restart);
+        self.tokens.extend(tokens);
+        self.spans.extend(spans);
+        self.tokens.append(&mut tail_tokens);
+        self.spans.append(&mut tail_spans);
+        self.push_eof(eof_line);
+        Ok(rescanned)
+    }
+
+    fn eof_line(&self) -> usize {
+        self.tokens.last().map_or(1, |eof| eof.line)
+    }
+
+    fn push_eof(&mut self, line: usize) {
+        let column = scanner::column_at(&self.source, self.source.len());
+        self.tokens.push(Token::new(
+            TokenType::Eof,
+            String::new(),
+            None,
+            line,
+            column,
+        ));
+        self.spans.push(Span {
+            offset: self.source.len(),
+            line,
+        });
+    }
+}
+
+fn shift(value: usize, delta: isize) -> usize {
+    (value as isize + delta) as usize
+}
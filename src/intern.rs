@@ -0,0 +1,53 @@
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use crate::scanner::LiteralValue;
+
+// Keeps one copy of each name an interpreter defines variables under, and
+// of each short string scripts write out, for every use of it to share.
+// Defining a variable then takes no copy of its name, the same literal in
+// two programs is one string, and two strings that are the same copy are
+// equal without looking at their text.
+//
+// Strings that scripts build as they run are not interned, as most are
+// used once and then dropped, and the interner would keep them all.
+
+// The longest string literal interned, in bytes; longer ones are rare
+// enough not to be worth keeping.
+const MAX_LENGTH: usize = 64;
+
+pub struct Interner {
+    strings: HashSet<Rc<str>>,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Interner {
+            strings: HashSet::new(),
+        }
+    }
+
+    pub fn intern(&mut self, text: &str) -> Rc<str> {
+        if let Some(interned) = self.strings.get(text) {
+            return Rc::clone(interned);
+        }
+        let interned: Rc<str> = Rc::from(text);
+        self.strings.insert(Rc::clone(&interned));
+        interned
+    }
+
+    // The value of a string literal: the interned copy, if it is short.
+    pub fn literal(&mut self, text: &str) -> LiteralValue {
+        if text.len() <= MAX_LENGTH {
+            LiteralValue::String(self.intern(text))
+        } else {
+            LiteralValue::String(Rc::from(text))
+        }
+    }
+}
+
+impl Default for Interner {
+    fn default() -> Self {
+        Interner::new()
+    }
+}
@@ -1,11 +1,42 @@
 use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fmt;
+use std::io::{self, Write};
+use std::mem;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
 
+#[cfg(feature = "jit")]
+use crate::jit::Jit;
 use crate::{
-    environment::Environment,
-    expr::{self, Expr},
-    scanner::{LiteralValue, Token, TokenType},
-    stmt::Stmt,
+    ast::{Ast, Program},
+    capability::Capabilities,
+    constants::Constants,
+    coverage::Coverage,
+    diagnostic::{self, Diagnostic, Frame, Span},
+    encoding,
+    engine::Engine,
+    environment::{Environment, Pool},
+    expr::{Expr, ExprId},
+    function::LoxFunction,
+    gc::{Collection, Heap},
+    hooks::Hooks,
+    intern::Interner,
+    log, memory,
+    natives::{self, FromValue, IntoValue, NativeCode, NativeResult},
+    parser::Parser,
+    plugin,
+    random::Random,
+    scanner::{Literal, LiteralValue, Scanner, Token, TokenType},
+    serialize::deserialize_program,
+    stack,
+    stmt::{Stmt, StmtId},
+    trace,
+    value::Value,
+    vm::{self, Vm},
+    InterpreterError,
 };
 
 // Define an error type for scanner errors.
@@ -15,155 +46,958 @@ pub enum RuntimeError {
     UndefinedVariable(Token),
     UnexpectedType(Token, String),
     InvalidOperand(Token, String),
+    NotCallable(Token),
+    UndefinedProperty(Token),
+    NativeError(Token, String),
+    // The script ran all the statements it was allowed, which were these
+    // many; see `Interpreter::set_fuel`.
+    OutOfFuel(Token, u64),
+    // The script's values took more than these many bytes; see `memory`.
+    OutOfMemory(Token, usize),
+    // Calls nested deeper than `Limits::depth`, or `MAX_DEPTH`.
+    StackOverflow(Token),
+    // The host stopped the script; see `InterruptHandle`.
+    Interrupted(Token),
+    // Something the tree-walker does not run yet, like `this`, or that the
+    // parser never gives but a program built some other way may have.
+    Unsupported(Token, String),
+    ArityMismatch {
+        token: Token,
+        expected: usize,
+        got: usize,
+    },
+    // Another error, with the calls to script functions that were being
+    // made when it happened, innermost first; see `error`.
+    Traced(Box<RuntimeError>, Vec<Frame>),
 }
 
-impl std::fmt::Display for RuntimeError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl RuntimeError {
+    // The token the error is reported at.
+    pub fn token(&self) -> &Token {
+        match self {
+            RuntimeError::DivisionByZero(token)
+            | RuntimeError::UndefinedVariable(token)
+            | RuntimeError::UnexpectedType(token, _)
+            | RuntimeError::InvalidOperand(token, _)
+            | RuntimeError::NotCallable(token)
+            | RuntimeError::UndefinedProperty(token)
+            | RuntimeError::NativeError(token, _)
+            | RuntimeError::OutOfFuel(token, _)
+            | RuntimeError::OutOfMemory(token, _)
+            | RuntimeError::StackOverflow(token)
+            | RuntimeError::Interrupted(token)
+            | RuntimeError::Unsupported(token, _)
+            | RuntimeError::ArityMismatch { token, .. } => token,
+            RuntimeError::Traced(error, _) => error.token(),
+        }
+    }
+
+    // The error itself, without the calls it happened in, for matching on
+    // what went wrong.
+    pub fn error(&self) -> &RuntimeError {
+        match self {
+            RuntimeError::Traced(error, _) => error,
+            error => error,
+        }
+    }
+
+    // The calls to script functions that were being made when the error
+    // happened, innermost first.
+    pub fn trace(&self) -> &[Frame] {
+        match self {
+            RuntimeError::Traced(_, trace) => trace,
+            _ => &[],
+        }
+    }
+
+    // The error with `calls` made around those it is traced in already.
+    pub(crate) fn within(self, calls: impl IntoIterator<Item = Frame>) -> RuntimeError {
+        let (error, mut trace) = match self {
+            RuntimeError::Traced(error, trace) => (error, trace),
+            error => (Box::new(error), Vec::new()),
+        };
+        trace.extend(calls);
+        match trace.is_empty() {
+            true => *error,
+            false => RuntimeError::Traced(error, trace),
+        }
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        let token = self.token();
+        let error = |code, message| Diagnostic::error(code, message, Some(Span::of(token)));
         match self {
             RuntimeError::DivisionByZero(token) => {
-                write!(
-                    f,
-                    "Line {}: Runtime Error:Division by zero: {}",
-                    token.line, token.lexeme
-                )
+                error("E1001", format!("Division by zero: {}", token.lexeme))
             }
             RuntimeError::UndefinedVariable(token) => {
-                write!(
-                    f,
-                    "Line {}: Runtime Error: Undefined variable '{}'",
-                    token.line, token.lexeme
-                )
+                error("E1003", format!("Undefined variable '{}'", token.lexeme))
+                    .with_help("Declare it with 'var' before using it.")
+            }
+            RuntimeError::UnexpectedType(token, message) => error(
+                "E1004",
+                format!("Unexpected type for '{}': {}", token.lexeme, message),
+            ),
+            RuntimeError::InvalidOperand(token, message) => error(
+                "E1002",
+                format!("Invalid operand for '{}': {}", token.lexeme, message),
+            ),
+            RuntimeError::NotCallable(_) => {
+                error("E1005", "Can only call functions and classes".to_string())
             }
-            RuntimeError::UnexpectedType(token, message) => {
-                write!(
-                    f,
-                    "Line {}: Runtime Error: Unexpected type for '{}': {}",
-                    token.line, token.lexeme, message
-                )
+            RuntimeError::UndefinedProperty(token) => {
+                error("E1006", format!("Undefined property '{}'", token.lexeme))
             }
-            RuntimeError::InvalidOperand(token, message) => {
-                write!(
-                    f,
-                    "Line {}: Runtime Error: Invalid operand for '{}': {}",
-                    token.line, token.lexeme, message
-                )
+            RuntimeError::NativeError(_, message) => error("E1008", message.clone()),
+            RuntimeError::OutOfFuel(_, fuel) => error(
+                "E1009",
+                format!("The script ran out of fuel after {} statements.", fuel),
+            ),
+            RuntimeError::OutOfMemory(_, limit) => error(
+                "E1013",
+                format!("The script's values took more than {} bytes.", limit),
+            ),
+            RuntimeError::StackOverflow(_) => error("E1011", "Stack overflow.".to_string())
+                .with_help("A recursive function may be missing the case that ends it."),
+            RuntimeError::Interrupted(_) => {
+                error("E1010", "The script was interrupted.".to_string())
             }
+            RuntimeError::Unsupported(_, message) => error("E1012", message.clone()),
+            RuntimeError::ArityMismatch { expected, got, .. } => error(
+                "E1007",
+                format!("Expected {} arguments but got {}.", expected, got),
+            )
+            .with_label("in this call"),
+            RuntimeError::Traced(error, trace) => error.diagnostic().with_trace(trace.clone()),
         }
     }
 }
 
+impl std::fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Line {}: Runtime Error: {}",
+            self.token().line,
+            self.diagnostic().message
+        )?;
+        for line in diagnostic::trace(self.trace(), None) {
+            write!(f, "\n  {}", line)?;
+        }
+        Ok(())
+    }
+}
+
 impl std::error::Error for RuntimeError {}
 
+// How a statement finished: normally, or by returning from the function
+// it is in.
+enum Flow {
+    Normal,
+    Return(LiteralValue),
+}
+
+// Where what scripts print goes: standard output, by default, the
+// interpreter itself, for tools that show it elsewhere, or a writer that
+// the program embedding the interpreter gave it.
+enum Output {
+    Stdout,
+    // See `capture_output`.
+    Captured(String),
+    Writer(Box<dyn Write>),
+    // Sent to the interpreter that started this one's thread, which writes
+    // it where its own output goes.
+    Parent(Sender<String>),
+}
+
 pub struct Interpreter {
     environment: Rc<RefCell<Environment>>,
+    // Backs the `random` natives; `seedRandom` makes a run reproducible.
+    random: Random,
+    // How this interpreter has been set up so far.
+    setup: Setup,
+    // The calls to script functions running, outermost first, to trace
+    // errors in, to indent the trace and to limit.
+    calls: Vec<Call>,
+    // How many calls to compiled functions are running, in the VM and in
+    // the runs of it natives start, which count toward the same limit as
+    // `calls`; see `vm`.
+    frames: usize,
+    // Where what scripts print goes.
+    output: Output,
+    // What threads this interpreter started print, when its own output does
+    // not go to standard output, waiting to be written where it does.
+    thread_output: Option<(Sender<String>, Receiver<String>)>,
+    limits: Option<Limits>,
+    // How many statements have run, to hold scripts to `limits` and for
+    // `interprete bench`.
+    steps: u64,
+    tests: Vec<Test>,
+    coverage: Option<Coverage>,
+    // What the program embedding the interpreter watches scripts with.
+    hooks: Option<Box<dyn Hooks>>,
+    interrupt: InterruptHandle,
+    metrics: Metrics,
+    // The scopes the one running was opened from, the globals first when
+    // there are any, so that what they hold can be measured.
+    outer: Vec<Rc<RefCell<Environment>>>,
+    // How many bytes scripts are taken to hold: what was last measured,
+    // and what they have allocated since; see `memory`.
+    charged: usize,
+    // What scripts have made that may end up in a cycle; see `gc`.
+    heap: Heap,
+    // The scopes blocks and calls have finished with, to reuse.
+    scopes: Pool,
+    // The names of variables and the string literals; see `intern`.
+    strings: Interner,
+    // The values of the string literals of the programs run; see
+    // `constants`.
+    constants: Constants,
+}
+
+// A call to a script function, kept as cheaply as it can be until an error
+// needs its `Frame`.
+struct Call {
+    function: Rc<LoxFunction>,
+    paren: Token,
+}
+
+// A test a script has declared with `test`, for `interprete test` to run
+// once the script has finished.
+pub struct Test {
+    pub name: String,
+    pub function: LiteralValue,
+    // The call to `test`, which failures to call the function are reported
+    // at.
+    pub token: Token,
+}
+
+// Bounds on what scripts may do, for running ones that are not trusted
+// without them running forever or overflowing the stack.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    // The most statements that may run, counting each time a loop's body
+    // runs.
+    pub steps: u64,
+    // The most calls to script functions that may be running at once.
+    pub depth: usize,
+    // The most bytes the values scripts hold may take, as `memory`
+    // estimates them.
+    pub memory: usize,
+}
+
+// How deeply calls to script functions may nest when no `Limits` say
+// otherwise. The tree-walker recurses in Rust for each call, moving onto
+// stacks of its own as it goes deeper (see `stack`), so this bounds the
+// memory those take rather than what fits on the thread's stack: a few
+// kilobytes a call.
+pub const MAX_DEPTH: usize = 10_000;
+
+// Asks an interpreter, from any thread, to stop the script it is running;
+// see `Interpreter::interrupt_handle`.
+#[derive(Debug, Clone, Default)]
+pub struct InterruptHandle(Arc<AtomicBool>);
+
+impl InterruptHandle {
+    // Makes the interpreter fail with `RuntimeError::Interrupted` before the
+    // next statement it runs, in this script or, if none is running, the
+    // next. Stopping a script uses the request up, so the interpreter can
+    // run others afterwards.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    // Whether a request to stop is still waiting for the interpreter to
+    // come to a statement, as one stuck waiting for input will not.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    // Whether the script has been asked to stop, clearing the request.
+    fn take(&self) -> bool {
+        // Loading first keeps the statements that are not stopped from
+        // writing to the flag.
+        self.0.load(Ordering::Relaxed) && self.0.swap(false, Ordering::Relaxed)
+    }
+}
+
+// What scripts have used of the machine, for a host to bill, log or alert
+// on, counted from when the interpreter was made or its metrics were last
+// reset. The VM counts only the calls to natives it makes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Metrics {
+    // Statements run, counting each time a loop's body runs.
+    pub statements: u64,
+    // Calls to functions, native or written in Lox.
+    pub calls: u64,
+    // Scopes opened by blocks and calls, functions declared, and lists,
+    // maps and bytes that natives made; a list of lists made by one call
+    // counts once.
+    pub allocations: u64,
+    // The most scopes that were open at once, besides the globals.
+    pub peak_depth: usize,
+    // Times values that referred to one another in a cycle were looked
+    // for, and how many were freed; see `collect_garbage`.
+    pub collections: u64,
+    pub collected: u64,
+}
+
+const PRELUDE: &str = include_str!("prelude.lox");
+
+// How an interpreter is set up beyond its defaults: what scripts may do,
+// and what is declared before they run. A thread sets up its interpreter
+// like the one that started it.
+#[derive(Debug, Clone)]
+pub struct Setup {
+    // Which groups of natives scripts may call.
+    pub capabilities: Capabilities,
+    // Whether to declare the functions in `prelude.lox`.
+    pub prelude: bool,
+    // Shared libraries whose natives to define; see `plugin`.
+    pub plugins: Vec<String>,
+    // Whether to describe each statement and call on standard error as it
+    // runs; see `trace`.
+    pub trace: bool,
+    // Whether to count which statements run, and where to write the counts
+    // as an lcov file, if anywhere; see `coverage`.
+    pub coverage: bool,
+    pub lcov: Option<String>,
+    pub backend: Backend,
+    // Whether `==` and `!=` fail on operands of different types, other than
+    // nil, rather than finding them unequal.
+    pub strict_types: bool,
+    // What scripts are held to, and a handle to stop them with that is
+    // shared with another interpreter, as a thread's is with the one that
+    // started it.
+    pub limits: Option<Limits>,
+    pub interrupt: Option<InterruptHandle>,
+    // Where to send what scripts print rather than to standard output, for
+    // a thread whose parent's output goes elsewhere.
+    pub output: Option<Sender<String>>,
+}
+
+// What runs scripts: the tree-walker in this module, or the bytecode
+// compiler and VM in `compiler` and `vm`, which are quicker but do not yet
+// support classes, `--trace`, `--coverage` or `spawn`. The JIT is the VM
+// with the functions it calls most compiled to machine code; see `jit`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Backend {
+    #[default]
+    Tree,
+    Vm,
+    Jit,
+}
+
+impl Backend {
+    pub fn from_name(name: &str) -> Option<Backend> {
+        match name {
+            "tree" => Some(Backend::Tree),
+            "vm" => Some(Backend::Vm),
+            "jit" => Some(Backend::Jit),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Setup {
+    fn default() -> Self {
+        Setup {
+            capabilities: Capabilities::default(),
+            prelude: true,
+            plugins: Vec::new(),
+            trace: false,
+            coverage: false,
+            lcov: None,
+            backend: Backend::Tree,
+            strict_types: false,
+            limits: None,
+            interrupt: None,
+            output: None,
+        }
+    }
+}
+
+impl Setup {
+    pub fn interpreter(&self) -> Result<Interpreter, String> {
+        let mut interpreter = Interpreter::new();
+        *interpreter.capabilities() = self.capabilities;
+        if self.prelude {
+            interpreter.load_prelude();
+        }
+        for path in &self.plugins {
+            interpreter.load_plugin(path)?;
+        }
+        // Only what the script does is traced or counted, not declaring the
+        // prelude.
+        interpreter.setup.trace = self.trace;
+        interpreter.setup.strict_types = self.strict_types;
+        interpreter.reset_metrics();
+        if self.coverage {
+            interpreter.coverage = Some(Coverage::default());
+        }
+        if let Some(limits) = self.limits {
+            interpreter.limit(limits);
+        }
+        if let Some(interrupt) = &self.interrupt {
+            interpreter.interrupt = interrupt.clone();
+        }
+        if let Some(sender) = &self.output {
+            interpreter.output = Output::Parent(sender.clone());
+        }
+        Ok(interpreter)
+    }
+
+    // An engine of the chosen backend, around an interpreter set up as
+    // above.
+    pub fn engine(&self) -> Result<Box<dyn Engine>, String> {
+        let interpreter = self.interpreter()?;
+        Ok(match self.backend {
+            Backend::Tree => Box::new(interpreter),
+            Backend::Vm => Box::new(Vm::new(interpreter, None)),
+            #[cfg(feature = "jit")]
+            Backend::Jit => Box::new(Vm::new(interpreter, Some(Box::new(Jit::new()?)))),
+            #[cfg(not(feature = "jit"))]
+            Backend::Jit => {
+                return Err("This build has no JIT; build with `--features jit`.".to_string())
+            }
+        })
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Interpreter::new()
+    }
 }
 
 impl Interpreter {
     pub fn new() -> Self {
+        let mut globals = Environment::new(None);
+        natives::define_globals(&mut globals);
         Interpreter {
-            environment: Rc::new(RefCell::new(Environment::new(None))),
+            environment: Rc::new(RefCell::new(globals)),
+            random: Random::from_time(),
+            setup: Setup {
+                prelude: false,
+                ..Setup::default()
+            },
+            calls: Vec::new(),
+            frames: 0,
+            output: Output::Stdout,
+            thread_output: None,
+            limits: None,
+            steps: 0,
+            tests: Vec::new(),
+            coverage: None,
+            hooks: None,
+            metrics: Metrics::default(),
+            outer: Vec::new(),
+            charged: 0,
+            interrupt: InterruptHandle::default(),
+            heap: Heap::new(),
+            scopes: Pool::new(),
+            strings: Interner::new(),
+            constants: Constants::new(),
         }
     }
 
-    pub fn interpret(&mut self, statements: &[Stmt]) -> Result<(), RuntimeError> {
-        for statement in statements {
-            self.execute(statement)?;
+    pub fn define_global(&mut self, name: &str, value: LiteralValue) {
+        self.track(&value);
+        self.environment
+            .borrow_mut()
+            .define(self.strings.intern(name), value);
+    }
+
+    pub fn random(&mut self) -> &mut Random {
+        &mut self.random
+    }
+
+    pub fn capabilities(&mut self) -> &mut Capabilities {
+        &mut self.setup.capabilities
+    }
+
+    // Holds scripts to `limits` from now on, counting the steps from zero.
+    pub fn limit(&mut self, limits: Limits) {
+        self.limits = Some(limits);
+        self.steps = 0;
+    }
+
+    // Lets scripts run `fuel` more statements, however many have run
+    // before, and fails them with `RuntimeError::OutOfFuel` after, as a host
+    // may before each request it serves. Other limits are kept.
+    pub fn set_fuel(&mut self, fuel: u64) {
+        let limits = self.limits.unwrap_or(Limits {
+            steps: u64::MAX,
+            depth: MAX_DEPTH,
+            memory: usize::MAX,
+        });
+        self.limit(Limits {
+            steps: fuel,
+            ..limits
+        });
+    }
+
+    // How many more statements scripts may run, if they are limited.
+    pub fn fuel(&self) -> Option<u64> {
+        self.limits
+            .map(|limits| limits.steps.saturating_sub(self.steps))
+    }
+
+    // How many statements have run, since the interpreter was made or last
+    // limited.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    // The most bytes scripts may hold, if memory is limited, for natives to
+    // check a length they are asked for against before allocating it.
+    pub fn memory_limit(&self) -> Option<usize> {
+        self.limits.map(|limits| limits.memory)
+    }
+
+    // A handle that stops scripts this interpreter runs, for a host to keep
+    // and cancel from another thread when one runs too long or is no longer
+    // wanted.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
+    // Whether the host has asked for the script to stop, for the VM, which
+    // has no statements to check before, to check as it loops and calls.
+    pub fn interrupted(&self) -> bool {
+        self.interrupt.take()
+    }
+
+    // Counts a step of compiled code, a call or a loop going round, toward
+    // `Limits::steps` as a statement is, and fails at the token `at` gives
+    // if there have been too many or the host has asked scripts to stop.
+    pub(crate) fn step(&mut self, at: impl FnOnce() -> Token) -> Result<(), RuntimeError> {
+        self.steps += 1;
+        if self.interrupt.take() {
+            return Err(RuntimeError::Interrupted(at()));
+        }
+        match self.limits {
+            Some(limits) if self.steps > limits.steps => {
+                Err(RuntimeError::OutOfFuel(at(), limits.steps))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // The most calls to script functions, run by either backend, that may
+    // be running at once.
+    fn max_depth(&self) -> usize {
+        self.limits.map_or(MAX_DEPTH, |limits| limits.depth)
+    }
+
+    // Counts a call to a compiled function, failing at `paren` if as many
+    // calls as may be are running already.
+    pub(crate) fn enter_frame(&mut self, paren: &Token) -> Result<(), RuntimeError> {
+        if self.calls.len() + self.frames >= self.max_depth() {
+            return Err(RuntimeError::StackOverflow(paren.clone()));
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    // Counts calls to compiled functions as finished.
+    pub(crate) fn leave_frames(&mut self, count: usize) {
+        self.frames -= count;
+    }
+
+    pub fn metrics(&self) -> Metrics {
+        self.metrics
+    }
+
+    // Counts from zero again, as between the runs a host bills for.
+    pub fn reset_metrics(&mut self) {
+        self.metrics = Metrics::default();
+    }
+
+    // Frees the values scripts can no longer reach that are only kept
+    // alive by referring to one another, giving how many there were. This
+    // happens on its own as scripts allocate, and hosts need only call it
+    // to free them sooner.
+    pub fn collect_garbage(&mut self) -> usize {
+        let collection = self.heap.collect();
+        self.collected(collection)
+    }
+
+    // Has a function, closure, list or map scripts have just made collected
+    // with the rest once it is only in a cycle.
+    pub(crate) fn track(&mut self, value: &LiteralValue) {
+        if let Some(collection) = self.heap.track(value) {
+            self.collected(collection);
+        }
+    }
+
+    fn collected(&mut self, collection: Collection) -> usize {
+        self.metrics.collections += 1;
+        self.metrics.collected += collection.freed as u64;
+        log::debug(format_args!(
+            "Collected {} values in cycles, {} still tracked",
+            collection.freed, collection.live
+        ));
+        collection.freed
+    }
+
+    // The calls to script functions running now, innermost first, as an
+    // error there would be traced; for natives and hooks to report where
+    // they were called from. Only the tree-walker keeps them.
+    pub fn backtrace(&self) -> Vec<Frame> {
+        self.calls
+            .iter()
+            .rev()
+            .map(|call| Frame {
+                function: call.function.name.lexeme.clone(),
+                span: Span::of(&call.paren),
+            })
+            .collect()
+    }
+
+    // Which statements have run, if they are being counted.
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    pub fn setup(&self) -> &Setup {
+        &self.setup
+    }
+
+    // How to set up the interpreter of a thread this one starts: like this
+    // one, held to the same limits, stopped when it is, and printing where
+    // it prints.
+    pub(crate) fn thread_setup(&mut self) -> Setup {
+        let output = match &self.output {
+            Output::Stdout => None,
+            Output::Parent(sender) => Some(sender.clone()),
+            Output::Captured(_) | Output::Writer(_) => {
+                let (sender, _) = self.thread_output.get_or_insert_with(mpsc::channel);
+                Some(sender.clone())
+            }
+        };
+        Setup {
+            limits: self.limits,
+            interrupt: Some(self.interrupt.clone()),
+            output,
+            ..self.setup.clone()
+        }
+    }
+
+    // Declares the convenience functions written in Lox in `prelude.lox`,
+    // which is compiled into the binary.
+    pub fn load_prelude(&mut self) {
+        log::info(format_args!("Loading the prelude"));
+        let tokens = Scanner::new(PRELUDE.to_string())
+            .scan_tokens()
+            .expect("the prelude scans");
+        let program = Parser::new(tokens).parse().expect("the prelude parses");
+        self.interpret(&program).expect("the prelude runs");
+        self.setup.prelude = true;
+    }
+
+    pub fn load_plugin(&mut self, path: &str) -> Result<(), String> {
+        log::info(format_args!("Loading plugin '{}'", path));
+        plugin::load(self, path)?;
+        self.setup.plugins.push(path.to_string());
+        Ok(())
+    }
+
+    // Looks up a global variable, for the VM or once a program has finished
+    // running.
+    pub fn global(&self, name: &Token) -> Result<LiteralValue, RuntimeError> {
+        self.environment.borrow().get(name)
+    }
+
+    pub fn assign_global(&mut self, name: &Token, value: LiteralValue) -> Result<(), RuntimeError> {
+        self.environment.borrow_mut().assign(name, value)
+    }
+
+    // Declares a global for scripts to read, or sets it if it is declared,
+    // from anything with a script value, such as a number or a `Value`.
+    pub fn set_global(&mut self, name: &str, value: impl IntoValue) {
+        self.define_global(name, value.into_value());
+    }
+
+    // Lets scripts call a closure of the host's as the global function
+    // `name`, with any number of arguments:
+    //
+    //     interpreter.register_fn("log", |arguments: &[Value]| {
+    //         println!("{:?}", arguments);
+    //     });
+    //
+    // The closure may give back anything with a script value, or a
+    // `Result<_, String>` whose error the call fails with.
+    pub fn register_fn<R: NativeResult>(
+        &mut self,
+        name: &str,
+        function: impl Fn(&[Value]) -> R + 'static,
+    ) {
+        natives::define_host(&mut *self.environment.borrow_mut(), name, function);
+    }
+
+    // A global that scripts have set, once they have run, if it is declared
+    // and converts to a `T`.
+    pub fn get_global<T: FromValue>(&self, name: &str) -> Option<T> {
+        let value = self.environment.borrow().lookup(name)?;
+        T::from_value(&value)
+    }
+
+    // The scope globals are defined in, which is the one running only when
+    // no script is.
+    pub(crate) fn global_scope(&self) -> &Rc<RefCell<Environment>> {
+        self.outer.first().unwrap_or(&self.environment)
+    }
+
+    // Every global variable, with its value, once a program has finished
+    // running.
+    pub fn globals(&self) -> Vec<(String, LiteralValue)> {
+        self.environment.borrow().entries()
+    }
+
+    pub fn add_test(&mut self, test: Test) {
+        self.tests.push(test);
+    }
+
+    // The tests declared so far, in order, which are forgotten here.
+    pub fn take_tests(&mut self) -> Vec<Test> {
+        std::mem::take(&mut self.tests)
+    }
+
+    pub fn interpret(&mut self, program: &Program) -> Result<(), RuntimeError> {
+        for statement in &program.statements {
+            // The parser rejects `return` outside a function, but a
+            // deserialized program may still contain one; it ends the run.
+            if let Flow::Return(_) = self.execute(&program.ast, *statement)? {
+                break;
+            }
         }
         Ok(())
     }
 
-    fn execute(&mut self, statement: &Stmt) -> Result<(), RuntimeError> {
-        match statement {
+    // Runs a program previously produced by `serialize::serialize_program`,
+    // skipping scanning and parsing entirely.
+    pub fn interpret_ast(&mut self, json: &str) -> Result<(), InterpreterError> {
+        let program = deserialize_program(json)?;
+        self.interpret(&program)?;
+        Ok(())
+    }
+
+    // Runs source among the globals, as the REPL does, giving the value of
+    // a lone expression, or nil for statements.
+    pub fn eval(&mut self, source: &str) -> Result<Value, InterpreterError> {
+        let value = crate::evaluate(self, source.to_string())?;
+        Ok(value.map_or(Value::Nil, |value| Value::from_literal(&value)))
+    }
+
+    pub fn evaluate_expression(
+        &mut self,
+        ast: &Arc<Ast>,
+        expression: ExprId,
+    ) -> Result<LiteralValue, RuntimeError> {
+        self.evaluate(ast, expression)
+    }
+
+    fn execute(&mut self, ast: &Arc<Ast>, statement: StmtId) -> Result<Flow, RuntimeError> {
+        if self.setup.trace {
+            if let Some(source) = trace::statement(ast, statement) {
+                self.trace(format_args!("{}", source));
+            }
+        }
+        if let Some(hooks) = &mut self.hooks {
+            if let Some(source) = trace::source(ast, statement) {
+                let (line, column) = trace::start(ast, statement)
+                    .map_or((0, 0), |token| (token.start_line(), token.column));
+                hooks.statement(&Span::new(line, column, &source));
+            }
+        }
+        self.steps += 1;
+        self.metrics.statements += 1;
+        if let Some(coverage) = &mut self.coverage {
+            coverage.record(ast, statement);
+        }
+        if self.interrupt.take() {
+            return Err(RuntimeError::Interrupted(at_statement(ast, statement)));
+        }
+        if let Some(limits) = self.limits {
+            if self.steps > limits.steps {
+                let token = at_statement(ast, statement);
+                return Err(RuntimeError::OutOfFuel(token, limits.steps));
+            }
+        }
+        match ast.stmt(statement) {
             Stmt::Expression(expr) => {
-                self.evaluate(expr)?;
-                Ok(())
+                self.evaluate(ast, *expr)?;
+                Ok(Flow::Normal)
             }
             Stmt::Print(expr) => {
-                let value = self.evaluate(expr)?;
-                println!("{}", self.stringify(value));
-                Ok(())
+                let value = self.evaluate(ast, *expr)?;
+                let text = self.stringify(value);
+                self.write_output(&format!("{}\n", text));
+                Ok(Flow::Normal)
             }
             Stmt::Var(name, initializer) => {
                 let value = match initializer {
-                    Some(expr) => self.evaluate(expr)?,
+                    Some(expr) => self.evaluate(ast, *expr)?,
                     None => LiteralValue::Nil,
                 };
-                self.environment
-                    .borrow_mut()
-                    .define(name.lexeme.clone(), value);
-                Ok(())
+                self.assigned(name, &value);
+                self.declare(ast, statement, name, value);
+                Ok(Flow::Normal)
             }
             Stmt::Block(statements) => {
-                let previous = Rc::clone(&self.environment);
-                self.environment = Rc::new(RefCell::new(Environment::new(Some(
-                    self.environment.clone(),
-                ))));
-                let result = self.execute_block(statements);
-                self.environment = previous; // Restore previous environment
-                result
+                let names = match ast.scope(statement) {
+                    Some(names) if names.is_empty() => return self.execute_all(ast, statements),
+                    names => names.cloned(),
+                };
+                let environment = self.scopes.open(Rc::clone(&self.environment), names);
+                self.execute_block(ast, statements, environment)
             }
             Stmt::If(condition, then_branch, else_branch) => {
-                let value = self.evaluate(condition)?;
+                let value = self.evaluate(ast, *condition)?;
                 if self.is_truthy(&value) {
-                    self.execute(then_branch)
+                    self.execute(ast, *then_branch)
                 } else if let Some(else_stmt) = else_branch {
-                    self.execute(else_stmt)
+                    self.execute(ast, *else_stmt)
                 } else {
-                    Ok(())
+                    Ok(Flow::Normal)
                 }
             }
             Stmt::While(condition, body) => {
                 loop {
-                    let value = self.evaluate(condition)?;
+                    let value = self.evaluate(ast, *condition)?;
                     if !self.is_truthy(&value) {
                         break;
                     }
-                    self.execute(body)?;
+                    if let Flow::Return(value) = self.execute(ast, *body)? {
+                        return Ok(Flow::Return(value));
+                    }
                 }
 
-                Ok(())
+                Ok(Flow::Normal)
+            }
+            Stmt::Function(name, params, body) => {
+                let function = LoxFunction {
+                    name: name.clone(),
+                    params: params.clone(),
+                    body: body.clone(),
+                    ast: Arc::clone(ast),
+                    closure: Rc::clone(&self.environment),
+                    locals: ast.scope(statement).cloned(),
+                };
+                self.metrics.allocations += 1;
+                let function = LiteralValue::Function(Rc::new(function));
+                self.track(&function);
+                self.declare(ast, statement, name, function);
+                Ok(Flow::Normal)
+            }
+            Stmt::Return(_, value) => {
+                let value = match value {
+                    Some(expr) => self.evaluate(ast, *expr)?,
+                    None => LiteralValue::Nil,
+                };
+                Ok(Flow::Return(value))
             }
-            _ => !unreachable!(),
+            Stmt::Class(name, _, _) => Err(RuntimeError::Unsupported(
+                name.clone(),
+                "Classes are not supported yet.".to_string(),
+            )),
         }
     }
 
-    fn execute_block(&mut self, statements: &[Stmt]) -> Result<(), RuntimeError> {
+    // Runs statements in the given environment, restoring the current one
+    // afterwards even if they fail.
+    fn execute_block(
+        &mut self,
+        ast: &Arc<Ast>,
+        statements: &[StmtId],
+        environment: Rc<RefCell<Environment>>,
+    ) -> Result<Flow, RuntimeError> {
+        let previous = std::mem::replace(&mut self.environment, environment);
+        self.outer.push(previous);
+        self.metrics.allocations += 1;
+        self.metrics.peak_depth = self.metrics.peak_depth.max(self.outer.len());
+        let result = self.execute_all(ast, statements);
+        // Restore previous environment
+        let previous = self.outer.pop().expect("pushed above");
+        let environment = std::mem::replace(&mut self.environment, previous);
+        self.scopes.close(environment);
+        result
+    }
+
+    // Runs statements in the environment running, until one returns.
+    fn execute_all(&mut self, ast: &Arc<Ast>, statements: &[StmtId]) -> Result<Flow, RuntimeError> {
         for statement in statements {
-            self.execute(statement)?;
+            let flow = self.execute(ast, *statement)?;
+            if !matches!(flow, Flow::Normal) {
+                return Ok(flow);
+            }
         }
-        Ok(())
+        Ok(Flow::Normal)
+    }
+
+    // Defines the variable a `var` or `fun` statement declares, in the slot
+    // `resolver` gave it if it gave one.
+    fn declare(&mut self, ast: &Ast, statement: StmtId, name: &Token, value: LiteralValue) {
+        let mut environment = self.environment.borrow_mut();
+        match ast.slot(statement) {
+            Some(slot) => environment.define_slot(slot, value),
+            None => environment.define(self.strings.intern(&name.lexeme), value),
+        }
+    }
+
+    fn evaluate(
+        &mut self,
+        ast: &Arc<Ast>,
+        expression: ExprId,
+    ) -> Result<LiteralValue, RuntimeError> {
+        stack::descend(|| self.evaluate_node(ast, expression))
     }
 
-    fn evaluate(&mut self, expression: &Expr) -> Result<LiteralValue, RuntimeError> {
+    fn evaluate_node(
+        &mut self,
+        ast: &Arc<Ast>,
+        expression: ExprId,
+    ) -> Result<LiteralValue, RuntimeError> {
         //println!("Evaluating: {expression:?}");
-        match expression {
-            Expr::Literal(value) => Ok(value.clone().unwrap_or(LiteralValue::Nil)),
-            Expr::Grouping(expr) => self.evaluate(expr),
+        match ast.expr(expression) {
+            Expr::Literal(Some(Literal::String(s))) => match ast.constant(expression) {
+                Some(index) => Ok(self.constants.get(ast, index, &mut self.strings)),
+                None => Ok(self.strings.literal(s)),
+            },
+            Expr::Literal(value) => Ok(value.as_ref().map_or(LiteralValue::Nil, Literal::value)),
+            Expr::Grouping(expr) => self.evaluate(ast, *expr),
 
             Expr::Unary(operator, right) => {
-                let right_val = self.evaluate(&right)?;
-                match operator.token_type {
-                    TokenType::Minus => match right_val {
-                        LiteralValue::Number(num) => Ok(LiteralValue::Number(-num)),
-                        _ => Err(RuntimeError::UnexpectedType(
-                            operator.clone(),
-                            "Operand must be a number.".to_string(),
-                        )),
-                    },
-                    TokenType::Bang => Ok(LiteralValue::Boolean(!self.is_truthy(&right_val))),
-                    _ => !unreachable!(),
-                }
+                let right = self.evaluate(ast, *right)?;
+                self.unary(operator, right)
             }
-            Expr::Variable(name) => self.lookup_variable(name),
+            Expr::Variable(name) => match ast.local(expression) {
+                Some(local) => self
+                    .environment
+                    .borrow()
+                    .get_at(local.depth as usize, local.slot as usize)
+                    .ok_or_else(|| RuntimeError::UndefinedVariable(name.clone())),
+                None => self.lookup_variable(name),
+            },
             Expr::Assignment(name, value) => {
-                let evaluated_value = self.evaluate(value)?;
-                self.environment
-                    .borrow_mut()
-                    .assign(name, evaluated_value.clone())?;
+                let evaluated_value = self.evaluate(ast, *value)?;
+                let mut environment = self.environment.borrow_mut();
+                match ast.local(expression) {
+                    Some(local) => {
+                        let (depth, slot) = (local.depth as usize, local.slot as usize);
+                        if !environment.assign_at(depth, slot, evaluated_value.clone()) {
+                            return Err(RuntimeError::UndefinedVariable(name.clone()));
+                        }
+                    }
+                    None => environment.assign(name, evaluated_value.clone())?,
+                }
+                drop(environment);
+                self.assigned(name, &evaluated_value);
                 Ok(evaluated_value)
             }
             Expr::Logical(left, operator, right) => {
-                let left_val = self.evaluate(left)?;
+                let left_val = self.evaluate(ast, *left)?;
 
                 if operator.token_type == TokenType::Or {
                     if self.is_truthy(&left_val) {
@@ -173,75 +1007,450 @@ impl Interpreter {
                     return Ok(left_val);
                 }
 
-                self.evaluate(right)
+                self.evaluate(ast, *right)
             }
             Expr::Binary(left, operator, right) => {
-                let left_val = self.evaluate(&left)?;
-                let right_val = self.evaluate(&right)?;
-                match operator.token_type {
-                    TokenType::Minus => {
-                        let (a, b) = self.check_number_operands(operator, &left_val, &right_val)?;
-                        Ok(LiteralValue::Number(a - b))
-                    }
-                    TokenType::Slash => {
-                        let (a, b) = self.check_number_operands(operator, &left_val, &right_val)?;
-                        if b == 0.0 {
-                            Err(RuntimeError::DivisionByZero(operator.clone()))
-                        } else {
-                            Ok(LiteralValue::Number(a / b))
-                        }
-                    }
-                    TokenType::Star => {
-                        let (a, b) = self.check_number_operands(operator, &left_val, &right_val)?;
-                        Ok(LiteralValue::Number(a * b))
-                    }
+                let left = self.evaluate(ast, *left)?;
+                let right = self.evaluate(ast, *right)?;
+                let value = self.binary(operator, left, right)?;
+                if let LiteralValue::String(s) = &value {
+                    self.charge(s.len(), operator)?;
+                }
+                Ok(value)
+            }
+            Expr::Call(callee, paren, arguments) => {
+                let callee_val = self.evaluate(ast, *callee)?;
+                let mut argument_vals = Vec::with_capacity(arguments.len());
+                for argument in arguments {
+                    argument_vals.push(self.evaluate(ast, *argument)?);
+                }
+                self.call_value(callee_val, paren, argument_vals)
+            }
+            Expr::Get(object, name) => {
+                let object = self.evaluate(ast, *object)?;
+                self.get_property(object, name)
+            }
+            Expr::Set(object, name, value) => {
+                let object = self.evaluate(ast, *object)?;
+                let value = self.evaluate(ast, *value)?;
+                self.set_property(object, name, value)
+            }
+            Expr::This(keyword) | Expr::Super(keyword, _) => {
+                let message = format!("'{}' is not supported yet.", keyword.lexeme);
+                Err(RuntimeError::Unsupported(keyword.clone(), message))
+            }
+        }
+    }
 
-                    TokenType::Plus => match (&left_val, &right_val) {
-                        (LiteralValue::Number(l), LiteralValue::Number(r)) => {
-                            Ok(LiteralValue::Number(l + r))
-                        }
-                        (LiteralValue::String(l), LiteralValue::String(r)) => {
-                            Ok(LiteralValue::String(format!("{}{}", l, r)))
-                        }
-                        _ => {
-                            return Err(RuntimeError::InvalidOperand(
-                                operator.clone(),
-                                "Operands must be two numbers or two strings.".to_string(),
-                            ))
-                        }
-                    },
-                    TokenType::Greater => {
-                        let (a, b) = self.check_number_operands(operator, &left_val, &right_val)?;
-                        Ok(LiteralValue::Boolean(a > b))
-                    }
-                    TokenType::GreaterEqual => {
-                        let (a, b) = self.check_number_operands(operator, &left_val, &right_val)?;
-                        Ok(LiteralValue::Boolean(a >= b))
-                    }
-                    TokenType::Less => {
-                        let (a, b) = self.check_number_operands(operator, &left_val, &right_val)?;
-                        Ok(LiteralValue::Boolean(a < b))
+    // Calls the global function `name`, as a host uses a script's functions
+    // as callbacks. Calling something that is not a function, with the
+    // wrong number of arguments, fails as it would in a script.
+    pub fn call(&mut self, name: &str, arguments: &[Value]) -> Result<Value, RuntimeError> {
+        let token = Token::new(TokenType::Identifier, name.to_string(), None, 0, 0);
+        let callee = self.global(&token)?;
+        let arguments = arguments.iter().cloned().map(Value::into_literal).collect();
+        let paren = Token::new(TokenType::RightParen, ")".to_string(), None, 0, 0);
+        let result = self.call_value(callee, &paren, arguments)?;
+        Ok(Value::from_literal(&result))
+    }
+
+    // Calls a function value with already evaluated arguments. Natives use
+    // this to call back into script functions.
+    pub fn call_value(
+        &mut self,
+        callee: LiteralValue,
+        paren: &Token,
+        arguments: Vec<LiteralValue>,
+    ) -> Result<LiteralValue, RuntimeError> {
+        match &callee {
+            LiteralValue::NativeFunction(function) => {
+                if function.variadic && arguments.len() < function.arity {
+                    return Err(RuntimeError::NativeError(
+                        paren.clone(),
+                        format!(
+                            "Expected at least {} arguments but got {}.",
+                            function.arity,
+                            arguments.len()
+                        ),
+                    ));
+                }
+                if !function.variadic && arguments.len() != function.arity {
+                    return Err(RuntimeError::ArityMismatch {
+                        token: paren.clone(),
+                        expected: function.arity,
+                        got: arguments.len(),
+                    });
+                }
+                if let Some(capability) = function.capability {
+                    if !self.setup.capabilities.allows(capability) {
+                        return Err(RuntimeError::NativeError(
+                            paren.clone(),
+                            format!(
+                                "'{}' needs the '{}' capability, which is denied.",
+                                function.name, capability
+                            ),
+                        ));
                     }
-                    TokenType::LessEqual => {
-                        let (a, b) = self.check_number_operands(operator, &left_val, &right_val)?;
-                        Ok(LiteralValue::Boolean(a >= b))
+                }
+                log::debug(format_args!(
+                    "Line {}: calling '{}' with {} arguments",
+                    paren.line,
+                    function.name,
+                    arguments.len()
+                ));
+                self.metrics.calls += 1;
+                // What the arguments hold, to tell how much the native adds
+                // to them, if memory is limited.
+                let metered = self.limits.is_some_and(|limits| limits.memory < usize::MAX);
+                let held: Vec<usize> = match metered {
+                    true => arguments.iter().map(memory::held).collect(),
+                    false => Vec::new(),
+                };
+                let value = match &function.function {
+                    NativeCode::Builtin(code) => code(self, paren, &arguments),
+                    NativeCode::Plugin(code) => {
+                        plugin::call(*code, &function.name, paren, &arguments)
                     }
-                    TokenType::BangEqual => {
-                        Ok(LiteralValue::Boolean(!self.is_equal(&left_val, &right_val)))
+                    NativeCode::Host(code) => code.call(paren, &arguments),
+                }?;
+                let new = is_new(&value);
+                if new {
+                    self.metrics.allocations += 1;
+                    self.track(&value);
+                }
+                if metered {
+                    let mut bytes = if new || matches!(value, LiteralValue::String(_)) {
+                        memory::value(&value)
+                    } else {
+                        0
+                    };
+                    for (argument, before) in arguments.iter().zip(held) {
+                        bytes += memory::held(argument).saturating_sub(before);
                     }
-                    TokenType::EqualEqual => {
-                        Ok(LiteralValue::Boolean(self.is_equal(&left_val, &right_val)))
+                    self.charge(bytes, paren)?;
+                }
+                Ok(value)
+            }
+            LiteralValue::Function(function) => {
+                if arguments.len() != function.arity() {
+                    return Err(RuntimeError::ArityMismatch {
+                        token: paren.clone(),
+                        expected: function.arity(),
+                        got: arguments.len(),
+                    });
+                }
+                if self.calls.len() + self.frames >= self.max_depth() {
+                    return Err(RuntimeError::StackOverflow(paren.clone()));
+                }
+                if self.setup.trace {
+                    let arguments: Vec<_> = arguments
+                        .iter()
+                        .map(|argument| self.stringify(argument.clone()))
+                        .collect();
+                    let name = &function.name.lexeme;
+                    self.trace(format_args!("call {}({})", name, arguments.join(", ")));
+                }
+                if let Some(hooks) = &mut self.hooks {
+                    let values: Vec<_> = arguments.iter().map(Value::from_literal).collect();
+                    hooks.call(&function.name.lexeme, &values, &Span::of(paren));
+                }
+                self.metrics.calls += 1;
+                let closure = Rc::clone(&function.closure);
+                let environment = self.scopes.open(closure, function.locals.clone());
+                {
+                    let mut scope = environment.borrow_mut();
+                    for (slot, (param, argument)) in
+                        function.params.iter().zip(arguments).enumerate()
+                    {
+                        match function.locals {
+                            Some(_) => scope.define_slot(slot, argument),
+                            None => scope.define(self.strings.intern(&param.lexeme), argument),
+                        }
                     }
+                }
+                self.calls.push(Call {
+                    function: Rc::clone(function),
+                    paren: paren.clone(),
+                });
+                let flow = match self.execute_block(&function.ast, &function.body, environment) {
+                    // Only the innermost call traces the error, as the
+                    // calls around it are still running then.
+                    Err(error) if error.trace().is_empty() => Err(error.within(self.backtrace())),
+                    flow => flow,
+                };
+                self.calls.pop();
+                let value = match flow? {
+                    Flow::Return(value) => value,
+                    Flow::Normal => LiteralValue::Nil,
+                };
+                if self.setup.trace {
+                    let result = self.stringify(value.clone());
+                    self.trace(format_args!("{} returned {}", function.name.lexeme, result));
+                }
+                if let Some(hooks) = &mut self.hooks {
+                    hooks.returned(&function.name.lexeme, &Value::from_literal(&value));
+                }
+                Ok(value)
+            }
+            LiteralValue::Closure(closure) => {
+                self.metrics.calls += 1;
+                vm::call(self, closure, paren, arguments)
+            }
+            _ => Err(RuntimeError::NotCallable(paren.clone())),
+        }
+    }
+
+    // Writes text that a script prints, with `print` or natives like
+    // `write`, to standard output or to the captured output.
+    pub fn write_output(&mut self, text: &str) {
+        self.forward_thread_output();
+        self.write(text);
+    }
+
+    // Writes what threads this interpreter started have printed so far, as
+    // it prints and once they are joined, so it comes out in between what
+    // this one prints.
+    pub(crate) fn forward_thread_output(&mut self) {
+        let Some((_, receiver)) = &self.thread_output else {
+            return;
+        };
+        let texts: Vec<String> = receiver.try_iter().collect();
+        for text in texts {
+            self.write(&text);
+        }
+    }
+
+    fn write(&mut self, text: &str) {
+        match &mut self.output {
+            Output::Stdout => {
+                print!("{}", text);
+                io::stdout().flush().ok();
+            }
+            Output::Captured(output) => output.push_str(text),
+            // Scripts cannot do anything about a writer that fails, so they
+            // carry on as they would with a closed standard output.
+            Output::Writer(writer) => {
+                writer.write_all(text.as_bytes()).ok();
+                writer.flush().ok();
+            }
+            // Nor about a parent that has finished.
+            Output::Parent(sender) => {
+                sender.send(text.to_string()).ok();
+            }
+        }
+    }
+
+    // Keeps what scripts print from now on, to be collected with
+    // `take_output`, for tools that run scripts and show their output
+    // elsewhere.
+    pub fn capture_output(&mut self) {
+        self.output = Output::Captured(String::new());
+    }
+
+    // What scripts have printed since the capture started or this was last
+    // called.
+    pub fn take_output(&mut self) -> String {
+        self.forward_thread_output();
+        match &mut self.output {
+            Output::Captured(output) => std::mem::take(output),
+            _ => String::new(),
+        }
+    }
+
+    // Writes what scripts print from now on to `writer` rather than to
+    // standard output, such as a file or a buffer shared with the host.
+    pub fn set_output(&mut self, writer: impl Write + 'static) {
+        self.output = Output::Writer(Box::new(writer));
+    }
+
+    // Calls `hooks` as scripts run from now on, in place of any given
+    // before.
+    pub fn set_hooks(&mut self, hooks: impl Hooks + 'static) {
+        self.hooks = Some(Box::new(hooks));
+    }
+
+    // Counts `bytes` that a script has allocated toward `Limits::memory`,
+    // and fails at `token` if it holds more than that. The bytes are those
+    // of a value just made, which is not yet in scope to be measured.
+    pub(crate) fn charge(&mut self, bytes: usize, token: &Token) -> Result<(), RuntimeError> {
+        let Some(limit) = self.limits.map(|limits| limits.memory) else {
+            return Ok(());
+        };
+        self.charged = self.charged.saturating_add(bytes);
+        if self.charged <= limit {
+            return Ok(());
+        }
+        let scopes = self.outer.iter().chain([&self.environment]);
+        self.charged = memory::measure(scopes).saturating_add(bytes);
+        if self.charged > limit {
+            return Err(RuntimeError::OutOfMemory(token.clone(), limit));
+        }
+        Ok(())
+    }
+
+    // Tells the hooks, if there are any, that a variable has been given a
+    // value.
+    fn assigned(&mut self, name: &Token, value: &LiteralValue) {
+        if let Some(hooks) = &mut self.hooks {
+            hooks.assign(&name.lexeme, &Value::from_literal(value), &Span::of(name));
+        }
+    }
+
+    // Prints a line of the trace, indented by how deep in calls it is.
+    fn trace(&self, message: fmt::Arguments) {
+        eprintln!("[trace] {}{}", "  ".repeat(self.calls.len()), message);
+    }
+
+    // Applies a unary operator. The tree-walker and the VM share this and
+    // the methods below, so they agree on what scripts mean.
+    pub fn unary(
+        &self,
+        operator: &Token,
+        right: LiteralValue,
+    ) -> Result<LiteralValue, RuntimeError> {
+        match operator.token_type {
+            TokenType::Minus => match right {
+                LiteralValue::Number(num) => Ok(LiteralValue::Number(-num)),
+                _ => Err(RuntimeError::UnexpectedType(
+                    operator.clone(),
+                    "Operand must be a number.".to_string(),
+                )),
+            },
+            TokenType::Bang => Ok(LiteralValue::Boolean(!self.is_truthy(&right))),
+            _ => Err(not_an_operator(operator, "unary")),
+        }
+    }
+
+    // Applies a binary operator other than `and` and `or`, which do not
+    // always evaluate their right operand.
+    pub fn binary(
+        &self,
+        operator: &Token,
+        left: LiteralValue,
+        right: LiteralValue,
+    ) -> Result<LiteralValue, RuntimeError> {
+        match operator.token_type {
+            TokenType::Minus => {
+                let (a, b) = self.check_number_operands(operator, &left, &right)?;
+                Ok(LiteralValue::Number(a - b))
+            }
+            TokenType::Slash => {
+                let (a, b) = self.check_number_operands(operator, &left, &right)?;
+                if b == 0.0 {
+                    Err(RuntimeError::DivisionByZero(operator.clone()))
+                } else {
+                    Ok(LiteralValue::Number(a / b))
+                }
+            }
+            TokenType::Star => {
+                let (a, b) = self.check_number_operands(operator, &left, &right)?;
+                Ok(LiteralValue::Number(a * b))
+            }
 
-                    _ => !unreachable!(),
+            TokenType::Plus => match (&left, &right) {
+                (LiteralValue::Number(l), LiteralValue::Number(r)) => {
+                    Ok(LiteralValue::Number(l + r))
+                }
+                (LiteralValue::String(l), LiteralValue::String(r)) => {
+                    Ok(LiteralValue::String(format!("{}{}", l, r).into()))
                 }
+                _ => Err(RuntimeError::InvalidOperand(
+                    operator.clone(),
+                    "Operands must be two numbers or two strings.".to_string(),
+                )),
+            },
+            TokenType::Greater => {
+                let (a, b) = self.check_number_operands(operator, &left, &right)?;
+                Ok(LiteralValue::Boolean(a > b))
+            }
+            TokenType::GreaterEqual => {
+                let (a, b) = self.check_number_operands(operator, &left, &right)?;
+                Ok(LiteralValue::Boolean(a >= b))
+            }
+            TokenType::Less => {
+                let (a, b) = self.check_number_operands(operator, &left, &right)?;
+                Ok(LiteralValue::Boolean(a < b))
+            }
+            TokenType::LessEqual => {
+                let (a, b) = self.check_number_operands(operator, &left, &right)?;
+                Ok(LiteralValue::Boolean(a <= b))
+            }
+            TokenType::BangEqual => {
+                self.check_comparable(operator, &left, &right)?;
+                Ok(LiteralValue::Boolean(!self.is_equal(&left, &right)))
+            }
+            TokenType::EqualEqual => {
+                self.check_comparable(operator, &left, &right)?;
+                Ok(LiteralValue::Boolean(self.is_equal(&left, &right)))
             }
-            _ => !unreachable!(),
+            _ => Err(not_an_operator(operator, "binary")),
         }
     }
+
+    pub fn get_property(
+        &self,
+        object: LiteralValue,
+        name: &Token,
+    ) -> Result<LiteralValue, RuntimeError> {
+        match &object {
+            LiteralValue::Namespace(namespace) => namespace
+                .members
+                .get(&name.lexeme)
+                .cloned()
+                .ok_or_else(|| RuntimeError::UndefinedProperty(name.clone())),
+            LiteralValue::Map(map) => map
+                .borrow()
+                .get(&name.lexeme)
+                .cloned()
+                .ok_or_else(|| RuntimeError::UndefinedProperty(name.clone())),
+            _ => Err(RuntimeError::UnexpectedType(
+                name.clone(),
+                "Only maps and namespaces have properties.".to_string(),
+            )),
+        }
+    }
+
+    pub fn set_property(
+        &self,
+        object: LiteralValue,
+        name: &Token,
+        value: LiteralValue,
+    ) -> Result<LiteralValue, RuntimeError> {
+        match &object {
+            LiteralValue::Map(map) => {
+                map.borrow_mut()
+                    .insert(name.lexeme.as_str().into(), value.clone());
+                Ok(value)
+            }
+            _ => Err(RuntimeError::UnexpectedType(
+                name.clone(),
+                "Only maps have assignable properties.".to_string(),
+            )),
+        }
+    }
+
     fn lookup_variable(&self, name: &Token) -> Result<LiteralValue, RuntimeError> {
         self.environment.borrow().get(name)
     }
+
+    // With `strict_types`, values of different types cannot be compared,
+    // but anything can be compared with nil.
+    fn check_comparable(
+        &self,
+        operator: &Token,
+        left: &LiteralValue,
+        right: &LiteralValue,
+    ) -> Result<(), RuntimeError> {
+        let nil = matches!(left, LiteralValue::Nil) || matches!(right, LiteralValue::Nil);
+        if self.setup.strict_types && !nil && mem::discriminant(left) != mem::discriminant(right) {
+            return Err(RuntimeError::UnexpectedType(
+                operator.clone(),
+                "Operands must be of the same type.".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
     fn check_number_operands(
         &self,
         operator: &Token,
@@ -257,7 +1466,7 @@ impl Interpreter {
         }
     }
 
-    fn is_truthy(&self, value: &LiteralValue) -> bool {
+    pub fn is_truthy(&self, value: &LiteralValue) -> bool {
         match value {
             LiteralValue::Nil => false,
             LiteralValue::Boolean(b) => *b,
@@ -266,22 +1475,107 @@ impl Interpreter {
         }
     }
 
-    fn is_equal(&self, a: &LiteralValue, b: &LiteralValue) -> bool {
+    pub fn is_equal(&self, a: &LiteralValue, b: &LiteralValue) -> bool {
         match (a, b) {
             (LiteralValue::Nil, LiteralValue::Nil) => true,
             (LiteralValue::Number(na), LiteralValue::Number(nb)) => na == nb,
-            (LiteralValue::String(sa), LiteralValue::String(sb)) => sa == sb,
+            (LiteralValue::String(sa), LiteralValue::String(sb)) => Rc::ptr_eq(sa, sb) || sa == sb,
             (LiteralValue::Boolean(ba), LiteralValue::Boolean(bb)) => ba == bb,
+            (LiteralValue::NativeFunction(fa), LiteralValue::NativeFunction(fb)) => {
+                Rc::ptr_eq(fa, fb)
+            }
+            (LiteralValue::Function(fa), LiteralValue::Function(fb)) => Rc::ptr_eq(fa, fb),
+            (LiteralValue::Closure(ca), LiteralValue::Closure(cb)) => Rc::ptr_eq(ca, cb),
+            (LiteralValue::List(la), LiteralValue::List(lb)) => Rc::ptr_eq(la, lb),
+            (LiteralValue::Map(ma), LiteralValue::Map(mb)) => Rc::ptr_eq(ma, mb),
+            (LiteralValue::Namespace(na), LiteralValue::Namespace(nb)) => Rc::ptr_eq(na, nb),
+            (LiteralValue::Buffer(ba), LiteralValue::Buffer(bb)) => Rc::ptr_eq(ba, bb),
+            (LiteralValue::Bytes(ba), LiteralValue::Bytes(bb)) => Rc::ptr_eq(ba, bb),
+            (LiteralValue::Thread(ta), LiteralValue::Thread(tb)) => Rc::ptr_eq(ta, tb),
+            (LiteralValue::Endpoint(ea), LiteralValue::Endpoint(eb)) => Rc::ptr_eq(ea, eb),
             _ => false,
         }
     }
 
-    fn stringify(&self, value: LiteralValue) -> String {
-        match value {
+    pub fn stringify(&self, value: LiteralValue) -> String {
+        self.stringify_nested(value, &mut HashSet::new())
+    }
+
+    // `parents` holds the lists and maps being printed around this value. A
+    // list or map that contains itself prints as `[...]` or `{...}` where
+    // it recurs.
+    fn stringify_nested(&self, value: LiteralValue, parents: &mut HashSet<*const ()>) -> String {
+        match &value {
             LiteralValue::Nil => "nil".to_string(),
             LiteralValue::Number(n) => format!("{}", n),
-            LiteralValue::String(s) => s,
+            LiteralValue::String(s) => s.to_string(),
             LiteralValue::Boolean(b) => format!("{}", b),
+            LiteralValue::NativeFunction(function) => format!("<native fn {}>", function.name),
+            LiteralValue::Function(function) => format!("<fn {}>", function.name.lexeme),
+            LiteralValue::Closure(closure) => format!("<fn {}>", closure.function.name),
+            LiteralValue::List(items) => {
+                let pointer = Rc::as_ptr(items) as *const ();
+                if !parents.insert(pointer) {
+                    return "[...]".to_string();
+                }
+                let items: Vec<String> = items
+                    .borrow()
+                    .iter()
+                    .map(|item| stack::descend(|| self.stringify_nested(item.clone(), parents)))
+                    .collect();
+                parents.remove(&pointer);
+                format!("[{}]", items.join(", "))
+            }
+            LiteralValue::Map(map) => {
+                let pointer = Rc::as_ptr(map) as *const ();
+                if !parents.insert(pointer) {
+                    return "{...}".to_string();
+                }
+                let entries: Vec<String> = map
+                    .borrow()
+                    .iter()
+                    .map(|(key, value)| {
+                        let value =
+                            stack::descend(|| self.stringify_nested(value.clone(), parents));
+                        format!("{}: {}", key, value)
+                    })
+                    .collect();
+                parents.remove(&pointer);
+                format!("{{{}}}", entries.join(", "))
+            }
+            LiteralValue::Namespace(namespace) => format!("<namespace {}>", namespace.name),
+            LiteralValue::Buffer(buffer) => buffer.borrow().clone(),
+            LiteralValue::Bytes(bytes) => {
+                format!("<bytes {}>", encoding::hex_encode(&bytes.borrow()))
+            }
+            LiteralValue::Thread(thread) => format!("<thread {}>", thread.name),
+            LiteralValue::Endpoint(endpoint) => endpoint.to_string(),
         }
     }
 }
+
+// A token on the line a statement starts on, to report an error that is
+// about the statement as a whole at, since not every statement keeps one.
+fn at_statement(ast: &Ast, statement: StmtId) -> Token {
+    let line = trace::line(ast, statement).unwrap_or(0);
+    Token::new(TokenType::Eof, String::new(), None, line, 0)
+}
+
+// For an operator where the parser never puts one of its kind, as a
+// program the host built itself might have.
+fn not_an_operator(operator: &Token, kind: &str) -> RuntimeError {
+    let message = format!("'{}' is not a {} operator.", operator.lexeme, kind);
+    RuntimeError::Unsupported(operator.clone(), message)
+}
+
+// Whether a value is a list, map or bytes that nothing else holds, as one a
+// native has just made is.
+fn is_new(value: &LiteralValue) -> bool {
+    match value {
+        LiteralValue::List(items) => Rc::strong_count(items) == 1,
+        LiteralValue::Map(map) => Rc::strong_count(map) == 1,
+        LiteralValue::Buffer(buffer) => Rc::strong_count(buffer) == 1,
+        LiteralValue::Bytes(bytes) => Rc::strong_count(bytes) == 1,
+        _ => false,
+    }
+}
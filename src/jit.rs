@@ -0,0 +1,539 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use cranelift_codegen::entity::EntityRef;
+use cranelift_codegen::ir::condcodes::{FloatCC, IntCC};
+use cranelift_codegen::ir::{
+    types, AbiParam, Block, InstBuilder, MemFlags, StackSlotData, StackSlotKind,
+};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Module};
+
+use crate::{
+    compiler::{Function, Op},
+    interpreter::Interpreter,
+    log,
+    scanner::{LiteralValue, Token, TokenType},
+    vm::{Accelerator, Closure},
+};
+
+// A JIT for `--backend=jit`, in builds with the `jit` feature. Scripts run
+// on the VM, which hands calls of compiled functions here; once a function
+// has been called `THRESHOLD` times, its bytecode is compiled to machine
+// code with Cranelift, and later calls run that instead.
+//
+// Only functions that work on numbers alone are compiled for now: ones
+// whose arguments, locals and results are numbers or booleans, and which
+// call nothing but themselves, by name. Such a function cannot print,
+// change a variable outside it or tell it was run twice, so when compiled
+// code meets something it does not handle, such as dividing by zero or
+// recursing very deep, it gives up and the VM makes the whole call again,
+// failing as it should. Every other function, and a call with an argument
+// that is not a number, is left to the VM.
+//
+// Compiled code does not check for an `InterruptHandle` being cancelled;
+// the script stops once the call returns to the VM.
+//
+// The scripts in `benches/` show the difference:
+//
+//     interprete --backend=vm bench benches/arithmetic.lox
+//     interprete --backend=jit bench benches/arithmetic.lox
+
+// How many times a function is called on the VM before it is compiled.
+const THRESHOLD: u32 = 50;
+
+// How deep compiled code may call itself before leaving the call to the
+// VM, whose frames are not on the machine's stack.
+const MAX_DEPTH: i64 = 10_000;
+
+// Compiled code takes its arguments, how deep it is called, and where to
+// put its result. It gives 0, or 1 if the VM must make the call instead.
+type Code = unsafe extern "C" fn(*const f64, i64, *mut f64) -> u8;
+
+pub struct Jit {
+    module: JITModule,
+    // By address; each entry keeps its function alive, so that the address
+    // is not reused.
+    functions: HashMap<*const Function, Entry>,
+}
+
+struct Entry {
+    function: Rc<Function>,
+    state: State,
+}
+
+enum State {
+    // Called this many times so far.
+    Counting(u32),
+    Compiled(Compiled),
+    Unsupported,
+}
+
+struct Compiled {
+    code: Code,
+    returns: Kind,
+    // Whether the function calls itself by name, which compiled code
+    // assumes still refers to it.
+    recursive: bool,
+}
+
+// What a value on the VM's stack is, in a function that can be compiled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Number,
+    Boolean,
+    // The function itself, about to be called.
+    Itself,
+}
+
+impl Jit {
+    pub fn new() -> Result<Jit, String> {
+        let builder = JITBuilder::with_flags(&[("opt_level", "speed")], default_libcall_names())
+            .map_err(|error| format!("The JIT cannot start: {}", error))?;
+        Ok(Jit {
+            module: JITModule::new(builder),
+            functions: HashMap::new(),
+        })
+    }
+}
+
+impl Accelerator for Jit {
+    fn call(
+        &mut self,
+        interpreter: &Interpreter,
+        closure: &Rc<Closure>,
+        arguments: &[LiteralValue],
+    ) -> Option<LiteralValue> {
+        let function = &closure.function;
+        let entry = self
+            .functions
+            .entry(Rc::as_ptr(function))
+            .or_insert_with(|| Entry {
+                function: Rc::clone(function),
+                state: State::Counting(0),
+            });
+        if let State::Counting(calls) = &mut entry.state {
+            *calls += 1;
+            if *calls < THRESHOLD {
+                return None;
+            }
+            entry.state = match compile(&mut self.module, &entry.function) {
+                Ok(compiled) => {
+                    log::info(format_args!("Compiled '{}' to machine code", function.name));
+                    State::Compiled(compiled)
+                }
+                Err(reason) => {
+                    log::debug(format_args!(
+                        "Not compiling '{}': {}",
+                        function.name, reason
+                    ));
+                    State::Unsupported
+                }
+            };
+        }
+        let State::Compiled(compiled) = &entry.state else {
+            return None;
+        };
+        let mut numbers = Vec::with_capacity(arguments.len());
+        for argument in arguments {
+            let LiteralValue::Number(n) = argument else {
+                return None;
+            };
+            numbers.push(*n);
+        }
+        if compiled.recursive && !refers_to(interpreter, function) {
+            return None;
+        }
+        let mut result = 0.0;
+        // SAFETY: the code was compiled with the signature of `Code`, and
+        // takes as many arguments as there are numbers.
+        let status = unsafe { (compiled.code)(numbers.as_ptr(), 0, &mut result) };
+        if status != 0 {
+            return None;
+        }
+        Some(match compiled.returns {
+            Kind::Boolean => LiteralValue::Boolean(result != 0.0),
+            _ => LiteralValue::Number(result),
+        })
+    }
+}
+
+// Whether the function's name still refers to it among the globals.
+fn refers_to(interpreter: &Interpreter, function: &Rc<Function>) -> bool {
+    let name = Token::new(TokenType::Identifier, function.name.clone(), None, 0, 0);
+    matches!(
+        interpreter.global(&name),
+        Ok(LiteralValue::Closure(ref closure)) if Rc::ptr_eq(&closure.function, function)
+    )
+}
+
+// The kinds of the values on the stack before each instruction that can
+// run, following the code from its start with numbers as arguments and
+// assuming the function returns `returns`, or why it cannot be compiled.
+fn analyze(function: &Function, returns: Kind) -> Result<Vec<Option<Vec<Kind>>>, String> {
+    let chunk = &function.chunk;
+    let mut kinds: Vec<Option<Vec<Kind>>> = vec![None; chunk.code.len()];
+    let mut pending = vec![(0, vec![Kind::Number; function.arity])];
+    while let Some((ip, mut stack)) = pending.pop() {
+        match &kinds[ip] {
+            Some(seen) if *seen == stack => continue,
+            Some(_) => return Err("values of different kinds meet".to_string()),
+            None => kinds[ip] = Some(stack.clone()),
+        }
+        let pop = |stack: &mut Vec<Kind>| stack.pop().expect("the compiler balances the stack");
+        let numbers = |stack: &mut Vec<Kind>| match (pop(stack), pop(stack)) {
+            (Kind::Number, Kind::Number) => Ok(()),
+            _ => Err("it does arithmetic on values other than numbers".to_string()),
+        };
+        let op = chunk.code[ip];
+        let mut next = vec![ip + 1];
+        match op {
+            Op::Constant(index) => match chunk.constants[index as usize] {
+                LiteralValue::Number(_) => stack.push(Kind::Number),
+                _ => return Err("it has constants other than numbers".to_string()),
+            },
+            Op::True | Op::False => stack.push(Kind::Boolean),
+            Op::Pop => {
+                pop(&mut stack);
+            }
+            Op::GetLocal(slot) => stack.push(stack[slot as usize]),
+            Op::SetLocal(slot) => {
+                if stack[slot as usize] != stack[stack.len() - 1] {
+                    return Err("a variable holds values of different kinds".to_string());
+                }
+            }
+            Op::GetGlobal(name) => {
+                let name = &chunk.tokens[name as usize].lexeme;
+                if *name != function.name {
+                    return Err(format!("it uses the global '{}'", name));
+                }
+                stack.push(Kind::Itself);
+            }
+            Op::Call { arguments, .. } => {
+                let start = stack.len() - arguments as usize;
+                if stack[start - 1] != Kind::Itself || arguments as usize != function.arity {
+                    return Err("it calls something other than itself".to_string());
+                }
+                if stack[start..].iter().any(|&kind| kind != Kind::Number) {
+                    return Err("it calls itself with values other than numbers".to_string());
+                }
+                stack.truncate(start - 1);
+                stack.push(returns);
+            }
+            Op::Add(_) | Op::Subtract(_) | Op::Multiply(_) => {
+                numbers(&mut stack)?;
+                stack.push(Kind::Number);
+            }
+            Op::Less(_) | Op::Greater(_) => {
+                numbers(&mut stack)?;
+                stack.push(Kind::Boolean);
+            }
+            Op::Binary(operator) => match chunk.tokens[operator as usize].token_type {
+                TokenType::Slash => {
+                    numbers(&mut stack)?;
+                    stack.push(Kind::Number);
+                }
+                TokenType::GreaterEqual | TokenType::LessEqual => {
+                    numbers(&mut stack)?;
+                    stack.push(Kind::Boolean);
+                }
+                TokenType::EqualEqual | TokenType::BangEqual => {
+                    let (right, left) = (pop(&mut stack), pop(&mut stack));
+                    if left != right || left == Kind::Itself {
+                        return Err("it compares values of different kinds".to_string());
+                    }
+                    stack.push(Kind::Boolean);
+                }
+                _ => return Err(format!("it has the instruction {:?}", op)),
+            },
+            Op::Negate(_) | Op::Not => {
+                let kind = match op {
+                    Op::Not => Kind::Boolean,
+                    _ => Kind::Number,
+                };
+                if pop(&mut stack) != kind {
+                    return Err("it negates values of another kind".to_string());
+                }
+                stack.push(kind);
+            }
+            Op::Jump(target) => next = vec![target as usize],
+            Op::JumpIfFalse(target) | Op::JumpIfTrue(target) => {
+                if stack.last() != Some(&Kind::Boolean) {
+                    return Err("it tests values other than booleans".to_string());
+                }
+                next.push(target as usize);
+            }
+            Op::Return => {
+                if pop(&mut stack) != returns {
+                    return Err("it returns values of different kinds".to_string());
+                }
+                next.clear();
+            }
+            _ => return Err(format!("it has the instruction {:?}", op)),
+        }
+        pending.extend(next.into_iter().map(|ip| (ip, stack.clone())));
+    }
+    Ok(kinds)
+}
+
+fn compile(module: &mut JITModule, function: &Function) -> Result<Compiled, String> {
+    let (returns, kinds) = match analyze(function, Kind::Number) {
+        Ok(kinds) => (Kind::Number, kinds),
+        Err(reason) => (
+            Kind::Boolean,
+            analyze(function, Kind::Boolean).map_err(|_| reason)?,
+        ),
+    };
+    let code = &function.chunk.code;
+    let recursive = code
+        .iter()
+        .zip(&kinds)
+        .any(|(op, kinds)| matches!(op, Op::GetGlobal(_)) && kinds.is_some());
+
+    let pointer = module.target_config().pointer_type();
+    let mut signature = module.make_signature();
+    signature.params.push(AbiParam::new(pointer));
+    signature.params.push(AbiParam::new(types::I64));
+    signature.params.push(AbiParam::new(pointer));
+    signature.returns.push(AbiParam::new(types::I8));
+    let id = module
+        .declare_anonymous_function(&signature)
+        .map_err(|error| error.to_string())?;
+    let mut context = module.make_context();
+    context.func.signature = signature;
+    let mut builder_context = FunctionBuilderContext::new();
+    let mut builder = FunctionBuilder::new(&mut context.func, &mut builder_context);
+    let itself = module.declare_func_in_func(id, builder.func);
+
+    // Each place on the stack is a pair of variables, for a number or a
+    // boolean, and Cranelift works out where their values come from when
+    // code jumps.
+    let height = kinds.iter().flatten().map(Vec::len).max().unwrap_or(0) + 1;
+    let variable =
+        |slot: usize, kind: Kind| Variable::new(slot * 2 + (kind == Kind::Boolean) as usize);
+    for slot in 0..height {
+        builder.declare_var(variable(slot, Kind::Number), types::F64);
+        builder.declare_var(variable(slot, Kind::Boolean), types::I8);
+    }
+    // A block starts at every instruction that is jumped to or follows a
+    // jump.
+    let mut blocks: Vec<Option<Block>> = vec![None; code.len()];
+    blocks[0] = Some(builder.create_block());
+    for (ip, op) in code.iter().enumerate() {
+        let targets = match *op {
+            Op::Jump(target) | Op::JumpIfFalse(target) | Op::JumpIfTrue(target) => {
+                vec![target as usize, ip + 1]
+            }
+            Op::Return => vec![ip + 1],
+            _ => continue,
+        };
+        for target in targets {
+            if kinds.get(target).is_some_and(Option::is_some) && blocks[target].is_none() {
+                blocks[target] = Some(builder.create_block());
+            }
+        }
+    }
+    let block = |ip: usize| blocks[ip].expect("jumps go to the start of a block");
+
+    let entry = builder.create_block();
+    let bail = builder.create_block();
+    builder.append_block_params_for_function_params(entry);
+    builder.switch_to_block(entry);
+    let [arguments, depth, result] = builder.block_params(entry) else {
+        unreachable!("the signature has three parameters")
+    };
+    let (arguments, depth, result) = (*arguments, *depth, *result);
+    for slot in 0..function.arity {
+        let offset = (slot * 8) as i32;
+        let argument = builder
+            .ins()
+            .load(types::F64, MemFlags::trusted(), arguments, offset);
+        builder.def_var(variable(slot, Kind::Number), argument);
+    }
+    let too_deep = builder
+        .ins()
+        .icmp_imm(IntCC::SignedGreaterThan, depth, MAX_DEPTH);
+    builder.ins().brif(too_deep, bail, &[], block(0), &[]);
+
+    let mut ended = true;
+    for (ip, stack) in kinds.iter().enumerate() {
+        let Some(stack) = stack else {
+            continue;
+        };
+        if let Some(start) = blocks[ip] {
+            if !ended {
+                builder.ins().jump(start, &[]);
+            }
+            builder.switch_to_block(start);
+            ended = false;
+        }
+        let top = stack.len();
+        // Copies a value between places on the stack, if it has one.
+        let copy = |builder: &mut FunctionBuilder, from: usize, to: usize| {
+            let kind = stack[from];
+            if kind != Kind::Itself {
+                let value = builder.use_var(variable(from, kind));
+                builder.def_var(variable(to, kind), value);
+            }
+        };
+        let operands = |builder: &mut FunctionBuilder, kind: Kind| {
+            let left = builder.use_var(variable(top - 2, kind));
+            let right = builder.use_var(variable(top - 1, kind));
+            (left, right)
+        };
+        match code[ip] {
+            Op::Constant(index) => {
+                let LiteralValue::Number(n) = function.chunk.constants[index as usize] else {
+                    unreachable!("only numbers are compiled")
+                };
+                let value = builder.ins().f64const(n);
+                builder.def_var(variable(top, Kind::Number), value);
+            }
+            Op::True | Op::False => {
+                let value = builder
+                    .ins()
+                    .iconst(types::I8, matches!(code[ip], Op::True) as i64);
+                builder.def_var(variable(top, Kind::Boolean), value);
+            }
+            Op::Pop | Op::GetGlobal(_) => {}
+            Op::GetLocal(slot) => copy(&mut builder, slot as usize, top),
+            Op::SetLocal(slot) => copy(&mut builder, top - 1, slot as usize),
+            Op::Call { arguments, .. } => {
+                let start = top - arguments as usize;
+                let size = arguments.max(1) * 8;
+                let slot = StackSlotData::new(StackSlotKind::ExplicitSlot, size, 3);
+                let slot = builder.create_sized_stack_slot(slot);
+                for (index, place) in (start..top).enumerate() {
+                    let argument = builder.use_var(variable(place, Kind::Number));
+                    builder
+                        .ins()
+                        .stack_store(argument, slot, (index * 8) as i32);
+                }
+                let slot_for_result = StackSlotData::new(StackSlotKind::ExplicitSlot, 8, 3);
+                let slot_for_result = builder.create_sized_stack_slot(slot_for_result);
+                let arguments = builder.ins().stack_addr(pointer, slot, 0);
+                let result = builder.ins().stack_addr(pointer, slot_for_result, 0);
+                let deeper = builder.ins().iadd_imm(depth, 1);
+                let call = builder.ins().call(itself, &[arguments, deeper, result]);
+                let status = builder.inst_results(call)[0];
+                let returned = builder.create_block();
+                builder.ins().brif(status, bail, &[], returned, &[]);
+                builder.switch_to_block(returned);
+                let mut value = builder.ins().stack_load(types::F64, slot_for_result, 0);
+                if returns == Kind::Boolean {
+                    let zero = builder.ins().f64const(0.0);
+                    value = builder.ins().fcmp(FloatCC::NotEqual, value, zero);
+                }
+                builder.def_var(variable(start - 1, returns), value);
+            }
+            Op::Add(_) | Op::Subtract(_) | Op::Multiply(_) => {
+                let (left, right) = operands(&mut builder, Kind::Number);
+                let value = match code[ip] {
+                    Op::Add(_) => builder.ins().fadd(left, right),
+                    Op::Subtract(_) => builder.ins().fsub(left, right),
+                    _ => builder.ins().fmul(left, right),
+                };
+                builder.def_var(variable(top - 2, Kind::Number), value);
+            }
+            Op::Less(_) | Op::Greater(_) => {
+                let (left, right) = operands(&mut builder, Kind::Number);
+                let condition = match code[ip] {
+                    Op::Less(_) => FloatCC::LessThan,
+                    _ => FloatCC::GreaterThan,
+                };
+                let value = builder.ins().fcmp(condition, left, right);
+                builder.def_var(variable(top - 2, Kind::Boolean), value);
+            }
+            Op::Binary(operator) => {
+                let kind = stack[top - 1];
+                let (left, right) = operands(&mut builder, kind);
+                let condition = match function.chunk.tokens[operator as usize].token_type {
+                    TokenType::Slash => {
+                        // Division by zero is an error, which the VM gives.
+                        let zero = builder.ins().f64const(0.0);
+                        let by_zero = builder.ins().fcmp(FloatCC::Equal, right, zero);
+                        let divide = builder.create_block();
+                        builder.ins().brif(by_zero, bail, &[], divide, &[]);
+                        builder.switch_to_block(divide);
+                        let value = builder.ins().fdiv(left, right);
+                        builder.def_var(variable(top - 2, Kind::Number), value);
+                        continue;
+                    }
+                    TokenType::GreaterEqual => FloatCC::GreaterThanOrEqual,
+                    TokenType::LessEqual => FloatCC::LessThanOrEqual,
+                    TokenType::EqualEqual => FloatCC::Equal,
+                    _ => FloatCC::NotEqual,
+                };
+                let value = if kind == Kind::Boolean {
+                    let condition = match condition {
+                        FloatCC::Equal => IntCC::Equal,
+                        _ => IntCC::NotEqual,
+                    };
+                    builder.ins().icmp(condition, left, right)
+                } else {
+                    builder.ins().fcmp(condition, left, right)
+                };
+                builder.def_var(variable(top - 2, Kind::Boolean), value);
+            }
+            Op::Negate(_) => {
+                let value = builder.use_var(variable(top - 1, Kind::Number));
+                let value = builder.ins().fneg(value);
+                builder.def_var(variable(top - 1, Kind::Number), value);
+            }
+            Op::Not => {
+                let value = builder.use_var(variable(top - 1, Kind::Boolean));
+                let value = builder.ins().icmp_imm(IntCC::Equal, value, 0);
+                builder.def_var(variable(top - 1, Kind::Boolean), value);
+            }
+            Op::Jump(target) => {
+                builder.ins().jump(block(target as usize), &[]);
+                ended = true;
+            }
+            Op::JumpIfFalse(target) | Op::JumpIfTrue(target) => {
+                let condition = builder.use_var(variable(top - 1, Kind::Boolean));
+                let (target, next) = (block(target as usize), block(ip + 1));
+                if let Op::JumpIfTrue(_) = code[ip] {
+                    builder.ins().brif(condition, target, &[], next, &[]);
+                } else {
+                    builder.ins().brif(condition, next, &[], target, &[]);
+                }
+                ended = true;
+            }
+            Op::Return => {
+                let mut value = builder.use_var(variable(top - 1, returns));
+                if returns == Kind::Boolean {
+                    let one = builder.ins().f64const(1.0);
+                    let zero = builder.ins().f64const(0.0);
+                    value = builder.ins().select(value, one, zero);
+                }
+                builder.ins().store(MemFlags::trusted(), value, result, 0);
+                let ok = builder.ins().iconst(types::I8, 0);
+                builder.ins().return_(&[ok]);
+                ended = true;
+            }
+            _ => unreachable!("`analyze` only lets through what is compiled"),
+        }
+    }
+    builder.switch_to_block(bail);
+    let failed = builder.ins().iconst(types::I8, 1);
+    builder.ins().return_(&[failed]);
+    builder.seal_all_blocks();
+    builder.finalize();
+
+    module
+        .define_function(id, &mut context)
+        .map_err(|error| error.to_string())?;
+    module.clear_context(&mut context);
+    module
+        .finalize_definitions()
+        .map_err(|error| error.to_string())?;
+    let address = module.get_finalized_function(id);
+    // SAFETY: the function was compiled with the signature of `Code`.
+    let code = unsafe { std::mem::transmute::<*const u8, Code>(address) };
+    Ok(Compiled {
+        code,
+        returns,
+        recursive,
+    })
+}
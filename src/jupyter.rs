@@ -0,0 +1,469 @@
+use std::fs;
+use std::io;
+use std::net::TcpListener;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use interprete::{
+    encoding,
+    engine::Engine,
+    hash,
+    interpreter::Setup,
+    json::{self, object, JsonValue},
+    log,
+    parser::Parser,
+    random::Random,
+    scanner::{ParseError, Scanner, TokenType},
+    InterpreterError,
+};
+
+use crate::zmtp::Socket;
+
+// A Jupyter kernel for `interprete jupyter CONNECTION_FILE`, so scripts can
+// be written and run in notebooks. Jupyter starts the kernel with a file
+// naming the ports to listen on and the key to sign messages with, once a
+// kernel spec such as this one is installed as `kernel.json` in a
+// `kernels/lox` directory where Jupyter looks:
+//
+//     {"argv": ["interprete", "jupyter", "{connection_file}"],
+//      "display_name": "Lox", "language": "lox"}
+//
+// Every cell runs in the same interpreter, so later cells see what earlier
+// ones declared. What a cell prints is sent to the notebook as it would be
+// to the terminal, and a cell holding a bare expression has its value shown
+// as the cell's result, as in the REPL.
+
+const PROTOCOL_VERSION: &str = "5.3";
+const DELIMITER: &[u8] = b"<IDS|MSG>";
+
+// Serves the notebook until it asks the kernel to shut down, and gives the
+// status to exit with.
+pub fn serve(connection_file: &str, setup: &Setup) -> i32 {
+    match run(connection_file, setup) {
+        Ok(()) => 0,
+        Err(error) => {
+            eprintln!("{}", error);
+            match error.kind() {
+                io::ErrorKind::NotFound => 66,
+                _ => 70,
+            }
+        }
+    }
+}
+
+fn run(connection_file: &str, setup: &Setup) -> io::Result<()> {
+    let text = fs::read_to_string(connection_file).map_err(|error| {
+        let message = format!("Error reading file '{}': {}", connection_file, error);
+        io::Error::new(error.kind(), message)
+    })?;
+    let connection = json::parse(&text).map_err(|error| invalid(&error.to_string()))?;
+    let string = |key: &str| connection.get(key).and_then(JsonValue::as_str);
+    let ip = string("ip").unwrap_or("127.0.0.1");
+    let bind = |key: &str| {
+        let port = connection
+            .get(key)
+            .and_then(JsonValue::as_f64)
+            .unwrap_or(0.0);
+        TcpListener::bind((ip, port as u16))
+    };
+
+    // Shell and control requests come to this thread, which owns the
+    // interpreter; the other sockets are served on threads of their own.
+    let (requests, incoming) = mpsc::channel();
+    accept(bind("shell_port")?, "ROUTER", Some(requests.clone()));
+    accept(bind("control_port")?, "ROUTER", Some(requests));
+    accept(bind("stdin_port")?, "ROUTER", None);
+    heartbeat(bind("hb_port")?);
+    let subscribers = Arc::new(Mutex::new(Vec::new()));
+    publish_to(bind("iopub_port")?, Arc::clone(&subscribers));
+
+    let mut engine = setup.engine().map_err(|error| invalid(&error))?;
+    engine.interpreter_mut().capture_output();
+    let mut random = Random::from_time();
+    let mut kernel = Kernel {
+        engine,
+        key: string("key").unwrap_or("").as_bytes().to_vec(),
+        session: encoding::hex_encode(&random.next_u64().to_be_bytes()),
+        random,
+        subscribers,
+        execution_count: 0,
+    };
+    log::info(format_args!(
+        "Serving the kernel described in '{}'",
+        connection_file
+    ));
+    for (frames, mut reply) in incoming {
+        let Some(request) = kernel.parse(frames) else {
+            log::info(format_args!(
+                "Ignoring a message that is not signed properly"
+            ));
+            continue;
+        };
+        if !kernel.handle(&request, &mut reply) {
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+// Accepts connections to a socket and reads each one's messages on a
+// thread of its own, sending them with a way to reply to `requests`, or
+// dropping them if there are none to send them to.
+fn accept(
+    listener: TcpListener,
+    socket_type: &'static str,
+    requests: Option<Sender<(Vec<Vec<u8>>, Socket)>>,
+) {
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let requests = requests.clone();
+            thread::spawn(move || -> io::Result<()> {
+                let mut socket = Socket::accept(stream, socket_type)?;
+                loop {
+                    let frames = socket.receive()?;
+                    if let Some(requests) = &requests {
+                        let reply = socket.try_clone()?;
+                        if requests.send((frames, reply)).is_err() {
+                            return Ok(());
+                        }
+                    }
+                }
+            });
+        }
+    });
+}
+
+// Echoes back whatever the notebook sends, so it can tell the kernel is
+// alive even while a cell runs.
+fn heartbeat(listener: TcpListener) {
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            thread::spawn(move || -> io::Result<()> {
+                let mut socket = Socket::accept(stream, "REP")?;
+                loop {
+                    let frames = socket.receive()?;
+                    socket.send(&frames)?;
+                }
+            });
+        }
+    });
+}
+
+fn publish_to(listener: TcpListener, subscribers: Arc<Mutex<Vec<Socket>>>) {
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            // Subscriptions are not read: every subscriber gets everything.
+            if let Ok(socket) = Socket::accept(stream, "PUB") {
+                subscribers
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .push(socket);
+            }
+        }
+    });
+}
+
+// A message from the notebook, split into its parts.
+struct Request {
+    // The routing frames before the delimiter, sent back with the reply.
+    identities: Vec<Vec<u8>>,
+    // The header as sent, which is the parent header of every message
+    // about this one.
+    raw_header: Vec<u8>,
+    msg_type: String,
+    content: JsonValue,
+}
+
+struct Kernel {
+    engine: Box<dyn Engine>,
+    key: Vec<u8>,
+    session: String,
+    random: Random,
+    subscribers: Arc<Mutex<Vec<Socket>>>,
+    execution_count: usize,
+}
+
+impl Kernel {
+    // Splits a message into its parts, or gives `None` if its signature is
+    // wrong or it is malformed.
+    fn parse(&self, mut frames: Vec<Vec<u8>>) -> Option<Request> {
+        let delimiter = frames.iter().position(|frame| frame == DELIMITER)?;
+        let parts = frames.split_off(delimiter);
+        let [_, signature, header, parent, metadata, content, ..] = &parts[..] else {
+            return None;
+        };
+        if self.sign(&[header, parent, metadata, content]).as_bytes() != &signature[..] {
+            return None;
+        }
+        let json = |bytes: &[u8]| json::parse(std::str::from_utf8(bytes).ok()?).ok();
+        let msg_type = json(header)?.get("msg_type")?.as_str()?.to_string();
+        Some(Request {
+            identities: frames,
+            raw_header: header.clone(),
+            msg_type,
+            content: json(content)?,
+        })
+    }
+
+    // Handles a request, and gives whether to carry on.
+    fn handle(&mut self, request: &Request, reply: &mut Socket) -> bool {
+        log::debug(format_args!("Handling '{}'", request.msg_type));
+        self.publish(
+            request,
+            "status",
+            object(vec![("execution_state", string("busy"))]),
+        );
+        let (reply_type, content) = match request.msg_type.as_str() {
+            "kernel_info_request" => ("kernel_info_reply", self.kernel_info()),
+            "execute_request" => ("execute_reply", self.execute(request)),
+            "is_complete_request" => {
+                let code = request.content.get("code").and_then(JsonValue::as_str);
+                let status = completeness(code.unwrap_or(""));
+                (
+                    "is_complete_reply",
+                    object(vec![("status", string(status))]),
+                )
+            }
+            "comm_info_request" => (
+                "comm_info_reply",
+                object(vec![("status", string("ok")), ("comms", object(vec![]))]),
+            ),
+            // A cell cannot be stopped part way, so this only acknowledges
+            // the request.
+            "interrupt_request" => ("interrupt_reply", object(vec![("status", string("ok"))])),
+            "shutdown_request" => {
+                let restart = request
+                    .content
+                    .get("restart")
+                    .cloned()
+                    .unwrap_or(JsonValue::Bool(false));
+                let content = object(vec![("status", string("ok")), ("restart", restart)]);
+                self.send(reply, request, "shutdown_reply", content);
+                return false;
+            }
+            other => {
+                log::info(format_args!("Ignoring an unknown request '{}'", other));
+                self.publish(
+                    request,
+                    "status",
+                    object(vec![("execution_state", string("idle"))]),
+                );
+                return true;
+            }
+        };
+        self.send(reply, request, reply_type, content);
+        self.publish(
+            request,
+            "status",
+            object(vec![("execution_state", string("idle"))]),
+        );
+        true
+    }
+
+    fn kernel_info(&self) -> JsonValue {
+        let version = string(env!("CARGO_PKG_VERSION"));
+        object(vec![
+            ("status", string("ok")),
+            ("protocol_version", string(PROTOCOL_VERSION)),
+            ("implementation", string("interprete")),
+            ("implementation_version", version.clone()),
+            (
+                "language_info",
+                object(vec![
+                    ("name", string("lox")),
+                    ("version", version),
+                    ("mimetype", string("text/x-lox")),
+                    ("file_extension", string(".lox")),
+                ]),
+            ),
+            ("banner", string("interprete, a Lox interpreter")),
+            ("help_links", JsonValue::Array(Vec::new())),
+        ])
+    }
+
+    // Runs a cell, publishing what it prints and its value or error, and
+    // gives the content of the reply.
+    fn execute(&mut self, request: &Request) -> JsonValue {
+        let code = request.content.get("code").and_then(JsonValue::as_str);
+        let code = code.unwrap_or("").to_string();
+        let silent = matches!(request.content.get("silent"), Some(JsonValue::Bool(true)));
+        if !silent {
+            self.execution_count += 1;
+        }
+        let count = JsonValue::Number(self.execution_count as f64);
+        self.publish(
+            request,
+            "execute_input",
+            object(vec![
+                ("code", string(&code)),
+                ("execution_count", count.clone()),
+            ]),
+        );
+
+        let result = interprete::evaluate(self.engine.as_mut(), code.clone());
+        let output = self.engine.interpreter_mut().take_output();
+        if !output.is_empty() && !silent {
+            let content = object(vec![("name", string("stdout")), ("text", string(&output))]);
+            self.publish(request, "stream", content);
+        }
+        match result {
+            Ok(value) => {
+                if let Some(value) = value.filter(|_| !silent) {
+                    let text = self.engine.interpreter().stringify(value);
+                    let content = object(vec![
+                        ("execution_count", count.clone()),
+                        ("data", object(vec![("text/plain", string(&text))])),
+                        ("metadata", object(vec![])),
+                    ]);
+                    self.publish(request, "execute_result", content);
+                }
+                object(vec![
+                    ("status", string("ok")),
+                    ("execution_count", count),
+                    ("payload", JsonValue::Array(Vec::new())),
+                    ("user_expressions", object(vec![])),
+                ])
+            }
+            Err(error) => {
+                let ename = match error {
+                    InterpreterError::Parse(_) => "ParseError",
+                    InterpreterError::Runtime(_) => "RuntimeError",
+                };
+                let diagnostic = error.diagnostic();
+                let traceback = diagnostic.render("<cell>", &code);
+                let content = vec![
+                    ("ename", string(ename)),
+                    ("evalue", string(&diagnostic.message)),
+                    (
+                        "traceback",
+                        JsonValue::Array(traceback.lines().map(string).collect()),
+                    ),
+                ];
+                self.publish(request, "error", object(content.clone()));
+                let mut reply = vec![("status", string("error")), ("execution_count", count)];
+                reply.extend(content);
+                object(reply)
+            }
+        }
+    }
+
+    // Sends a reply to a request on the socket it came in on.
+    fn send(&mut self, socket: &mut Socket, request: &Request, msg_type: &str, content: JsonValue) {
+        let mut frames = request.identities.clone();
+        frames.extend(self.message(request, msg_type, content));
+        if let Err(error) = socket.send(&frames) {
+            log::info(format_args!("Could not send '{}': {}", msg_type, error));
+        }
+    }
+
+    // Sends a message about a request to every subscriber, dropping those
+    // that have gone.
+    fn publish(&mut self, request: &Request, msg_type: &str, content: JsonValue) {
+        let mut frames = vec![msg_type.as_bytes().to_vec()];
+        frames.extend(self.message(request, msg_type, content));
+        let mut subscribers = self.subscribers.lock().unwrap_or_else(|e| e.into_inner());
+        subscribers.retain_mut(|subscriber| subscriber.send(&frames).is_ok());
+    }
+
+    // The frames of a message from the delimiter on, signed.
+    fn message(&mut self, request: &Request, msg_type: &str, content: JsonValue) -> Vec<Vec<u8>> {
+        let msg_id = encoding::hex_encode(&self.random.next_u64().to_be_bytes());
+        let header = object(vec![
+            ("msg_id", string(&msg_id)),
+            ("session", string(&self.session)),
+            ("username", string("kernel")),
+            ("date", string(&now())),
+            ("msg_type", string(msg_type)),
+            ("version", string(PROTOCOL_VERSION)),
+        ]);
+        let header = header.to_string().into_bytes();
+        let metadata = b"{}".to_vec();
+        let content = content.to_string().into_bytes();
+        let signature = self.sign(&[&header, &request.raw_header, &metadata, &content]);
+        vec![
+            DELIMITER.to_vec(),
+            signature.into_bytes(),
+            header,
+            request.raw_header.clone(),
+            metadata,
+            content,
+        ]
+    }
+
+    // The hex HMAC of a message's parts, or nothing when the connection
+    // file gives no key, which turns signing off.
+    fn sign(&self, parts: &[&Vec<u8>]) -> String {
+        if self.key.is_empty() {
+            return String::new();
+        }
+        let data: Vec<u8> = parts.iter().flat_map(|part| part.iter().copied()).collect();
+        encoding::hex_encode(&hash::hmac_sha256(&self.key, &data))
+    }
+}
+
+// Whether a cell is ready to run, for consoles that ask before running
+// what has been typed: "incomplete" if it stops part way through, as in an
+// unclosed block, so more lines should be read.
+fn completeness(code: &str) -> &'static str {
+    let tokens = match Scanner::new(code.to_string()).scan_tokens() {
+        Ok(tokens) => tokens,
+        Err(ParseError::UnterminatedString(..)) => return "incomplete",
+        Err(_) => return "invalid",
+    };
+    if Parser::new(tokens.clone()).parse_expression().is_ok() {
+        return "complete";
+    }
+    match Parser::new(tokens).parse() {
+        Ok(_) => "complete",
+        Err(ParseError::EndOfFile) => "incomplete",
+        Err(ParseError::ExpectedToken(_, token) | ParseError::UnexpectedToken(token, _))
+            if token.is_type(TokenType::Eof) =>
+        {
+            "incomplete"
+        }
+        Err(_) => "invalid",
+    }
+}
+
+// The current time in UTC, in ISO 8601 form.
+fn now() -> String {
+    let elapsed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let seconds = elapsed.as_secs();
+    // Howard Hinnant's conversion of a day count to a civil date.
+    let days = (seconds / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+        year,
+        month,
+        day,
+        seconds / 3600 % 24,
+        seconds / 60 % 60,
+        seconds % 60,
+        elapsed.subsec_micros()
+    )
+}
+
+fn string(s: &str) -> JsonValue {
+    JsonValue::String(s.to_string())
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
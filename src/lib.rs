@@ -0,0 +1,212 @@
+// The interpreter as a library, for Rust programs that run Lox scripts
+// without the command line in `main.rs`. The simplest use is `run`:
+//
+//     interprete::run("print 1 + 2;")?;
+//
+// `Interpreter::eval` gives the host the value of an expression instead:
+//
+//     let mut interpreter = Interpreter::new();
+//     let sum = interpreter.eval("1 + 2")?; // Value::Number(3.0)
+//
+// and `set_global` and `get_global` pass values in and out of scripts:
+//
+//     interpreter.set_global("limit", 10.0);
+//     interpreter.eval("var result = limit * 2;")?;
+//     let result: Option<f64> = interpreter.get_global("result");
+//
+// and `call` calls a function a script declared:
+//
+//     interpreter.eval("fun double(n) { return n * 2; }")?;
+//     let doubled: f64 = interpreter.call("double", &[4.0.into()])?.try_into()?;
+//
+// and a program that keeps an interpreter between scripts scans, parses
+// and interprets them itself:
+//
+//     let tokens = Scanner::new(source).scan_tokens()?;
+//     let program = Parser::new(tokens).parse()?;
+//     interpreter.interpret(&program)?;
+//
+// A parsed program can be shared between threads, and run by an
+// interpreter on each:
+//
+//     let program = Arc::new(interprete::parse(source)?);
+//     for _ in 0..4 {
+//         let program = Arc::clone(&program);
+//         thread::spawn(move || Interpreter::new().interpret(&program));
+//     }
+
+pub mod ast;
+#[cfg(feature = "serde")]
+mod bridge;
+mod builder;
+pub mod capability;
+pub mod check;
+pub mod compiler;
+mod constants;
+pub mod coverage;
+mod debugger;
+pub mod diagnostic;
+pub mod encoding;
+pub mod engine;
+mod environment;
+pub mod expr;
+mod ffi;
+pub mod formatter;
+pub mod function;
+mod gc;
+pub mod hash;
+mod hooks;
+mod http;
+pub mod incremental;
+mod intern;
+pub mod interpreter;
+#[cfg(feature = "jit")]
+mod jit;
+pub mod json;
+mod library;
+pub mod line_editor;
+pub mod log;
+mod map;
+mod memory;
+#[cfg(feature = "nanbox")]
+mod nanbox;
+pub mod natives;
+pub mod parser;
+mod plugin;
+pub mod printer;
+pub mod random;
+pub mod resolver;
+pub mod scanner;
+pub mod serialize;
+pub mod snapshot;
+pub mod stack;
+pub mod stmt;
+mod thread;
+mod trace;
+pub mod value;
+pub mod vm;
+#[cfg(target_arch = "wasm32")]
+mod wasm;
+mod worker;
+
+use std::sync::Arc;
+
+use diagnostic::Diagnostic;
+use engine::Engine;
+use interpreter::RuntimeError;
+use scanner::{LiteralValue, ParseError};
+
+pub use ast::Program;
+pub use builder::InterpreterBuilder;
+pub use hooks::Hooks;
+pub use interpreter::{Interpreter, InterruptHandle, Metrics};
+pub use natives::{FromValue, IntoValue};
+pub use parser::Parser;
+pub use scanner::Scanner;
+pub use value::{ConversionError, Value};
+pub use worker::Worker;
+
+#[cfg(feature = "serde")]
+pub use bridge::{from_value, to_value, Error as SerdeError};
+
+// Define your generic error type
+#[derive(Debug)]
+pub enum InterpreterError {
+    Parse(ParseError),
+    Runtime(RuntimeError),
+}
+
+// Implement the `Display` trait for better error messages
+impl std::fmt::Display for InterpreterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InterpreterError::Parse(err) => write!(f, "Parse error: {}", err),
+            InterpreterError::Runtime(err) => write!(f, "Runtime error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for InterpreterError {}
+
+// Implement `From` trait for automatic conversion
+impl InterpreterError {
+    pub fn diagnostic(&self) -> Diagnostic {
+        match self {
+            InterpreterError::Parse(err) => err.diagnostic(),
+            InterpreterError::Runtime(err) => err.diagnostic(),
+        }
+    }
+
+    // The status to exit with when a script fails with this error.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            InterpreterError::Parse(_) => 65,
+            InterpreterError::Runtime(error) => match error.error() {
+                // As a shell gives a command stopped with Control-C.
+                RuntimeError::Interrupted(_) => 130,
+                _ => 70,
+            },
+        }
+    }
+}
+
+impl From<ParseError> for InterpreterError {
+    fn from(err: ParseError) -> Self {
+        InterpreterError::Parse(err)
+    }
+}
+
+impl From<RuntimeError> for InterpreterError {
+    fn from(err: RuntimeError) -> Self {
+        InterpreterError::Runtime(err)
+    }
+}
+
+pub fn parse(source: String) -> Result<Program, InterpreterError> {
+    let mut scanner = Scanner::new(source);
+    let tokens = scanner.scan_tokens()?;
+    let mut parser = Parser::new(tokens);
+    Ok(parser.parse()?)
+}
+
+// A line holding a single bare expression has its value printed, where
+// scripts print; anything else is run as a sequence of statements.
+pub fn run_line(engine: &mut dyn Engine, line: String) -> Result<(), InterpreterError> {
+    if let Some(value) = evaluate(engine, line)? {
+        let text = engine.interpreter().stringify(value);
+        engine.interpreter_mut().write_output(&format!("{}\n", text));
+    }
+    Ok(())
+}
+
+// Runs source that may be a single bare expression, whose value it gives,
+// or a sequence of statements.
+pub fn evaluate(
+    engine: &mut dyn Engine,
+    source: String,
+) -> Result<Option<LiteralValue>, InterpreterError> {
+    let mut scanner = Scanner::new(source);
+    let tokens = scanner.scan_tokens()?;
+    if let Ok((ast, expr)) = Parser::new(tokens.clone()).parse_expression() {
+        let value = engine.evaluate(&Arc::new(ast), expr)?;
+        return Ok(Some(value));
+    }
+    let program = Parser::new(tokens).parse()?;
+    engine.interpret(&program)?;
+    Ok(None)
+}
+
+// Runs a script in an engine that may have run others before it, keeping
+// what it declares.
+pub fn run_in(engine: &mut dyn Engine, source: String) -> Result<(), InterpreterError> {
+    let program = parse(source)?;
+    engine.interpret(&program)
+}
+
+// Runs a script on its own, in a fresh interpreter with the prelude and the
+// default capabilities, printing what it prints on standard output.
+pub fn run(source: &str) -> Result<(), InterpreterError> {
+    let mut interpreter = Interpreter::new();
+    interpreter.load_prelude();
+    run_in(&mut interpreter, source.to_string())
+}
@@ -0,0 +1,63 @@
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+
+// Finding functions in shared libraries, for plugins and `ffiCall`.
+// Libraries are loaded on first use and never unloaded.
+
+// The address of a symbol in a library. An empty path searches the
+// interpreter's own process, including the C library it links.
+#[cfg(unix)]
+pub fn symbol(path: &str, name: &str) -> Result<*mut c_void, String> {
+    extern "C" {
+        fn dlopen(filename: *const c_char, flags: c_int) -> *mut c_void;
+        fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+        fn dlerror() -> *mut c_char;
+    }
+    const RTLD_NOW: c_int = 2;
+
+    let last_error = || {
+        // SAFETY: `dlerror` returns null or a NUL-terminated message.
+        let message = unsafe { dlerror() };
+        if message.is_null() {
+            "unknown error".to_string()
+        } else {
+            unsafe { CStr::from_ptr(message) }
+                .to_string_lossy()
+                .into_owned()
+        }
+    };
+    let library = if path.is_empty() {
+        "the interpreter".to_string()
+    } else {
+        format!("'{}'", path)
+    };
+    let c_path = CString::new(path).map_err(|_| format!("'{}' is not a valid path.", path))?;
+    let c_name = CString::new(name).map_err(|_| format!("'{}' is not a valid symbol.", name))?;
+    // SAFETY: both strings are NUL-terminated; a null path opens the
+    // process itself.
+    let handle = unsafe {
+        dlopen(
+            if path.is_empty() {
+                std::ptr::null()
+            } else {
+                c_path.as_ptr()
+            },
+            RTLD_NOW,
+        )
+    };
+    if handle.is_null() {
+        return Err(format!("Could not load {}: {}.", library, last_error()));
+    }
+    let address = unsafe { dlsym(handle, c_name.as_ptr()) };
+    if address.is_null() {
+        return Err(format!("There is no symbol '{}' in {}.", name, library));
+    }
+    Ok(address)
+}
+
+#[cfg(not(unix))]
+pub fn symbol(path: &str, _: &str) -> Result<*mut c_void, String> {
+    Err(format!(
+        "Could not load '{}': shared libraries can only be loaded on Unix.",
+        path
+    ))
+}
@@ -0,0 +1,330 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::PathBuf;
+
+use crate::natives;
+
+// Reads lines for the REPL. At a terminal the line can be edited, and the
+// up and down arrows step through the lines entered before, in this session
+// and earlier ones, which are kept in a history file. Anywhere else, such
+// as when input is piped in, lines are read as they are.
+
+// The most lines the history file keeps; older ones are dropped.
+const HISTORY_LIMIT: usize = 1000;
+
+pub struct LineEditor {
+    history: Vec<String>,
+    path: Option<PathBuf>,
+}
+
+impl LineEditor {
+    // Starts an editor, loading its history from `path`, if any.
+    pub fn new(path: Option<PathBuf>) -> Self {
+        let mut history: Vec<String> = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|text| text.lines().map(str::to_string).collect())
+            .unwrap_or_default();
+        if history.len() > HISTORY_LIMIT {
+            history.drain(..history.len() - HISTORY_LIMIT);
+            if let Some(path) = &path {
+                let mut text = history.join("\n");
+                text.push('\n');
+                let _ = fs::write(path, text);
+            }
+        }
+        LineEditor { history, path }
+    }
+
+    // The history file is `$INTERPRETE_HISTORY`, or `~/.interprete_history`
+    // if that is not set. Setting it to nothing turns the history file off.
+    pub fn history_path() -> Option<PathBuf> {
+        match std::env::var_os("INTERPRETE_HISTORY") {
+            Some(path) if path.is_empty() => None,
+            Some(path) => Some(PathBuf::from(path)),
+            None => {
+                std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".interprete_history"))
+            }
+        }
+    }
+
+    // Reads a line, or None at the end of input.
+    pub fn read_line(&mut self, prompt: &str) -> io::Result<Option<String>> {
+        let raw = match RawMode::enter() {
+            Some(raw) => raw,
+            None => return natives::read_line(),
+        };
+        let line = self.edit(prompt);
+        drop(raw);
+        println!();
+        let line = line?;
+        if let Some(line) = &line {
+            self.add(line);
+        }
+        Ok(line)
+    }
+
+    // Remembers a line, unless it is blank or repeats the one before.
+    fn add(&mut self, line: &str) {
+        if line.trim().is_empty() || self.history.last().map(String::as_str) == Some(line) {
+            return;
+        }
+        self.history.push(line.to_string());
+        if let Some(path) = &self.path {
+            // A history that cannot be saved is not worth failing over.
+            if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(path) {
+                let _ = writeln!(file, "{}", line);
+            }
+        }
+    }
+
+    fn edit(&self, prompt: &str) -> io::Result<Option<String>> {
+        let mut line: Vec<char> = Vec::new();
+        let mut cursor = 0;
+        // The history entry being shown, and the line that was being typed
+        // before stepping into the history.
+        let mut entry = self.history.len();
+        let mut typed = Vec::new();
+        redraw(prompt, &line, cursor)?;
+        loop {
+            match read_key()? {
+                Key::Enter => return Ok(Some(line.into_iter().collect())),
+                // Control-C abandons the line.
+                Key::Interrupt => return Ok(Some(String::new())),
+                Key::Eof if line.is_empty() => return Ok(None),
+                Key::Eof | Key::Delete => {
+                    if cursor < line.len() {
+                        line.remove(cursor);
+                    }
+                }
+                Key::Backspace => {
+                    if cursor > 0 {
+                        cursor -= 1;
+                        line.remove(cursor);
+                    }
+                }
+                Key::Char(c) => {
+                    line.insert(cursor, c);
+                    cursor += 1;
+                }
+                Key::Left => cursor = cursor.saturating_sub(1),
+                Key::Right => cursor = (cursor + 1).min(line.len()),
+                Key::Home => cursor = 0,
+                Key::End => cursor = line.len(),
+                Key::ClearToStart => {
+                    line.drain(..cursor);
+                    cursor = 0;
+                }
+                Key::Up if entry > 0 => {
+                    if entry == self.history.len() {
+                        typed = line;
+                    }
+                    entry -= 1;
+                    line = self.history[entry].chars().collect();
+                    cursor = line.len();
+                }
+                Key::Down if entry < self.history.len() => {
+                    entry += 1;
+                    line = match self.history.get(entry) {
+                        Some(previous) => previous.chars().collect(),
+                        None => std::mem::take(&mut typed),
+                    };
+                    cursor = line.len();
+                }
+                Key::Up | Key::Down | Key::Other => continue,
+            }
+            redraw(prompt, &line, cursor)?;
+        }
+    }
+}
+
+enum Key {
+    Char(char),
+    Enter,
+    Interrupt,
+    Eof,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    ClearToStart,
+    Other,
+}
+
+fn read_byte() -> io::Result<Option<u8>> {
+    let mut byte = [0];
+    Ok(match io::stdin().read(&mut byte)? {
+        0 => None,
+        _ => Some(byte[0]),
+    })
+}
+
+fn read_key() -> io::Result<Key> {
+    let byte = match read_byte()? {
+        Some(byte) => byte,
+        None => return Ok(Key::Eof),
+    };
+    Ok(match byte {
+        b'\r' | b'\n' => Key::Enter,
+        0x03 => Key::Interrupt,
+        0x04 => Key::Eof,
+        0x7f | 0x08 => Key::Backspace,
+        0x01 => Key::Home,
+        0x05 => Key::End,
+        0x15 => Key::ClearToStart,
+        0x1b => read_escape()?,
+        byte if byte < 0x20 => Key::Other,
+        byte if byte < 0x80 => Key::Char(byte as char),
+        lead => {
+            // The rest of a UTF-8 character.
+            let length = match lead {
+                0xc0..=0xdf => 2,
+                0xe0..=0xef => 3,
+                _ => 4,
+            };
+            let mut bytes = vec![lead];
+            for _ in 1..length {
+                bytes.extend(read_byte()?);
+            }
+            match std::str::from_utf8(&bytes)
+                .ok()
+                .and_then(|s| s.chars().next())
+            {
+                Some(c) => Key::Char(c),
+                None => Key::Other,
+            }
+        }
+    })
+}
+
+// Reads the rest of an escape sequence such as `ESC [ A`, which the
+// terminal sends for the keys that have no character of their own.
+fn read_escape() -> io::Result<Key> {
+    if !matches!(read_byte()?, Some(b'[' | b'O')) {
+        return Ok(Key::Other);
+    }
+    Ok(match read_byte()? {
+        Some(b'A') => Key::Up,
+        Some(b'B') => Key::Down,
+        Some(b'C') => Key::Right,
+        Some(b'D') => Key::Left,
+        Some(b'H') => Key::Home,
+        Some(b'F') => Key::End,
+        Some(digit @ b'0'..=b'9') => {
+            // Keys such as `ESC [ 3 ~`, for delete, end with a tilde.
+            let mut code = vec![digit];
+            loop {
+                match read_byte()? {
+                    Some(b'~') | None => break,
+                    Some(byte) => code.push(byte),
+                }
+            }
+            match code.as_slice() {
+                b"3" => Key::Delete,
+                b"1" | b"7" => Key::Home,
+                b"4" | b"8" => Key::End,
+                _ => Key::Other,
+            }
+        }
+        _ => Key::Other,
+    })
+}
+
+// Draws the prompt and line over the current one, and puts the cursor in
+// place.
+fn redraw(prompt: &str, line: &[char], cursor: usize) -> io::Result<()> {
+    let mut out = io::stdout().lock();
+    let text: String = line.iter().collect();
+    write!(out, "\r{}{}\x1b[K", prompt, text)?;
+    if cursor < line.len() {
+        write!(out, "\x1b[{}D", line.len() - cursor)?;
+    }
+    out.flush()
+}
+
+// While this is alive, the terminal passes each key straight through rather
+// than a line at a time, and does not echo it; dropping it restores the
+// terminal.
+struct RawMode {
+    #[cfg(target_os = "linux")]
+    original: termios::Termios,
+}
+
+#[cfg(target_os = "linux")]
+mod termios {
+    use std::ffi::c_int;
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    pub struct Termios {
+        pub iflag: u32,
+        pub oflag: u32,
+        pub cflag: u32,
+        pub lflag: u32,
+        pub line: u8,
+        pub cc: [u8; 32],
+        pub ispeed: u32,
+        pub ospeed: u32,
+    }
+
+    pub const ISIG: u32 = 0o1;
+    pub const ICANON: u32 = 0o2;
+    pub const ECHO: u32 = 0o10;
+    pub const VTIME: usize = 5;
+    pub const VMIN: usize = 6;
+    pub const TCSANOW: c_int = 0;
+
+    extern "C" {
+        pub fn tcgetattr(fd: c_int, termios: *mut Termios) -> c_int;
+        pub fn tcsetattr(fd: c_int, action: c_int, termios: *const Termios) -> c_int;
+    }
+}
+
+impl RawMode {
+    // Switches the terminal over, or gives None if standard input and
+    // output are not both a terminal.
+    #[cfg(target_os = "linux")]
+    fn enter() -> Option<RawMode> {
+        use termios::*;
+
+        if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+            return None;
+        }
+        let mut original = std::mem::MaybeUninit::<Termios>::uninit();
+        // SAFETY: `tcgetattr` fills in the struct when it succeeds.
+        let original = unsafe {
+            if tcgetattr(0, original.as_mut_ptr()) != 0 {
+                return None;
+            }
+            original.assume_init()
+        };
+        let mut raw = original;
+        raw.lflag &= !(ISIG | ICANON | ECHO);
+        raw.cc[VMIN] = 1;
+        raw.cc[VTIME] = 0;
+        // SAFETY: `raw` is a valid settings struct.
+        if unsafe { tcsetattr(0, TCSANOW, &raw) } != 0 {
+            return None;
+        }
+        Some(RawMode { original })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn enter() -> Option<RawMode> {
+        None
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        // SAFETY: restores the settings `enter` read.
+        #[cfg(target_os = "linux")]
+        unsafe {
+            termios::tcsetattr(0, termios::TCSANOW, &self.original);
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+// How much the interpreter says about itself on standard error, set once
+// from the command line with `--quiet`, `--verbose` or `-vv`, and shared by
+// every thread. Errors are always reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    // Errors only, without warnings.
+    Quiet,
+    // Errors and warnings.
+    Normal,
+    // Also what the interpreter is doing: files read, plugins loaded,
+    // threads started.
+    Verbose,
+    // Also every call to a native.
+    Debug,
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Normal as u8);
+
+pub fn set_level(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn level() -> Level {
+    match LEVEL.load(Ordering::Relaxed) {
+        0 => Level::Quiet,
+        1 => Level::Normal,
+        2 => Level::Verbose,
+        _ => Level::Debug,
+    }
+}
+
+// Logs an event at the verbose level. Taking `format_args!` means nothing
+// is formatted unless the event is shown.
+pub fn info(message: fmt::Arguments) {
+    if level() >= Level::Verbose {
+        eprintln!("[info] {}", message);
+    }
+}
+
+pub fn debug(message: fmt::Arguments) {
+    if level() >= Level::Debug {
+        eprintln!("[debug] {}", message);
+    }
+}
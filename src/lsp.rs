@@ -0,0 +1,558 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{self, BufRead, Write};
+
+use interprete::{
+    ast::{Ast, Program},
+    check,
+    diagnostic::{Diagnostic, Severity},
+    expr::{Expr, ExprId},
+    incremental::{Document, TextEdit},
+    interpreter::{Interpreter, Setup},
+    json::{self, object, JsonValue},
+    log,
+    parser::Parser,
+    scanner::{ParseError, Scanner, Token, TokenType},
+    stack,
+    stmt::{Stmt, StmtId},
+};
+
+// A language server for `interprete lsp`, so editors can show problems in a
+// script as it is typed, jump from a name to where it is declared, and
+// describe a name when the pointer rests on it. It speaks the Language
+// Server Protocol over standard input and output: JSON-RPC messages, each
+// after a `Content-Length` header.
+//
+// Editors send only the text that changed, and only the tokens around it are
+// scanned again; see `incremental`. Positions are taken to count
+// characters, which is what the protocol's UTF-16 units are for all but the
+// rarest characters.
+
+// Serves requests until the editor sends `exit`, and gives the status to
+// exit with: 0 if the editor asked to shut down first, as it should.
+pub fn serve(setup: &Setup) -> i32 {
+    let interpreter = match setup.interpreter() {
+        Ok(interpreter) => interpreter,
+        Err(error) => {
+            eprintln!("{}", error);
+            return 70;
+        }
+    };
+    let mut server = Server {
+        interpreter,
+        documents: HashMap::new(),
+        shutdown: false,
+    };
+    let mut input = io::stdin().lock();
+    loop {
+        let message = match read_message(&mut input) {
+            Ok(Some(message)) => message,
+            Ok(None) => return 1,
+            Err(error) => {
+                eprintln!("Error reading a message: {}", error);
+                return 1;
+            }
+        };
+        let method = message.get("method").and_then(JsonValue::as_str);
+        if method == Some("exit") {
+            return if server.shutdown { 0 } else { 1 };
+        }
+        if let Some(method) = method {
+            log::debug(format_args!("Handling '{}'", method));
+            server.handle(method, &message);
+        }
+    }
+}
+
+struct Server {
+    // Knows the natives and prelude functions, which scripts use without
+    // declaring them.
+    interpreter: Interpreter,
+    // Each open document, by URI.
+    documents: HashMap<String, Document>,
+    shutdown: bool,
+}
+
+impl Server {
+    fn handle(&mut self, method: &str, message: &JsonValue) {
+        let params = message.get("params").unwrap_or(&json::NULL);
+        let uri = params
+            .get("textDocument")
+            .and_then(|document| document.get("uri"))
+            .and_then(JsonValue::as_str)
+            .unwrap_or("")
+            .to_string();
+        let result = match method {
+            "initialize" => object(vec![
+                (
+                    "capabilities",
+                    object(vec![
+                        // Only what changed is sent.
+                        ("textDocumentSync", JsonValue::Number(2.0)),
+                        ("definitionProvider", JsonValue::Bool(true)),
+                        ("hoverProvider", JsonValue::Bool(true)),
+                    ]),
+                ),
+                (
+                    "serverInfo",
+                    object(vec![("name", JsonValue::String("interprete".to_string()))]),
+                ),
+            ]),
+            "shutdown" => {
+                self.shutdown = true;
+                JsonValue::Null
+            }
+            "textDocument/didOpen" => {
+                let text = params
+                    .get("textDocument")
+                    .and_then(|document| document.get("text"))
+                    .and_then(JsonValue::as_str);
+                if let Some(text) = text {
+                    self.documents
+                        .insert(uri.clone(), Document::new(text.to_string()));
+                    self.publish(&uri);
+                }
+                return;
+            }
+            "textDocument/didChange" => {
+                let changes = params.get("contentChanges").and_then(JsonValue::as_array);
+                for change in changes.into_iter().flatten() {
+                    self.change(&uri, change);
+                }
+                self.publish(&uri);
+                return;
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                publish(&uri, Vec::new());
+                return;
+            }
+            "textDocument/definition" | "textDocument/hover" => match self.find(&uri, params) {
+                Some(found) if method == "textDocument/definition" => match found.declaration {
+                    Some(declaration) => location(&uri, &declaration.name),
+                    None => JsonValue::Null,
+                },
+                Some(found) => self.hover(&found),
+                None => JsonValue::Null,
+            },
+            // Other notifications, such as `initialized`, need nothing done.
+            _ if message.get("id").is_none() => return,
+            _ => {
+                respond_error(message, -32601, &format!("Unknown method '{}'.", method));
+                return;
+            }
+        };
+        respond(message, result);
+    }
+
+    // Applies one change to a document: an edit of a range, or without one,
+    // the whole new text.
+    fn change(&mut self, uri: &str, change: &JsonValue) {
+        let Some(document) = self.documents.get_mut(uri) else {
+            return;
+        };
+        let Some(text) = change.get("text").and_then(JsonValue::as_str) else {
+            return;
+        };
+        let Some(range) = change.get("range") else {
+            *document = Document::new(text.to_string());
+            return;
+        };
+        let Some(edit) = text_edit(document.source(), range, text) else {
+            log::info(format_args!("Ignoring a change to '{}' outside it", uri));
+            return;
+        };
+        // A source that does not scan is reported by `publish`.
+        if let Err(ParseError::InvalidEdit(..)) = document.edit(&edit) {
+            log::info(format_args!("Ignoring a change to '{}' outside it", uri));
+        }
+    }
+
+    // Reports the problems in a document.
+    fn publish(&self, uri: &str) {
+        if let Some(document) = self.documents.get(uri) {
+            publish(uri, self.diagnostics(document));
+        }
+    }
+
+    // The errors in a script, found as by `--check`, or if it parses, the
+    // warnings about names nothing declares.
+    fn diagnostics(&self, document: &Document) -> Vec<Diagnostic> {
+        let program = match document.scans() {
+            true => Parser::new(document.tokens().to_vec()).parse_all(),
+            // Every error, not just the first the document stopped at.
+            false => match Scanner::new(document.source().to_string()).scan_all() {
+                (tokens, errors) if errors.is_empty() => Parser::new(tokens).parse_all(),
+                (_, errors) => Err(errors),
+            },
+        };
+        match program {
+            Ok(program) => {
+                let declared: HashSet<String> = check::declarations(&program);
+                check::check(&program, |name| {
+                    declared.contains(name) || self.global(name).is_some()
+                })
+            }
+            Err(errors) => errors.iter().map(|error| error.diagnostic()).collect(),
+        }
+    }
+
+    // The name at the position a request is about.
+    fn find(&self, uri: &str, params: &JsonValue) -> Option<Found> {
+        let program = self.documents.get(uri)?.parse().ok()?;
+        let (line, character) = position(params.get("position")?)?;
+        let found = Names::of(&program).at(line, character);
+        found
+    }
+
+    fn global(&self, name: &str) -> Option<String> {
+        let token = Token::new(TokenType::Identifier, name.to_string(), None, 0, 0);
+        let value = self.interpreter.global(&token).ok()?;
+        Some(self.interpreter.stringify(value))
+    }
+
+    // Describes the name under the pointer by its declaration, or if the
+    // script does not declare it, as the native or prelude function it is.
+    fn hover(&self, found: &Found) -> JsonValue {
+        let text = match &found.declaration {
+            Some(declaration) => declaration.signature.clone(),
+            None => match self.global(&found.name.lexeme) {
+                Some(value) => value,
+                None => return JsonValue::Null,
+            },
+        };
+        object(vec![(
+            "contents",
+            object(vec![
+                ("kind", JsonValue::String("markdown".to_string())),
+                ("value", JsonValue::String(format!("```lox\n{}\n```", text))),
+            ]),
+        )])
+    }
+}
+
+// A protocol position, as a zero-based line and character.
+fn position(position: &JsonValue) -> Option<(usize, usize)> {
+    let line = position.get("line")?.as_f64()?;
+    let character = position.get("character")?.as_f64()?;
+    Some((line as usize, character as usize))
+}
+
+// An edit replacing a protocol range of a source with `text`.
+fn text_edit(source: &str, range: &JsonValue, text: &str) -> Option<TextEdit> {
+    Some(TextEdit {
+        start: offset(source, position(range.get("start")?)?)?,
+        end: offset(source, position(range.get("end")?)?)?,
+        text: text.to_string(),
+    })
+}
+
+// The byte offset in a source of a zero-based line and character. The end
+// of the source is on the line after a final newline.
+fn offset(source: &str, (line, character): (usize, usize)) -> Option<usize> {
+    let start = match line {
+        0 => 0,
+        line => source.match_indices('\n').nth(line - 1)?.0 + 1,
+    };
+    let text = &source[start..];
+    let text = &text[..text.find('\n').unwrap_or(text.len())];
+    let column = text
+        .char_indices()
+        .nth(character)
+        .map_or(text.len(), |(i, _)| i);
+    Some(start + column)
+}
+
+// A name declared in a script, and how to describe it.
+#[derive(Clone)]
+struct Declaration {
+    name: Token,
+    signature: String,
+    global: bool,
+}
+
+// A name in a script, and where it is declared, if the script declares it.
+struct Found {
+    name: Token,
+    declaration: Option<Declaration>,
+}
+
+// Every name a program declares or uses, with the declaration each use
+// refers to. A use refers to the nearest declaration in the scopes around
+// it, or failing that, to a global declared anywhere, since a function may
+// use a global declared after it.
+struct Names<'a> {
+    ast: &'a Ast,
+    declarations: Vec<Declaration>,
+    uses: Vec<(&'a Token, Option<usize>)>,
+    // The declarations in each scope around the current point, innermost
+    // last, by their index in `declarations`.
+    scopes: Vec<Vec<usize>>,
+}
+
+impl<'a> Names<'a> {
+    fn of(program: &'a Program) -> Names<'a> {
+        let mut names = Names {
+            ast: &program.ast,
+            declarations: Vec::new(),
+            uses: Vec::new(),
+            scopes: vec![Vec::new()],
+        };
+        names.stmts(&program.statements);
+        names
+    }
+
+    // The name at a zero-based line and character, used or declared.
+    fn at(&self, line: usize, character: usize) -> Option<Found> {
+        let covers = |token: &Token| {
+            let start = token.column.saturating_sub(1);
+            token.line == line + 1
+                && (start..start + token.lexeme.chars().count()).contains(&character)
+        };
+        if let Some(declaration) = self.declarations.iter().find(|d| covers(&d.name)) {
+            return Some(Found {
+                name: declaration.name.clone(),
+                declaration: Some(declaration.clone()),
+            });
+        }
+        let &(name, index) = self.uses.iter().find(|(name, _)| covers(name))?;
+        let declaration = index.or_else(|| {
+            self.declarations
+                .iter()
+                .position(|d| d.global && d.name.lexeme == name.lexeme)
+        });
+        Some(Found {
+            name: name.clone(),
+            declaration: declaration.map(|index| self.declarations[index].clone()),
+        })
+    }
+
+    fn declare(&mut self, name: &Token, signature: String) {
+        self.declarations.push(Declaration {
+            name: name.clone(),
+            signature,
+            global: self.scopes.len() == 1,
+        });
+        let index = self.declarations.len() - 1;
+        self.scopes
+            .last_mut()
+            .expect("there is a scope")
+            .push(index);
+    }
+
+    fn refer(&mut self, name: &'a Token) {
+        let declaration = self.scopes.iter().rev().find_map(|scope| {
+            scope
+                .iter()
+                .rev()
+                .copied()
+                .find(|&index| self.declarations[index].name.lexeme == name.lexeme)
+        });
+        self.uses.push((name, declaration));
+    }
+
+    fn stmt(&mut self, id: StmtId) {
+        let ast = self.ast;
+        match ast.stmt(id) {
+            Stmt::Expression(expr) | Stmt::Print(expr) => self.expr(*expr),
+            Stmt::Var(name, initializer) => {
+                if let Some(initializer) = initializer {
+                    self.expr(*initializer);
+                }
+                self.declare(name, format!("var {}", name.lexeme));
+            }
+            Stmt::Block(statements) => {
+                self.scopes.push(Vec::new());
+                self.stmts(statements);
+                self.scopes.pop();
+            }
+            Stmt::If(condition, then_branch, else_branch) => {
+                self.expr(*condition);
+                self.stmt(*then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.stmt(*else_branch);
+                }
+            }
+            Stmt::While(condition, body) => {
+                self.expr(*condition);
+                self.stmt(*body);
+            }
+            Stmt::Function(name, params, body) => {
+                let list: Vec<_> = params.iter().map(|param| param.lexeme.as_str()).collect();
+                self.declare(name, format!("fun {}({})", name.lexeme, list.join(", ")));
+                self.scopes.push(Vec::new());
+                for param in params {
+                    let signature = format!("{} (parameter of {})", param.lexeme, name.lexeme);
+                    self.declare(param, signature);
+                }
+                self.stmts(body);
+                self.scopes.pop();
+            }
+            Stmt::Return(_, value) => {
+                if let Some(value) = value {
+                    self.expr(*value);
+                }
+            }
+            Stmt::Class(name, _, _) => self.declare(name, format!("class {}", name.lexeme)),
+        }
+    }
+
+    fn stmts(&mut self, statements: &[StmtId]) {
+        for &stmt in statements {
+            self.stmt(stmt);
+        }
+    }
+
+    fn expr(&mut self, id: ExprId) {
+        stack::descend(|| self.expr_node(id))
+    }
+
+    fn expr_node(&mut self, id: ExprId) {
+        let ast = self.ast;
+        match ast.expr(id) {
+            Expr::Binary(left, _, right) | Expr::Logical(left, _, right) => {
+                self.expr(*left);
+                self.expr(*right);
+            }
+            Expr::Unary(_, expr) | Expr::Grouping(expr) | Expr::Get(expr, _) => self.expr(*expr),
+            Expr::Literal(_) | Expr::This(_) | Expr::Super(..) => {}
+            Expr::Variable(name) => self.refer(name),
+            Expr::Assignment(name, value) => {
+                self.expr(*value);
+                self.refer(name);
+            }
+            Expr::Call(callee, _, arguments) => {
+                self.expr(*callee);
+                for &argument in arguments {
+                    self.expr(argument);
+                }
+            }
+            Expr::Set(object, _, value) => {
+                self.expr(*object);
+                self.expr(*value);
+            }
+        }
+    }
+}
+
+// A protocol range over `length` characters from a one-based line and
+// column; a column of zero gives an empty range at the start of the line.
+fn range(line: usize, column: usize, length: usize) -> JsonValue {
+    let point = |character: usize| {
+        object(vec![
+            ("line", JsonValue::Number(line.saturating_sub(1) as f64)),
+            ("character", JsonValue::Number(character as f64)),
+        ])
+    };
+    let (start, end) = match column {
+        0 => (0, 0),
+        column => (column - 1, column - 1 + length),
+    };
+    object(vec![("start", point(start)), ("end", point(end))])
+}
+
+fn location(uri: &str, token: &Token) -> JsonValue {
+    object(vec![
+        ("uri", JsonValue::String(uri.to_string())),
+        (
+            "range",
+            range(token.line, token.column, token.lexeme.chars().count()),
+        ),
+    ])
+}
+
+fn publish(uri: &str, diagnostics: Vec<Diagnostic>) {
+    let diagnostics = diagnostics
+        .into_iter()
+        .map(|diagnostic| {
+            let range = match &diagnostic.span {
+                Some(span) => range(span.line, span.column, span.text.chars().count()),
+                None => range(1, 0, 0),
+            };
+            let severity = match diagnostic.severity {
+                Severity::Error => 1.0,
+                Severity::Warning => 2.0,
+            };
+            object(vec![
+                ("range", range),
+                ("severity", JsonValue::Number(severity)),
+                ("code", JsonValue::String(diagnostic.code.to_string())),
+                ("source", JsonValue::String("interprete".to_string())),
+                ("message", JsonValue::String(diagnostic.message)),
+            ])
+        })
+        .collect();
+    send(object(vec![
+        ("jsonrpc", JsonValue::String("2.0".to_string())),
+        (
+            "method",
+            JsonValue::String("textDocument/publishDiagnostics".to_string()),
+        ),
+        (
+            "params",
+            object(vec![
+                ("uri", JsonValue::String(uri.to_string())),
+                ("diagnostics", JsonValue::Array(diagnostics)),
+            ]),
+        ),
+    ]));
+}
+
+fn respond(request: &JsonValue, result: JsonValue) {
+    send(object(vec![
+        ("jsonrpc", JsonValue::String("2.0".to_string())),
+        ("id", request.get("id").cloned().unwrap_or(JsonValue::Null)),
+        ("result", result),
+    ]));
+}
+
+fn respond_error(request: &JsonValue, code: i32, message: &str) {
+    send(object(vec![
+        ("jsonrpc", JsonValue::String("2.0".to_string())),
+        ("id", request.get("id").cloned().unwrap_or(JsonValue::Null)),
+        (
+            "error",
+            object(vec![
+                ("code", JsonValue::Number(code as f64)),
+                ("message", JsonValue::String(message.to_string())),
+            ]),
+        ),
+    ]));
+}
+
+fn send(message: JsonValue) {
+    let body = message.to_string();
+    let mut output = io::stdout().lock();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body).ok();
+    output.flush().ok();
+}
+
+// Reads the next message, or `None` once the input ends.
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<JsonValue>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let length = length.ok_or_else(|| invalid("A message has no Content-Length."))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    let body = String::from_utf8(body).map_err(|_| invalid("A message is not UTF-8."))?;
+    json::parse(&body)
+        .map(Some)
+        .map_err(|error| invalid(&error.to_string()))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
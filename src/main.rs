@@ -1,77 +1,299 @@
-mod expr;
-mod interpreter;
-mod parser;
-mod scanner;
-mod stmt;
-mod environment;
-
-use interpreter::{Interpreter, RuntimeError};
-use parser::Parser;
-use scanner::{ParseError, Scanner};
+mod bench;
+mod cache;
+mod config;
+mod ctrl_c;
+mod explain;
+mod golden;
+mod jupyter;
+mod lsp;
+mod playground;
+mod rpc;
+mod zmtp;
+
+use config::Setting;
+use interprete::{
+    ast,
+    capability::Capability,
+    check,
+    diagnostic::Format,
+    engine::Engine,
+    formatter,
+    interpreter::{Backend, Interpreter, Setup},
+    line_editor::LineEditor,
+    log, natives, parse,
+    parser::Parser,
+    printer, run_line,
+    scanner::{Scanner, Token, TokenType},
+    serialize, snapshot, InterpreterError,
+};
 use std::{
+    collections::HashSet,
     env, fs,
-    io::{self, BufRead},
+    io::{self, IsTerminal},
+    path::Path,
     process,
+    sync::Arc,
 };
 
-// Define your generic error type
-#[derive(Debug)]
-pub enum InterpreterError {
-    Parse(ParseError),
-    Runtime(RuntimeError),
+// Sets up an interpreter as the config file and then the options before the
+// script ask:
+//
+// - `--allow` and `--deny` take a comma-separated list of capabilities, or
+//   `all`; `--allow-net` is short for `--allow net`.
+// - `--trust-config` accepts a config file that allows capabilities or
+//   loads plugins; see `config`.
+// - `--no-prelude` leaves out the functions in `prelude.lox`.
+// - `--plugin path` loads the natives of a plugin, and may be repeated.
+// - `--trace` describes each statement and call on standard error as it
+//   runs.
+// - `--coverage` counts which statements of the scripts run, and
+//   `--coverage=PATH` writes the counts to PATH as well; see `coverage`.
+// - `--backend=vm` runs scripts on the bytecode VM rather than the
+//   tree-walker, `--backend=jit` on the VM with hot functions compiled to
+//   machine code, in builds with the `jit` feature, and `--backend=tree` on
+//   the tree-walker, as by default.
+fn new_engine(setup: &Setup) -> Box<dyn Engine> {
+    let engine = setup.engine().unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        process::exit(70)
+    });
+    ctrl_c::install(engine.interpreter().interrupt_handle());
+    engine
 }
 
-// Implement the `Display` trait for better error messages
-impl std::fmt::Display for InterpreterError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            InterpreterError::Parse(err) => write!(f, "Parse error: {}", err),
-            InterpreterError::Runtime(err) => write!(f, "Runtime error: {}", err),
+// The exit status follows the BSD `sysexits` convention: 64 for a bad
+// command line, 65 for a script that does not parse, 66 for a file that
+// cannot be read, 70 for a script that fails while running, or a plugin
+// that does not load, 74 for a file that cannot be written, and 78 for a
+// config file that is wrong. `interprete test` exits with 1 when a test
+// fails, and a script stopped with Control-C with 130, as shells expect.
+fn main() {
+    // Compiled to WebAssembly, the interpreter is a library for JavaScript
+    // to call instead; see `wasm`.
+    if cfg!(target_arch = "wasm32") {
+        return;
+    }
+    let args: Vec<String> = env::args().collect();
+    let mut settings = Vec::new();
+    let mut format = Format::Human;
+    let mut trust_config = false;
+    let mut rest = &args[1..];
+    loop {
+        rest = match rest {
+            [flag, tail @ ..] if flag == "--allow-net" => {
+                settings.push(Setting::Allow(Capability::Net));
+                tail
+            }
+            [flag, tail @ ..] if flag == "--trust-config" => {
+                trust_config = true;
+                tail
+            }
+            [flag, tail @ ..] if flag == "-q" || flag == "--quiet" => {
+                log::set_level(log::Level::Quiet);
+                tail
+            }
+            // Given twice, `-v` is the same as `-vv`.
+            [flag, tail @ ..] if flag == "-v" || flag == "--verbose" => {
+                if log::level() >= log::Level::Verbose {
+                    log::set_level(log::Level::Debug);
+                } else {
+                    log::set_level(log::Level::Verbose);
+                }
+                tail
+            }
+            [flag, tail @ ..] if flag == "-vv" => {
+                log::set_level(log::Level::Debug);
+                tail
+            }
+            [flag, tail @ ..] if flag == "--trace" => {
+                settings.push(Setting::Trace);
+                tail
+            }
+            [flag, tail @ ..] if flag == "--coverage" => {
+                settings.push(Setting::Coverage(None));
+                tail
+            }
+            [flag, tail @ ..] if flag.starts_with("--coverage=") => {
+                let path = flag["--coverage=".len()..].to_string();
+                settings.push(Setting::Coverage(Some(path)));
+                tail
+            }
+            [flag, tail @ ..] if flag.starts_with("--backend=") => {
+                let name = &flag["--backend=".len()..];
+                let backend = Backend::from_name(name).unwrap_or_else(|| usage());
+                settings.push(Setting::Backend(backend));
+                tail
+            }
+            [flag, tail @ ..] if flag == "--no-prelude" => {
+                settings.push(Setting::Prelude(false));
+                tail
+            }
+            [flag, tail @ ..] if flag.starts_with("--diagnostics=") => {
+                let name = &flag["--diagnostics=".len()..];
+                format = Format::from_name(name).unwrap_or_else(|| usage());
+                tail
+            }
+            [flag, path, tail @ ..] if flag == "--plugin" => {
+                settings.push(Setting::Plugin(path.clone()));
+                tail
+            }
+            [flag, names, tail @ ..] if flag == "--allow" || flag == "--deny" => {
+                for capability in capabilities(names) {
+                    settings.push(if flag == "--allow" {
+                        Setting::Allow(capability)
+                    } else {
+                        Setting::Deny(capability)
+                    });
+                }
+                tail
+            }
+            _ => break,
+        }
+    }
+    let config = config::load(config_dir(rest), trust_config).unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        process::exit(78)
+    });
+    let mut setup = Setup::default();
+    for setting in config.into_iter().chain(settings) {
+        setting.apply(&mut setup);
+    }
+    // Only the tree-walker traces and counts statements; see `vm`.
+    if setup.backend != Backend::Tree && (setup.trace || setup.coverage) {
+        eprintln!("--trace and --coverage only work with --backend=tree.");
+        process::exit(64);
+    }
+    // The options before the command, for the playground to run scripts
+    // with in processes of their own.
+    let options = &args[1..args.len() - rest.len()];
+    match rest {
+        // Piped input is a whole script, not lines for the REPL.
+        [] if !io::stdin().is_terminal() => run_files(&["-".to_string()], &[], &setup, format),
+        [] => run_prompt(&setup, format),
+        [flag, code] if flag == "--explain" => explain(code),
+        [flag, code, script_args @ ..] if flag == "-e" || flag == "--eval" => {
+            run_sources(&[("<eval>", code.clone())], script_args, &setup, format)
+        }
+        [flag, paths @ ..] if flag == "--check" && !paths.is_empty() => {
+            check_files(paths, &setup, format)
+        }
+        [flag, path] if flag == "--tokens" => dump_tokens(path, format),
+        [flag, path] if flag == "--ast" => dump_ast(path, false, format),
+        [flag, path] if flag == "--ast=json" => dump_ast(path, true, format),
+        [flag, path] if flag == "--emit-ast" => emit_ast(path, format),
+        [flag, path] if flag == "--run-ast" => run_ast_file(path, &setup, format),
+        [command, path] if command == "fmt" => format_file(path, format),
+        [command, path] if command == "compile" => {
+            process::exit(cache::compile(path, None, format))
+        }
+        [command, path, flag, output] if command == "compile" && flag == "-o" => {
+            process::exit(cache::compile(path, Some(output), format))
+        }
+        [command, path] if command == "bench" => {
+            process::exit(bench::run(path, 10, &setup, format))
+        }
+        [command, path, flag, count] if command == "bench" && flag == "--iterations" => {
+            let iterations = count.parse().ok().filter(|&count| count > 0);
+            let iterations = iterations.unwrap_or_else(|| usage());
+            process::exit(bench::run(path, iterations, &setup, format))
+        }
+        [command, paths @ ..] if command == "test" && !paths.is_empty() => {
+            process::exit(golden::run(paths, &setup))
+        }
+        [command] if command == "lsp" => process::exit(lsp::serve(&setup)),
+        [command] if command == "rpc" => process::exit(rpc::serve(&setup)),
+        [command] if command == "serve" => process::exit(playground::serve(8080, options)),
+        [command, flag, port] if command == "serve" && flag == "--port" => {
+            let port = port.parse().unwrap_or_else(|_| usage());
+            process::exit(playground::serve(port, options))
+        }
+        // What the playground runs each script in; see `playground`.
+        [command, flag] if command == "serve" && flag == "--script" => {
+            process::exit(playground::run_script(&setup))
+        }
+        [command, path] if command == "jupyter" => process::exit(jupyter::serve(path, &setup)),
+        [path, tail @ ..] if path == "-" || !path.starts_with('-') => {
+            // Any `.lox` files straight after the first script run after it,
+            // in the same interpreter.
+            let count = 1 + tail.iter().take_while(|arg| arg.ends_with(".lox")).count();
+            run_files(&rest[..count], &rest[count..], &setup, format)
+        }
+        _ => {
+            usage();
         }
     }
 }
 
-// Implement `From` trait for automatic conversion
-impl From<ParseError> for InterpreterError {
-    fn from(err: ParseError) -> Self {
-        InterpreterError::Parse(err)
-    }
+fn usage() -> ! {
+    eprintln!(
+        "Usage: interprete [-q | -v | -vv] [--allow CAPS] [--deny CAPS] [--allow-net] \
+         [--trust-config] [--no-prelude] [--plugin PATH]... [--trace] [--coverage[=PATH]] \
+         [--backend=tree|vm|jit] [--diagnostics=human|json] \
+         [fmt | --check | --tokens | --ast[=json] | --emit-ast | --run-ast] \
+         [(script.lox... | - | -e CODE) [--] [args...]]\n       \
+         interprete --explain CODE\n       interprete test PATH...\n       \
+         interprete bench SCRIPT [--iterations N]\n       \
+         interprete compile SCRIPT [-o OUTPUT]\n       \
+         interprete lsp\n       interprete rpc\n       \
+         interprete jupyter CONNECTION_FILE\n       interprete serve [--port PORT]"
+    );
+    eprintln!(
+        "CAPS is `all` or a comma-separated list of: {}",
+        Capability::ALL.map(Capability::name).join(", ")
+    );
+    process::exit(64);
 }
 
-impl From<RuntimeError> for InterpreterError {
-    fn from(err: RuntimeError) -> Self {
-        InterpreterError::Runtime(err)
-    }
+// The directory whose config file applies: that of the script, or of the
+// first of several, or the current one when the script is not in a file.
+fn config_dir(rest: &[String]) -> &Path {
+    let script = match rest {
+        [flag, ..] if flag == "-" || flag == "-e" || flag == "--eval" => None,
+        [flag, path, ..] if flag.starts_with('-') || flag == "fmt" => Some(path),
+        [path, ..] => Some(path),
+        [] => None,
+    };
+    script
+        .and_then(|path| Path::new(path).parent())
+        .unwrap_or(Path::new(""))
 }
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() == 1 {
-        run_prompt();
-    } else if args.len() == 2 {
-        run_file(&args[1]);
-    } else {
-        println!("Error: Too many arguments");
-        process::exit(64);
+// Parses the list given to `--allow` or `--deny`.
+fn capabilities(names: &str) -> Vec<Capability> {
+    if names == "all" {
+        return Capability::ALL.to_vec();
     }
+    names
+        .split(',')
+        .map(|name| Capability::from_name(name.trim()).unwrap_or_else(|| usage()))
+        .collect()
 }
 
-fn run_prompt() {
-    let stdin = io::stdin();
-    // Lock the standard input handle and wrap it in a buffered reader
-    let handle = stdin.lock();
-    let reader = io::BufReader::new(handle);
-
-    // Iterate over the lines of input
-    for line_result in reader.lines() {
-        match line_result {
-            Ok(line) => {
+fn run_prompt(setup: &Setup, format: Format) {
+    let mut engine = new_engine(setup);
+    let mut editor = LineEditor::new(LineEditor::history_path());
+    // The lines that ran without error, for `:save`.
+    let mut session = Vec::new();
+    // Read one line at a time through the shared standard input buffer,
+    // without holding its lock, so scripts can read input with `readLine`
+    // without it being swallowed here first.
+    loop {
+        match editor.read_line("> ") {
+            Ok(Some(line)) => {
+                if let Some(command) = line.trim_start().strip_prefix(':') {
+                    run_command(engine.as_mut(), &mut session, command, format);
+                    continue;
+                }
+                // A Control-C that came while no line was running is not
+                // for this one.
+                engine.interpreter().interrupted();
                 // Process the line
-                let exec = run(line);
-                if let Err(e) = exec {
-                    println!("{e}");
-                };
+                match run_line(engine.as_mut(), line.clone()) {
+                    Ok(()) => session.push(saved_line(&line)),
+                    Err(e) => format.report(&e.diagnostic(), "<repl>", &line),
+                }
             }
+            Ok(None) => break,
             Err(e) => {
                 // Handle the error
                 eprintln!("Error reading line: {}", e);
@@ -81,27 +303,332 @@ fn run_prompt() {
     }
 }
 
-fn run_file(file_path: &str) {
+// A REPL line as `:save` writes it. A bare expression, which only the REPL
+// runs, becomes a `print` statement, as the REPL printed its value.
+fn saved_line(line: &str) -> String {
+    let Ok(tokens) = Scanner::new(line.to_string()).scan_tokens() else {
+        return line.to_string();
+    };
+    // The last token is the end of the line.
+    let ([.., last, _], Ok(_)) = (
+        tokens.as_slice(),
+        Parser::new(tokens.clone()).parse_expression(),
+    ) else {
+        return line.to_string();
+    };
+    // The semicolon goes straight after the expression, before any comment.
+    let end = line
+        .char_indices()
+        .nth(last.column - 1 + last.lexeme.chars().count())
+        .map_or(line.len(), |(index, _)| index);
+    format!("print {};{}", &line[..end], &line[end..])
+}
+
+// Runs a REPL command, given without its leading colon: `load path` runs a
+// script in the session, and `save path` writes the session so far to a
+// file, as a script that repeats it. `snapshot path` writes the globals
+// themselves to a file, and `restore path` defines them again, in this
+// session or a later one; see `snapshot`.
+fn run_command(engine: &mut dyn Engine, session: &mut Vec<String>, command: &str, format: Format) {
+    let command = command.trim();
+    let (name, path) = command.split_once(' ').unwrap_or((command, ""));
+    let path = path.trim();
+    match name {
+        "load" | "save" | "snapshot" | "restore" if path.is_empty() => {
+            println!("Usage: :{} path", name)
+        }
+        "load" => match fs::read_to_string(path) {
+            Ok(source) => match parse(source.clone()) {
+                Ok(program) => match engine.interpret(&program) {
+                    Ok(()) => session.push(source.trim_end().to_string()),
+                    Err(e) => format.report(&e.diagnostic(), path, &source),
+                },
+                Err(e) => format.report(&e.diagnostic(), path, &source),
+            },
+            Err(error) => eprintln!("Error reading file: {}", error),
+        },
+        "save" => {
+            let mut text = session.join("\n");
+            text.push('\n');
+            if let Err(error) = fs::write(path, text) {
+                eprintln!("Error writing file: {}", error);
+            }
+        }
+        "snapshot" => {
+            let saved = snapshot::save(engine.interpreter());
+            if let Err(error) = fs::write(path, saved.text) {
+                eprintln!("Error writing file: {}", error);
+            } else if !saved.skipped.is_empty() {
+                println!("Not saved: {}", saved.skipped.join(", "));
+            }
+        }
+        "restore" => match fs::read_to_string(path) {
+            Ok(text) => {
+                if let Err(error) = snapshot::restore(engine.interpreter_mut(), &text) {
+                    eprintln!("{}", error);
+                }
+            }
+            Err(error) => eprintln!("Error reading file: {}", error),
+        },
+        _ => println!(
+            "Unknown command ':{}'; the commands are :load, :save, :snapshot and :restore.",
+            name
+        ),
+    }
+}
+
+// Runs scripts one after another in the same interpreter, so later ones
+// can use what earlier ones declare. A path of `-` reads a script from
+// standard input. The arguments after the paths, less a leading `--`, are
+// given to the scripts as the `args` list.
+fn run_files(paths: &[String], script_args: &[String], setup: &Setup, format: Format) {
+    let mut sources = Vec::new();
+    for path in paths {
+        let source = if path == "-" {
+            io::read_to_string(io::stdin())
+        } else {
+            fs::read_to_string(path)
+        };
+        log::info(format_args!("Reading '{}'", path));
+        match source {
+            Ok(source) => sources.push((if path == "-" { "<stdin>" } else { path }, source)),
+            Err(error) => {
+                eprintln!("Error reading file '{}': {}", path, error);
+                process::exit(66)
+            }
+        }
+    }
+    run_sources(&sources, script_args, setup, format)
+}
+
+// Runs scripts read from the given places, whether files, standard input
+// or the command line, exiting if one fails.
+fn run_sources(sources: &[(&str, String)], script_args: &[String], setup: &Setup, format: Format) {
+    let script_args = match script_args {
+        [separator, rest @ ..] if separator == "--" => rest,
+        _ => script_args,
+    };
+    let mut engine = new_engine(setup);
+    engine.define_global("args", natives::list(script_args.to_vec()));
+    // The scripts that have parsed, for reporting coverage.
+    let mut scripts = Vec::new();
+    for (name, source) in sources {
+        log::info(format_args!("Running '{}'", name));
+        // A script compiled with `interprete compile` need not be parsed.
+        let program = match cache::load(name, source) {
+            Some(program) => Ok(program),
+            None => parse(source.clone()),
+        };
+        let result = program.and_then(|program| {
+            scripts.push((*name, program.ast.clone()));
+            engine.interpret(&program)
+        });
+        if let Err(e) = result {
+            format.report(&e.diagnostic(), name, source);
+            report_coverage(engine.interpreter(), &scripts, setup);
+            process::exit(e.exit_code())
+        }
+    }
+    report_coverage(engine.interpreter(), &scripts, setup);
+}
+
+// Summarizes which statements of the scripts ran, if they were counted,
+// and writes the lcov file if one was asked for.
+fn report_coverage(interpreter: &Interpreter, scripts: &[(&str, Arc<ast::Ast>)], setup: &Setup) {
+    let Some(coverage) = interpreter.coverage() else {
+        return;
+    };
+    eprintln!("Coverage:");
+    for (name, ast) in scripts {
+        eprintln!("  {}", coverage.summary(name, ast));
+    }
+    if let Some(path) = &setup.lcov {
+        let records: String = scripts
+            .iter()
+            .map(|(name, ast)| coverage.lcov(name, ast))
+            .collect();
+        if let Err(error) = fs::write(path, records) {
+            eprintln!("Error writing file '{}': {}", path, error);
+            process::exit(74)
+        }
+    }
+}
+
+// Describes an error code at length, such as `E1003`.
+fn explain(code: &str) {
+    match explain::explanation(code) {
+        Some(text) => println!("{}: {}", code.to_uppercase(), text),
+        None => {
+            eprintln!("'{}' is not an error code.", code);
+            process::exit(64)
+        }
+    }
+}
+
+// Checks scripts without running them, reporting every error and warning
+// found, and exits with 65 if there are any errors. The scripts are
+// checked as if run together, so names one declares count for the others.
+fn check_files(paths: &[String], setup: &Setup, format: Format) {
+    let mut failed = false;
+    let mut programs = Vec::new();
+    for path in paths {
+        let source = fs::read_to_string(path).unwrap_or_else(|error| {
+            eprintln!("Error reading file '{}': {}", path, error);
+            process::exit(66)
+        });
+        // A script that does not scan is not parsed, since the errors that
+        // would follow from the missing tokens are noise.
+        let errors = match Scanner::new(source.clone()).scan_all() {
+            (tokens, errors) if errors.is_empty() => match Parser::new(tokens).parse_all() {
+                Ok(program) => {
+                    programs.push((path, source, program));
+                    continue;
+                }
+                Err(errors) => errors,
+            },
+            (_, errors) => errors,
+        };
+        for error in &errors {
+            format.report(&error.diagnostic(), path, &source);
+        }
+        failed = true;
+    }
+
+    // Checking runs nothing of the scripts, and nothing of plugins either:
+    // their natives are taken to be undeclared.
+    let mut setup = setup.clone();
+    setup.plugins.clear();
+    let engine = new_engine(&setup);
+    let declared: HashSet<String> = programs
+        .iter()
+        .flat_map(|(_, _, program)| check::declarations(program))
+        .collect();
+    let is_declared = |name: &str| {
+        let token = Token::new(TokenType::Identifier, name.to_string(), None, 0, 0);
+        declared.contains(name) || engine.interpreter().global(&token).is_ok()
+    };
+    for (path, source, program) in &programs {
+        for warning in check::check(program, is_declared) {
+            if log::level() > log::Level::Quiet {
+                format.report(&warning, path, source);
+            }
+        }
+    }
+    if failed {
+        process::exit(65)
+    }
+}
+
+// Scans a script and prints its tokens, one per line with the line and
+// column where each starts, without parsing it.
+fn dump_tokens(file_path: &str, format: Format) {
     match fs::read_to_string(file_path) {
-        Ok(source) => {
-            let exec = run(source);
-            if let Err(e) = exec {
-                println!("{e}");
+        Ok(source) => match Scanner::new(source.clone()).scan_tokens() {
+            Ok(tokens) => {
+                for token in tokens {
+                    println!("{}:{} {}", token.start_line(), token.column, token);
+                }
+            }
+            Err(e) => {
+                format.report(&e.diagnostic(), file_path, &source);
                 process::exit(65)
+            }
+        },
+        Err(error) => {
+            eprintln!("Error reading file: {}", error);
+            process::exit(66)
+        }
+    }
+}
+
+// Parses a script and prints its AST, as a tree or as indented JSON,
+// without running it.
+fn dump_ast(file_path: &str, json: bool, format: Format) {
+    match fs::read_to_string(file_path) {
+        Ok(source) => match parse(source.clone()) {
+            Ok(program) if json => {
+                let json = serialize::program_to_json(&program);
+                println!("{}", json.to_pretty_string())
+            }
+            Ok(program) => print!("{}", printer::print_program(&program)),
+            Err(e) => {
+                format.report(&e.diagnostic(), file_path, &source);
+                process::exit(65)
+            }
+        },
+        Err(error) => {
+            eprintln!("Error reading file: {}", error);
+            process::exit(66)
+        }
+    }
+}
+
+// Parses a script and prints its AST as JSON, to be executed later with --run-ast.
+fn emit_ast(file_path: &str, format: Format) {
+    match fs::read_to_string(file_path) {
+        Ok(source) => match parse(source.clone()) {
+            Ok(program) => println!("{}", serialize::serialize_program(&program)),
+            Err(e) => {
+                format.report(&e.diagnostic(), file_path, &source);
+                process::exit(65)
+            }
+        },
+        Err(error) => {
+            eprintln!("Error reading file: {}", error);
+            process::exit(66)
+        }
+    }
+}
+
+fn run_ast_file(file_path: &str, setup: &Setup, format: Format) {
+    match fs::read_to_string(file_path) {
+        Ok(json) => {
+            let mut engine = new_engine(setup);
+            // The script's source is not at hand, only its line numbers.
+            let result = serialize::deserialize_program(&json)
+                .map_err(InterpreterError::from)
+                .and_then(|program| engine.interpret(&program));
+            if let Err(e) = result {
+                format.report(&e.diagnostic(), file_path, "");
+                process::exit(e.exit_code())
             };
         }
         Err(error) => {
             eprintln!("Error reading file: {}", error);
+            process::exit(66)
         }
     }
 }
 
-fn run(source: String) -> Result<(), InterpreterError> {
-    let mut interpreter = Interpreter::new();
-    let mut scanner = Scanner::new(source);
-    let tokens = scanner.scan_tokens()?;
-    let mut parser = Parser::new(tokens);
-    let program = parser.parse()?;
-    interpreter.interpret(&program)?;
-    Ok(())
+// Rewrites a script in canonical form. The file is left untouched unless
+// it parses successfully.
+fn format_file(file_path: &str, format: Format) {
+    match fs::read_to_string(file_path) {
+        Ok(source) => {
+            let mut scanner = Scanner::new(source.clone());
+            let formatted = match scanner.scan_tokens() {
+                Ok(tokens) => match Parser::new(tokens.clone()).parse() {
+                    Ok(_) => Ok(formatter::format_source(&tokens, scanner.comments())),
+                    Err(e) => Err(e),
+                },
+                Err(e) => Err(e),
+            };
+            match formatted {
+                Ok(text) => {
+                    if let Err(error) = fs::write(file_path, text) {
+                        eprintln!("Error writing file: {}", error);
+                        process::exit(74)
+                    }
+                }
+                Err(e) => {
+                    format.report(&e.diagnostic(), file_path, &source);
+                    process::exit(65)
+                }
+            }
+        }
+        Err(error) => {
+            eprintln!("Error reading file: {}", error);
+            process::exit(66)
+        }
+    }
 }
@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::scanner::LiteralValue;
+
+// A dictionary from strings to values that remembers the order in which its
+// keys were first inserted, so iterating over it is deterministic. Keys
+// share their text with the strings they were set from and are read as.
+#[derive(Debug, Clone, Default)]
+pub struct Map {
+    entries: Vec<(Rc<str>, LiteralValue)>,
+    // Position of each key in `entries`.
+    index: HashMap<Rc<str>, usize>,
+}
+
+impl Map {
+    pub fn new() -> Self {
+        Map::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&LiteralValue> {
+        self.index.get(key).map(|&i| &self.entries[i].1)
+    }
+
+    // Sets the value for a key. A key that is already present keeps its
+    // place in the order.
+    pub fn insert(&mut self, key: Rc<str>, value: LiteralValue) {
+        match self.index.get(&key) {
+            Some(&i) => self.entries[i].1 = value,
+            None => {
+                self.index.insert(Rc::clone(&key), self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<LiteralValue> {
+        let i = self.index.remove(key)?;
+        let (_, value) = self.entries.remove(i);
+        // The entries after it have moved down by one.
+        for (key, _) in &self.entries[i..] {
+            *self.index.get_mut(key).unwrap() -= 1;
+        }
+        Some(value)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.index.clear();
+    }
+
+    // Removes every entry, giving them in order.
+    pub fn drain(&mut self) -> impl Iterator<Item = (Rc<str>, LiteralValue)> + '_ {
+        self.index.clear();
+        self.entries.drain(..)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Rc<str>, &LiteralValue)> {
+        self.entries.iter().map(|(key, value)| (key, value))
+    }
+}
@@ -0,0 +1,161 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::mem::size_of;
+use std::rc::Rc;
+
+use crate::{environment::Environment, scanner::LiteralValue};
+
+// Estimates the memory script values take, to hold scripts to
+// `Limits::memory`. Values are freed as soon as nothing refers to them, with
+// nothing to tell the interpreter so, so it keeps a rough count instead: it
+// adds what it sees scripts allocate, and only once that passes the limit
+// measures what the variables in scope still hold, to see whether the
+// script really is over.
+//
+// The estimates are of what the script's data takes, not of the exact
+// bytes allocated: each variable, list item or map entry counts as the size
+// of a value, plus its name or key, and strings and bytes as their length.
+// Values a script is only in the middle of using, like the arguments of a
+// call being made, are not counted.
+
+// What a variable, list item or map entry takes besides what it holds.
+pub const SLOT: usize = size_of::<LiteralValue>();
+
+// What the variables in `environments` and the scopes around them hold, and
+// what the values in them refer to in turn, counting each list, map or scope
+// once however often it is referred to.
+pub fn measure<'a>(environments: impl IntoIterator<Item = &'a Rc<RefCell<Environment>>>) -> usize {
+    let mut measure = Measure::default();
+    measure
+        .pending
+        .extend(environments.into_iter().cloned().map(Pending::Scope));
+    measure.run();
+    measure.bytes
+}
+
+// What a value takes on its own, as one just made does. What it refers to
+// that something else refers to as well was there before it, and is
+// counted where that holds it, so that a list made of another in a loop
+// does not count the other again each time round.
+pub fn value(value: &LiteralValue) -> usize {
+    let mut measure = Measure::default();
+    measure.value(value);
+    measure.new = true;
+    measure.run();
+    measure.bytes
+}
+
+// What a list, map or bytes holds directly, without what its items refer
+// to, to tell how much a native adds to one it is given.
+pub fn held(value: &LiteralValue) -> usize {
+    match value {
+        LiteralValue::List(items) => items.borrow().len() * SLOT,
+        LiteralValue::Map(map) => map.borrow().len() * SLOT,
+        LiteralValue::Buffer(buffer) => buffer.borrow().len(),
+        LiteralValue::Bytes(bytes) => bytes.borrow().len(),
+        _ => 0,
+    }
+}
+
+// Lists, maps and scopes are measured from a queue rather than by
+// recursion, so that one nested very deep cannot overflow the stack.
+#[derive(Default)]
+struct Measure {
+    bytes: usize,
+    seen: HashSet<*const ()>,
+    pending: Vec<Pending>,
+    // Whether only what a new value alone refers to is counted.
+    new: bool,
+}
+
+enum Pending {
+    Value(LiteralValue),
+    Scope(Rc<RefCell<Environment>>),
+}
+
+impl Measure {
+    fn run(&mut self) {
+        while let Some(pending) = self.pending.pop() {
+            match &pending {
+                Pending::Scope(scope) => {
+                    if !self.first(Rc::as_ptr(scope) as *const ()) {
+                        continue;
+                    }
+                    let scope = scope.borrow();
+                    for (name, value) in scope.values() {
+                        self.bytes += SLOT + name.len();
+                        self.value(value);
+                    }
+                    if let Some(enclosing) = scope.enclosing() {
+                        self.pending.push(Pending::Scope(Rc::clone(enclosing)));
+                    }
+                }
+                Pending::Value(LiteralValue::List(items)) => {
+                    for item in items.borrow().iter() {
+                        self.bytes += SLOT;
+                        self.value(item);
+                    }
+                }
+                Pending::Value(LiteralValue::Map(map)) => {
+                    for (key, value) in map.borrow().iter() {
+                        self.bytes += SLOT + key.len();
+                        self.value(value);
+                    }
+                }
+                Pending::Value(_) => {}
+            }
+        }
+    }
+
+    // Counts what a value holds itself, and queues what it refers to if it
+    // has not been seen.
+    fn value(&mut self, value: &LiteralValue) {
+        let pointer = match value {
+            LiteralValue::String(s) => {
+                self.bytes += s.len();
+                return;
+            }
+            LiteralValue::Function(function) if self.new && Rc::strong_count(function) > 1 => {
+                return;
+            }
+            LiteralValue::Function(function) => {
+                self.pending
+                    .push(Pending::Scope(Rc::clone(&function.closure)));
+                return;
+            }
+            LiteralValue::Buffer(buffer) => Rc::as_ptr(buffer) as *const (),
+            LiteralValue::Bytes(bytes) => Rc::as_ptr(bytes) as *const (),
+            LiteralValue::List(items) => Rc::as_ptr(items) as *const (),
+            LiteralValue::Map(map) => Rc::as_ptr(map) as *const (),
+            _ => return,
+        };
+        if self.new && shared(value) {
+            return;
+        }
+        if !self.first(pointer) {
+            return;
+        }
+        match value {
+            LiteralValue::Buffer(buffer) => self.bytes += buffer.borrow().len(),
+            LiteralValue::Bytes(bytes) => self.bytes += bytes.borrow().len(),
+            _ => self.pending.push(Pending::Value(value.clone())),
+        }
+    }
+
+    // Whether this is the first time the list, map or scope at `pointer`
+    // has been met.
+    fn first(&mut self, pointer: *const ()) -> bool {
+        self.seen.insert(pointer)
+    }
+}
+
+// Whether something besides `value` refers to what it does.
+fn shared(value: &LiteralValue) -> bool {
+    match value {
+        LiteralValue::Buffer(buffer) => Rc::strong_count(buffer) > 1,
+        LiteralValue::Bytes(bytes) => Rc::strong_count(bytes) > 1,
+        LiteralValue::List(items) => Rc::strong_count(items) > 1,
+        LiteralValue::Map(map) => Rc::strong_count(map) > 1,
+        _ => false,
+    }
+}
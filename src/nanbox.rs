@@ -0,0 +1,147 @@
+use std::marker::PhantomData;
+use std::mem;
+use std::rc::Rc;
+
+use crate::{
+    scanner::LiteralValue,
+    vm::{Closure, StackValue},
+};
+
+// The VM's values in eight bytes, rather than the sixteen of a
+// `LiteralValue`, in builds with the `nanbox` feature. A number is kept as
+// its bits. Every other value hides in the bits of a NaN that arithmetic
+// never gives, as NaNs that it does give are all stored as the same one:
+// nil, true and false are NaNs with a small payload, and anything else is
+// moved to the heap, behind a counted reference, whose address is the
+// payload of a NaN with its sign set.
+//
+// Arithmetic on numbers then moves half as much on the stack, and needs no
+// look at a tag before it; other values take an allocation each time they
+// go onto the stack from outside it, and one more step to reach. The
+// scripts in `benches/` compare the two:
+//
+//     cargo run --release -- --backend=vm bench benches/arithmetic.lox
+//     cargo run --release --features nanbox -- --backend=vm bench benches/arithmetic.lox
+
+// The bits every value that is not a number has set: a quiet NaN, and one
+// more bit that no NaN arithmetic gives has.
+const QUIET_NAN: u64 = 0x7ffc_0000_0000_0000;
+const SIGN: u64 = 0x8000_0000_0000_0000;
+const NIL: u64 = QUIET_NAN | 1;
+const FALSE: u64 = QUIET_NAN | 2;
+const TRUE: u64 = QUIET_NAN | 3;
+const OBJECT: u64 = SIGN | QUIET_NAN;
+// The bits an address may use, which are all of them on machines whose
+// addresses fit in 48 bits.
+const ADDRESS: u64 = 0x0000_ffff_ffff_ffff;
+
+pub struct Boxed {
+    bits: u64,
+    // An object is an `Rc`, which may not go to another thread.
+    owns: PhantomData<Rc<LiteralValue>>,
+}
+
+impl Boxed {
+    fn new(bits: u64) -> Boxed {
+        Boxed {
+            bits,
+            owns: PhantomData,
+        }
+    }
+
+    fn object(value: LiteralValue) -> Boxed {
+        let address = Rc::into_raw(Rc::new(value)) as u64;
+        assert!(address & !ADDRESS == 0, "an address does not fit in a NaN");
+        Boxed::new(OBJECT | address)
+    }
+
+    fn address(&self) -> Option<*const LiteralValue> {
+        (self.bits & OBJECT == OBJECT).then_some((self.bits & ADDRESS) as *const LiteralValue)
+    }
+
+    fn get(&self) -> Option<&LiteralValue> {
+        // SAFETY: the address came from `Rc::into_raw`, and this box holds
+        // one of the references to it until it is dropped.
+        self.address().map(|address| unsafe { &*address })
+    }
+}
+
+impl Clone for Boxed {
+    fn clone(&self) -> Boxed {
+        if let Some(address) = self.address() {
+            // SAFETY: as in `get`; the clone holds the new reference.
+            unsafe { Rc::increment_strong_count(address) };
+        }
+        Boxed::new(self.bits)
+    }
+}
+
+impl Drop for Boxed {
+    fn drop(&mut self) {
+        if let Some(address) = self.address() {
+            // SAFETY: as in `get`; the reference this box held is given up.
+            drop(unsafe { Rc::from_raw(address) });
+        }
+    }
+}
+
+impl StackValue for Boxed {
+    fn from_literal(value: LiteralValue) -> Boxed {
+        match value {
+            LiteralValue::Number(n) => Boxed::number(n),
+            LiteralValue::Boolean(b) => Boxed::boolean(b),
+            LiteralValue::Nil => Boxed::nil(),
+            value => Boxed::object(value),
+        }
+    }
+
+    fn number(n: f64) -> Boxed {
+        let n = if n.is_nan() { f64::NAN } else { n };
+        Boxed::new(n.to_bits())
+    }
+
+    fn boolean(b: bool) -> Boxed {
+        Boxed::new(if b { TRUE } else { FALSE })
+    }
+
+    fn nil() -> Boxed {
+        Boxed::new(NIL)
+    }
+
+    fn as_number(&self) -> Option<f64> {
+        (self.bits & QUIET_NAN != QUIET_NAN).then_some(f64::from_bits(self.bits))
+    }
+
+    fn as_closure(&self) -> Option<&Rc<Closure>> {
+        match self.get() {
+            Some(LiteralValue::Closure(closure)) => Some(closure),
+            _ => None,
+        }
+    }
+
+    fn is_truthy(&self) -> bool {
+        self.bits != NIL && self.bits != FALSE
+    }
+
+    fn into_literal(self) -> LiteralValue {
+        let Some(address) = self.address() else {
+            return self.to_literal();
+        };
+        mem::forget(self);
+        // SAFETY: as in `get`; the reference this box held is taken over.
+        let value = unsafe { Rc::from_raw(address) };
+        Rc::try_unwrap(value).unwrap_or_else(|value| (*value).clone())
+    }
+
+    fn to_literal(&self) -> LiteralValue {
+        match self.bits {
+            NIL => LiteralValue::Nil,
+            FALSE => LiteralValue::Boolean(false),
+            TRUE => LiteralValue::Boolean(true),
+            _ => match self.get() {
+                Some(value) => value.clone(),
+                None => LiteralValue::Number(f64::from_bits(self.bits)),
+            },
+        }
+    }
+}
@@ -0,0 +1,1890 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::mem;
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::{
+    capability::Capability,
+    debugger, encoding,
+    environment::Environment,
+    ffi, hash, http,
+    interpreter::{Interpreter, RuntimeError, Test, MAX_DEPTH},
+    json::{self, JsonValue},
+    map::Map,
+    plugin::PluginFn,
+    scanner::{LiteralValue, Token},
+    stack,
+    thread::{Endpoint, Message, Thread},
+    value::Value,
+};
+
+pub type NativeFn =
+    fn(&mut Interpreter, &Token, &[LiteralValue]) -> Result<LiteralValue, RuntimeError>;
+
+// A function implemented outside the script and callable from it. The
+// interpreter checks the number of arguments against `arity` before
+// calling it; a variadic function takes `arity` or more.
+#[derive(Debug)]
+pub struct NativeFunction {
+    pub name: String,
+    pub arity: usize,
+    pub variadic: bool,
+    pub function: NativeCode,
+    // What the interpreter must allow for the function to be called.
+    pub capability: Option<Capability>,
+}
+
+// Natives are written in Rust, come from a plugin, or are registered by the
+// program embedding the interpreter.
+#[derive(Debug, Clone)]
+pub enum NativeCode {
+    Builtin(NativeFn),
+    Plugin(PluginFn),
+    Host(HostFn),
+}
+
+// A closure registered with `Interpreter::register_fn`, wrapped to convert
+// its arguments and result.
+#[derive(Clone)]
+pub struct HostFn(Rc<HostCode>);
+
+type HostCode = dyn Fn(&Token, &[LiteralValue]) -> Result<LiteralValue, RuntimeError>;
+
+impl HostFn {
+    pub fn call(
+        &self,
+        paren: &Token,
+        arguments: &[LiteralValue],
+    ) -> Result<LiteralValue, RuntimeError> {
+        (self.0)(paren, arguments)
+    }
+}
+
+impl fmt::Debug for HostFn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "HostFn")
+    }
+}
+
+// A named group of natives, such as `fs`, whose members are reached with
+// `namespace.member`.
+#[derive(Debug)]
+pub struct Namespace {
+    pub name: String,
+    pub members: HashMap<String, LiteralValue>,
+}
+
+impl Namespace {
+    fn new(name: &str) -> Self {
+        Namespace {
+            name: name.to_string(),
+            members: HashMap::new(),
+        }
+    }
+}
+
+// Where natives can be defined: the global environment or a namespace.
+pub trait Scope {
+    fn define_value(&mut self, name: &str, value: LiteralValue);
+
+    // The capability that natives defined here need.
+    fn capability(&self) -> Option<Capability> {
+        None
+    }
+}
+
+// A scope whose natives need a capability.
+struct Gated<'a, S: Scope> {
+    scope: &'a mut S,
+    capability: Capability,
+}
+
+impl<'a, S: Scope> Gated<'a, S> {
+    fn new(scope: &'a mut S, capability: Capability) -> Self {
+        Gated { scope, capability }
+    }
+}
+
+impl<S: Scope> Scope for Gated<'_, S> {
+    fn define_value(&mut self, name: &str, value: LiteralValue) {
+        self.scope.define_value(name, value);
+    }
+
+    fn capability(&self) -> Option<Capability> {
+        Some(self.capability)
+    }
+}
+
+impl Scope for Environment {
+    fn define_value(&mut self, name: &str, value: LiteralValue) {
+        self.define(name.into(), value);
+    }
+}
+
+impl Scope for Namespace {
+    fn define_value(&mut self, name: &str, value: LiteralValue) {
+        self.members.insert(name.to_string(), value);
+    }
+}
+
+// Defines a native function from a typed parameter list and a body:
+//
+//     define_native!(globals, "len", |s: String| s.chars().count() as f64);
+//
+// The arity comes from the parameter list, and each argument is converted
+// with `FromValue`, reporting a runtime error if it has the wrong type. The
+// body may evaluate to anything implementing `IntoValue`, or to a
+// `Result<_, String>` whose error becomes a runtime error. Natives that need
+// the interpreter name it before a semicolon: `|interpreter; value: LiteralValue|`.
+macro_rules! define_native {
+    ($globals:expr, $name:literal, || $body:expr) => {
+        define_native!($globals, $name, |_interpreter;| $body)
+    };
+    ($globals:expr, $name:literal, |$($arg:ident : $ty:ty),*| $body:expr) => {
+        define_native!($globals, $name, |_interpreter; $($arg : $ty),*| $body)
+    };
+    ($globals:expr, $name:literal, |$interpreter:ident; $($arg:ident : $ty:ty),*| $body:expr) => {{
+        #[allow(unused_mut, unused_variables)]
+        fn native(
+            $interpreter: &mut $crate::interpreter::Interpreter,
+            paren: &$crate::scanner::Token,
+            arguments: &[$crate::scanner::LiteralValue],
+        ) -> Result<$crate::scanner::LiteralValue, $crate::interpreter::RuntimeError> {
+            let mut arguments = arguments.iter().enumerate();
+            $(
+                let $arg: $ty = match arguments.next() {
+                    Some((index, value)) => $crate::natives::from_argument($name, paren, index, value)?,
+                    None => unreachable!("arity is checked before calling a native"),
+                };
+            )*
+            // Binding the result first drops any temporaries in the body,
+            // such as a `RefCell` borrow of an argument, before the
+            // arguments themselves.
+            let result = $body;
+            $crate::natives::NativeResult::into_result(result, paren)
+        }
+        $crate::natives::define($globals, $name, <[&str]>::len(&[$(stringify!($arg)),*]), native);
+    }};
+}
+
+// Registers every native function in the global environment.
+pub fn define_globals(globals: &mut Environment) {
+    // Natives that reach outside the interpreter are defined in a scope
+    // gated by the capability they need; see `capability`.
+    let mut time = Gated::new(globals, Capability::Time);
+    define_native!(&mut time, "clock", || seconds_since_epoch());
+    define_native!(globals, "random", |interpreter;| interpreter.random().next_f64());
+    define_native!(globals, "randomInt", |interpreter; lo: f64, hi: f64| {
+        random_int(interpreter, lo, hi)
+    });
+    define_native!(globals, "seedRandom", |interpreter; seed: f64| {
+        interpreter.random().seed(seed.to_bits())
+    });
+
+    // Strings are indexed by Unicode scalar value, not by byte.
+    define_native!(globals, "len", |value: LiteralValue| match &value {
+        LiteralValue::String(s) => Ok(s.chars().count() as f64),
+        LiteralValue::List(items) => Ok(items.borrow().len() as f64),
+        LiteralValue::Map(map) => Ok(map.borrow().len() as f64),
+        LiteralValue::Buffer(buffer) => Ok(buffer.borrow().chars().count() as f64),
+        LiteralValue::Bytes(bytes) => Ok(bytes.borrow().len() as f64),
+        _ => Err(
+            "Argument 1 to 'len' must be a string, a list, a map, a buffer or bytes.".to_string()
+        ),
+    });
+    define_native!(globals, "substring", |s: String, start: f64, end: f64| {
+        substring(&s, start, end)
+    });
+    // The position of a substring in a string, or of an element in a list,
+    // or -1 when it is absent.
+    define_native!(globals, "indexOf", |interpreter; within: LiteralValue, item: LiteralValue| {
+        index_of(interpreter, &within, &item).map(|i| i.map_or(-1.0, |i| i as f64))
+    });
+    define_native!(globals, "contains", |s: String, needle: String| {
+        s.contains(&needle)
+    });
+    define_native!(globals, "startsWith", |s: String, prefix: String| {
+        s.starts_with(&prefix)
+    });
+    define_native!(globals, "endsWith", |s: String, suffix: String| {
+        s.ends_with(&suffix)
+    });
+    define_native!(globals, "split", |s: String, separator: String| {
+        split(&s, &separator)
+    });
+    // `join` also waits for a thread; see `spawn`.
+    define_variadic(globals, "join", 1, join);
+    define_native!(globals, "trim", |s: String| s.trim().to_string());
+    define_native!(globals, "trimStart", |s: String| s.trim_start().to_string());
+    define_native!(globals, "trimEnd", |s: String| s.trim_end().to_string());
+    // Full Unicode case mapping, so "ß" becomes "SS".
+    define_native!(globals, "toUpper", |s: String| s.to_uppercase());
+    define_native!(globals, "toLower", |s: String| s.to_lowercase());
+    define_native!(globals, "ord", |s: String| {
+        let mut chars = s.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Ok(c as u32 as f64),
+            _ => Err("Argument to 'ord' must be a single character.".to_string()),
+        }
+    });
+    define_native!(globals, "chr", |code: f64| chr(code));
+    // Surrounding whitespace is ignored; anything else that is not a finite
+    // number gives nil.
+    define_native!(globals, "toNumber", |s: String| {
+        s.trim().parse::<f64>().ok().filter(|n| n.is_finite())
+    });
+    define_native!(globals, "toString", |interpreter; value: LiteralValue| {
+        interpreter.stringify(value)
+    });
+    define_native!(globals, "replace", |s: String, from: String, to: String| {
+        if from.is_empty() {
+            Err("Cannot replace an empty string.".to_string())
+        } else {
+            Ok(s.replace(&from, &to))
+        }
+    });
+
+    // Unlike the `print` statement, `write` leaves the cursor on the same
+    // line, so scripts can build up a line piece by piece.
+    define_native!(globals, "write", |interpreter; value: LiteralValue| {
+        let text = interpreter.stringify(value);
+        interpreter.write_output(&text);
+    });
+    define_native!(globals, "writeLine", |interpreter; value: LiteralValue| {
+        let text = interpreter.stringify(value);
+        interpreter.write_output(&format!("{}\n", text));
+    });
+
+    // Diagnostics go to standard error, ending with a newline like `print`,
+    // so they stay out of a pipeline's data.
+    let mut console = Gated::new(globals, Capability::Console);
+    define_native!(&mut console, "eprint", |interpreter; value: LiteralValue| {
+        eprintln!("{}", interpreter.stringify(value));
+    });
+
+    // Both give nil at the end of input. Input is read a line at a time, so
+    // it can be shared with the REPL.
+    define_native!(&mut console, "readLine", || read_line()
+        .map_err(|e| e.to_string()));
+    define_native!(&mut console, "input", |interpreter; prompt: LiteralValue| {
+        let prompt = interpreter.stringify(prompt);
+        interpreter.write_output(&prompt);
+        read_line().map_err(|e| e.to_string())
+    });
+    // Pauses the script for the user to look around; see `debugger`.
+    define(&mut console, "breakpoint", 0, breakpoint);
+    // Frees what only cycles keep alive now rather than when the
+    // interpreter next would, giving how many values that was; see `gc`.
+    define_native!(globals, "gc", |interpreter;| interpreter.collect_garbage() as f64);
+
+    // Tests are declared as the script reaches them, and run by
+    // `interprete test` once it has finished; other runs skip them. An
+    // assertion that does not hold is a runtime error, which fails the test
+    // it is in.
+    define(globals, "test", 2, test);
+    define_native!(
+        globals,
+        "assertEqual",
+        |interpreter; actual: LiteralValue, expected: LiteralValue| {
+            if interpreter.is_equal(&actual, &expected) {
+                Ok(())
+            } else {
+                Err(format!(
+                    "Expected {}, but got {}.",
+                    shown(interpreter, expected),
+                    shown(interpreter, actual)
+                ))
+            }
+        }
+    );
+    define_native!(globals, "assertTrue", |interpreter; value: LiteralValue| {
+        if interpreter.is_truthy(&value) {
+            Ok(())
+        } else {
+            Err(format!("Expected a true value, but got {}.", shown(interpreter, value)))
+        }
+    });
+
+    // Failing to read or write a file is a runtime error naming the path.
+    let mut files = Gated::new(globals, Capability::Fs);
+    define_native!(&mut files, "readFile", |path: String| {
+        fs::read_to_string(&path).map_err(|e| file_error("read", &path, e))
+    });
+    define_native!(&mut files, "writeFile", |path: String, text: String| {
+        fs::write(&path, text).map_err(|e| file_error("write", &path, e))
+    });
+    define_native!(&mut files, "appendFile", |path: String, text: String| {
+        fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&path)
+            .and_then(|mut file| file.write_all(text.as_bytes()))
+            .map_err(|e| file_error("append to", &path, e))
+    });
+
+    // A variable that is unset, or not valid Unicode, reads as nil.
+    let mut environment = Gated::new(globals, Capability::Env);
+    define_native!(&mut environment, "getEnv", |name: String| env::var(&name)
+        .ok());
+    define_native!(&mut environment, "setEnv", |name: String, value: String| {
+        set_env(&name, &value)
+    });
+
+    define_native!(globals, "jsonParse", |text: String| {
+        json::parse(&text)
+            .map_err(|e| e.to_string())
+            .and_then(|json| json_to_value(json, 0))
+    });
+    // `pretty` spreads the text over several indented lines.
+    define_variadic(globals, "jsonStringify", 1, |_, paren, arguments| {
+        let pretty = match arguments {
+            [_] => false,
+            [_, pretty] => from_argument("jsonStringify", paren, 1, pretty)?,
+            _ => {
+                return Err(RuntimeError::ArityMismatch {
+                    token: paren.clone(),
+                    expected: 2,
+                    got: arguments.len(),
+                })
+            }
+        };
+        let json = value_to_json(&arguments[0], &mut HashSet::new())
+            .map_err(|message| RuntimeError::NativeError(paren.clone(), message))?;
+        let text = if pretty {
+            json.to_pretty_string()
+        } else {
+            json.to_string()
+        };
+        Ok(LiteralValue::String(text.into()))
+    });
+
+    // Lists. Reading, replacing or removing an element at an index outside
+    // the list is a runtime error, as is inserting past its end; popping an
+    // empty list gives nil. `slice` copies the elements from a start index
+    // up to an end index, and also works on strings and bytes.
+    define_variadic(globals, "list", 0, |_, _, arguments| {
+        Ok(list(arguments.to_vec()))
+    });
+    // `get` and `set` also work on maps, with string keys.
+    define_native!(
+        globals,
+        "get",
+        |collection: LiteralValue, key: LiteralValue| { get(&collection, &key) }
+    );
+    define_native!(
+        globals,
+        "set",
+        |collection: LiteralValue, key: LiteralValue, value: LiteralValue| {
+            set(&collection, &key, value)
+        }
+    );
+    define_native!(
+        globals,
+        "slice",
+        |value: LiteralValue, start: f64, end: f64| { slice(&value, start, end) }
+    );
+    define_native!(globals, "push", |items: List, value: LiteralValue| {
+        items.borrow_mut().push(value);
+    });
+    define_native!(globals, "pop", |items: List| items.borrow_mut().pop());
+    define_native!(
+        globals,
+        "insert",
+        |items: List, at: f64, value: LiteralValue| {
+            let mut items = items.borrow_mut();
+            index(at, items.len(), "insert").map(|i| items.insert(i, value))
+        }
+    );
+    define_native!(globals, "removeAt", |items: List, i: f64| {
+        let mut items = items.borrow_mut();
+        element(i, items.len(), "removeAt").map(|i| items.remove(i))
+    });
+
+    // Maps. `keys` and `values` list their contents in insertion order;
+    // `remove` gives the removed value, or nil if the key was absent.
+    define_native!(globals, "keys", |map: Rc<RefCell<Map>>| {
+        map.borrow()
+            .iter()
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>()
+    });
+    define_native!(globals, "values", |map: Rc<RefCell<Map>>| {
+        map.borrow()
+            .iter()
+            .map(|(_, value)| value.clone())
+            .collect::<Vec<_>>()
+    });
+    define_native!(globals, "has", |map: Rc<RefCell<Map>>, key: Rc<str>| {
+        map.borrow().get(&key).is_some()
+    });
+    define_native!(globals, "remove", |map: Rc<RefCell<Map>>, key: Rc<str>| {
+        map.borrow_mut().remove(&key)
+    });
+    // A new map with the entries of both, those of `b` taking precedence.
+    define_native!(
+        globals,
+        "merge",
+        |a: Rc<RefCell<Map>>, b: Rc<RefCell<Map>>| {
+            let mut merged = a.borrow().clone();
+            for (key, value) in b.borrow().iter() {
+                merged.insert(key.clone(), value.clone());
+            }
+            LiteralValue::Map(Rc::new(RefCell::new(merged)))
+        }
+    );
+
+    // A buffer collects text in place. It prints as, and `toString` returns,
+    // the text appended so far.
+    define_native!(globals, "buffer", || {
+        LiteralValue::Buffer(Rc::new(RefCell::new(String::new())))
+    });
+    define_native!(globals, "append", |interpreter; buffer: Buffer, value: LiteralValue| {
+        let text = interpreter.stringify(value);
+        buffer.borrow_mut().push_str(&text);
+    });
+
+    // Compares lists, maps and bytes by their contents, recursively, where
+    // `==` only asks whether they are the same list, map or bytes. Maps are
+    // equal when they hold the same keys, in any order. Everything else
+    // compares as with `==`.
+    define_native!(globals, "deepEquals", |interpreter; a: LiteralValue, b: LiteralValue| {
+        deep_equals(interpreter, &a, &b, &mut HashSet::new())
+    });
+
+    // Copies lists and maps recursively, so the copy can be changed without
+    // touching the original; buffers and bytes inside are copied too. A
+    // collection reached twice, including through a cycle, is copied once
+    // and the copy is shared the same way. Functions and namespaces are not
+    // copied: the copy refers to the same ones as the original.
+    define_native!(globals, "deepCopy", |value: LiteralValue| {
+        deep_copy(&value, &mut HashMap::new())
+    });
+
+    // These call back into the function they are given, once per element in
+    // order, and build a new list rather than changing the one given.
+    define(globals, "map", 2, map);
+    define(globals, "filter", 2, filter);
+    define(globals, "reduce", 3, reduce);
+    define_variadic(globals, "sort", 1, sort);
+
+    // Bytes hold binary data. `bytes` makes them from a length, giving that
+    // many zeros, from a list of numbers between 0 and 255, from other bytes,
+    // or from a string in an encoding: "utf8" (the default), "latin1", "hex"
+    // or "base64". `decode` turns them back into a string the same way.
+    define_variadic(globals, "bytes", 1, make_bytes);
+    define_variadic(globals, "decode", 1, decode_bytes);
+    let mut files = Gated::new(globals, Capability::Fs);
+    define_native!(&mut files, "readBytes", |path: String| {
+        fs::read(&path)
+            .map(new_bytes)
+            .map_err(|e| file_error("read", &path, e))
+    });
+    define_native!(&mut files, "writeBytes", |path: String, data: Vec<u8>| {
+        fs::write(&path, data).map_err(|e| file_error("write", &path, e))
+    });
+
+    // Strings are encoded as their UTF-8 bytes, and decoding must give
+    // valid UTF-8 back. The encoders and digests also take bytes.
+    define_native!(globals, "base64Encode", |data: Vec<u8>| {
+        encoding::base64_encode(&data)
+    });
+    define_native!(globals, "base64Decode", |s: String| {
+        encoding::base64_decode(&s).and_then(utf8)
+    });
+    define_native!(globals, "hexEncode", |data: Vec<u8>| {
+        encoding::hex_encode(&data)
+    });
+    define_native!(globals, "hexDecode", |s: String| {
+        encoding::hex_decode(&s).and_then(utf8)
+    });
+
+    // A whole number that is the same for values that are equal.
+    define_native!(globals, "hash", |value: LiteralValue| {
+        // Keep it within the integers a number represents exactly.
+        (hash::hash_value(&value) & ((1 << 53) - 1)) as f64
+    });
+    // Hexadecimal digests of bytes, or of a string's UTF-8 bytes.
+    define_native!(globals, "sha256", |data: Vec<u8>| {
+        encoding::hex_encode(&hash::sha256(&data))
+    });
+    define_native!(globals, "md5", |data: Vec<u8>| {
+        encoding::hex_encode(&hash::md5(&data))
+    });
+
+    // Blocking HTTP requests to http:// URLs, with an optional map of
+    // request headers. The response is a map of the `status` code, the
+    // `headers`, with lowercase names, the `body` as text and its raw
+    // `bytes`.
+    let mut net = Gated::new(globals, Capability::Net);
+    define_variadic(&mut net, "httpGet", 1, http_get);
+    define_variadic(&mut net, "httpPost", 2, http_post);
+
+    // `spawn(function, arguments...)` calls a function on a new thread, in
+    // an interpreter of its own that shares nothing with this one; see
+    // `thread`. The arguments are copied to it, and must be data: nil,
+    // booleans, numbers, strings, buffers, bytes, channel ends, and lists
+    // and maps of these. `join(thread)` waits for the function to finish
+    // and gives back a copy of its result, or fails with the error that
+    // stopped it.
+    define_variadic(
+        &mut Gated::new(globals, Capability::Process),
+        "spawn",
+        1,
+        spawn,
+    );
+    // `channel()` gives a list of a sending and a receiving end. `send`
+    // copies a value, of the kinds `spawn` accepts, into the channel, and
+    // `receive` waits for the next one. Once every sending end is gone and
+    // the channel is empty, `receive` gives nil.
+    define_native!(globals, "channel", || {
+        let (sender, receiver) = Endpoint::channel();
+        list(vec![
+            LiteralValue::Endpoint(Rc::new(sender)),
+            LiteralValue::Endpoint(Rc::new(receiver)),
+        ])
+    });
+    define_native!(
+        globals,
+        "send",
+        |endpoint: Rc<Endpoint>, value: LiteralValue| {
+            Message::from_value(&value).and_then(|message| endpoint.send(message))
+        }
+    );
+    define_native!(globals, "receive", |endpoint: Rc<Endpoint>| {
+        endpoint
+            .receive()
+            .map(|message| message.map(Message::into_value))
+    });
+
+    // Calls a C function in a shared library, or in the interpreter itself
+    // when the library is "", with a list of arguments. The signature gives
+    // the C types of the parameters and the result, as in "(dd)d"; see
+    // `ffi`. A wrong signature can crash the interpreter.
+    define_native!(
+        &mut Gated::new(globals, Capability::Ffi),
+        "ffiCall",
+        |library: String, symbol: String, signature: String, arguments: Vec<LiteralValue>| {
+            ffi::call(&library, &symbol, &signature, &arguments)
+        }
+    );
+
+    // The command line arguments given to a script; see `main::run_file`.
+    globals.define_value("args", list(Vec::<String>::new()));
+
+    let mut namespace = Namespace::new("fs");
+    define_fs(&mut Gated::new(&mut namespace, Capability::Fs));
+    globals.define_value("fs", LiteralValue::Namespace(Rc::new(namespace)));
+
+    define_variadic(globals, "format", 1, |interpreter, paren, arguments| {
+        let text = format_arguments(interpreter, paren, "format", arguments)?;
+        Ok(LiteralValue::String(text.into()))
+    });
+    define_variadic(globals, "printf", 1, |interpreter, paren, arguments| {
+        let text = format_arguments(interpreter, paren, "printf", arguments)?;
+        interpreter.write_output(&text);
+        Ok(LiteralValue::Nil)
+    });
+}
+
+pub fn define(scope: &mut impl Scope, name: &str, arity: usize, function: NativeFn) {
+    let native = NativeFunction {
+        name: name.to_string(),
+        arity,
+        variadic: false,
+        function: NativeCode::Builtin(function),
+        capability: scope.capability(),
+    };
+    scope.define_value(name, LiteralValue::NativeFunction(Rc::new(native)));
+}
+
+// Defines a native taking at least `arity` arguments. Its arguments are
+// passed as they are, without conversion.
+pub fn define_variadic(scope: &mut impl Scope, name: &str, arity: usize, function: NativeFn) {
+    let native = NativeFunction {
+        name: name.to_string(),
+        arity,
+        variadic: true,
+        function: NativeCode::Builtin(function),
+        capability: scope.capability(),
+    };
+    scope.define_value(name, LiteralValue::NativeFunction(Rc::new(native)));
+}
+
+// Defines a native that runs a closure of the host's, taking any number of
+// arguments. They are given to it as `Value`s, and what it gives back is
+// converted as the result of a `define_native!` body is.
+pub fn define_host<R: NativeResult>(
+    scope: &mut impl Scope,
+    name: &str,
+    function: impl Fn(&[Value]) -> R + 'static,
+) {
+    let code = move |paren: &Token, arguments: &[LiteralValue]| {
+        let arguments: Vec<Value> = arguments.iter().map(Value::from_literal).collect();
+        function(&arguments).into_result(paren)
+    };
+    let native = NativeFunction {
+        name: name.to_string(),
+        arity: 0,
+        variadic: true,
+        function: NativeCode::Host(HostFn(Rc::new(code))),
+        capability: scope.capability(),
+    };
+    scope.define_value(name, LiteralValue::NativeFunction(Rc::new(native)));
+}
+
+// Conversion from a script value to the Rust type a native expects.
+pub trait FromValue: Sized {
+    // Describes the accepted values, for error messages: "a number".
+    const EXPECTED: &'static str;
+
+    fn from_value(value: &LiteralValue) -> Option<Self>;
+}
+
+impl FromValue for f64 {
+    const EXPECTED: &'static str = "a number";
+
+    fn from_value(value: &LiteralValue) -> Option<Self> {
+        match value {
+            LiteralValue::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for bool {
+    const EXPECTED: &'static str = "a boolean";
+
+    fn from_value(value: &LiteralValue) -> Option<Self> {
+        match value {
+            LiteralValue::Boolean(b) => Some(*b),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for String {
+    const EXPECTED: &'static str = "a string";
+
+    fn from_value(value: &LiteralValue) -> Option<Self> {
+        match value {
+            LiteralValue::String(s) => Some(s.to_string()),
+            _ => None,
+        }
+    }
+}
+
+// A string without copying its text, for natives that keep it.
+impl FromValue for Rc<str> {
+    const EXPECTED: &'static str = "a string";
+
+    fn from_value(value: &LiteralValue) -> Option<Self> {
+        match value {
+            LiteralValue::String(s) => Some(Rc::clone(s)),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for Vec<LiteralValue> {
+    const EXPECTED: &'static str = "a list";
+
+    fn from_value(value: &LiteralValue) -> Option<Self> {
+        match value {
+            LiteralValue::List(items) => Some(items.borrow().clone()),
+            _ => None,
+        }
+    }
+}
+
+// A list to be modified in place.
+pub type List = Rc<RefCell<Vec<LiteralValue>>>;
+
+impl FromValue for List {
+    const EXPECTED: &'static str = "a list";
+
+    fn from_value(value: &LiteralValue) -> Option<Self> {
+        match value {
+            LiteralValue::List(items) => Some(Rc::clone(items)),
+            _ => None,
+        }
+    }
+}
+
+pub type Buffer = Rc<RefCell<String>>;
+
+impl FromValue for Buffer {
+    const EXPECTED: &'static str = "a buffer";
+
+    fn from_value(value: &LiteralValue) -> Option<Self> {
+        match value {
+            LiteralValue::Buffer(buffer) => Some(Rc::clone(buffer)),
+            _ => None,
+        }
+    }
+}
+
+// Binary data given either as bytes or as a string, which stands for its
+// UTF-8 encoding.
+impl FromValue for Vec<u8> {
+    const EXPECTED: &'static str = "bytes or a string";
+
+    fn from_value(value: &LiteralValue) -> Option<Self> {
+        match value {
+            LiteralValue::Bytes(bytes) => Some(bytes.borrow().clone()),
+            LiteralValue::String(s) => Some(s.as_bytes().to_vec()),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for Rc<Endpoint> {
+    const EXPECTED: &'static str = "a channel end";
+
+    fn from_value(value: &LiteralValue) -> Option<Self> {
+        match value {
+            LiteralValue::Endpoint(endpoint) => Some(Rc::clone(endpoint)),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for Rc<RefCell<Map>> {
+    const EXPECTED: &'static str = "a map";
+
+    fn from_value(value: &LiteralValue) -> Option<Self> {
+        match value {
+            LiteralValue::Map(map) => Some(Rc::clone(map)),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for Value {
+    const EXPECTED: &'static str = "a value";
+
+    fn from_value(value: &LiteralValue) -> Option<Self> {
+        Some(Value::from_literal(value))
+    }
+}
+
+impl FromValue for LiteralValue {
+    const EXPECTED: &'static str = "a value";
+
+    fn from_value(value: &LiteralValue) -> Option<Self> {
+        Some(value.clone())
+    }
+}
+
+pub fn from_argument<T: FromValue>(
+    name: &str,
+    paren: &Token,
+    index: usize,
+    value: &LiteralValue,
+) -> Result<T, RuntimeError> {
+    T::from_value(value).ok_or_else(|| {
+        RuntimeError::NativeError(
+            paren.clone(),
+            format!(
+                "Argument {} to '{}' must be {}.",
+                index + 1,
+                name,
+                T::EXPECTED
+            ),
+        )
+    })
+}
+
+// Conversion from a native's Rust result back to a script value.
+pub trait IntoValue {
+    fn into_value(self) -> LiteralValue;
+}
+
+impl IntoValue for LiteralValue {
+    fn into_value(self) -> LiteralValue {
+        self
+    }
+}
+
+impl IntoValue for Value {
+    fn into_value(self) -> LiteralValue {
+        self.into_literal()
+    }
+}
+
+impl IntoValue for f64 {
+    fn into_value(self) -> LiteralValue {
+        LiteralValue::Number(self)
+    }
+}
+
+impl IntoValue for bool {
+    fn into_value(self) -> LiteralValue {
+        LiteralValue::Boolean(self)
+    }
+}
+
+impl IntoValue for String {
+    fn into_value(self) -> LiteralValue {
+        LiteralValue::String(self.into())
+    }
+}
+
+impl IntoValue for &str {
+    fn into_value(self) -> LiteralValue {
+        LiteralValue::String(self.into())
+    }
+}
+
+impl IntoValue for Rc<str> {
+    fn into_value(self) -> LiteralValue {
+        LiteralValue::String(self)
+    }
+}
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn into_value(self) -> LiteralValue {
+        let items = self.into_iter().map(IntoValue::into_value).collect();
+        LiteralValue::List(Rc::new(RefCell::new(items)))
+    }
+}
+
+impl IntoValue for () {
+    fn into_value(self) -> LiteralValue {
+        LiteralValue::Nil
+    }
+}
+
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn into_value(self) -> LiteralValue {
+        self.map_or(LiteralValue::Nil, IntoValue::into_value)
+    }
+}
+
+// What the body of a `define_native!` may evaluate to.
+pub trait NativeResult {
+    fn into_result(self, paren: &Token) -> Result<LiteralValue, RuntimeError>;
+}
+
+impl<T: IntoValue> NativeResult for T {
+    fn into_result(self, _paren: &Token) -> Result<LiteralValue, RuntimeError> {
+        Ok(self.into_value())
+    }
+}
+
+impl<T: IntoValue> NativeResult for Result<T, String> {
+    fn into_result(self, paren: &Token) -> Result<LiteralValue, RuntimeError> {
+        self.map(IntoValue::into_value)
+            .map_err(|message| RuntimeError::NativeError(paren.clone(), message))
+    }
+}
+
+impl<T: IntoValue> NativeResult for Result<T, RuntimeError> {
+    fn into_result(self, _paren: &Token) -> Result<LiteralValue, RuntimeError> {
+        self.map(IntoValue::into_value)
+    }
+}
+
+fn utf8(bytes: Vec<u8>) -> Result<String, String> {
+    String::from_utf8(bytes).map_err(|_| "Decoded data is not valid UTF-8 text.".to_string())
+}
+
+// Converts parsed JSON to a value, `depth` arrays and objects inside the
+// whole. JSON nested deeper than calls may be is refused, as scripts could
+// not walk it.
+fn json_to_value(mut json: JsonValue, depth: usize) -> Result<LiteralValue, String> {
+    if depth > MAX_DEPTH {
+        return Err(format!(
+            "JSON nested more than {} deep cannot be parsed.",
+            MAX_DEPTH
+        ));
+    }
+    let convert = |value| stack::descend(|| json_to_value(value, depth + 1));
+    Ok(match &mut json {
+        JsonValue::Null => LiteralValue::Nil,
+        JsonValue::Bool(b) => LiteralValue::Boolean(*b),
+        JsonValue::Number(n) => LiteralValue::Number(*n),
+        JsonValue::String(s) => LiteralValue::String(mem::take(s).into()),
+        JsonValue::Array(items) => list(
+            mem::take(items)
+                .into_iter()
+                .map(convert)
+                .collect::<Result<Vec<_>, _>>()?,
+        ),
+        JsonValue::Object(entries) => {
+            let mut map = Map::new();
+            for (key, value) in mem::take(entries) {
+                map.insert(key.into(), convert(value)?);
+            }
+            LiteralValue::Map(Rc::new(RefCell::new(map)))
+        }
+    })
+}
+
+// Converts a value to JSON. `parents` holds the lists and maps being
+// converted, to refuse cycles rather than recurse forever.
+fn value_to_json(
+    value: &LiteralValue,
+    parents: &mut HashSet<*const ()>,
+) -> Result<JsonValue, String> {
+    let json = match value {
+        LiteralValue::Nil => JsonValue::Null,
+        LiteralValue::Boolean(b) => JsonValue::Bool(*b),
+        LiteralValue::Number(n) if n.is_finite() => JsonValue::Number(*n),
+        LiteralValue::Number(n) => return Err(format!("{} cannot be converted to JSON.", n)),
+        LiteralValue::String(s) => JsonValue::String(s.to_string()),
+        LiteralValue::List(items) => {
+            let pointer = Rc::as_ptr(items) as *const ();
+            enter(parents, pointer)?;
+            let items = items
+                .borrow()
+                .iter()
+                .map(|item| stack::descend(|| value_to_json(item, parents)))
+                .collect::<Result<_, _>>()?;
+            parents.remove(&pointer);
+            JsonValue::Array(items)
+        }
+        LiteralValue::Map(map) => {
+            let pointer = Rc::as_ptr(map) as *const ();
+            enter(parents, pointer)?;
+            let entries = map
+                .borrow()
+                .iter()
+                .map(|(key, value)| {
+                    let json = stack::descend(|| value_to_json(value, parents))?;
+                    Ok((key.to_string(), json))
+                })
+                .collect::<Result<_, String>>()?;
+            parents.remove(&pointer);
+            JsonValue::Object(entries)
+        }
+        LiteralValue::Buffer(buffer) => JsonValue::String(buffer.borrow().clone()),
+        LiteralValue::Bytes(bytes) => JsonValue::Array(
+            bytes
+                .borrow()
+                .iter()
+                .map(|&b| JsonValue::Number(b as f64))
+                .collect(),
+        ),
+        LiteralValue::NativeFunction(_)
+        | LiteralValue::Function(_)
+        | LiteralValue::Closure(_)
+        | LiteralValue::Namespace(_)
+        | LiteralValue::Thread(_)
+        | LiteralValue::Endpoint(_) => {
+            return Err(
+                "Functions, namespaces, threads and channels cannot be converted to JSON."
+                    .to_string(),
+            )
+        }
+    };
+    Ok(json)
+}
+
+fn enter(parents: &mut HashSet<*const ()>, collection: *const ()) -> Result<(), String> {
+    if !parents.insert(collection) {
+        return Err("Cannot convert a value that contains itself to JSON.".to_string());
+    }
+    Ok(())
+}
+
+// A new list holding the given values.
+pub fn list<T: IntoValue>(items: Vec<T>) -> LiteralValue {
+    items.into_value()
+}
+
+// Reads a line from standard input without its line ending, or `None` at
+// the end of input.
+pub fn read_line() -> io::Result<Option<String>> {
+    let mut line = String::new();
+    if io::stdin().read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    if line.ends_with('\n') {
+        line.pop();
+        if line.ends_with('\r') {
+            line.pop();
+        }
+    }
+    Ok(Some(line))
+}
+
+// The `fs` namespace, for working with files and directories as a whole.
+fn define_fs(namespace: &mut impl Scope) {
+    define_native!(namespace, "exists", |path: String| Path::new(&path)
+        .exists());
+    // The names of the entries in a directory, sorted.
+    define_native!(namespace, "listDir", |path: String| {
+        list_dir(&path).map_err(|e| file_error("list", &path, e))
+    });
+    // Removes a file or an empty directory.
+    define_native!(namespace, "remove", |path: String| {
+        let removed = if Path::new(&path).is_dir() {
+            fs::remove_dir(&path)
+        } else {
+            fs::remove_file(&path)
+        };
+        removed.map_err(|e| file_error("remove", &path, e))
+    });
+    // Creates a directory along with any missing parents.
+    define_native!(namespace, "mkdir", |path: String| {
+        fs::create_dir_all(&path).map_err(|e| file_error("create", &path, e))
+    });
+}
+
+fn list_dir(path: &str) -> io::Result<Vec<String>> {
+    let mut names = Vec::new();
+    for entry in fs::read_dir(path)? {
+        names.push(entry?.file_name().to_string_lossy().into_owned());
+    }
+    names.sort();
+    Ok(names)
+}
+
+// Sets an environment variable for this process and the ones it starts,
+// rejecting the names and values the operating system cannot store.
+fn set_env(name: &str, value: &str) -> Result<(), String> {
+    if name.is_empty() || name.contains(['=', '\0']) {
+        return Err(format!(
+            "'{}' is not a valid environment variable name.",
+            name
+        ));
+    }
+    if value.contains('\0') {
+        return Err("Environment variable values cannot contain NUL.".to_string());
+    }
+    env::set_var(name, value);
+    Ok(())
+}
+
+fn file_error(action: &str, path: &str, error: io::Error) -> String {
+    format!("Could not {} '{}': {}.", action, path, error)
+}
+
+// Formats `arguments[1..]` according to the template in `arguments[0]`.
+fn format_arguments(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    name: &str,
+    arguments: &[LiteralValue],
+) -> Result<String, RuntimeError> {
+    let template: String = from_argument(name, paren, 0, &arguments[0])?;
+    format_template(interpreter, &template, &arguments[1..])
+        .map_err(|message| RuntimeError::NativeError(paren.clone(), message))
+}
+
+// Expands a printf-style template. Each `%` directive consumes one value:
+//
+//     %[flags][width][.precision]conversion
+//
+// where the flags are `-` (align left), `0` (pad numbers with zeros) and
+// `+` (always show the sign), and the conversions are `d` (whole number),
+// `f` (fixed-point number, six decimals unless a precision is given), `x`
+// (hexadecimal whole number) and `s` (any value, as `print` shows it; a
+// precision truncates it). `%%` writes a single `%`.
+fn format_template(
+    interpreter: &mut Interpreter,
+    template: &str,
+    values: &[LiteralValue],
+) -> Result<String, String> {
+    let mut out = String::new();
+    let mut values = values.iter();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        if chars.peek() == Some(&'%') {
+            chars.next();
+            out.push('%');
+            continue;
+        }
+
+        let mut spec = FormatSpec::default();
+        while let Some(&flag) = chars.peek() {
+            match flag {
+                '-' => spec.left = true,
+                '0' => spec.zero = true,
+                '+' => spec.plus = true,
+                _ => break,
+            }
+            chars.next();
+        }
+        spec.width = digits(&mut chars).unwrap_or(0);
+        if chars.peek() == Some(&'.') {
+            chars.next();
+            spec.precision = Some(digits(&mut chars).unwrap_or(0));
+        }
+        if spec.width.max(spec.precision.unwrap_or(0)) > MAX_FIELD {
+            return Err(format!(
+                "Field widths and precisions in format may be at most {}.",
+                MAX_FIELD
+            ));
+        }
+        let conversion = chars
+            .next()
+            .ok_or_else(|| "Incomplete format directive at end of template.".to_string())?;
+        let value = values
+            .next()
+            .ok_or_else(|| format!("Missing value for '%{}' in format.", conversion))?;
+
+        let (sign, body) = match conversion {
+            'd' | 'x' | 'f' => {
+                let LiteralValue::Number(n) = value else {
+                    return Err(format!("Value for '%{}' must be a number.", conversion));
+                };
+                let magnitude = match conversion {
+                    'd' => format!("{}", n.abs().trunc()),
+                    'x' => format!("{:x}", n.abs().trunc() as u64),
+                    _ => format!("{:.*}", spec.precision.unwrap_or(6), n.abs()),
+                };
+                // Rounding to zero drops the sign, as `-0.4` shows as `0`.
+                let negative = *n < 0.0 && magnitude.chars().any(|d| d != '0' && d != '.');
+                let sign = if negative {
+                    "-"
+                } else if spec.plus {
+                    "+"
+                } else {
+                    ""
+                };
+                (sign, magnitude)
+            }
+            's' => {
+                let text = interpreter.stringify(value.clone());
+                let text = match spec.precision {
+                    Some(precision) => text.chars().take(precision).collect(),
+                    None => text,
+                };
+                spec.zero = false;
+                ("", text)
+            }
+            other => return Err(format!("Unknown format conversion '%{}'.", other)),
+        };
+        spec.pad(&mut out, sign, &body);
+    }
+    if values.next().is_some() {
+        return Err("Too many values for format template.".to_string());
+    }
+    Ok(out)
+}
+
+// The widest field, and the most digits after the point, a directive may
+// ask for, so that a template cannot make a string too long to allocate.
+const MAX_FIELD: usize = 1024;
+
+#[derive(Default)]
+struct FormatSpec {
+    left: bool,
+    zero: bool,
+    plus: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl FormatSpec {
+    // Writes `sign` and `body` padded to the field width.
+    fn pad(&self, out: &mut String, sign: &str, body: &str) {
+        let length = sign.len() + body.chars().count();
+        let padding = self.width.saturating_sub(length);
+        if self.left {
+            out.push_str(sign);
+            out.push_str(body);
+            out.push_str(&" ".repeat(padding));
+        } else if self.zero {
+            out.push_str(sign);
+            out.push_str(&"0".repeat(padding));
+            out.push_str(body);
+        } else {
+            out.push_str(&" ".repeat(padding));
+            out.push_str(sign);
+            out.push_str(body);
+        }
+    }
+}
+
+fn digits(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<usize> {
+    let mut number = None;
+    while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+        chars.next();
+        number = Some(
+            number
+                .unwrap_or(0usize)
+                .saturating_mul(10)
+                .saturating_add(digit as usize),
+        );
+    }
+    number
+}
+
+// A whole number between `lo` and `hi`, both included.
+fn random_int(interpreter: &mut Interpreter, lo: f64, hi: f64) -> Result<f64, String> {
+    if lo.fract() != 0.0 || hi.fract() != 0.0 || !lo.is_finite() || !hi.is_finite() {
+        return Err("Bounds to 'randomInt' must be whole numbers.".to_string());
+    }
+    if lo > hi {
+        return Err("Lower bound to 'randomInt' must not exceed the upper bound.".to_string());
+    }
+    let span = (hi - lo) as u64 + 1;
+    Ok(lo + interpreter.random().below(span) as f64)
+}
+
+// The character with the given code point. Surrogates are not characters.
+fn chr(code: f64) -> Result<String, String> {
+    let character = if code.fract() == 0.0 && (0.0..=u32::MAX as f64).contains(&code) {
+        char::from_u32(code as u32)
+    } else {
+        None
+    };
+    character
+        .map(String::from)
+        .ok_or_else(|| format!("{} is not a Unicode code point.", code))
+}
+
+// Splits `s` at every occurrence of `separator`, or into single characters
+// when the separator is empty.
+fn split(s: &str, separator: &str) -> Vec<String> {
+    if separator.is_empty() {
+        s.chars().map(String::from).collect()
+    } else {
+        s.split(separator).map(String::from).collect()
+    }
+}
+
+// The characters of `s` from `start` up to but not including `end`.
+fn substring(s: &str, start: f64, end: f64) -> Result<String, String> {
+    let length = s.chars().count();
+    let start = index(start, length, "substring")?;
+    let end = index(end, length, "substring")?;
+    if start > end {
+        return Err("Start index to 'substring' must not exceed the end index.".to_string());
+    }
+    Ok(s.chars().skip(start).take(end - start).collect())
+}
+
+fn slice(value: &LiteralValue, start: f64, end: f64) -> Result<LiteralValue, String> {
+    let length = match value {
+        LiteralValue::String(s) => {
+            return substring(s, start, end).map(|s| LiteralValue::String(s.into()))
+        }
+        LiteralValue::List(items) => items.borrow().len(),
+        LiteralValue::Bytes(bytes) => bytes.borrow().len(),
+        _ => return Err("Argument 1 to 'slice' must be a string, a list or bytes.".to_string()),
+    };
+    let start = index(start, length, "slice")?;
+    let end = index(end, length, "slice")?;
+    if start > end {
+        return Err("Start index to 'slice' must not exceed the end index.".to_string());
+    }
+    Ok(match value {
+        LiteralValue::List(items) => list(items.borrow()[start..end].to_vec()),
+        LiteralValue::Bytes(bytes) => new_bytes(bytes.borrow()[start..end].to_vec()),
+        _ => unreachable!(),
+    })
+}
+
+// The longest bytes a script may ask for by length, whatever memory it may
+// hold, so that asking for too many fails instead of aborting the process.
+const MAX_LENGTH: usize = 1 << 30;
+
+// Fails at `paren` if `length` bytes are more than a native may allocate:
+// more than `MAX_LENGTH`, or than the memory limit, if there is one.
+fn check_length(
+    interpreter: &Interpreter,
+    paren: &Token,
+    length: usize,
+) -> Result<(), RuntimeError> {
+    match interpreter.memory_limit() {
+        Some(limit) if length > limit => Err(RuntimeError::OutOfMemory(paren.clone(), limit)),
+        _ if length > MAX_LENGTH => Err(RuntimeError::NativeError(
+            paren.clone(),
+            format!(
+                "{} bytes are too many; at most {} may be made.",
+                length, MAX_LENGTH
+            ),
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn make_bytes(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    arguments: &[LiteralValue],
+) -> Result<LiteralValue, RuntimeError> {
+    let encoding = optional_encoding("bytes", paren, arguments)?;
+    let data = match (&arguments[0], encoding) {
+        (LiteralValue::String(s), encoding) => encoding::encode(s, &encoding),
+        (LiteralValue::Number(n), _) if n.fract() == 0.0 && *n >= 0.0 => {
+            // The cast saturates, so a length too large for memory stays so.
+            check_length(interpreter, paren, *n as usize)?;
+            Ok(vec![0; *n as usize])
+        }
+        (LiteralValue::Number(n), _) => Err(format!("{} is not a valid length for bytes.", n)),
+        (LiteralValue::List(items), _) => items
+            .borrow()
+            .iter()
+            .map(|item| match item {
+                LiteralValue::Number(n) => byte(*n),
+                _ => Err("A list made into bytes must hold only numbers.".to_string()),
+            })
+            .collect(),
+        (LiteralValue::Bytes(bytes), _) => Ok(bytes.borrow().clone()),
+        _ => Err(
+            "Argument 1 to 'bytes' must be a length, a list of numbers, bytes or a string."
+                .to_string(),
+        ),
+    };
+    data.map(new_bytes)
+        .map_err(|message| RuntimeError::NativeError(paren.clone(), message))
+}
+
+fn decode_bytes(
+    _: &mut Interpreter,
+    paren: &Token,
+    arguments: &[LiteralValue],
+) -> Result<LiteralValue, RuntimeError> {
+    let encoding = optional_encoding("decode", paren, arguments)?;
+    let data: Vec<u8> = from_argument("decode", paren, 0, &arguments[0])?;
+    encoding::decode(&data, &encoding)
+        .map(|text| LiteralValue::String(text.into()))
+        .map_err(|message| RuntimeError::NativeError(paren.clone(), message))
+}
+
+// The encoding given as the second of at most two arguments, or "utf8".
+fn optional_encoding(
+    name: &str,
+    paren: &Token,
+    arguments: &[LiteralValue],
+) -> Result<String, RuntimeError> {
+    match arguments {
+        [_] => Ok("utf8".to_string()),
+        [_, encoding] => from_argument(name, paren, 1, encoding),
+        _ => Err(RuntimeError::ArityMismatch {
+            token: paren.clone(),
+            expected: 2,
+            got: arguments.len(),
+        }),
+    }
+}
+
+fn http_get(
+    _: &mut Interpreter,
+    paren: &Token,
+    arguments: &[LiteralValue],
+) -> Result<LiteralValue, RuntimeError> {
+    http_request(paren, "httpGet", "GET", None, arguments)
+}
+
+fn http_post(
+    _: &mut Interpreter,
+    paren: &Token,
+    arguments: &[LiteralValue],
+) -> Result<LiteralValue, RuntimeError> {
+    let body: Vec<u8> = from_argument("httpPost", paren, 1, &arguments[1])?;
+    let mut rest = vec![arguments[0].clone()];
+    rest.extend_from_slice(&arguments[2..]);
+    http_request(paren, "httpPost", "POST", Some(&body), &rest)
+}
+
+// `arguments` holds the URL and, optionally, the map of headers.
+fn http_request(
+    paren: &Token,
+    name: &str,
+    method: &str,
+    body: Option<&[u8]>,
+    arguments: &[LiteralValue],
+) -> Result<LiteralValue, RuntimeError> {
+    let error = |message| RuntimeError::NativeError(paren.clone(), message);
+    let url: String = from_argument(name, paren, 0, &arguments[0])?;
+    let headers = match arguments {
+        [_] => Vec::new(),
+        [_, headers] => {
+            let index = 1 + body.is_some() as usize;
+            let headers: Rc<RefCell<Map>> = from_argument(name, paren, index, headers)?;
+            let headers = headers.borrow();
+            headers
+                .iter()
+                .map(|(key, value)| match value {
+                    LiteralValue::String(value) => Ok((key.to_string(), value.to_string())),
+                    _ => Err(error(format!("Header '{}' must be a string.", key))),
+                })
+                .collect::<Result<_, _>>()?
+        }
+        _ => {
+            return Err(RuntimeError::ArityMismatch {
+                token: paren.clone(),
+                expected: if body.is_some() { 3 } else { 2 },
+                got: arguments.len() + body.is_some() as usize,
+            })
+        }
+    };
+    let response = http::request(method, &url, &headers, body).map_err(error)?;
+
+    let mut response_headers = Map::new();
+    for (key, value) in response.headers {
+        response_headers.insert(key.into(), LiteralValue::String(value.into()));
+    }
+    let mut map = Map::new();
+    map.insert(
+        "status".into(),
+        LiteralValue::Number(response.status as f64),
+    );
+    map.insert(
+        "headers".into(),
+        LiteralValue::Map(Rc::new(RefCell::new(response_headers))),
+    );
+    map.insert(
+        "body".into(),
+        LiteralValue::String(String::from_utf8_lossy(&response.body).into()),
+    );
+    map.insert("bytes".into(), new_bytes(response.body));
+    Ok(LiteralValue::Map(Rc::new(RefCell::new(map))))
+}
+
+fn join(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    arguments: &[LiteralValue],
+) -> Result<LiteralValue, RuntimeError> {
+    let error = |message| RuntimeError::NativeError(paren.clone(), message);
+    match arguments {
+        [LiteralValue::Thread(thread)] => {
+            let result = thread.join().map_err(error);
+            interpreter.forward_thread_output();
+            result
+        }
+        [_] => Err(error(
+            "'join' takes a thread, or a list and a separator.".to_string(),
+        )),
+        [items, separator] => {
+            let items: Vec<LiteralValue> = from_argument("join", paren, 0, items)?;
+            let separator: String = from_argument("join", paren, 1, separator)?;
+            let items: Vec<String> = items
+                .into_iter()
+                .map(|item| interpreter.stringify(item))
+                .collect();
+            Ok(LiteralValue::String(items.join(&separator).into()))
+        }
+        _ => Err(RuntimeError::ArityMismatch {
+            token: paren.clone(),
+            expected: 2,
+            got: arguments.len(),
+        }),
+    }
+}
+
+fn spawn(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    arguments: &[LiteralValue],
+) -> Result<LiteralValue, RuntimeError> {
+    let error = |message| RuntimeError::NativeError(paren.clone(), message);
+    let function = match &arguments[0] {
+        LiteralValue::Function(function) => function,
+        // Threads run their functions on the tree-walker.
+        LiteralValue::Closure(_) => {
+            return Err(error(
+                "'spawn' is not supported by the VM backend yet.".to_string(),
+            ))
+        }
+        _ => {
+            return Err(error(
+                "Argument 1 to 'spawn' must be a function declared in the script.".to_string(),
+            ))
+        }
+    };
+    if arguments.len() - 1 != function.arity() {
+        return Err(RuntimeError::ArityMismatch {
+            token: paren.clone(),
+            expected: function.arity(),
+            got: arguments.len() - 1,
+        });
+    }
+    let messages = arguments[1..]
+        .iter()
+        .map(Message::from_value)
+        .collect::<Result<_, _>>()
+        .map_err(error)?;
+    let thread = Thread::spawn(function, messages, interpreter.thread_setup()).map_err(error)?;
+    Ok(LiteralValue::Thread(Rc::new(thread)))
+}
+
+fn byte(value: f64) -> Result<u8, String> {
+    if value.fract() != 0.0 || !(0.0..=255.0).contains(&value) {
+        return Err(format!("{} is not a byte value from 0 to 255.", value));
+    }
+    Ok(value as u8)
+}
+
+fn new_bytes(data: Vec<u8>) -> LiteralValue {
+    LiteralValue::Bytes(Rc::new(RefCell::new(data)))
+}
+
+fn breakpoint(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    _arguments: &[LiteralValue],
+) -> Result<LiteralValue, RuntimeError> {
+    debugger::pause(interpreter, paren.line)
+        .map_err(|e| RuntimeError::NativeError(paren.clone(), e.to_string()))?;
+    Ok(LiteralValue::Nil)
+}
+
+fn test(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    arguments: &[LiteralValue],
+) -> Result<LiteralValue, RuntimeError> {
+    let name: String = from_argument("test", paren, 0, &arguments[0])?;
+    let (LiteralValue::Function(_) | LiteralValue::Closure(_) | LiteralValue::NativeFunction(_)) =
+        &arguments[1]
+    else {
+        let message = "Argument 2 to 'test' must be a function.".to_string();
+        return Err(RuntimeError::NativeError(paren.clone(), message));
+    };
+    interpreter.add_test(Test {
+        name,
+        function: arguments[1].clone(),
+        token: paren.clone(),
+    });
+    Ok(LiteralValue::Nil)
+}
+
+// A value as an assertion describes it, with strings quoted so that `"1"`
+// and `1` can be told apart.
+fn shown(interpreter: &Interpreter, value: LiteralValue) -> String {
+    match &value {
+        LiteralValue::String(s) => format!("\"{}\"", s),
+        _ => interpreter.stringify(value),
+    }
+}
+
+fn map(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    arguments: &[LiteralValue],
+) -> Result<LiteralValue, RuntimeError> {
+    let items: Vec<LiteralValue> = from_argument("map", paren, 0, &arguments[0])?;
+    let mut mapped = Vec::with_capacity(items.len());
+    for item in items {
+        mapped.push(interpreter.call_value(arguments[1].clone(), paren, vec![item])?);
+    }
+    Ok(list(mapped))
+}
+
+fn filter(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    arguments: &[LiteralValue],
+) -> Result<LiteralValue, RuntimeError> {
+    let items: Vec<LiteralValue> = from_argument("filter", paren, 0, &arguments[0])?;
+    let mut kept = Vec::new();
+    for item in items {
+        let keep = interpreter.call_value(arguments[1].clone(), paren, vec![item.clone()])?;
+        if interpreter.is_truthy(&keep) {
+            kept.push(item);
+        }
+    }
+    Ok(list(kept))
+}
+
+// Folds a list from the left: `reduce([1, 2], f, 0)` is `f(f(0, 1), 2)`.
+fn reduce(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    arguments: &[LiteralValue],
+) -> Result<LiteralValue, RuntimeError> {
+    let items: Vec<LiteralValue> = from_argument("reduce", paren, 0, &arguments[0])?;
+    let mut accumulator = arguments[2].clone();
+    for item in items {
+        accumulator =
+            interpreter.call_value(arguments[1].clone(), paren, vec![accumulator, item])?;
+    }
+    Ok(accumulator)
+}
+
+// Returns a sorted copy of a list. Without a comparator, the elements must
+// be all numbers or all strings, which sort in ascending order. A
+// comparator is called as `compare(a, b)` and must return a number: below
+// zero when `a` goes first, above zero when `b` does, and zero to keep them
+// in their original order. If it fails or returns anything else, `sort`
+// fails with that error and the list is left as it was.
+fn sort(
+    interpreter: &mut Interpreter,
+    paren: &Token,
+    arguments: &[LiteralValue],
+) -> Result<LiteralValue, RuntimeError> {
+    let items: Vec<LiteralValue> = from_argument("sort", paren, 0, &arguments[0])?;
+    let sorted = match arguments {
+        [_] => merge_sort(items, &mut |a, b| default_order(paren, a, b))?,
+        [_, compare] => merge_sort(items, &mut |a, b| {
+            let order =
+                interpreter.call_value(compare.clone(), paren, vec![a.clone(), b.clone()])?;
+            match order {
+                LiteralValue::Number(n) if !n.is_nan() => Ok(n.partial_cmp(&0.0).unwrap()),
+                _ => Err(RuntimeError::NativeError(
+                    paren.clone(),
+                    "Comparator given to 'sort' must return a number.".to_string(),
+                )),
+            }
+        })?,
+        _ => {
+            return Err(RuntimeError::ArityMismatch {
+                token: paren.clone(),
+                expected: 2,
+                got: arguments.len(),
+            })
+        }
+    };
+    Ok(list(sorted))
+}
+
+fn default_order(
+    paren: &Token,
+    a: &LiteralValue,
+    b: &LiteralValue,
+) -> Result<Ordering, RuntimeError> {
+    match (a, b) {
+        (LiteralValue::Number(a), LiteralValue::Number(b)) => Ok(a.total_cmp(b)),
+        (LiteralValue::String(a), LiteralValue::String(b)) => Ok(a.cmp(b)),
+        _ => Err(RuntimeError::NativeError(
+            paren.clone(),
+            "Without a comparator, 'sort' needs a list of only numbers or only strings."
+                .to_string(),
+        )),
+    }
+}
+
+// A stable merge sort with a comparison that may fail. Unlike the standard
+// library's sorts, it gives a result, if not a meaningful one, even when
+// the comparison is not a consistent order, as a script's may not be.
+fn merge_sort<T: Clone>(
+    items: Vec<T>,
+    compare: &mut dyn FnMut(&T, &T) -> Result<Ordering, RuntimeError>,
+) -> Result<Vec<T>, RuntimeError> {
+    if items.len() <= 1 {
+        return Ok(items);
+    }
+    let mut left = items;
+    let right = left.split_off(left.len() / 2);
+    let left = merge_sort(left, compare)?;
+    let right = merge_sort(right, compare)?;
+
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let (mut i, mut j) = (0, 0);
+    while i < left.len() && j < right.len() {
+        if compare(&right[j], &left[i])? == Ordering::Less {
+            merged.push(right[j].clone());
+            j += 1;
+        } else {
+            merged.push(left[i].clone());
+            i += 1;
+        }
+    }
+    merged.extend_from_slice(&left[i..]);
+    merged.extend_from_slice(&right[j..]);
+    Ok(merged)
+}
+
+// `assumed` holds the pairs of collections already being compared further
+// up. Meeting such a pair again means following a cycle, and the pair is
+// taken to be equal unless some other difference turns up.
+fn deep_equals(
+    interpreter: &Interpreter,
+    a: &LiteralValue,
+    b: &LiteralValue,
+    assumed: &mut HashSet<(*const (), *const ())>,
+) -> bool {
+    let pair = match (a, b) {
+        (LiteralValue::List(x), LiteralValue::List(y)) => {
+            (Rc::as_ptr(x) as *const (), Rc::as_ptr(y) as *const ())
+        }
+        (LiteralValue::Map(x), LiteralValue::Map(y)) => {
+            (Rc::as_ptr(x) as *const (), Rc::as_ptr(y) as *const ())
+        }
+        (LiteralValue::Bytes(x), LiteralValue::Bytes(y)) => return *x.borrow() == *y.borrow(),
+        _ => return interpreter.is_equal(a, b),
+    };
+    if pair.0 == pair.1 || !assumed.insert(pair) {
+        return true;
+    }
+    match (a, b) {
+        (LiteralValue::List(x), LiteralValue::List(y)) => {
+            let (x, y) = (x.borrow(), y.borrow());
+            x.len() == y.len()
+                && x.iter()
+                    .zip(y.iter())
+                    .all(|(a, b)| stack::descend(|| deep_equals(interpreter, a, b, assumed)))
+        }
+        (LiteralValue::Map(x), LiteralValue::Map(y)) => {
+            let (x, y) = (x.borrow(), y.borrow());
+            x.len() == y.len()
+                && x.iter().all(|(key, a)| {
+                    y.get(key)
+                        .is_some_and(|b| stack::descend(|| deep_equals(interpreter, a, b, assumed)))
+                })
+        }
+        _ => unreachable!(),
+    }
+}
+
+// `copies` maps each collection copied so far to its copy.
+fn deep_copy(value: &LiteralValue, copies: &mut HashMap<*const (), LiteralValue>) -> LiteralValue {
+    let pointer = match value {
+        LiteralValue::List(items) => Rc::as_ptr(items) as *const (),
+        LiteralValue::Map(map) => Rc::as_ptr(map) as *const (),
+        LiteralValue::Buffer(buffer) => {
+            return LiteralValue::Buffer(Rc::new(RefCell::new(buffer.borrow().clone())))
+        }
+        LiteralValue::Bytes(bytes) => return new_bytes(bytes.borrow().clone()),
+        _ => return value.clone(),
+    };
+    if let Some(copy) = copies.get(&pointer) {
+        return copy.clone();
+    }
+    match value {
+        LiteralValue::List(items) => {
+            let copy = Rc::new(RefCell::new(Vec::new()));
+            copies.insert(pointer, LiteralValue::List(Rc::clone(&copy)));
+            let items = items.borrow().clone();
+            let items = items
+                .iter()
+                .map(|item| stack::descend(|| deep_copy(item, copies)))
+                .collect();
+            *copy.borrow_mut() = items;
+            LiteralValue::List(copy)
+        }
+        LiteralValue::Map(map) => {
+            let copy = Rc::new(RefCell::new(Map::new()));
+            copies.insert(pointer, LiteralValue::Map(Rc::clone(&copy)));
+            let entries: Vec<(Rc<str>, LiteralValue)> = map
+                .borrow()
+                .iter()
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect();
+            for (key, value) in entries {
+                let value = stack::descend(|| deep_copy(&value, copies));
+                copy.borrow_mut().insert(key, value);
+            }
+            LiteralValue::Map(copy)
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn get(collection: &LiteralValue, key: &LiteralValue) -> Result<LiteralValue, String> {
+    match (collection, key) {
+        (LiteralValue::List(items), LiteralValue::Number(i)) => {
+            let items = items.borrow();
+            element(*i, items.len(), "get").map(|i| items[i].clone())
+        }
+        (LiteralValue::Bytes(bytes), LiteralValue::Number(i)) => {
+            let bytes = bytes.borrow();
+            element(*i, bytes.len(), "get").map(|i| LiteralValue::Number(bytes[i] as f64))
+        }
+        (LiteralValue::Map(map), LiteralValue::String(key)) => map
+            .borrow()
+            .get(key)
+            .cloned()
+            .ok_or_else(|| format!("Map has no key '{}'.", key)),
+        _ => Err(collection_error("get")),
+    }
+}
+
+fn set(
+    collection: &LiteralValue,
+    key: &LiteralValue,
+    value: LiteralValue,
+) -> Result<LiteralValue, String> {
+    match (collection, key) {
+        (LiteralValue::List(items), LiteralValue::Number(i)) => {
+            let mut items = items.borrow_mut();
+            let i = element(*i, items.len(), "set")?;
+            items[i] = value.clone();
+        }
+        (LiteralValue::Bytes(bytes), LiteralValue::Number(i)) => {
+            let byte = match value {
+                LiteralValue::Number(n) => byte(n)?,
+                _ => return Err("Only numbers can be stored in bytes.".to_string()),
+            };
+            let mut bytes = bytes.borrow_mut();
+            let i = element(*i, bytes.len(), "set")?;
+            bytes[i] = byte;
+        }
+        (LiteralValue::Map(map), LiteralValue::String(key)) => {
+            map.borrow_mut().insert(Rc::clone(key), value.clone());
+        }
+        _ => return Err(collection_error("set")),
+    }
+    Ok(value)
+}
+
+fn collection_error(name: &str) -> String {
+    format!(
+        "'{}' takes a list or bytes and an index, or a map and a string key.",
+        name
+    )
+}
+
+// Checks that a number is a whole index no greater than `length`.
+fn index(value: f64, length: usize, name: &str) -> Result<usize, String> {
+    if value.fract() != 0.0 || value < 0.0 || value > length as f64 {
+        return Err(format!(
+            "Index {} to '{}' is out of range 0..{}.",
+            value, name, length
+        ));
+    }
+    Ok(value as usize)
+}
+
+// Checks that a number is the index of one of `length` elements.
+fn element(value: f64, length: usize, name: &str) -> Result<usize, String> {
+    if value.fract() != 0.0 || value < 0.0 || value >= length as f64 {
+        return Err(format!(
+            "Index {} to '{}' is out of bounds for length {}.",
+            value, name, length
+        ));
+    }
+    Ok(value as usize)
+}
+
+fn index_of(
+    interpreter: &Interpreter,
+    haystack: &LiteralValue,
+    needle: &LiteralValue,
+) -> Result<Option<usize>, String> {
+    match (haystack, needle) {
+        (LiteralValue::String(s), LiteralValue::String(needle)) => {
+            Ok(s.find(&**needle).map(|byte| s[..byte].chars().count()))
+        }
+        (LiteralValue::String(_), _) => {
+            Err("Argument 2 to 'indexOf' must be a string when searching a string.".to_string())
+        }
+        (LiteralValue::List(items), needle) => Ok(items
+            .borrow()
+            .iter()
+            .position(|item| interpreter.is_equal(item, needle))),
+        _ => Err("Argument 1 to 'indexOf' must be a string or a list.".to_string()),
+    }
+}
+
+// Seconds since the Unix epoch, for timing scripts and seeding `random`.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn seconds_since_epoch() -> f64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs_f64())
+        .unwrap_or(0.0)
+}
+
+// WebAssembly has no clock of its own, so the browser's is used.
+#[cfg(target_arch = "wasm32")]
+pub use crate::wasm::seconds_since_epoch;
@@ -1,28 +1,126 @@
+use std::sync::Arc;
+
 use crate::{
-    expr::Expr,
-    scanner::{LiteralValue, ParseError, Token, TokenType},
-    stmt::Stmt,
+    ast::{Ast, Program},
+    expr::{Expr, ExprId},
+    resolver,
+    scanner::{Literal, ParseError, Token, TokenType},
+    stack,
+    stmt::{Stmt, StmtId},
 };
 
+// How deeply expressions and statements may nest before parsing gives up.
+// Recursive descent uses the Rust stack, so without a limit pathological
+// input such as ten thousand '(' would overflow it and abort the process.
+pub const DEFAULT_MAX_DEPTH: usize = 256;
+
 pub struct Parser {
     tokens: Vec<Token>,
     current: usize,
+    ast: Ast,
+    depth: usize,
+    max_depth: usize,
+    // How many function bodies enclose the current token, to reject
+    // `return` outside of one.
+    function_depth: usize,
 }
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Self {
-        Parser { tokens, current: 0 }
+        Parser::with_max_depth(tokens, DEFAULT_MAX_DEPTH)
+    }
+
+    // The scanner ends the tokens with `Eof`, and one is added to those a
+    // host gives without it, so that parsing always has a token to look at.
+    pub fn with_max_depth(mut tokens: Vec<Token>, max_depth: usize) -> Self {
+        if tokens.last().map(|token| token.token_type) != Some(TokenType::Eof) {
+            let line = tokens.last().map_or(1, |token| token.line);
+            tokens.push(Token::new(TokenType::Eof, String::new(), None, line, 0));
+        }
+        Parser {
+            tokens,
+            current: 0,
+            ast: Ast::new(),
+            depth: 0,
+            max_depth,
+            function_depth: 0,
+        }
     }
 
-    pub fn parse(&mut self) -> Result<Vec<Stmt>, ParseError> {
+    pub fn parse(&mut self) -> Result<Program, ParseError> {
         let mut statements = Vec::new();
         while !self.is_at_end() {
             statements.push(self.declaration()?);
         }
-        Ok(statements)
+        resolver::resolve(&mut self.ast, &statements);
+        Ok(Program {
+            ast: Arc::new(std::mem::take(&mut self.ast)),
+            statements,
+        })
+    }
+
+    // Parses the whole program like `parse`, but after an error carries on
+    // from the next statement, so that every error can be reported.
+    pub fn parse_all(&mut self) -> Result<Program, Vec<ParseError>> {
+        let mut statements = Vec::new();
+        let mut errors = Vec::new();
+        while !self.is_at_end() {
+            match self.declaration() {
+                Ok(statement) => statements.push(statement),
+                Err(error) => {
+                    errors.push(error);
+                    self.synchronize();
+                }
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        resolver::resolve(&mut self.ast, &statements);
+        Ok(Program {
+            ast: Arc::new(std::mem::take(&mut self.ast)),
+            statements,
+        })
     }
 
-    fn declaration(&mut self) -> Result<Stmt, ParseError> {
+    // Skips ahead after an error to where the next statement probably
+    // starts: just past a `;`, or at a keyword that begins a statement.
+    fn synchronize(&mut self) {
+        self.advance();
+        while !self.is_at_end() {
+            if self.previous().token_type == TokenType::Semicolon {
+                return;
+            }
+            match self.peek().token_type {
+                TokenType::Class
+                | TokenType::Fun
+                | TokenType::Var
+                | TokenType::For
+                | TokenType::If
+                | TokenType::While
+                | TokenType::Print
+                | TokenType::Return => return,
+                _ => {
+                    self.advance();
+                }
+            }
+        }
+    }
+
+    // Parses the tokens as one bare expression, such as `1 + 2`, rejecting
+    // anything left over after it.
+    pub fn parse_expression(&mut self) -> Result<(Ast, ExprId), ParseError> {
+        let expr = self.expression()?;
+        if !self.is_at_end() {
+            return Err(ParseError::UnexpectedToken(
+                self.peek().clone(),
+                "Expect end of expression.".to_string(),
+            ));
+        }
+        Ok((std::mem::take(&mut self.ast), expr))
+    }
+
+    fn declaration(&mut self) -> Result<StmtId, ParseError> {
         if self.match_token(&[TokenType::Var]) {
             self.var_declaration()
         }
@@ -38,7 +136,7 @@ impl Parser {
         }
     }
 
-    fn var_declaration(&mut self) -> Result<Stmt, ParseError> {
+    fn var_declaration(&mut self) -> Result<StmtId, ParseError> {
         let name = self.consume(TokenType::Identifier, "Expect variable name.")?;
         let initializer = if self.match_token(&[TokenType::Equal]) {
             Some(self.expression()?)
@@ -50,10 +148,10 @@ impl Parser {
             TokenType::Semicolon,
             "Expect ';' after variable declaration.",
         )?;
-        Ok(Stmt::Var(name, initializer))
+        Ok(self.ast.add_stmt(Stmt::Var(name, initializer)))
     }
 
-    fn function(&mut self, kind: &str) -> Result<Stmt, ParseError> {
+    fn function(&mut self, kind: &str) -> Result<StmtId, ParseError> {
         let name = self.consume(TokenType::Identifier, &format!("Expect {} name.", kind))?;
 
         self.consume(TokenType::LeftParen, &format!("Expect '(' after {} name.", kind))?;
@@ -74,27 +172,36 @@ impl Parser {
         self.consume(TokenType::RightParen, "Expect ')' after parameters.")?;
 
         self.consume(TokenType::LeftBrace, &format!("Expect '{{' before {} body.", kind))?;
-        let body = self.block()?;
-        Ok(Stmt::Function(name, parameters, body))
+        self.function_depth += 1;
+        let body = self.block();
+        self.function_depth -= 1;
+        Ok(self.ast.add_stmt(Stmt::Function(name, parameters, body?)))
     }
 
-    fn statement(&mut self) -> Result<Stmt, ParseError> {
+    fn statement(&mut self) -> Result<StmtId, ParseError> {
+        self.nested(Self::statement_inner)
+    }
+
+    fn statement_inner(&mut self) -> Result<StmtId, ParseError> {
         if self.match_token(&[TokenType::If]) {
             self.if_statement()
         } else if self.match_token(&[TokenType::Print]) {
             self.print_statement() 
+        } else if self.match_token(&[TokenType::Return]) {
+            self.return_statement()
         } else if self.match_token(&[TokenType::While]) {
             self.while_statement()
         } else if self.match_token(&[TokenType::For]) {
             self.for_statement()
         }else if self.match_token(&[TokenType::LeftBrace]) {
-            Ok(Stmt::Block(self.block()?))
+            let statements = self.block()?;
+            Ok(self.ast.add_stmt(Stmt::Block(statements)))
         } else {
             self.expression_statement()
         }
     }
 
-    fn block(&mut self) -> Result<Vec<Stmt>, ParseError> {
+    fn block(&mut self) -> Result<Vec<StmtId>, ParseError> {
         let mut statements = Vec::new();
         while !self.check(TokenType::RightBrace) && !self.is_at_end() {
             statements.push(self.declaration()?);
@@ -103,15 +210,32 @@ impl Parser {
         Ok(statements)
     }
 
-    fn while_statement(&mut self) -> Result<Stmt, ParseError> {
+    fn return_statement(&mut self) -> Result<StmtId, ParseError> {
+        let keyword = self.previous().clone();
+        if self.function_depth == 0 {
+            return Err(ParseError::UnexpectedToken(
+                keyword,
+                "Can't return from top-level code.".to_string(),
+            ));
+        }
+        let value = if self.check(TokenType::Semicolon) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        self.consume(TokenType::Semicolon, "Expect ';' after return value.")?;
+        Ok(self.ast.add_stmt(Stmt::Return(keyword, value)))
+    }
+
+    fn while_statement(&mut self) -> Result<StmtId, ParseError> {
         self.consume(TokenType::LeftParen, "Expect '(' after 'while'.")?;
         let condition = self.expression()?;
         self.consume(TokenType::RightParen, "Expect ')' after while condition.")?;
         let body = self.statement()?;
-        Ok(Stmt::While(condition, Box::new(body)))
+        Ok(self.ast.add_stmt(Stmt::While(condition, body)))
     }
 
-    fn for_statement(&mut self) -> Result<Stmt, ParseError> {
+    fn for_statement(&mut self) -> Result<StmtId, ParseError> {
         self.consume(TokenType::LeftParen, "Expect '(' after 'for'.")?;
 
         let initializer = if self.match_token(&[TokenType::Semicolon]) {
@@ -125,7 +249,7 @@ impl Parser {
         let condition = if !self.check(TokenType::Semicolon) {
             self.expression()?
         } else {
-            Expr::Literal(None) // Equivalent to true
+            self.ast.add_expr(Expr::Literal(None)) // Equivalent to true
         };
         self.consume(TokenType::Semicolon, "Expect ';' after loop condition.")?;
 
@@ -139,59 +263,68 @@ impl Parser {
         let mut body = self.statement()?;
 
         if let Some(increment_expr) = increment {
-            body = Stmt::Block(vec![body, Stmt::Expression(increment_expr)]);
+            let increment_stmt = self.ast.add_stmt(Stmt::Expression(increment_expr));
+            body = self.ast.add_stmt(Stmt::Block(vec![body, increment_stmt]));
         }
 
-        body = Stmt::While(condition, Box::new(body));
+        body = self.ast.add_stmt(Stmt::While(condition, body));
 
         if let Some(initializer_stmt) = initializer {
-            body = Stmt::Block(vec![initializer_stmt, body]);
+            body = self.ast.add_stmt(Stmt::Block(vec![initializer_stmt, body]));
         }
 
         Ok(body)
     }
 
-    fn if_statement(&mut self) -> Result<Stmt, ParseError> {
+    fn if_statement(&mut self) -> Result<StmtId, ParseError> {
         self.consume(TokenType::LeftParen, "Expect '(' after 'if'.")?;
         let condition = self.expression()?;
         self.consume(TokenType::RightParen, "Expect ')' after if condition.")?;
 
         let then_branch = self.statement()?;
         let else_branch = if self.match_token(&[TokenType::Else]) {
-            Some(Box::new(self.statement()?))
+            Some(self.statement()?)
         } else {
             None
         };
 
-        Ok(Stmt::If(condition, Box::new(then_branch), else_branch))
+        Ok(self
+            .ast
+            .add_stmt(Stmt::If(condition, then_branch, else_branch)))
     }
 
-    fn print_statement(&mut self) -> Result<Stmt, ParseError> {
+    fn print_statement(&mut self) -> Result<StmtId, ParseError> {
         let value = self.expression()?;
         self.consume(TokenType::Semicolon, "Expect ';' after value.")?;
-        Ok(Stmt::Print(value))
+        Ok(self.ast.add_stmt(Stmt::Print(value)))
     }
 
-    fn expression_statement(&mut self) -> Result<Stmt, ParseError> {
+    fn expression_statement(&mut self) -> Result<StmtId, ParseError> {
         let value = self.expression()?;
         self.consume(TokenType::Semicolon, "Expect ';' after value.")?;
-        Ok(Stmt::Expression(value))
+        Ok(self.ast.add_stmt(Stmt::Expression(value)))
     }
 
-    fn expression(&mut self) -> Result<Expr, ParseError> {
-        self.assignment()
+    fn expression(&mut self) -> Result<ExprId, ParseError> {
+        self.nested(Self::assignment)
     }
 
-    fn assignment(&mut self) -> Result<Expr, ParseError> {
+    fn assignment(&mut self) -> Result<ExprId, ParseError> {
         let expr = self.or()?;
 
         if self.match_token(&[TokenType::Equal]) {
             let equals = self.previous().clone();
-            let value = self.assignment()?;
+            let value = self.nested(Self::assignment)?;
 
-            match expr {
-                Expr::Variable(name) => Ok(Expr::Assignment(name, Box::new(value))),
-                Expr::Get(object, name) => Ok(Expr::Set(object, name, Box::new(value))),
+            match self.ast.expr(expr) {
+                Expr::Variable(name) => {
+                    let name = name.clone();
+                    Ok(self.ast.add_expr(Expr::Assignment(name, value)))
+                }
+                Expr::Get(object, name) => {
+                    let (object, name) = (*object, name.clone());
+                    Ok(self.ast.add_expr(Expr::Set(object, name, value)))
+                }
                 _ => Err(ParseError::UnexpectedToken(
                     equals,
                     "Invalid assignment target.".to_string(),
@@ -202,41 +335,41 @@ impl Parser {
         }
     }
 
-    fn or(&mut self) -> Result<Expr, ParseError> {
+    fn or(&mut self) -> Result<ExprId, ParseError> {
         let mut expr = self.and()?;
 
         while self.match_token(&[TokenType::Or]) {
             let operator = self.previous().clone();
             let right = self.and()?;
-            expr = Expr::Logical(Box::new(expr), operator, Box::new(right));
+            expr = self.ast.add_expr(Expr::Logical(expr, operator, right));
         }
 
         Ok(expr)
     }
 
-    fn and(&mut self) -> Result<Expr, ParseError> {
+    fn and(&mut self) -> Result<ExprId, ParseError> {
         let mut expr = self.equality()?;
 
         while self.match_token(&[TokenType::And]) {
             let operator = self.previous().clone();
             let right = self.equality()?;
-            expr = Expr::Logical(Box::new(expr), operator, Box::new(right));
+            expr = self.ast.add_expr(Expr::Logical(expr, operator, right));
         }
 
         Ok(expr)
     }
 
-    fn equality(&mut self) -> Result<Expr, ParseError> {
+    fn equality(&mut self) -> Result<ExprId, ParseError> {
         let mut expr = self.comparison()?;
         while self.match_token(&[TokenType::BangEqual, TokenType::EqualEqual]) {
             let operator = self.previous().clone();
             let right = self.comparison()?;
-            expr = Expr::Binary(Box::new(expr), operator, Box::new(right));
+            expr = self.ast.add_expr(Expr::Binary(expr, operator, right));
         }
 
         Ok(expr)
     }
-    fn comparison(&mut self) -> Result<Expr, ParseError> {
+    fn comparison(&mut self) -> Result<ExprId, ParseError> {
         let mut expr = self.term()?;
         while self.match_token(&[
             TokenType::Greater,
@@ -246,83 +379,146 @@ impl Parser {
         ]) {
             let operator = self.previous().clone();
             let right = self.term()?;
-            expr = Expr::Binary(Box::new(expr), operator, Box::new(right));
+            expr = self.ast.add_expr(Expr::Binary(expr, operator, right));
         }
 
         Ok(expr)
     }
-    fn term(&mut self) -> Result<Expr, ParseError> {
+    fn term(&mut self) -> Result<ExprId, ParseError> {
         let mut expr = self.factor()?;
         while self.match_token(&[TokenType::Minus, TokenType::Plus]) {
             let operator = self.previous().clone();
             let right = self.factor()?;
-            expr = Expr::Binary(Box::new(expr), operator, Box::new(right));
+            expr = self.ast.add_expr(Expr::Binary(expr, operator, right));
         }
 
         Ok(expr)
     }
-    fn factor(&mut self) -> Result<Expr, ParseError> {
+    fn factor(&mut self) -> Result<ExprId, ParseError> {
         let mut expr = self.unary()?;
         while self.match_token(&[TokenType::Slash, TokenType::Star]) {
             let operator = self.previous().clone();
             let right = self.unary()?;
-            expr = Expr::Binary(Box::new(expr), operator, Box::new(right));
+            expr = self.ast.add_expr(Expr::Binary(expr, operator, right));
         }
 
         Ok(expr)
     }
-    fn unary(&mut self) -> Result<Expr, ParseError> {
+    fn unary(&mut self) -> Result<ExprId, ParseError> {
         if self.match_token(&[TokenType::Bang, TokenType::Minus]) {
             let operator = self.previous().clone();
-            let right = self.unary()?;
-            Ok(Expr::Unary(operator, Box::new(right)))
+            let right = self.nested(Self::unary)?;
+            Ok(self.ast.add_expr(Expr::Unary(operator, right)))
         } else {
-            self.primary()
+            self.call()
+        }
+    }
+
+    fn call(&mut self) -> Result<ExprId, ParseError> {
+        let mut expr = self.primary()?;
+
+        loop {
+            if self.match_token(&[TokenType::LeftParen]) {
+                expr = self.finish_call(expr)?;
+            } else if self.match_token(&[TokenType::Dot]) {
+                let name =
+                    self.consume(TokenType::Identifier, "Expect property name after '.'.")?;
+                expr = self.ast.add_expr(Expr::Get(expr, name));
+            } else {
+                break;
+            }
+        }
+
+        Ok(expr)
+    }
+
+    fn finish_call(&mut self, callee: ExprId) -> Result<ExprId, ParseError> {
+        let mut arguments = Vec::new();
+        if !self.check(TokenType::RightParen) {
+            loop {
+                if arguments.len() >= 255 {
+                    return Err(ParseError::UnexpectedToken(
+                        self.peek().clone(),
+                        "Cannot have more than 255 arguments.".to_string(),
+                    ));
+                }
+                arguments.push(self.expression()?);
+                if !self.match_token(&[TokenType::Comma]) {
+                    break;
+                }
+            }
         }
+
+        let paren = self.consume(TokenType::RightParen, "Expect ')' after arguments.")?;
+
+        Ok(self.ast.add_expr(Expr::Call(callee, paren, arguments)))
     }
-    fn primary(&mut self) -> Result<Expr, ParseError> {
-        if self.match_token(&[TokenType::False]) {
-            Ok(Expr::Literal(Some(LiteralValue::Boolean(false))))
+    fn primary(&mut self) -> Result<ExprId, ParseError> {
+        let expr = if self.match_token(&[TokenType::False]) {
+            Expr::Literal(Some(Literal::Boolean(false)))
         } else if self.match_token(&[TokenType::True]) {
-            Ok(Expr::Literal(Some(LiteralValue::Boolean(true))))
+            Expr::Literal(Some(Literal::Boolean(true)))
         } else if self.match_token(&[TokenType::Nil]) {
-            Ok(Expr::Literal(None))
-        } else if self.match_token(&[TokenType::Number, TokenType::String]) {
-            match self.previous().token_type {
-                TokenType::Number => {
-                    if let Ok(num) = self.previous().lexeme.parse::<f64>() {
-                        Ok(Expr::Literal(Some(LiteralValue::Number(num))))
-                    } else {
-                        Err(ParseError::UnexpectedToken(
-                            self.previous().clone(),
-                            "Invalid number format".to_string(),
-                        ))
-                    }
+            Expr::Literal(None)
+        } else if self.match_token(&[TokenType::Number]) {
+            if let Ok(num) = self.previous().lexeme.parse::<f64>() {
+                Expr::Literal(Some(Literal::Number(num)))
+            } else {
+                return Err(ParseError::UnexpectedToken(
+                    self.previous().clone(),
+                    "Invalid number format".to_string(),
+                ));
+            }
+        } else if self.match_token(&[TokenType::String]) {
+            // Tokens from the scanner always have both quotes, but those a
+            // host hands the parser need not.
+            let lexeme = &self.previous().lexeme;
+            match lexeme.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+                Some(string) => Expr::Literal(Some(Literal::String(string.to_string()))),
+                None => {
+                    return Err(ParseError::UnexpectedToken(
+                        self.previous().clone(),
+                        "Invalid string format".to_string(),
+                    ))
                 }
-                TokenType::String => Ok(Expr::Literal(Some(LiteralValue::String(
-                    self.previous().lexeme[1..self.previous().lexeme.len() - 1].to_string(),
-                )))),
-                _ => unreachable!(),
             }
         } else if self.match_token(&[TokenType::This]) {
-            Ok(Expr::This(self.previous().clone()))
+            Expr::This(self.previous().clone())
         } else if self.match_token(&[TokenType::Super]) {
             let keyword = self.previous().clone();
             self.consume(TokenType::Dot, "Expect '.' after 'super'.")?;
             let method = self.consume(TokenType::Identifier, "Expect superclass method name.")?;
-            Ok(Expr::Super(keyword, method))
+            Expr::Super(keyword, method)
         } else if self.match_token(&[TokenType::Identifier]) {
-            Ok(Expr::Variable(self.previous().clone()))
+            Expr::Variable(self.previous().clone())
         } else if self.match_token(&[TokenType::LeftParen]) {
             let expr = self.expression()?;
             self.consume(TokenType::RightParen, "Expect ')' after expression.")?;
-            Ok(Expr::Grouping(Box::new(expr)))
+            Expr::Grouping(expr)
         } else {
-            Err(ParseError::UnexpectedToken(
+            return Err(ParseError::UnexpectedToken(
                 self.peek().clone(),
                 "Expect expression.".to_string(),
-            ))
+            ));
+        };
+        Ok(self.ast.add_expr(expr))
+    }
+
+    // Runs one level of recursive descent, failing once the nesting limit
+    // is reached. Each level takes several calls, so even within the limit
+    // it moves onto a new stack when the thread's, which may be small, runs
+    // low.
+    fn nested<T>(
+        &mut self,
+        rule: impl FnOnce(&mut Self) -> Result<T, ParseError>,
+    ) -> Result<T, ParseError> {
+        if self.depth >= self.max_depth {
+            return Err(ParseError::TooDeep(self.peek().clone(), self.max_depth));
         }
+        self.depth += 1;
+        let result = stack::descend(|| rule(self));
+        self.depth -= 1;
+        result
     }
 
     fn match_token(&mut self, types: &[TokenType]) -> bool {
@@ -334,7 +530,7 @@ impl Parser {
         }
     }
 
-    fn consume(&mut self, token_type: TokenType, message: &str) -> Result<Token, ParseError> {
+    fn consume(&mut self, token_type: TokenType, _message: &str) -> Result<Token, ParseError> {
         if self.check(token_type) {
             Ok(self.advance().clone())
         } else {
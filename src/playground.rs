@@ -0,0 +1,378 @@
+use std::env;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+use interprete::{
+    capability::Capability,
+    interpreter::{Limits, Setup},
+    json::{self, JsonValue},
+    log,
+};
+
+// The backend of a web playground, for `interprete serve [--port PORT]`. It
+// answers `POST /run` with a script as the body, either as plain text or as
+// JSON like `{"source": "print 1;"}`, by running it and sending back what
+// it printed and any errors, as JSON:
+//
+//     {"ok":false,"stdout":"1\n","truncated":false,
+//      "diagnostics":[{"severity":"error","code":"E1003",...}]}
+//
+// The scripts come from anyone who can reach the port, so each runs in a
+// fresh interpreter with every capability denied, whatever the options
+// say, and no plugins, under `LIMITS` and stopped after `DEADLINE`. Each
+// also runs in a process of its own, `interprete serve --script` with the
+// same options, so that one which brings its process down, or is stuck in
+// a native past the deadline and is killed, fails alone and the server
+// carries on. At most `MAX_RUNNING` requests are handled at once; others
+// are turned away with 503 until one finishes. Memory is limited only
+// roughly, and what scripts print not until they finish, so the port
+// should not be open to the world without something in front of it. The
+// server listens on the loopback interface only.
+
+const LIMITS: Limits = Limits {
+    steps: 1_000_000,
+    depth: 200,
+    memory: 64 * 1024 * 1024,
+};
+// The most a script may print that is sent back; the rest is cut off.
+const MAX_OUTPUT: usize = 64 * 1024;
+// The longest script accepted.
+const MAX_BODY: usize = 64 * 1024;
+const TIMEOUT: Duration = Duration::from_secs(10);
+// How long a script may run, however few steps it takes.
+const DEADLINE: Duration = Duration::from_secs(5);
+// How long after the deadline a script's process is killed, if it has not
+// stopped, as it will not while a native runs.
+const GRACE: Duration = Duration::from_secs(1);
+const MAX_RUNNING: usize = 16;
+// Each script runs on a thread with a stack this large, which `LIMITS`
+// keeps it well within.
+const STACK_SIZE: usize = 64 * 1024 * 1024;
+
+// Serves requests until the process is stopped, and gives the status to
+// exit with if the port cannot be listened on. `options` are those the
+// command line gave before `serve`, to run scripts with.
+pub fn serve(port: u16, options: &[String]) -> i32 {
+    let listener = match TcpListener::bind(("127.0.0.1", port)) {
+        Ok(listener) => listener,
+        Err(error) => {
+            eprintln!("Could not listen on port {}: {}", port, error);
+            return 70;
+        }
+    };
+    let runner = match env::current_exe() {
+        Ok(program) => Arc::new(Runner {
+            program,
+            options: options.to_vec(),
+        }),
+        Err(error) => {
+            eprintln!("Could not find the interpreter to run scripts: {}", error);
+            return 70;
+        }
+    };
+    eprintln!("Serving the playground at http://127.0.0.1:{}/run", port);
+    let running = Arc::new(AtomicUsize::new(0));
+    for mut stream in listener.incoming().flatten() {
+        let Some(slot) = Slot::take(&running) else {
+            log::info(format_args!(
+                "Turning a request away, with {} running",
+                MAX_RUNNING
+            ));
+            stream.set_write_timeout(Some(TIMEOUT)).ok();
+            let body = message("The playground is busy; try again shortly.");
+            write_response(&mut stream, 503, body);
+            continue;
+        };
+        let runner = Arc::clone(&runner);
+        let spawned = thread::Builder::new().spawn(move || {
+            handle(stream, &runner);
+            drop(slot);
+        });
+        if let Err(error) = spawned {
+            log::info(format_args!("Could not start a thread: {}", error));
+        }
+    }
+    0
+}
+
+// One of the `MAX_RUNNING` requests that may be handled at once, given back
+// when dropped.
+struct Slot(Arc<AtomicUsize>);
+
+impl Slot {
+    fn take(running: &Arc<AtomicUsize>) -> Option<Slot> {
+        running
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (n < MAX_RUNNING).then_some(n + 1)
+            })
+            .ok()
+            .map(|_| Slot(Arc::clone(running)))
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+// How to start the process a script runs in.
+struct Runner {
+    program: PathBuf,
+    options: Vec<String>,
+}
+
+fn handle(mut stream: TcpStream, runner: &Runner) {
+    stream.set_read_timeout(Some(TIMEOUT)).ok();
+    stream.set_write_timeout(Some(TIMEOUT)).ok();
+    let (status, body) = match read_request(&stream) {
+        Ok(request) => respond(request, runner),
+        Err(error) => (400, message(&error.to_string())),
+    };
+    write_response(&mut stream, status, body);
+}
+
+fn write_response(stream: &mut TcpStream, status: u16, body: Option<JsonValue>) {
+    let reason = match status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    };
+    let body = body.map(|body| body.to_string()).unwrap_or_default();
+    // Pages on other sites may call the playground too.
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+         Access-Control-Allow-Origin: *\r\nAccess-Control-Allow-Methods: POST, OPTIONS\r\n\
+         Access-Control-Allow-Headers: Content-Type\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).ok();
+}
+
+struct Request {
+    method: String,
+    path: String,
+    json: bool,
+    // `None` when the body is longer than `MAX_BODY`.
+    body: Option<Vec<u8>>,
+}
+
+// Gives the status and body of the response to a request.
+fn respond(request: Request, runner: &Runner) -> (u16, Option<JsonValue>) {
+    log::info(format_args!("{} {}", request.method, request.path));
+    match (request.method.as_str(), request.path.as_str()) {
+        ("OPTIONS", _) => (204, None),
+        ("POST", "/run") => {
+            let Some(body) = request.body else {
+                let text = format!("Scripts may be at most {} bytes long.", MAX_BODY);
+                return (413, message(&text));
+            };
+            match source(body, request.json) {
+                Ok(source) => match run_apart(source, runner) {
+                    Ok(result) => (200, Some(result)),
+                    Err(error) => (500, message(&error)),
+                },
+                Err(error) => (400, message(&error)),
+            }
+        }
+        (_, "/run") => (405, message("Scripts are run with POST.")),
+        _ => (404, message("The playground runs scripts at /run.")),
+    }
+}
+
+// The script in a request's body.
+fn source(body: Vec<u8>, json: bool) -> Result<String, String> {
+    let text = String::from_utf8(body).map_err(|_| "The body is not UTF-8.".to_string())?;
+    if !json {
+        return Ok(text);
+    }
+    let json = json::parse(&text).map_err(|error| error.to_string())?;
+    match json.get("source").and_then(JsonValue::as_str) {
+        Some(source) => Ok(source.to_string()),
+        None => Err("The JSON body has no \"source\" string.".to_string()),
+    }
+}
+
+// Runs a script in a process of its own, and gives the result it sends
+// back.
+fn run_apart(source: String, runner: &Runner) -> Result<JsonValue, String> {
+    let mut process = Command::new(&runner.program)
+        .args(&runner.options)
+        .args(["serve", "--script"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|error| format!("Could not start a process to run the script: {}", error))?;
+    // The script is sent and the result read on threads of their own, so
+    // that the process can be killed at the deadline however it behaves.
+    let mut input = process.stdin.take().unwrap();
+    thread::spawn(move || input.write_all(source.as_bytes()));
+    let mut output = process.stdout.take().unwrap();
+    let (sent, received) = mpsc::channel();
+    thread::spawn(move || {
+        let mut text = String::new();
+        output.read_to_string(&mut text).ok();
+        sent.send(text).ok();
+    });
+    let result = received.recv_timeout(DEADLINE + GRACE);
+    if result.is_err() {
+        process.kill().ok();
+    }
+    let status = process.wait().map_err(|error| error.to_string())?;
+    let Ok(text) = result else {
+        log::info(format_args!("Killed a script that ran past the deadline"));
+        return Err("The script did not stop at the deadline, and was killed.".to_string());
+    };
+    match json::parse(&text) {
+        Ok(result) if status.success() => Ok(result),
+        Ok(result) if result.get("error").is_some() => Err(result
+            .get("error")
+            .and_then(JsonValue::as_str)
+            .unwrap_or_default()
+            .to_string()),
+        _ => {
+            log::info(format_args!("A script's process failed: {}", status));
+            Err(format!(
+                "The script stopped the process running it ({}).",
+                status
+            ))
+        }
+    }
+}
+
+// Runs the script on standard input as the playground does, and writes the
+// result to standard output, for `interprete serve --script`, which
+// `serve` starts for each script.
+pub fn run_script(setup: &Setup) -> i32 {
+    let mut source = String::new();
+    let read = io::stdin().read_to_string(&mut source);
+    let mut setup = setup.clone();
+    for capability in Capability::ALL {
+        setup.capabilities.deny(capability);
+    }
+    setup.plugins.clear();
+    let result = read.map_err(|error| error.to_string()).and_then(|_| {
+        thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn(move || run(source, &setup))
+            .map_err(|error| error.to_string())?
+            .join()
+            .unwrap_or_else(|_| Err("The interpreter panicked.".to_string()))
+    });
+    match result {
+        Ok(result) => {
+            println!("{}", result);
+            0
+        }
+        Err(error) => {
+            println!("{}", message(&error).unwrap());
+            70
+        }
+    }
+}
+
+// Scripts run on the tree-walker whatever the backend, since only it holds
+// them to `LIMITS`.
+fn run(source: String, setup: &Setup) -> Result<JsonValue, String> {
+    let mut interpreter = setup.interpreter()?;
+    interpreter.capture_output();
+    interpreter.limit(LIMITS);
+    // A watchdog stops the script at the deadline, unless it has finished,
+    // dropping `finished`, before then.
+    let interrupt = interpreter.interrupt_handle();
+    let (finished, done) = mpsc::channel::<()>();
+    thread::spawn(move || {
+        if done.recv_timeout(DEADLINE) == Err(mpsc::RecvTimeoutError::Timeout) {
+            interrupt.cancel();
+        }
+    });
+    let result = interprete::run_in(&mut interpreter, source.clone());
+    drop(finished);
+    let mut output = interpreter.take_output();
+    let truncated = output.len() > MAX_OUTPUT;
+    if truncated {
+        let mut end = MAX_OUTPUT;
+        while !output.is_char_boundary(end) {
+            end -= 1;
+        }
+        output.truncate(end);
+    }
+    let diagnostics = match &result {
+        Ok(()) => Vec::new(),
+        Err(error) => vec![error.diagnostic().to_json("<playground>")],
+    };
+    Ok(JsonValue::Object(vec![
+        ("ok".to_string(), JsonValue::Bool(result.is_ok())),
+        ("stdout".to_string(), JsonValue::String(output)),
+        ("truncated".to_string(), JsonValue::Bool(truncated)),
+        ("diagnostics".to_string(), JsonValue::Array(diagnostics)),
+    ]))
+}
+
+fn read_request(stream: &TcpStream) -> io::Result<Request> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Err(invalid("The request line is malformed."));
+    };
+    let mut request = Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        json: false,
+        body: None,
+    };
+    let mut length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Err(invalid("The request ended in its headers."));
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("Content-Length") {
+            length = value
+                .parse()
+                .map_err(|_| invalid("The Content-Length is not a number."))?;
+        } else if name.eq_ignore_ascii_case("Content-Type") {
+            request.json = value.starts_with("application/json");
+        }
+    }
+    if length <= MAX_BODY {
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body)?;
+        request.body = Some(body);
+    }
+    Ok(request)
+}
+
+fn message(text: &str) -> Option<JsonValue> {
+    let entry = ("error".to_string(), JsonValue::String(text.to_string()));
+    Some(JsonValue::Object(vec![entry]))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
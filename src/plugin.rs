@@ -0,0 +1,189 @@
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::ptr;
+use std::rc::Rc;
+
+use crate::{
+    interpreter::{Interpreter, RuntimeError},
+    library, log,
+    natives::{NativeCode, NativeFunction},
+    scanner::{LiteralValue, Token},
+};
+
+// Plugins are shared libraries that define natives, loaded at startup with
+// `--plugin path`. They are written against a small C interface, so any
+// language that can export C functions can be used. A plugin exports
+//
+//     int interprete_plugin_init(const PluginApi *api);
+//
+// which calls `api->define` once for each native it provides, and returns
+// zero, or anything else if it cannot start. Plugin natives take and
+// return nil, booleans, numbers and strings only.
+
+pub const ABI_VERSION: u32 = 1;
+
+const INIT_SYMBOL: &str = "interprete_plugin_init";
+
+pub const NIL: u32 = 0;
+pub const BOOLEAN: u32 = 1;
+pub const NUMBER: u32 = 2;
+pub const STRING: u32 = 3;
+
+// A value passed to or from a plugin. A boolean is stored in `number` as
+// zero or one. A string is NUL-terminated UTF-8; one returned by a plugin
+// is copied before the plugin is called again, so it may live in a buffer
+// the plugin reuses.
+#[repr(C)]
+pub struct PluginValue {
+    pub kind: u32,
+    pub number: f64,
+    pub string: *const c_char,
+}
+
+// A native defined by a plugin. It is given its arguments and fills in
+// `result`, returning zero; or it returns anything else, with `result` a
+// string describing the error.
+pub type PluginFn = unsafe extern "C" fn(
+    arguments: *const PluginValue,
+    count: usize,
+    result: *mut PluginValue,
+) -> c_int;
+
+#[repr(C)]
+pub struct PluginApi {
+    pub version: u32,
+    pub context: *mut c_void,
+    pub define: unsafe extern "C" fn(
+        context: *mut c_void,
+        name: *const c_char,
+        arity: usize,
+        function: PluginFn,
+    ),
+}
+
+type InitFn = unsafe extern "C" fn(api: *const PluginApi) -> c_int;
+
+// Loads a plugin and defines its natives as globals. The library stays
+// loaded for as long as the process runs.
+pub fn load(interpreter: &mut Interpreter, path: &str) -> Result<(), String> {
+    let init = open(path)?;
+    let mut natives: Vec<(String, usize, PluginFn)> = Vec::new();
+    let api = PluginApi {
+        version: ABI_VERSION,
+        context: &mut natives as *mut Vec<(String, usize, PluginFn)> as *mut c_void,
+        define: define_native,
+    };
+    // SAFETY: the plugin promises that its init function follows the
+    // interface above, and `api` outlives the call.
+    if unsafe { init(&api) } != 0 {
+        return Err(format!("Plugin '{}' failed to start.", path));
+    }
+    for (name, arity, function) in natives {
+        log::debug(format_args!("Plugin '{}' defines '{}'", path, name));
+        let native = NativeFunction {
+            name: name.clone(),
+            arity,
+            variadic: false,
+            function: NativeCode::Plugin(function),
+            capability: None,
+        };
+        interpreter.define_global(&name, LiteralValue::NativeFunction(Rc::new(native)));
+    }
+    Ok(())
+}
+
+unsafe extern "C" fn define_native(
+    context: *mut c_void,
+    name: *const c_char,
+    arity: usize,
+    function: PluginFn,
+) {
+    // SAFETY: `context` is the vector `load` passed in `PluginApi`, and
+    // `name` is a NUL-terminated string from the plugin.
+    let natives = unsafe { &mut *(context as *mut Vec<(String, usize, PluginFn)>) };
+    if let Ok(name) = unsafe { CStr::from_ptr(name) }.to_str() {
+        natives.push((name.to_string(), arity, function));
+    }
+}
+
+// Calls a plugin native, converting the arguments and the result.
+pub fn call(
+    function: PluginFn,
+    name: &str,
+    paren: &Token,
+    arguments: &[LiteralValue],
+) -> Result<LiteralValue, RuntimeError> {
+    let error = |message: String| RuntimeError::NativeError(paren.clone(), message);
+    // The strings must stay alive until the call returns.
+    let mut strings = Vec::new();
+    let mut values = Vec::with_capacity(arguments.len());
+    for (index, argument) in arguments.iter().enumerate() {
+        let (kind, number, string) = match argument {
+            LiteralValue::Nil => (NIL, 0.0, ptr::null()),
+            LiteralValue::Boolean(b) => (BOOLEAN, *b as u8 as f64, ptr::null()),
+            LiteralValue::Number(n) => (NUMBER, *n, ptr::null()),
+            LiteralValue::String(s) => {
+                let s = CString::new(&**s).map_err(|_| {
+                    error(format!(
+                        "Argument {} to '{}' cannot contain a NUL character.",
+                        index + 1,
+                        name
+                    ))
+                })?;
+                strings.push(s);
+                (STRING, 0.0, strings.last().unwrap().as_ptr())
+            }
+            _ => {
+                return Err(error(format!(
+                    "Argument {} to '{}' must be nil, a boolean, a number or a string.",
+                    index + 1,
+                    name
+                )))
+            }
+        };
+        values.push(PluginValue {
+            kind,
+            number,
+            string,
+        });
+    }
+
+    let mut result = PluginValue {
+        kind: NIL,
+        number: 0.0,
+        string: ptr::null(),
+    };
+    // SAFETY: the plugin promises that its natives follow the interface
+    // above; the arguments and their strings outlive the call.
+    let status = unsafe { function(values.as_ptr(), values.len(), &mut result) };
+    let value = from_plugin(&result)
+        .ok_or_else(|| error(format!("'{}' returned a value of an unknown kind.", name)))?;
+    match (status, &value) {
+        (0, _) => Ok(value),
+        (_, LiteralValue::String(message)) => Err(error(message.to_string())),
+        _ => Err(error(format!("'{}' failed.", name))),
+    }
+}
+
+fn from_plugin(value: &PluginValue) -> Option<LiteralValue> {
+    match value.kind {
+        NIL => Some(LiteralValue::Nil),
+        BOOLEAN => Some(LiteralValue::Boolean(value.number != 0.0)),
+        NUMBER => Some(LiteralValue::Number(value.number)),
+        STRING if value.string.is_null() => None,
+        // SAFETY: the plugin gives a NUL-terminated string, valid until it
+        // is called again.
+        STRING => Some(LiteralValue::String(
+            unsafe { CStr::from_ptr(value.string) }
+                .to_string_lossy()
+                .into(),
+        )),
+        _ => None,
+    }
+}
+
+fn open(path: &str) -> Result<InitFn, String> {
+    let init = library::symbol(path, INIT_SYMBOL).map_err(|e| format!("Plugin error: {}", e))?;
+    // SAFETY: the symbol is the plugin's init function, whose signature
+    // the interface fixes.
+    Ok(unsafe { std::mem::transmute::<*mut c_void, InitFn>(init) })
+}
@@ -0,0 +1,160 @@
+use crate::{
+    ast::{Ast, Program},
+    expr::{Expr, ExprId},
+    scanner::{Literal, Token},
+    stack,
+    stmt::{Stmt, StmtId},
+};
+
+// Prints a parsed program as an indented tree, one node per line with its
+// children beneath it, for `--ast`. It shows the program as the parser
+// left it, so a `for` loop appears as the `while` it is desugared into.
+//
+//     Var total
+//       Binary +
+//         Literal 1
+//         Variable x
+
+pub fn print_program(program: &Program) -> String {
+    let mut printer = Printer {
+        ast: &program.ast,
+        out: String::new(),
+        depth: 0,
+    };
+    for &stmt in &program.statements {
+        printer.stmt(stmt);
+    }
+    printer.out
+}
+
+struct Printer<'a> {
+    ast: &'a Ast,
+    out: String,
+    depth: usize,
+}
+
+impl Printer<'_> {
+    fn line(&mut self, text: &str) {
+        self.out.push_str(&"  ".repeat(self.depth));
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    // Prints a line, then whatever `children` prints indented beneath it.
+    fn node(&mut self, text: &str, children: impl FnOnce(&mut Self)) {
+        self.line(text);
+        self.depth += 1;
+        children(self);
+        self.depth -= 1;
+    }
+
+    fn stmt(&mut self, id: StmtId) {
+        let ast = self.ast;
+        match ast.stmt(id) {
+            Stmt::Expression(expr) => self.node("Expression", |p| p.expr(*expr)),
+            Stmt::Print(expr) => self.node("Print", |p| p.expr(*expr)),
+            Stmt::Var(name, initializer) => self.node(&format!("Var {}", name.lexeme), |p| {
+                if let Some(initializer) = initializer {
+                    p.expr(*initializer);
+                }
+            }),
+            Stmt::Block(statements) => self.node("Block", |p| p.stmts(statements)),
+            Stmt::If(condition, then_branch, else_branch) => self.node("If", |p| {
+                p.expr(*condition);
+                p.node("Then", |p| p.stmt(*then_branch));
+                if let Some(else_branch) = else_branch {
+                    p.node("Else", |p| p.stmt(*else_branch));
+                }
+            }),
+            Stmt::While(condition, body) => self.node("While", |p| {
+                p.expr(*condition);
+                p.stmt(*body);
+            }),
+            Stmt::Function(name, params, body) => {
+                let header = format!("Function {}({})", name.lexeme, names(params));
+                self.node(&header, |p| p.stmts(body))
+            }
+            Stmt::Return(_, value) => self.node("Return", |p| {
+                if let Some(value) = value {
+                    p.expr(*value);
+                }
+            }),
+            Stmt::Class(name, superclass, _) => match superclass {
+                Some(superclass) => {
+                    self.line(&format!("Class {} < {}", name.lexeme, superclass.lexeme))
+                }
+                None => self.line(&format!("Class {}", name.lexeme)),
+            },
+        }
+    }
+
+    fn stmts(&mut self, statements: &[StmtId]) {
+        for &stmt in statements {
+            self.stmt(stmt);
+        }
+    }
+
+    fn expr(&mut self, id: ExprId) {
+        stack::descend(|| self.expr_node(id))
+    }
+
+    fn expr_node(&mut self, id: ExprId) {
+        let ast = self.ast;
+        match ast.expr(id) {
+            Expr::Binary(left, operator, right) => {
+                self.node(&format!("Binary {}", operator.lexeme), |p| {
+                    p.expr(*left);
+                    p.expr(*right);
+                })
+            }
+            Expr::Logical(left, operator, right) => {
+                self.node(&format!("Logical {}", operator.lexeme), |p| {
+                    p.expr(*left);
+                    p.expr(*right);
+                })
+            }
+            Expr::Unary(operator, right) => {
+                self.node(&format!("Unary {}", operator.lexeme), |p| p.expr(*right))
+            }
+            Expr::Literal(value) => self.line(&format!("Literal {}", literal(value.as_ref()))),
+            Expr::Grouping(expr) => self.node("Grouping", |p| p.expr(*expr)),
+            Expr::Variable(name) => self.line(&format!("Variable {}", name.lexeme)),
+            Expr::Assignment(name, value) => {
+                self.node(&format!("Assign {}", name.lexeme), |p| p.expr(*value))
+            }
+            Expr::Call(callee, _, arguments) => self.node("Call", |p| {
+                p.expr(*callee);
+                for &argument in arguments {
+                    p.expr(argument);
+                }
+            }),
+            Expr::Get(object, name) => {
+                self.node(&format!("Get {}", name.lexeme), |p| p.expr(*object))
+            }
+            Expr::Set(object, name, value) => self.node(&format!("Set {}", name.lexeme), |p| {
+                p.expr(*object);
+                p.expr(*value);
+            }),
+            Expr::This(_) => self.line("This"),
+            Expr::Super(_, method) => self.line(&format!("Super {}", method.lexeme)),
+        }
+    }
+}
+
+fn names(tokens: &[Token]) -> String {
+    tokens
+        .iter()
+        .map(|token| token.lexeme.as_str())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// A literal as it would be written in a script.
+pub fn literal(value: Option<&Literal>) -> String {
+    match value {
+        Some(Literal::String(s)) => format!("\"{}\"", s),
+        Some(Literal::Number(n)) => n.to_string(),
+        Some(Literal::Boolean(b)) => b.to_string(),
+        _ => "nil".to_string(),
+    }
+}
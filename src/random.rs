@@ -0,0 +1,45 @@
+use crate::natives;
+
+// A small deterministic pseudo-random generator (SplitMix64). Not suitable
+// for cryptography, but fast and reproducible: the same seed always yields
+// the same sequence.
+#[derive(Debug, Clone)]
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Self {
+        Random { state: seed }
+    }
+
+    // A generator seeded from the current time, for runs that have not
+    // asked for a particular sequence.
+    pub fn from_time() -> Self {
+        Random::new((natives::seconds_since_epoch() * 1e9) as u64)
+    }
+
+    pub fn seed(&mut self, seed: u64) {
+        self.state = seed;
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    // A number in `[0, 1)`, using the top 53 bits so every value is exactly
+    // representable.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // A number in `0..bound`, taken from the high bits of a widening
+    // multiply rather than a modulo of the low bits.
+    pub fn below(&mut self, bound: u64) -> u64 {
+        ((self.next_u64() as u128 * bound as u128) >> 64) as u64
+    }
+}
@@ -0,0 +1,232 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::{
+    ast::Ast,
+    expr::{Expr, ExprId},
+    scanner::Literal,
+    stack,
+    stmt::{Stmt, StmtId},
+};
+
+// Finds, once a program is parsed, where each of its local variables
+// lives, so that the tree-walker reads and sets them by index rather than
+// looking their names up in scope after scope. Each block that declares
+// variables and each call has a scope, which keeps its variables in
+// slots, numbered as they are declared; a variable expression refers to
+// the slot of a scope so many scopes out from the one it runs in.
+//
+// A variable is found as the code is read, as in the VM's compiler, so a
+// function sees the variables declared before it and not those declared
+// after it in the same block. What is declared outside any block or
+// function is global, and is looked up by name as before, as is anything
+// in a program that was not resolved.
+//
+// It also gathers the program's string literals into a table of
+// constants, each text once, for the interpreter to make a value of each
+// once rather than every time one runs; see `constants`.
+
+// Where a local variable lives: in `slot` of the scope `depth` scopes out
+// from the one running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Local {
+    pub depth: u32,
+    pub slot: u32,
+}
+
+// What `resolve` found, by expression and statement; see `Ast::local`,
+// `Ast::slot`, `Ast::scope` and `Ast::constant`.
+#[derive(Debug, Default)]
+pub struct Resolution {
+    pub(crate) locals: Vec<Option<Local>>,
+    pub(crate) slots: Vec<Option<u32>>,
+    pub(crate) scopes: Vec<Option<Arc<[String]>>>,
+    // The constant of each string literal, by expression, and the text of
+    // each constant.
+    pub(crate) literals: Vec<Option<u32>>,
+    pub(crate) constants: Vec<String>,
+}
+
+pub fn resolve(ast: &mut Ast, statements: &[StmtId]) {
+    let mut resolver = Resolver {
+        ast: &*ast,
+        scopes: Vec::new(),
+        constants: HashMap::new(),
+        resolution: Resolution::default(),
+    };
+    for statement in statements {
+        resolver.statement(*statement);
+    }
+    let resolution = resolver.resolution;
+    ast.resolved(resolution);
+}
+
+struct Resolver<'a> {
+    ast: &'a Ast,
+    // The names of the variables declared so far in each scope around the
+    // code being read, outermost first, by slot.
+    scopes: Vec<Vec<String>>,
+    // The constant each text has been given.
+    constants: HashMap<String, u32>,
+    resolution: Resolution,
+}
+
+impl Resolver<'_> {
+    fn statement(&mut self, statement: StmtId) {
+        stack::descend(|| self.statement_node(statement))
+    }
+
+    fn statement_node(&mut self, statement: StmtId) {
+        let ast = self.ast;
+        match ast.stmt(statement) {
+            Stmt::Expression(expr) | Stmt::Print(expr) => self.expression(*expr),
+            Stmt::Var(name, initializer) => {
+                // The initializer sees the variables around, so that
+                // `var a = a;` copies the one of an outer scope.
+                if let Some(initializer) = initializer {
+                    self.expression(*initializer);
+                }
+                self.declare(statement, &name.lexeme);
+            }
+            Stmt::Block(statements) => {
+                if !statements.iter().any(|&statement| declares(ast, statement)) {
+                    self.scope(statement, Vec::new());
+                    for statement in statements {
+                        self.statement(*statement);
+                    }
+                    return;
+                }
+                self.scopes.push(Vec::new());
+                for statement in statements {
+                    self.statement(*statement);
+                }
+                let names = self.scopes.pop().unwrap_or_default();
+                self.scope(statement, names);
+            }
+            Stmt::If(condition, then_branch, else_branch) => {
+                self.expression(*condition);
+                self.statement(*then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.statement(*else_branch);
+                }
+            }
+            Stmt::While(condition, body) => {
+                self.expression(*condition);
+                self.statement(*body);
+            }
+            Stmt::Function(name, params, body) => {
+                // Declared before its body is read, so it can call itself.
+                self.declare(statement, &name.lexeme);
+                let params = params.iter().map(|param| param.lexeme.clone()).collect();
+                self.scopes.push(params);
+                for statement in body {
+                    self.statement(*statement);
+                }
+                let names = self.scopes.pop().unwrap_or_default();
+                self.scope(statement, names);
+            }
+            Stmt::Return(_, value) => {
+                if let Some(value) = value {
+                    self.expression(*value);
+                }
+            }
+            Stmt::Class(..) => {}
+        }
+    }
+
+    fn expression(&mut self, expression: ExprId) {
+        stack::descend(|| self.expression_node(expression))
+    }
+
+    fn expression_node(&mut self, expression: ExprId) {
+        match self.ast.expr(expression) {
+            Expr::Variable(name) => self.refer(expression, &name.lexeme),
+            Expr::Assignment(name, value) => {
+                self.expression(*value);
+                self.refer(expression, &name.lexeme);
+            }
+            Expr::Binary(left, _, right) | Expr::Logical(left, _, right) => {
+                self.expression(*left);
+                self.expression(*right);
+            }
+            Expr::Unary(_, expr) | Expr::Grouping(expr) | Expr::Get(expr, _) => {
+                self.expression(*expr)
+            }
+            Expr::Call(callee, _, arguments) => {
+                self.expression(*callee);
+                for argument in arguments {
+                    self.expression(*argument);
+                }
+            }
+            Expr::Set(object, _, value) => {
+                self.expression(*object);
+                self.expression(*value);
+            }
+            Expr::Literal(Some(Literal::String(text))) => self.constant(expression, text),
+            Expr::Literal(_) | Expr::This(_) | Expr::Super(..) => {}
+        }
+    }
+
+    fn constant(&mut self, expression: ExprId, text: &str) {
+        let constants = &mut self.resolution.constants;
+        let index = *self.constants.entry(text.to_string()).or_insert_with(|| {
+            constants.push(text.to_string());
+            constants.len() as u32 - 1
+        });
+        set(&mut self.resolution.literals, expression.0, index);
+    }
+
+    // Gives the variable a declaration declares a slot in the innermost
+    // scope, the one it had if it was declared there before.
+    fn declare(&mut self, statement: StmtId, name: &str) {
+        let Some(scope) = self.scopes.last_mut() else {
+            return;
+        };
+        let slot = match scope.iter().rposition(|declared| declared == name) {
+            Some(slot) => slot,
+            None => {
+                scope.push(name.to_string());
+                scope.len() - 1
+            }
+        };
+        set(&mut self.resolution.slots, statement.0, slot as u32);
+    }
+
+    fn refer(&mut self, expression: ExprId, name: &str) {
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if let Some(slot) = scope.iter().rposition(|declared| declared == name) {
+                let local = Local {
+                    depth: depth as u32,
+                    slot: slot as u32,
+                };
+                set(&mut self.resolution.locals, expression.0, local);
+                return;
+            }
+        }
+    }
+
+    fn scope(&mut self, statement: StmtId, names: Vec<String>) {
+        set(&mut self.resolution.scopes, statement.0, Arc::from(names));
+    }
+}
+
+// Whether a statement declares a variable in the scope it is in, rather
+// than in one of its own. Only a program that was not parsed has a
+// declaration as the body of an `if` or `while`.
+fn declares(ast: &Ast, statement: StmtId) -> bool {
+    stack::descend(|| match ast.stmt(statement) {
+        Stmt::Var(..) | Stmt::Function(..) => true,
+        Stmt::If(_, then_branch, else_branch) => {
+            declares(ast, *then_branch) || else_branch.is_some_and(|branch| declares(ast, branch))
+        }
+        Stmt::While(_, body) => declares(ast, *body),
+        _ => false,
+    })
+}
+
+fn set<T>(table: &mut Vec<Option<T>>, index: usize, value: T) {
+    if table.len() <= index {
+        table.resize_with(index + 1, || None);
+    }
+    table[index] = Some(value);
+}
@@ -0,0 +1,217 @@
+use std::collections::HashSet;
+use std::io::{self, Write};
+
+use interprete::{
+    capability::Capability,
+    engine::Engine,
+    interpreter::Setup,
+    json::{self, object, JsonValue},
+    log, natives, InterpreterError,
+};
+
+// An evaluation server for `interprete rpc`, so other programs can run the
+// interpreter as a subprocess and hand it code. It reads JSON-RPC 2.0
+// requests from standard input, one per line, and writes each response on
+// a line of standard output:
+//
+//     --> {"jsonrpc":"2.0","id":1,"method":"evaluate","params":{"source":"var x = 2;"}}
+//     <-- {"jsonrpc":"2.0","id":1,"result":{"value":null,"output":""}}
+//     --> {"jsonrpc":"2.0","id":2,"method":"evaluate","params":{"source":"x * 3"}}
+//     <-- {"jsonrpc":"2.0","id":2,"result":{"value":"6","output":""}}
+//
+// The methods are:
+//
+// - `evaluate`, with `source`: runs it in the same interpreter as every
+//   source before it. `value` is the value of a bare expression, as `print`
+//   would show it, or null; `output` is what the source printed, and the
+//   threads it joined. If the source fails, the error has code -32000 and
+//   its data holds `output` and `diagnostics`, in the form
+//   `--diagnostics=json` gives.
+// - `reset`: starts again with a new interpreter.
+// - `getGlobals`: the global variables scripts have declared, as an object
+//   from their names to their values as `print` would show them.
+//
+// Requests come in on standard input and responses go out on standard
+// output, so scripts run without the `console` capability: reading input
+// or pausing in the debugger would take a request, or answer in the middle
+// of the responses.
+
+// A script failed; the error's data says how.
+const SCRIPT_ERROR: i32 = -32000;
+const PARSE_ERROR: i32 = -32700;
+const INVALID_REQUEST: i32 = -32600;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+
+// Serves requests until standard input ends, and gives the status to exit
+// with.
+pub fn serve(setup: &Setup) -> i32 {
+    let mut server = match Server::new(setup) {
+        Ok(server) => server,
+        Err(error) => {
+            eprintln!("{}", error);
+            return 70;
+        }
+    };
+    loop {
+        let line = match natives::read_line() {
+            Ok(Some(line)) => line,
+            Ok(None) => return 0,
+            Err(error) => {
+                eprintln!("Error reading a request: {}", error);
+                return 74;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match json::parse(&line) {
+            Ok(request) => server.handle(&request),
+            Err(error) => {
+                let failure = Failure::new(PARSE_ERROR, error.to_string());
+                Some(failure.response(JsonValue::Null))
+            }
+        };
+        if let Some(response) = response {
+            let mut output = io::stdout().lock();
+            writeln!(output, "{}", response).ok();
+            output.flush().ok();
+        }
+    }
+}
+
+struct Server {
+    setup: Setup,
+    engine: Box<dyn Engine>,
+    // The globals an interpreter starts with, which `getGlobals` leaves out.
+    builtins: HashSet<String>,
+}
+
+impl Server {
+    fn new(setup: &Setup) -> Result<Server, String> {
+        let mut setup = setup.clone();
+        setup.capabilities.deny(Capability::Console);
+        let mut engine = setup.engine()?;
+        engine.interpreter_mut().capture_output();
+        let builtins = engine.interpreter().globals().into_iter();
+        Ok(Server {
+            setup,
+            builtins: builtins.map(|(name, _)| name).collect(),
+            engine,
+        })
+    }
+
+    // The response to a request, or none if it is a notification.
+    fn handle(&mut self, request: &JsonValue) -> Option<JsonValue> {
+        let id = request.get("id").cloned();
+        let Some(method) = request.get("method").and_then(JsonValue::as_str) else {
+            let failure = Failure::new(INVALID_REQUEST, "The request has no method.".into());
+            return Some(failure.response(id.unwrap_or(JsonValue::Null)));
+        };
+        log::debug(format_args!("Handling '{}'", method));
+        let params = request.get("params").unwrap_or(&json::NULL);
+        let result = match method {
+            "evaluate" => match params.get("source").and_then(JsonValue::as_str) {
+                Some(source) => self.evaluate(source.to_string()),
+                None => Err(Failure::new(
+                    INVALID_PARAMS,
+                    "'evaluate' needs a 'source' string.".into(),
+                )),
+            },
+            "reset" => match Server::new(&self.setup) {
+                Ok(server) => {
+                    *self = server;
+                    Ok(JsonValue::Null)
+                }
+                Err(error) => Err(Failure::new(SCRIPT_ERROR, error)),
+            },
+            "getGlobals" => Ok(self.globals()),
+            _ => Err(Failure::new(
+                METHOD_NOT_FOUND,
+                format!("Unknown method '{}'.", method),
+            )),
+        };
+        let id = id?;
+        Some(match result {
+            Ok(result) => response(id, "result", result),
+            Err(failure) => failure.response(id),
+        })
+    }
+
+    fn evaluate(&mut self, source: String) -> Result<JsonValue, Failure> {
+        let result = interprete::evaluate(self.engine.as_mut(), source);
+        let output = JsonValue::String(self.engine.interpreter_mut().take_output());
+        match result {
+            Ok(value) => {
+                let value = match value {
+                    Some(value) => JsonValue::String(self.engine.interpreter().stringify(value)),
+                    None => JsonValue::Null,
+                };
+                Ok(object(vec![("value", value), ("output", output)]))
+            }
+            Err(error) => {
+                let message = match &error {
+                    InterpreterError::Parse(_) => "The source does not parse.",
+                    InterpreterError::Runtime(_) => "The source failed while running.",
+                };
+                let diagnostics = vec![error.diagnostic().to_json("<rpc>")];
+                let data = object(vec![
+                    ("output", output),
+                    ("diagnostics", JsonValue::Array(diagnostics)),
+                ]);
+                Err(Failure {
+                    data: Some(data),
+                    ..Failure::new(SCRIPT_ERROR, message.to_string())
+                })
+            }
+        }
+    }
+
+    fn globals(&self) -> JsonValue {
+        let interpreter = self.engine.interpreter();
+        let mut globals: Vec<_> = interpreter
+            .globals()
+            .into_iter()
+            .filter(|(name, _)| !self.builtins.contains(name))
+            .map(|(name, value)| (name, JsonValue::String(interpreter.stringify(value))))
+            .collect();
+        globals.sort_by(|(a, _), (b, _)| a.cmp(b));
+        JsonValue::Object(globals)
+    }
+}
+
+fn response(id: JsonValue, key: &str, value: JsonValue) -> JsonValue {
+    object(vec![
+        ("jsonrpc", JsonValue::String("2.0".to_string())),
+        ("id", id),
+        (key, value),
+    ])
+}
+
+// The error a request failed with.
+struct Failure {
+    code: i32,
+    message: String,
+    data: Option<JsonValue>,
+}
+
+impl Failure {
+    fn new(code: i32, message: String) -> Failure {
+        Failure {
+            code,
+            message,
+            data: None,
+        }
+    }
+
+    fn response(self, id: JsonValue) -> JsonValue {
+        let mut error = vec![
+            ("code", JsonValue::Number(self.code as f64)),
+            ("message", JsonValue::String(self.message)),
+        ];
+        if let Some(data) = self.data {
+            error.push(("data", data));
+        }
+        response(id, "error", object(error))
+    }
+}
@@ -1,63 +1,94 @@
-use std::collections::HashMap;
-use std::sync::LazyLock;
-
-pub static KEYWORDS: LazyLock<HashMap<&str, TokenType>> = LazyLock::new(|| {
-    //println!("Initializing shared HashMap!");
-    let mut map = HashMap::new();
-    map.insert("and", TokenType::And);
-    map.insert("class", TokenType::Class);
-    map.insert("else", TokenType::Else);
-    map.insert("false", TokenType::False);
-    map.insert("for", TokenType::For);
-    map.insert("fun", TokenType::Fun);
-    map.insert("if", TokenType::If);
-    map.insert("nil", TokenType::Nil);
-    map.insert("or", TokenType::Or);
-    map.insert("print", TokenType::Print);
-    map.insert("return", TokenType::Return);
-    map.insert("super", TokenType::Super);
-    map.insert("this", TokenType::This);
-    map.insert("true", TokenType::True);
-    map.insert("var", TokenType::Var);
-    map.insert("while", TokenType::While);
-    map
-});
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::{
+    diagnostic::{Diagnostic, Span},
+    function::LoxFunction,
+    map::Map,
+    natives::{Namespace, NativeFunction},
+    thread::{Endpoint, Thread},
+    vm::Closure,
+};
 
 // Define an error type for scanner errors.
 #[derive(Debug, Clone)]
 pub enum ParseError {
-    UnexpectedCharacter(char, usize),
+    UnexpectedCharacter(char, usize, usize),
     UnexpectedToken(Token, String),
     ExpectedToken(TokenType, Token),
-    UnterminatedString(usize),
+    UnterminatedString(usize, usize),
+    MalformedAst(String),
+    TooDeep(Token, usize),
+    InvalidEdit(usize, usize),
+    InvalidNumber(String, usize, usize),
     EndOfFile,
     // Add more specific parsing errors as needed
 }
 
-impl std::fmt::Display for ParseError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl ParseError {
+    pub fn diagnostic(&self) -> Diagnostic {
         match self {
-            ParseError::UnexpectedCharacter(character, line) => {
-                write!(f, "Line {}: Unexpected character '{}'", line, character)
-            }
-            ParseError::UnexpectedToken(token, message) => {
-                write!(
-                    f,
-                    "Line {}: Unexpected token '{}': {}",
-                    token.line, token.lexeme, message
-                )
+            ParseError::UnexpectedCharacter(character, line, column) => Diagnostic::error(
+                "E0002",
+                format!("Unexpected character '{}'", character),
+                Some(Span::new(*line, *column, &character.to_string())),
+            ),
+            ParseError::UnexpectedToken(token, message) => Diagnostic::error(
+                "E0003",
+                format!("Unexpected token '{}': {}", token.lexeme, message),
+                Some(Span::of(token)),
+            ),
+            ParseError::ExpectedToken(expected, found) => Diagnostic::error(
+                "E0004",
+                format!(
+                    "Expected token '{:?}', but found '{}'",
+                    expected, found.lexeme
+                ),
+                Some(Span::of(found)),
+            )
+            .with_label(&format!("expected '{:?}' here", expected)),
+            ParseError::UnterminatedString(line, column) => Diagnostic::error(
+                "E0001",
+                "Unterminated string".to_string(),
+                Some(Span::new(*line, *column, "\"")),
+            )
+            .with_label("this string is never closed")
+            .with_help("Add a closing '\"'; strings cannot contain one."),
+            ParseError::MalformedAst(message) => {
+                Diagnostic::error("E0007", format!("Malformed AST: {}", message), None)
             }
-            ParseError::ExpectedToken(expected, found) => {
-                write!(
-                    f,
-                    "Line {}: Expected token '{:?}', but found '{}'",
-                    found.line, expected, found.lexeme
-                )
+            ParseError::TooDeep(token, limit) => Diagnostic::error(
+                "E0005",
+                format!(
+                    "Nesting too deep at '{}' (limit is {})",
+                    token.lexeme, limit
+                ),
+                Some(Span::of(token)),
+            )
+            .with_help("Split the code into smaller functions or variables."),
+            ParseError::InvalidEdit(start, end) => Diagnostic::error(
+                "E0008",
+                format!("Invalid edit range {}..{}", start, end),
+                None,
+            ),
+            ParseError::InvalidNumber(text, line, column) => Diagnostic::error(
+                "E0009",
+                format!("Invalid number '{}'", text),
+                Some(Span::new(*line, *column, text)),
+            ),
+            ParseError::EndOfFile => {
+                Diagnostic::error("E0006", "Unexpected end of file".to_string(), None)
             }
-            ParseError::UnterminatedString(line) => {
-                write!(f, "Line {}: Unterminated string", line)
-            }
-            ParseError::EndOfFile => write!(f, "Unexpected end of file"),
+        }
+    }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let diagnostic = self.diagnostic();
+        match diagnostic.span {
+            Some(span) => write!(f, "Line {}: {}", span.line, diagnostic.message),
+            None => write!(f, "{}", diagnostic.message),
         }
     }
 }
@@ -69,33 +100,49 @@ pub struct Scanner {
     start: usize,
     current: usize,
     line: usize,
+    comments: Vec<Comment>,
+}
+
+// A `//` comment, kept as trivia alongside the tokens so tools such as the
+// formatter can reproduce it.
+#[derive(Debug, Clone)]
+pub struct Comment {
+    pub text: String,
+    pub line: usize,
 }
 #[derive(Debug, Clone)]
 pub struct Token {
     pub token_type: TokenType,
     pub lexeme: String,
-    pub literal: Option<LiteralValue>,
+    pub literal: Option<Literal>,
     pub line: usize,
+    // The column where the token starts, counted in characters from one;
+    // for a token spanning lines, that is on the line where it starts. Zero
+    // for tokens made up outside the scanner, whose place is not known.
+    pub column: usize,
 }
 
 impl Token {
     pub fn new(
         token_type: TokenType,
         lexeme: String,
-        literal: Option<LiteralValue>,
+        literal: Option<Literal>,
         line: usize,
+        column: usize,
     ) -> Self {
         Token {
             token_type,
             lexeme,
             literal,
             line,
+            column,
         }
     }
 
-    // Implement ToString
-    pub fn to_string(&self) -> String {
-        format!("{:?} {} {:?}", self.token_type, self.lexeme, self.literal)
+    // The line where the token starts, which for a multi-line string is
+    // before its `line`.
+    pub fn start_line(&self) -> usize {
+        self.line - self.lexeme.matches('\n').count()
     }
 
     // New method to check if the token is of a specific type.
@@ -104,6 +151,16 @@ impl Token {
     }
 }
 
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:?} {} {:?}",
+            self.token_type, self.lexeme, self.literal
+        )
+    }
+}
+
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum TokenType {
     // Single-character tokens.
@@ -157,12 +214,91 @@ pub enum TokenType {
 
 #[derive(Debug, Clone)]
 pub enum LiteralValue {
+    // Strings never change once made, so copies share the text.
+    String(Rc<str>),
+    Number(f64),
+    Boolean(bool),
+    Nil,
+    NativeFunction(Rc<NativeFunction>),
+    Function(Rc<LoxFunction>),
+    // Lists are shared by reference, like objects in other scripting
+    // languages.
+    List(Rc<RefCell<Vec<LiteralValue>>>),
+    // Maps are shared by reference too. Keys are strings.
+    Map(Rc<RefCell<Map>>),
+    // A string built up in place by `append`, which avoids copying the text
+    // so far on every step as `+` does.
+    Buffer(Rc<RefCell<String>>),
+    // Binary data, shared by reference like lists.
+    Bytes(Rc<RefCell<Vec<u8>>>),
+    Thread(Rc<Thread>),
+    // One end of a channel between threads.
+    Endpoint(Rc<Endpoint>),
+    Namespace(Rc<Namespace>),
+    // A function compiled for the bytecode VM; see `vm`.
+    Closure(Rc<Closure>),
+}
+
+// A list or map that is dropped with the last reference to it gives up the
+// values it holds to a list rather than dropping them one inside the other,
+// as they would be by default, so that a value nested very deep, like one
+// a script builds in a loop, cannot overflow the stack.
+impl Drop for LiteralValue {
+    fn drop(&mut self) {
+        let mut pending = Vec::new();
+        take_children(self, &mut pending);
+        while let Some(mut value) = pending.pop() {
+            take_children(&mut value, &mut pending);
+        }
+    }
+}
+
+// Moves the values in `value` to `pending` if dropping it would drop them
+// and some of them are lists or maps, which may nest further.
+fn take_children(value: &mut LiteralValue, pending: &mut Vec<LiteralValue>) {
+    let nested =
+        |value: &LiteralValue| matches!(value, LiteralValue::List(_) | LiteralValue::Map(_));
+    match value {
+        LiteralValue::List(items) if Rc::strong_count(items) == 1 => {
+            if let Ok(mut items) = items.try_borrow_mut() {
+                if items.iter().any(nested) {
+                    pending.append(&mut items);
+                }
+            }
+        }
+        LiteralValue::Map(map) if Rc::strong_count(map) == 1 => {
+            if let Ok(mut map) = map.try_borrow_mut() {
+                if map.iter().any(|(_, value)| nested(value)) {
+                    pending.extend(map.drain().map(|(_, value)| value));
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+// A value written out in a script, as a token or literal expression holds
+// it. Unlike a `LiteralValue` it shares nothing, so a parsed program can be
+// handed to interpreters on other threads.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
     String(String),
     Number(f64),
     Boolean(bool),
     Nil,
 }
 
+impl Literal {
+    pub fn value(&self) -> LiteralValue {
+        match self {
+            Literal::String(s) => LiteralValue::String(Rc::from(s.as_str())),
+            Literal::Number(n) => LiteralValue::Number(*n),
+            Literal::Boolean(b) => LiteralValue::Boolean(*b),
+            Literal::Nil => LiteralValue::Nil,
+        }
+    }
+}
+
 impl Scanner {
     pub fn new(source: String) -> Self {
         Self {
@@ -170,21 +306,66 @@ impl Scanner {
             start: 0,
             current: 0,
             line: 1,
+            comments: Vec::new(),
+        }
+    }
+
+    // Starts scanning partway through the source, at an offset where a
+    // token begins and which lies on the given line.
+    pub fn new_at(source: String, offset: usize, line: usize) -> Self {
+        Self {
+            current: offset,
+            line,
+            ..Self::new(source)
         }
     }
 
+    pub fn comments(&self) -> &[Comment] {
+        &self.comments
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
     pub fn scan_tokens(&mut self) -> Result<Vec<Token>, ParseError> {
         let mut tokens = Vec::new();
+        while let Some((token, _, _)) = self.next_token()? {
+            tokens.push(token);
+        }
+        tokens.push(self.eof());
+        Ok(tokens)
+    }
+
+    // Scans the whole source like `scan_tokens`, but skips past errors to
+    // carry on scanning, returning them all alongside the tokens.
+    pub fn scan_all(&mut self) -> (Vec<Token>, Vec<ParseError>) {
+        let mut tokens = Vec::new();
+        let mut errors = Vec::new();
+        loop {
+            match self.next_token() {
+                Ok(Some((token, _, _))) => tokens.push(token),
+                Ok(None) => break,
+                // The character at fault has been consumed already.
+                Err(error) => errors.push(error),
+            }
+        }
+        tokens.push(self.eof());
+        (tokens, errors)
+    }
+
+    // Scans up to and including the next token, skipping whitespace and
+    // comments. Returns the token with the offset and line where it starts,
+    // or `None` at the end of the source.
+    pub fn next_token(&mut self) -> Result<Option<(Token, usize, usize)>, ParseError> {
         while !self.is_at_end() {
             self.start = self.current;
-            match self.scan_token() {
-                Ok(Some(token)) => tokens.push(token), // Add the token to the vector of tokens
-                Ok(None) => continue,
-                Err(error) => return Err(error), // If there is an error, return the error.
-            };
+            let line = self.line;
+            if let Some(token) = self.scan_token()? {
+                return Ok(Some((token, self.start, line)));
+            }
         }
-        tokens.push(Token::new(TokenType::Eof, String::new(), None, self.line));
-        Ok(tokens)
+        Ok(None)
     }
 
     fn is_at_end(&self) -> bool {
@@ -237,6 +418,10 @@ impl Scanner {
                     while self.peek() != '\n' && !self.is_at_end() {
                         self.advance();
                     }
+                    self.comments.push(Comment {
+                        text: self.source[self.start..self.current].trim_end().to_owned(),
+                        line: self.line,
+                    });
                     Ok(None)
                 } else {
                     Ok(Some(self.create_token(TokenType::Slash)))
@@ -257,7 +442,11 @@ impl Scanner {
                 } else if is_alpha(c) {
                     self.identifier()
                 } else {
-                    Err(ParseError::UnexpectedCharacter(c, self.line)) // Example of error handling
+                    Err(ParseError::UnexpectedCharacter(
+                        c,
+                        self.line,
+                        column_at(&self.source, self.start),
+                    ))
                 }
             }
         }
@@ -268,7 +457,7 @@ impl Scanner {
             self.advance();
         }
         let text = &self.source[self.start..self.current];
-        let token_type = *KEYWORDS.get(text).unwrap_or(&TokenType::Identifier);
+        let token_type = keyword(text).unwrap_or(TokenType::Identifier);
         Ok(Some(self.create_token(token_type)))
     }
 
@@ -285,10 +474,17 @@ impl Scanner {
             }
         }
 
-        let value: f64 = self.source[self.start..self.current].parse().unwrap();
+        let text = &self.source[self.start..self.current];
+        let value: f64 = text.parse().map_err(|_| {
+            ParseError::InvalidNumber(
+                text.to_string(),
+                self.line,
+                column_at(&self.source, self.start),
+            )
+        })?;
         Ok(Some(self.create_token_with_literal(
             TokenType::Number,
-            Some(LiteralValue::Number(value)),
+            Some(Literal::Number(value)),
         )))
     }
 
@@ -301,7 +497,12 @@ impl Scanner {
         }
 
         if self.is_at_end() {
-            return Err(ParseError::UnterminatedString(self.line)); //
+            // Point at the opening quote.
+            let line = self.line - self.source[self.start..].matches('\n').count();
+            return Err(ParseError::UnterminatedString(
+                line,
+                column_at(&self.source, self.start),
+            ));
         }
 
         // The closing ".
@@ -311,60 +512,86 @@ impl Scanner {
         let value = self.source[self.start + 1..self.current - 1].to_owned();
         Ok(Some(self.create_token_with_literal(
             TokenType::String,
-            Some(LiteralValue::String(value)),
+            Some(Literal::String(value)),
         )))
     }
 
+    // `current` is a byte offset, so the scanner can slice the source
+    // directly; these helpers step over whole characters.
     fn peek(&self) -> char {
-        if self.is_at_end() {
-            '\0'
-        } else {
-            self.source.chars().nth(self.current).unwrap()
-        }
+        self.source[self.current..].chars().next().unwrap_or('\0')
     }
 
     fn peek_next(&self) -> char {
-        if self.current + 1 >= self.source.len() {
-            '\0'
-        } else {
-            self.source.chars().nth(self.current + 1).unwrap()
-        }
+        let mut chars = self.source[self.current..].chars();
+        chars.next();
+        chars.next().unwrap_or('\0')
     }
 
     fn match_next(&mut self, expected: char) -> bool {
-        if self.is_at_end() {
-            return false;
-        }
-        if self.source.chars().nth(self.current).unwrap() != expected {
+        if self.is_at_end() || self.peek() != expected {
             return false;
         }
-        self.current += 1;
+        self.current += expected.len_utf8();
         true
     }
 
     fn advance(&mut self) -> char {
-        let c = self.source.chars().nth(self.current);
-        self.current += 1;
-        match c {
-            Some(c) => c,
-            None => ' ',
-        }
+        let c = self.peek();
+        self.current += c.len_utf8();
+        c
+    }
+
+    fn eof(&self) -> Token {
+        let column = column_at(&self.source, self.source.len());
+        Token::new(TokenType::Eof, String::new(), None, self.line, column)
     }
 
     fn create_token(&self, token_type: TokenType) -> Token {
         self.create_token_with_literal(token_type, None)
     }
 
-    fn create_token_with_literal(
-        &self,
-        token_type: TokenType,
-        literal: Option<LiteralValue>,
-    ) -> Token {
+    fn create_token_with_literal(&self, token_type: TokenType, literal: Option<Literal>) -> Token {
         let lexeme = self.source[self.start..self.current].to_owned();
-        Token::new(token_type, lexeme, literal, self.line)
+        let column = column_at(&self.source, self.start);
+        Token::new(token_type, lexeme, literal, self.line, column)
     }
 }
 
+// The column of a byte offset in the source, counted in characters from
+// one.
+pub fn column_at(source: &str, offset: usize) -> usize {
+    let line_start = source[..offset].rfind('\n').map_or(0, |i| i + 1);
+    source[line_start..offset].chars().count() + 1
+}
+
+// The keyword a word is, if it is one. Its length and first letter leave
+// at most one keyword it can be, or two told apart by the second letter,
+// so a word is compared with that one alone.
+pub fn keyword(text: &str) -> Option<TokenType> {
+    let bytes = text.as_bytes();
+    let (keyword, token_type) = match (bytes.len(), *bytes.first()?) {
+        (2, b'i') => ("if", TokenType::If),
+        (2, b'o') => ("or", TokenType::Or),
+        (3, b'a') => ("and", TokenType::And),
+        (3, b'f') if bytes[1] == b'o' => ("for", TokenType::For),
+        (3, b'f') => ("fun", TokenType::Fun),
+        (3, b'n') => ("nil", TokenType::Nil),
+        (3, b'v') => ("var", TokenType::Var),
+        (4, b'e') => ("else", TokenType::Else),
+        (4, b't') if bytes[1] == b'h' => ("this", TokenType::This),
+        (4, b't') => ("true", TokenType::True),
+        (5, b'c') => ("class", TokenType::Class),
+        (5, b'f') => ("false", TokenType::False),
+        (5, b'p') => ("print", TokenType::Print),
+        (5, b's') => ("super", TokenType::Super),
+        (5, b'w') => ("while", TokenType::While),
+        (6, b'r') => ("return", TokenType::Return),
+        _ => return None,
+    };
+    (text == keyword).then_some(token_type)
+}
+
 fn is_alpha(c: char) -> bool {
     c.is_ascii_alphabetic() || c == '_'
 }
@@ -0,0 +1,513 @@
+use std::sync::Arc;
+
+use crate::{
+    ast::{Ast, Program},
+    expr::{Expr, ExprId},
+    function::LoxFunction,
+    json::{self, JsonValue},
+    resolver,
+    scanner::{Literal, ParseError, Token, TokenType},
+    stack,
+    stmt::{Stmt, StmtId},
+};
+
+// Serialization of the AST to JSON, so a program can be parsed once and
+// executed many times, or produced and consumed by other tools. The JSON
+// form is a nested tree and does not depend on the arena layout.
+
+pub fn serialize_program(program: &Program) -> String {
+    program_to_json(program).to_string()
+}
+
+pub fn program_to_json(program: &Program) -> JsonValue {
+    stmts_to_json(&program.ast, &program.statements)
+}
+
+pub fn deserialize_program(source: &str) -> Result<Program, ParseError> {
+    let document = json::parse(source).map_err(|e| ParseError::MalformedAst(e.to_string()))?;
+    program_from_json(&document)
+}
+
+pub fn program_from_json(document: &JsonValue) -> Result<Program, ParseError> {
+    let statements = document
+        .as_array()
+        .ok_or_else(|| malformed("program must be an array of statements"))?;
+    let mut ast = Ast::new();
+    let statements = statements
+        .iter()
+        .map(|stmt| stmt_from_json(&mut ast, stmt))
+        .collect::<Result<Vec<_>, _>>()?;
+    resolver::resolve(&mut ast, &statements);
+    Ok(Program {
+        ast: Arc::new(ast),
+        statements,
+    })
+}
+
+// Serializes a function as a program that declares it, so it can be
+// declared again in another interpreter.
+pub fn serialize_function(function: &LoxFunction) -> String {
+    function_to_program(function).to_string()
+}
+
+pub fn function_to_program(function: &LoxFunction) -> JsonValue {
+    let declaration = function_to_json(
+        &function.ast,
+        &function.name,
+        &function.params,
+        &function.body,
+    );
+    JsonValue::Array(vec![declaration])
+}
+
+fn node(kind: &str, fields: Vec<(&str, JsonValue)>) -> JsonValue {
+    let mut entries = vec![("kind".to_string(), JsonValue::String(kind.to_string()))];
+    entries.extend(fields.into_iter().map(|(k, v)| (k.to_string(), v)));
+    JsonValue::Object(entries)
+}
+
+fn function_to_json(ast: &Ast, name: &Token, params: &[Token], body: &[StmtId]) -> JsonValue {
+    node(
+        "Function",
+        vec![
+            ("name", token_to_json(name)),
+            (
+                "params",
+                JsonValue::Array(params.iter().map(token_to_json).collect()),
+            ),
+            ("body", stmts_to_json(ast, body)),
+        ],
+    )
+}
+
+fn stmt_to_json(ast: &Ast, stmt: StmtId) -> JsonValue {
+    let expr_to_json = |expr: &ExprId| expr_to_json(ast, *expr);
+    let stmt_to_json = |stmt: &StmtId| stmt_to_json(ast, *stmt);
+    match ast.stmt(stmt) {
+        Stmt::Expression(expr) => node("Expression", vec![("expression", expr_to_json(expr))]),
+        Stmt::Print(expr) => node("Print", vec![("expression", expr_to_json(expr))]),
+        Stmt::Var(name, initializer) => node(
+            "Var",
+            vec![
+                ("name", token_to_json(name)),
+                ("initializer", optional(initializer.as_ref(), expr_to_json)),
+            ],
+        ),
+        Stmt::Block(statements) => node(
+            "Block",
+            vec![("statements", stmts_to_json(ast, statements))],
+        ),
+        Stmt::If(condition, then_branch, else_branch) => node(
+            "If",
+            vec![
+                ("condition", expr_to_json(condition)),
+                ("then", stmt_to_json(then_branch)),
+                ("else", optional(else_branch.as_ref(), stmt_to_json)),
+            ],
+        ),
+        Stmt::While(condition, body) => node(
+            "While",
+            vec![
+                ("condition", expr_to_json(condition)),
+                ("body", stmt_to_json(body)),
+            ],
+        ),
+        Stmt::Function(name, params, body) => function_to_json(ast, name, params, body),
+        Stmt::Return(keyword, value) => node(
+            "Return",
+            vec![
+                ("keyword", token_to_json(keyword)),
+                ("value", optional(value.as_ref(), expr_to_json)),
+            ],
+        ),
+        Stmt::Class(name, superclass, methods) => node(
+            "Class",
+            vec![
+                ("name", token_to_json(name)),
+                ("superclass", optional(superclass.as_ref(), token_to_json)),
+                (
+                    "methods",
+                    JsonValue::Array(
+                        methods
+                            .iter()
+                            .map(|m| JsonValue::String(m.clone()))
+                            .collect(),
+                    ),
+                ),
+            ],
+        ),
+    }
+}
+
+fn stmts_to_json(ast: &Ast, statements: &[StmtId]) -> JsonValue {
+    JsonValue::Array(
+        statements
+            .iter()
+            .map(|stmt| stmt_to_json(ast, *stmt))
+            .collect(),
+    )
+}
+
+fn expr_to_json(ast: &Ast, expr: ExprId) -> JsonValue {
+    stack::descend(|| expr_node_to_json(ast, expr))
+}
+
+fn expr_node_to_json(ast: &Ast, expr: ExprId) -> JsonValue {
+    let expr_to_json = |expr: &ExprId| expr_to_json(ast, *expr);
+    match ast.expr(expr) {
+        Expr::Binary(left, operator, right) => node(
+            "Binary",
+            vec![
+                ("left", expr_to_json(left)),
+                ("operator", token_to_json(operator)),
+                ("right", expr_to_json(right)),
+            ],
+        ),
+        Expr::Unary(operator, right) => node(
+            "Unary",
+            vec![
+                ("operator", token_to_json(operator)),
+                ("right", expr_to_json(right)),
+            ],
+        ),
+        Expr::Literal(value) => node(
+            "Literal",
+            vec![("value", optional(value.as_ref(), literal_to_json))],
+        ),
+        Expr::Grouping(expr) => node("Grouping", vec![("expression", expr_to_json(expr))]),
+        Expr::Variable(name) => node("Variable", vec![("name", token_to_json(name))]),
+        Expr::Assignment(name, value) => node(
+            "Assignment",
+            vec![
+                ("name", token_to_json(name)),
+                ("value", expr_to_json(value)),
+            ],
+        ),
+        Expr::Logical(left, operator, right) => node(
+            "Logical",
+            vec![
+                ("left", expr_to_json(left)),
+                ("operator", token_to_json(operator)),
+                ("right", expr_to_json(right)),
+            ],
+        ),
+        Expr::Call(callee, paren, arguments) => node(
+            "Call",
+            vec![
+                ("callee", expr_to_json(callee)),
+                ("paren", token_to_json(paren)),
+                (
+                    "arguments",
+                    JsonValue::Array(arguments.iter().map(expr_to_json).collect()),
+                ),
+            ],
+        ),
+        Expr::Get(object, name) => node(
+            "Get",
+            vec![
+                ("object", expr_to_json(object)),
+                ("name", token_to_json(name)),
+            ],
+        ),
+        Expr::Set(object, name, value) => node(
+            "Set",
+            vec![
+                ("object", expr_to_json(object)),
+                ("name", token_to_json(name)),
+                ("value", expr_to_json(value)),
+            ],
+        ),
+        Expr::This(keyword) => node("This", vec![("keyword", token_to_json(keyword))]),
+        Expr::Super(keyword, method) => node(
+            "Super",
+            vec![
+                ("keyword", token_to_json(keyword)),
+                ("method", token_to_json(method)),
+            ],
+        ),
+    }
+}
+
+fn token_to_json(token: &Token) -> JsonValue {
+    let mut entries = vec![
+        (
+            "type".to_string(),
+            JsonValue::String(format!("{:?}", token.token_type)),
+        ),
+        (
+            "lexeme".to_string(),
+            JsonValue::String(token.lexeme.clone()),
+        ),
+        ("line".to_string(), JsonValue::Number(token.line as f64)),
+        ("column".to_string(), JsonValue::Number(token.column as f64)),
+    ];
+    if let Some(literal) = &token.literal {
+        entries.push(("literal".to_string(), literal_to_json(literal)));
+    }
+    JsonValue::Object(entries)
+}
+
+fn literal_to_json(value: &Literal) -> JsonValue {
+    match value {
+        Literal::String(s) => JsonValue::String(s.clone()),
+        Literal::Number(n) => JsonValue::Number(*n),
+        Literal::Boolean(b) => JsonValue::Bool(*b),
+        Literal::Nil => JsonValue::Null,
+    }
+}
+
+fn optional<T: ?Sized>(value: Option<&T>, convert: impl Fn(&T) -> JsonValue) -> JsonValue {
+    value.map(convert).unwrap_or(JsonValue::Null)
+}
+
+fn malformed(message: &str) -> ParseError {
+    ParseError::MalformedAst(message.to_string())
+}
+
+fn field<'a>(json: &'a JsonValue, key: &str) -> Result<&'a JsonValue, ParseError> {
+    json.get(key)
+        .ok_or_else(|| malformed(&format!("missing field '{}'", key)))
+}
+
+fn kind(json: &JsonValue) -> Result<&str, ParseError> {
+    field(json, "kind")?
+        .as_str()
+        .ok_or_else(|| malformed("'kind' must be a string"))
+}
+
+fn stmt_from_json(ast: &mut Ast, json: &JsonValue) -> Result<StmtId, ParseError> {
+    let stmt = match kind(json)? {
+        "Expression" => Stmt::Expression(expr_field(ast, json, "expression")?),
+        "Print" => Stmt::Print(expr_field(ast, json, "expression")?),
+        "Var" => Stmt::Var(
+            token_field(json, "name")?,
+            optional_field(json, "initializer", |value| expr_from_json(ast, value))?,
+        ),
+        "Block" => Stmt::Block(stmts_field(ast, json, "statements")?),
+        "If" => Stmt::If(
+            expr_field(ast, json, "condition")?,
+            stmt_from_json(ast, field(json, "then")?)?,
+            optional_field(json, "else", |value| stmt_from_json(ast, value))?,
+        ),
+        "While" => Stmt::While(
+            expr_field(ast, json, "condition")?,
+            stmt_from_json(ast, field(json, "body")?)?,
+        ),
+        "Function" => Stmt::Function(
+            token_field(json, "name")?,
+            array_field(json, "params")?
+                .iter()
+                .map(token_from_json)
+                .collect::<Result<_, _>>()?,
+            stmts_field(ast, json, "body")?,
+        ),
+        "Return" => Stmt::Return(
+            token_field(json, "keyword")?,
+            optional_field(json, "value", |value| expr_from_json(ast, value))?,
+        ),
+        "Class" => Stmt::Class(
+            token_field(json, "name")?,
+            optional_field(json, "superclass", token_from_json)?,
+            array_field(json, "methods")?
+                .iter()
+                .map(|m| {
+                    m.as_str()
+                        .map(str::to_string)
+                        .ok_or_else(|| malformed("method must be a string"))
+                })
+                .collect::<Result<_, _>>()?,
+        ),
+        other => return Err(malformed(&format!("unknown statement kind '{}'", other))),
+    };
+    Ok(ast.add_stmt(stmt))
+}
+
+fn expr_from_json(ast: &mut Ast, json: &JsonValue) -> Result<ExprId, ParseError> {
+    stack::descend(|| expr_node_from_json(ast, json))
+}
+
+fn expr_node_from_json(ast: &mut Ast, json: &JsonValue) -> Result<ExprId, ParseError> {
+    let expr = match kind(json)? {
+        "Binary" => Expr::Binary(
+            expr_field(ast, json, "left")?,
+            operator_field(json, BINARY)?,
+            expr_field(ast, json, "right")?,
+        ),
+        "Unary" => Expr::Unary(
+            operator_field(json, &[TokenType::Minus, TokenType::Bang])?,
+            expr_field(ast, json, "right")?,
+        ),
+        "Literal" => Expr::Literal(optional_field(json, "value", literal_from_json)?),
+        "Grouping" => Expr::Grouping(expr_field(ast, json, "expression")?),
+        "Variable" => Expr::Variable(token_field(json, "name")?),
+        "Assignment" => {
+            Expr::Assignment(token_field(json, "name")?, expr_field(ast, json, "value")?)
+        }
+        "Logical" => Expr::Logical(
+            expr_field(ast, json, "left")?,
+            operator_field(json, &[TokenType::And, TokenType::Or])?,
+            expr_field(ast, json, "right")?,
+        ),
+        "Call" => Expr::Call(
+            expr_field(ast, json, "callee")?,
+            token_field(json, "paren")?,
+            array_field(json, "arguments")?
+                .iter()
+                .map(|argument| expr_from_json(ast, argument))
+                .collect::<Result<_, _>>()?,
+        ),
+        "Get" => Expr::Get(expr_field(ast, json, "object")?, token_field(json, "name")?),
+        "Set" => Expr::Set(
+            expr_field(ast, json, "object")?,
+            token_field(json, "name")?,
+            expr_field(ast, json, "value")?,
+        ),
+        "This" => Expr::This(token_field(json, "keyword")?),
+        "Super" => Expr::Super(token_field(json, "keyword")?, token_field(json, "method")?),
+        other => return Err(malformed(&format!("unknown expression kind '{}'", other))),
+    };
+    Ok(ast.add_expr(expr))
+}
+
+fn expr_field(ast: &mut Ast, json: &JsonValue, key: &str) -> Result<ExprId, ParseError> {
+    expr_from_json(ast, field(json, key)?)
+}
+
+fn token_field(json: &JsonValue, key: &str) -> Result<Token, ParseError> {
+    token_from_json(field(json, key)?)
+}
+
+// The operators a binary expression may have, as the parser gives them.
+const BINARY: &[TokenType] = &[
+    TokenType::Plus,
+    TokenType::Minus,
+    TokenType::Star,
+    TokenType::Slash,
+    TokenType::Greater,
+    TokenType::GreaterEqual,
+    TokenType::Less,
+    TokenType::LessEqual,
+    TokenType::BangEqual,
+    TokenType::EqualEqual,
+];
+
+// The operator of an expression, which must be one of `operators`: the
+// backends take any other for granted never to be there.
+fn operator_field(json: &JsonValue, operators: &[TokenType]) -> Result<Token, ParseError> {
+    let operator = token_field(json, "operator")?;
+    if !operators.contains(&operator.token_type) {
+        let message = format!("'{}' is not an operator here", operator.lexeme);
+        return Err(malformed(&message));
+    }
+    Ok(operator)
+}
+
+fn array_field<'a>(json: &'a JsonValue, key: &str) -> Result<&'a [JsonValue], ParseError> {
+    field(json, key)?
+        .as_array()
+        .ok_or_else(|| malformed(&format!("'{}' must be an array", key)))
+}
+
+fn stmts_field(ast: &mut Ast, json: &JsonValue, key: &str) -> Result<Vec<StmtId>, ParseError> {
+    array_field(json, key)?
+        .iter()
+        .map(|stmt| stmt_from_json(ast, stmt))
+        .collect()
+}
+
+fn optional_field<T>(
+    json: &JsonValue,
+    key: &str,
+    convert: impl FnOnce(&JsonValue) -> Result<T, ParseError>,
+) -> Result<Option<T>, ParseError> {
+    match json.get(key) {
+        None | Some(JsonValue::Null) => Ok(None),
+        Some(value) => convert(value).map(Some),
+    }
+}
+
+fn token_from_json(json: &JsonValue) -> Result<Token, ParseError> {
+    let type_name = field(json, "type")?
+        .as_str()
+        .ok_or_else(|| malformed("token 'type' must be a string"))?;
+    let token_type = token_type_from_name(type_name)
+        .ok_or_else(|| malformed(&format!("unknown token type '{}'", type_name)))?;
+    let lexeme = field(json, "lexeme")?
+        .as_str()
+        .ok_or_else(|| malformed("token 'lexeme' must be a string"))?;
+    let line = field(json, "line")?
+        .as_f64()
+        .ok_or_else(|| malformed("token 'line' must be a number"))?;
+    // Older ASTs have no columns.
+    let column = optional_field(json, "column", |value| {
+        value
+            .as_f64()
+            .ok_or_else(|| malformed("token 'column' must be a number"))
+    })?;
+    let literal = optional_field(json, "literal", literal_from_json)?;
+    Ok(Token::new(
+        token_type,
+        lexeme.to_string(),
+        literal,
+        line as usize,
+        column.unwrap_or(0.0) as usize,
+    ))
+}
+
+fn literal_from_json(json: &JsonValue) -> Result<Literal, ParseError> {
+    match json {
+        JsonValue::String(s) => Ok(Literal::String(s.clone())),
+        JsonValue::Number(n) => Ok(Literal::Number(*n)),
+        JsonValue::Bool(b) => Ok(Literal::Boolean(*b)),
+        JsonValue::Null => Ok(Literal::Nil),
+        _ => Err(malformed(
+            "literal must be a string, number, boolean or null",
+        )),
+    }
+}
+
+fn token_type_from_name(name: &str) -> Option<TokenType> {
+    let token_type = match name {
+        "LeftParen" => TokenType::LeftParen,
+        "RightParen" => TokenType::RightParen,
+        "LeftBrace" => TokenType::LeftBrace,
+        "RightBrace" => TokenType::RightBrace,
+        "Comma" => TokenType::Comma,
+        "Dot" => TokenType::Dot,
+        "Minus" => TokenType::Minus,
+        "Plus" => TokenType::Plus,
+        "Semicolon" => TokenType::Semicolon,
+        "Slash" => TokenType::Slash,
+        "Star" => TokenType::Star,
+        "Bang" => TokenType::Bang,
+        "BangEqual" => TokenType::BangEqual,
+        "Equal" => TokenType::Equal,
+        "EqualEqual" => TokenType::EqualEqual,
+        "Greater" => TokenType::Greater,
+        "GreaterEqual" => TokenType::GreaterEqual,
+        "Less" => TokenType::Less,
+        "LessEqual" => TokenType::LessEqual,
+        "Identifier" => TokenType::Identifier,
+        "String" => TokenType::String,
+        "Number" => TokenType::Number,
+        "And" => TokenType::And,
+        "Class" => TokenType::Class,
+        "Else" => TokenType::Else,
+        "False" => TokenType::False,
+        "Fun" => TokenType::Fun,
+        "For" => TokenType::For,
+        "If" => TokenType::If,
+        "Nil" => TokenType::Nil,
+        "Or" => TokenType::Or,
+        "Print" => TokenType::Print,
+        "Return" => TokenType::Return,
+        "Super" => TokenType::Super,
+        "This" => TokenType::This,
+        "True" => TokenType::True,
+        "Var" => TokenType::Var,
+        "While" => TokenType::While,
+        "Eof" => TokenType::Eof,
+        _ => return None,
+    };
+    Some(token_type)
+}
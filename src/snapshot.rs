@@ -0,0 +1,235 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::{
+    encoding,
+    environment::Environment,
+    function::LoxFunction,
+    interpreter::Interpreter,
+    json::{self, object, JsonValue},
+    map::Map,
+    scanner::LiteralValue,
+    serialize,
+    stmt::Stmt,
+};
+
+// Saves the globals of an interpreter as text, and defines them again in
+// another with `restore`, to carry a REPL session or a long job over to a
+// later run. A snapshot is a line with the version of the format, then the
+// globals as JSON, in order of name:
+//
+//     loxs 1
+//     {"globals":[{"name":"count","value":3},{"name":"seen","value":...}]}
+//
+// Values are tagged where JSON alone would lose what they are, as in
+// `{"list":[...]}`, `{"map":{...}}`, `{"bytes":"00ff"}`, `{"buffer":"..."}`
+// or `{"number":"NaN"}`. A function is saved as a program that declares it,
+// as threads send them, and so only one declared outside any other, the
+// prelude's among them, can be: one declared inside another would lose the
+// variables around it. Natives are left out, as every interpreter has its
+// own, and so are the globals holding what cannot be saved: natives under
+// other names, functions compiled by the VM, threads, channels and values
+// that contain themselves.
+// A list or map that two globals share is restored as two.
+
+const HEADER: &str = "loxs 1";
+
+pub struct Snapshot {
+    pub text: String,
+    // The globals left out, other than the natives.
+    pub skipped: Vec<String>,
+}
+
+pub fn save(interpreter: &Interpreter) -> Snapshot {
+    let scope = interpreter.global_scope();
+    let mut globals = interpreter.globals();
+    globals.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let mut saved = Vec::new();
+    let mut skipped = Vec::new();
+    for (name, value) in globals {
+        let native = match &value {
+            LiteralValue::NativeFunction(function) => function.name == name,
+            LiteralValue::Namespace(namespace) => namespace.name == name,
+            _ => false,
+        };
+        if native {
+            continue;
+        }
+        match encode(&value, scope, &mut Vec::new()) {
+            Some(value) => saved.push(object(vec![
+                ("name", JsonValue::String(name)),
+                ("value", value),
+            ])),
+            None => skipped.push(name),
+        }
+    }
+    let document = object(vec![("globals", JsonValue::Array(saved))]);
+    Snapshot {
+        text: format!("{}\n{}\n", HEADER, document),
+        skipped,
+    }
+}
+
+// Defines the globals saved in `text`, replacing any of the same names.
+// Nothing is defined if the snapshot is malformed.
+pub fn restore(interpreter: &mut Interpreter, text: &str) -> Result<(), String> {
+    let (header, body) = text.split_once('\n').unwrap_or((text, ""));
+    if header != HEADER {
+        return Err(format!(
+            "Not a snapshot, or one in another version of the format than '{}'.",
+            HEADER
+        ));
+    }
+    let document = json::parse(body).map_err(|error| malformed(&error.to_string()))?;
+    let scope = Rc::clone(interpreter.global_scope());
+    let globals = document
+        .get("globals")
+        .and_then(JsonValue::as_array)
+        .ok_or_else(|| malformed("it has no globals"))?
+        .iter()
+        .map(|global| {
+            let name = global
+                .get("name")
+                .and_then(JsonValue::as_str)
+                .ok_or_else(|| malformed("a global has no name"))?;
+            let value = global
+                .get("value")
+                .ok_or_else(|| malformed(&format!("'{}' has no value", name)))?;
+            Ok((name.to_string(), decode(value, &scope)?))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    for (name, value) in globals {
+        interpreter.define_global(&name, value);
+    }
+    Ok(())
+}
+
+// `parents` holds the lists and maps being saved around this value. Gives
+// none for a value that cannot be saved.
+fn encode(
+    value: &LiteralValue,
+    globals: &Rc<RefCell<Environment>>,
+    parents: &mut Vec<*const ()>,
+) -> Option<JsonValue> {
+    let tagged = |tag: &str, value: JsonValue| object(vec![(tag, value)]);
+    let json = match value {
+        LiteralValue::Nil => JsonValue::Null,
+        LiteralValue::Boolean(b) => JsonValue::Bool(*b),
+        LiteralValue::Number(n) if n.is_finite() => JsonValue::Number(*n),
+        LiteralValue::Number(n) => tagged("number", JsonValue::String(n.to_string())),
+        LiteralValue::String(s) => JsonValue::String(s.to_string()),
+        LiteralValue::Buffer(buffer) => {
+            tagged("buffer", JsonValue::String(buffer.borrow().clone()))
+        }
+        LiteralValue::Bytes(bytes) => tagged(
+            "bytes",
+            JsonValue::String(encoding::hex_encode(&bytes.borrow())),
+        ),
+        LiteralValue::List(items) => {
+            let pointer = Rc::as_ptr(items) as *const ();
+            if parents.contains(&pointer) {
+                return None;
+            }
+            parents.push(pointer);
+            let items = items
+                .borrow()
+                .iter()
+                .map(|item| encode(item, globals, parents))
+                .collect::<Option<_>>()?;
+            parents.pop();
+            tagged("list", JsonValue::Array(items))
+        }
+        LiteralValue::Map(map) => {
+            let pointer = Rc::as_ptr(map) as *const ();
+            if parents.contains(&pointer) {
+                return None;
+            }
+            parents.push(pointer);
+            let entries = map
+                .borrow()
+                .iter()
+                .map(|(key, value)| Some((key.to_string(), encode(value, globals, parents)?)))
+                .collect::<Option<_>>()?;
+            parents.pop();
+            tagged("map", JsonValue::Object(entries))
+        }
+        LiteralValue::Function(function) if Rc::ptr_eq(&function.closure, globals) => {
+            tagged("function", serialize::function_to_program(function))
+        }
+        _ => return None,
+    };
+    Some(json)
+}
+
+fn decode(json: &JsonValue, globals: &Rc<RefCell<Environment>>) -> Result<LiteralValue, String> {
+    let value = match json {
+        JsonValue::Null => LiteralValue::Nil,
+        JsonValue::Bool(b) => LiteralValue::Boolean(*b),
+        JsonValue::Number(n) => LiteralValue::Number(*n),
+        JsonValue::String(s) => LiteralValue::String(s.as_str().into()),
+        JsonValue::Object(entries) if entries.len() == 1 => {
+            let (tag, value) = &entries[0];
+            match (tag.as_str(), value) {
+                ("number", JsonValue::String(n)) => {
+                    LiteralValue::Number(n.parse().map_err(|_| malformed("a number is not one"))?)
+                }
+                ("buffer", JsonValue::String(s)) => {
+                    LiteralValue::Buffer(Rc::new(RefCell::new(s.clone())))
+                }
+                ("bytes", JsonValue::String(hex)) => {
+                    let bytes = encoding::hex_decode(hex).map_err(|error| malformed(&error))?;
+                    LiteralValue::Bytes(Rc::new(RefCell::new(bytes)))
+                }
+                ("list", JsonValue::Array(items)) => {
+                    let items = items
+                        .iter()
+                        .map(|item| decode(item, globals))
+                        .collect::<Result<_, _>>()?;
+                    LiteralValue::List(Rc::new(RefCell::new(items)))
+                }
+                ("map", JsonValue::Object(entries)) => {
+                    let mut map = Map::new();
+                    for (key, value) in entries {
+                        map.insert(key.as_str().into(), decode(value, globals)?);
+                    }
+                    LiteralValue::Map(Rc::new(RefCell::new(map)))
+                }
+                ("function", program) => {
+                    LiteralValue::Function(Rc::new(function(program, globals)?))
+                }
+                (tag, _) => return Err(malformed(&format!("'{}' is not a kind of value", tag))),
+            }
+        }
+        _ => return Err(malformed("a value is not tagged with its kind")),
+    };
+    Ok(value)
+}
+
+// Declares the function `program` declares among `globals`.
+fn function(
+    program: &JsonValue,
+    globals: &Rc<RefCell<Environment>>,
+) -> Result<LoxFunction, String> {
+    let program =
+        serialize::program_from_json(program).map_err(|error| malformed(&error.to_string()))?;
+    let statement = match program.statements.as_slice() {
+        [statement] => *statement,
+        _ => return Err(malformed("a function is not a single declaration")),
+    };
+    let Stmt::Function(name, params, body) = program.ast.stmt(statement) else {
+        return Err(malformed("a function is not a function declaration"));
+    };
+    Ok(LoxFunction {
+        name: name.clone(),
+        params: params.clone(),
+        body: body.clone(),
+        ast: Arc::clone(&program.ast),
+        closure: Rc::clone(globals),
+        locals: program.ast.scope(statement).cloned(),
+    })
+}
+
+fn malformed(message: &str) -> String {
+    format!("The snapshot is malformed: {}.", message)
+}
@@ -0,0 +1,15 @@
+// How little of the stack may be left before a recursive walk moves onto a
+// new one, and how large each new one is.
+const RED_ZONE: usize = 128 * 1024;
+const STACK_SIZE: usize = 4 * 1024 * 1024;
+
+// Goes one level deeper into a recursive walk with `walk`. Expressions can
+// nest deeper than the parser's limit, since a chain like `1 + 1 + ... + 1`
+// is parsed in a loop, and so can the JSON they are sent to threads as;
+// running a script goes deeper with every call it makes. So rather than
+// overflow the stack and abort, the walks over expressions and JSON go
+// through here, and continue on a new stack on the heap when the one they
+// are on runs low.
+pub fn descend<R>(walk: impl FnOnce() -> R) -> R {
+    stacker::maybe_grow(RED_ZONE, STACK_SIZE, walk)
+}
@@ -1,16 +1,20 @@
-use crate::{expr::Expr, scanner::Token};
+use crate::{expr::ExprId, scanner::Token};
 
 type Function = String;
 
+// Index of a statement node inside an `Ast` arena.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StmtId(pub usize);
+
 #[derive(Debug)]
 pub enum Stmt {
-    Expression(Expr),
-    Print(Expr),
-    Var(Token, Option<Expr>),
-    Block(Vec<Stmt>),
-    If(Expr, Box<Stmt>, Option<Box<Stmt>>),
-    While(Expr, Box<Stmt>),
-    Function(Token, Vec<Token>, Vec<Stmt>),
-    Return(Token, Option<Expr>),
+    Expression(ExprId),
+    Print(ExprId),
+    Var(Token, Option<ExprId>),
+    Block(Vec<StmtId>),
+    If(ExprId, StmtId, Option<StmtId>),
+    While(ExprId, StmtId),
+    Function(Token, Vec<Token>, Vec<StmtId>),
+    Return(Token, Option<ExprId>),
     Class(Token, Option<Token>, Vec<Function>),
 }
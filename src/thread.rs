@@ -0,0 +1,270 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fmt;
+use std::mem;
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::{
+    function::LoxFunction,
+    interpreter::Setup,
+    log,
+    map::Map,
+    scanner::{LiteralValue, Token, TokenType},
+    serialize, stack,
+};
+
+// Threads run functions in interpreters of their own, which share nothing
+// with the one that started them but its limits, the handle that stops it
+// and where it prints. Only plain data crosses between them: it is copied
+// into a `Message` on one side and rebuilt on the other. The function
+// itself is sent as its source, and is declared again in the new
+// interpreter, so it sees that interpreter's globals rather than the
+// variables around it where it was written. Channels carry more data
+// between threads while they run.
+
+// A copy of a value that can be moved to another thread.
+#[derive(Debug)]
+pub enum Message {
+    Nil,
+    Boolean(bool),
+    Number(f64),
+    String(String),
+    List(Vec<Message>),
+    Map(Vec<(String, Message)>),
+    Bytes(Vec<u8>),
+    Endpoint(Endpoint),
+}
+
+impl Message {
+    pub fn from_value(value: &LiteralValue) -> Result<Message, String> {
+        Self::from_nested(value, &mut HashSet::new())
+    }
+
+    // `parents` holds the lists and maps being copied around this value.
+    fn from_nested(
+        value: &LiteralValue,
+        parents: &mut HashSet<*const ()>,
+    ) -> Result<Message, String> {
+        let enter = |parents: &mut HashSet<*const ()>, collection: *const ()| {
+            if !parents.insert(collection) {
+                return Err("Cannot send a value that contains itself to a thread.".to_string());
+            }
+            Ok(())
+        };
+        let message = match value {
+            LiteralValue::Nil => Message::Nil,
+            LiteralValue::Boolean(b) => Message::Boolean(*b),
+            LiteralValue::Number(n) => Message::Number(*n),
+            LiteralValue::String(s) => Message::String(s.to_string()),
+            LiteralValue::Buffer(buffer) => Message::String(buffer.borrow().clone()),
+            LiteralValue::Bytes(bytes) => Message::Bytes(bytes.borrow().clone()),
+            LiteralValue::Endpoint(endpoint) => Message::Endpoint(Endpoint::clone(endpoint)),
+            LiteralValue::List(items) => {
+                let pointer = Rc::as_ptr(items) as *const ();
+                enter(parents, pointer)?;
+                let items = items
+                    .borrow()
+                    .iter()
+                    .map(|item| stack::descend(|| Self::from_nested(item, parents)))
+                    .collect::<Result<_, _>>()?;
+                parents.remove(&pointer);
+                Message::List(items)
+            }
+            LiteralValue::Map(map) => {
+                let pointer = Rc::as_ptr(map) as *const ();
+                enter(parents, pointer)?;
+                let entries = map
+                    .borrow()
+                    .iter()
+                    .map(|(key, value)| {
+                        let message = stack::descend(|| Self::from_nested(value, parents))?;
+                        Ok((key.to_string(), message))
+                    })
+                    .collect::<Result<_, String>>()?;
+                parents.remove(&pointer);
+                Message::Map(entries)
+            }
+            LiteralValue::NativeFunction(_)
+            | LiteralValue::Function(_)
+            | LiteralValue::Closure(_)
+            | LiteralValue::Namespace(_)
+            | LiteralValue::Thread(_) => {
+                return Err("Only data can be sent to or from a thread, not functions, \
+                            namespaces or threads."
+                    .to_string())
+            }
+        };
+        Ok(message)
+    }
+
+    pub fn into_value(mut self) -> LiteralValue {
+        match &mut self {
+            Message::Nil => LiteralValue::Nil,
+            Message::Boolean(b) => LiteralValue::Boolean(*b),
+            Message::Number(n) => LiteralValue::Number(*n),
+            Message::String(s) => LiteralValue::String(mem::take(s).into()),
+            Message::Bytes(bytes) => LiteralValue::Bytes(Rc::new(RefCell::new(mem::take(bytes)))),
+            Message::Endpoint(endpoint) => LiteralValue::Endpoint(Rc::new(endpoint.clone())),
+            Message::List(items) => {
+                let items = mem::take(items)
+                    .into_iter()
+                    .map(|item| stack::descend(|| item.into_value()))
+                    .collect();
+                LiteralValue::List(Rc::new(RefCell::new(items)))
+            }
+            Message::Map(entries) => {
+                let mut map = Map::new();
+                for (key, value) in mem::take(entries) {
+                    map.insert(key.into(), stack::descend(|| value.into_value()));
+                }
+                LiteralValue::Map(Rc::new(RefCell::new(map)))
+            }
+        }
+    }
+}
+
+// Lists and maps are taken apart from a list rather than dropped one inside
+// the other, as `JsonValue`s are, so that a message nested very deep cannot
+// overflow the stack of the thread that drops it.
+impl Drop for Message {
+    fn drop(&mut self) {
+        let mut pending = Vec::new();
+        take_children(self, &mut pending);
+        while let Some(mut message) = pending.pop() {
+            take_children(&mut message, &mut pending);
+        }
+    }
+}
+
+fn take_children(message: &mut Message, pending: &mut Vec<Message>) {
+    match message {
+        Message::List(items) => pending.append(items),
+        Message::Map(entries) => pending.extend(entries.drain(..).map(|(_, value)| value)),
+        _ => {}
+    }
+}
+
+// One end of a channel. Both ends can be copied and sent to other threads;
+// the copies of the receiving end take turns to receive, each message going
+// to only one of them.
+#[derive(Debug, Clone)]
+pub enum Endpoint {
+    Sender(Sender<Message>),
+    Receiver(Arc<Mutex<Receiver<Message>>>),
+}
+
+impl Endpoint {
+    pub fn channel() -> (Endpoint, Endpoint) {
+        let (sender, receiver) = mpsc::channel();
+        (
+            Endpoint::Sender(sender),
+            Endpoint::Receiver(Arc::new(Mutex::new(receiver))),
+        )
+    }
+
+    pub fn send(&self, message: Message) -> Result<(), String> {
+        match self {
+            Endpoint::Sender(sender) => sender
+                .send(message)
+                .map_err(|_| "Cannot send on a channel whose receivers are gone.".to_string()),
+            Endpoint::Receiver(_) => {
+                Err("Cannot send on the receiving end of a channel.".to_string())
+            }
+        }
+    }
+
+    // Waits for a message, or gives `None` once every sending end is gone
+    // and the channel is empty.
+    pub fn receive(&self) -> Result<Option<Message>, String> {
+        match self {
+            Endpoint::Receiver(receiver) => {
+                let receiver = receiver.lock().unwrap_or_else(|e| e.into_inner());
+                Ok(receiver.recv().ok())
+            }
+            Endpoint::Sender(_) => {
+                Err("Cannot receive on the sending end of a channel.".to_string())
+            }
+        }
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Endpoint::Sender(_) => write!(f, "<sender>"),
+            Endpoint::Receiver(_) => write!(f, "<receiver>"),
+        }
+    }
+}
+
+// A running thread, which gives back the function's result, or the error
+// that ended it, when joined. It can be joined only once.
+pub struct Thread {
+    pub name: String,
+    handle: RefCell<Option<JoinHandle<Result<Message, String>>>>,
+}
+
+impl Thread {
+    pub fn spawn(
+        function: &LoxFunction,
+        arguments: Vec<Message>,
+        setup: Setup,
+    ) -> Result<Thread, String> {
+        let name = function.name.lexeme.clone();
+        let line = function.name.line;
+        let source = serialize::serialize_function(function);
+        let thread_name = name.clone();
+        log::info(format_args!("Starting a thread running '{}'", name));
+        let handle = thread::Builder::new()
+            .name(name.clone())
+            .spawn(move || run(&thread_name, line, &source, arguments, setup))
+            .map_err(|e| format!("Could not start a thread: {}.", e))?;
+        Ok(Thread {
+            name,
+            handle: RefCell::new(Some(handle)),
+        })
+    }
+
+    pub fn join(&self) -> Result<LiteralValue, String> {
+        let handle = self
+            .handle
+            .borrow_mut()
+            .take()
+            .ok_or_else(|| "Thread has already been joined.".to_string())?;
+        match handle.join() {
+            Ok(result) => result.map(Message::into_value),
+            Err(_) => Err(format!("Thread '{}' panicked.", self.name)),
+        }
+    }
+}
+
+impl fmt::Debug for Thread {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<thread {}>", self.name)
+    }
+}
+
+// Declares the function in a fresh interpreter, set up like the one that
+// started the thread, and calls it.
+fn run(
+    name: &str,
+    line: usize,
+    source: &str,
+    arguments: Vec<Message>,
+    setup: Setup,
+) -> Result<Message, String> {
+    let failed = |error: &dyn fmt::Display| format!("Thread '{}' failed: {}", name, error);
+    let mut interpreter = setup.interpreter().map_err(|e| failed(&e))?;
+    interpreter.interpret_ast(source).map_err(|e| failed(&e))?;
+    let paren = Token::new(TokenType::RightParen, ")".to_string(), None, line, 0);
+    let token = Token::new(TokenType::Identifier, name.to_string(), None, line, 0);
+    let function = interpreter.global(&token).map_err(|e| failed(&e))?;
+    let arguments = arguments.into_iter().map(Message::into_value).collect();
+    let result = interpreter
+        .call_value(function, &paren, arguments)
+        .map_err(|e| failed(&e))?;
+    Message::from_value(&result)
+}
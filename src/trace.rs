@@ -0,0 +1,122 @@
+use crate::{
+    ast::Ast,
+    expr::{Expr, ExprId},
+    printer,
+    scanner::Token,
+    stack,
+    stmt::{Stmt, StmtId},
+};
+
+// Describes what runs for `--trace`: each statement as one line of source,
+// rebuilt from the AST, with the line it was written on. A statement that
+// holds others, like an `if` or a loop, is shown by its header alone, and a
+// block not at all, since the statements in it follow.
+//
+//     3: if (n < 2)
+//     4: return n * fact(n - 1);
+
+pub fn statement(ast: &Ast, id: StmtId) -> Option<String> {
+    let source = source(ast, id)?;
+    match line(ast, id) {
+        Some(line) => Some(format!("{}: {}", line, source)),
+        None => Some(source),
+    }
+}
+
+// A statement's source as the trace shows it, without the line.
+pub fn source(ast: &Ast, id: StmtId) -> Option<String> {
+    let source = match ast.stmt(id) {
+        Stmt::Expression(expr) => format!("{};", expression(ast, *expr)),
+        Stmt::Print(expr) => format!("print {};", expression(ast, *expr)),
+        Stmt::Var(name, Some(initializer)) => {
+            format!("var {} = {};", name.lexeme, expression(ast, *initializer))
+        }
+        Stmt::Var(name, None) => format!("var {};", name.lexeme),
+        Stmt::Block(_) => return None,
+        Stmt::If(condition, _, _) => format!("if ({})", expression(ast, *condition)),
+        Stmt::While(condition, _) => format!("while ({})", expression(ast, *condition)),
+        Stmt::Function(name, params, _) => {
+            let params: Vec<_> = params.iter().map(|param| param.lexeme.as_str()).collect();
+            format!("fun {}({})", name.lexeme, params.join(", "))
+        }
+        Stmt::Return(_, Some(value)) => format!("return {};", expression(ast, *value)),
+        Stmt::Return(_, None) => "return;".to_string(),
+        Stmt::Class(name, _, _) => format!("class {}", name.lexeme),
+    };
+    Some(source)
+}
+
+fn expression(ast: &Ast, id: ExprId) -> String {
+    stack::descend(|| expression_node(ast, id))
+}
+
+fn expression_node(ast: &Ast, id: ExprId) -> String {
+    match ast.expr(id) {
+        Expr::Binary(left, operator, right) | Expr::Logical(left, operator, right) => format!(
+            "{} {} {}",
+            expression(ast, *left),
+            operator.lexeme,
+            expression(ast, *right)
+        ),
+        Expr::Unary(operator, right) => format!("{}{}", operator.lexeme, expression(ast, *right)),
+        Expr::Literal(value) => printer::literal(value.as_ref()),
+        Expr::Grouping(expr) => format!("({})", expression(ast, *expr)),
+        Expr::Variable(name) => name.lexeme.clone(),
+        Expr::Assignment(name, value) => format!("{} = {}", name.lexeme, expression(ast, *value)),
+        Expr::Call(callee, _, arguments) => {
+            let arguments: Vec<_> = arguments.iter().map(|&a| expression(ast, a)).collect();
+            format!("{}({})", expression(ast, *callee), arguments.join(", "))
+        }
+        Expr::Get(object, name) => format!("{}.{}", expression(ast, *object), name.lexeme),
+        Expr::Set(object, name, value) => format!(
+            "{}.{} = {}",
+            expression(ast, *object),
+            name.lexeme,
+            expression(ast, *value)
+        ),
+        Expr::This(_) => "this".to_string(),
+        Expr::Super(_, method) => format!("super.{}", method.lexeme),
+    }
+}
+
+// The line a statement starts on, as far as its tokens tell. A literal
+// keeps no token, so `print 1;` has no line, and a block is taken to start
+// where its first statement does.
+pub fn line(ast: &Ast, id: StmtId) -> Option<usize> {
+    start(ast, id).map(|token| token.line)
+}
+
+// The first token of a statement that has one, as `line` finds it.
+pub fn start(ast: &Ast, id: StmtId) -> Option<&Token> {
+    match ast.stmt(id) {
+        Stmt::Var(name, _) | Stmt::Function(name, _, _) | Stmt::Class(name, _, _) => Some(name),
+        Stmt::Return(keyword, _) => Some(keyword),
+        Stmt::Expression(expr)
+        | Stmt::Print(expr)
+        | Stmt::If(expr, _, _)
+        | Stmt::While(expr, _) => expression_start(ast, *expr),
+        Stmt::Block(statements) => start(ast, *statements.first()?),
+    }
+}
+
+fn expression_start(ast: &Ast, id: ExprId) -> Option<&Token> {
+    stack::descend(|| expression_node_start(ast, id))
+}
+
+fn expression_node_start(ast: &Ast, id: ExprId) -> Option<&Token> {
+    match ast.expr(id) {
+        Expr::Binary(left, operator, _) | Expr::Logical(left, operator, _) => {
+            expression_start(ast, *left).or(Some(operator))
+        }
+        Expr::Call(object, token, _) | Expr::Get(object, token) | Expr::Set(object, token, _) => {
+            expression_start(ast, *object).or(Some(token))
+        }
+        Expr::Grouping(expr) => expression_start(ast, *expr),
+        Expr::Unary(token, _)
+        | Expr::Variable(token)
+        | Expr::Assignment(token, _)
+        | Expr::This(token)
+        | Expr::Super(token, _) => Some(token),
+        Expr::Literal(_) => None,
+    }
+}
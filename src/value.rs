@@ -0,0 +1,269 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::{map::Map, scanner::LiteralValue, stack};
+
+// A script value as a program embedding the interpreter sees it. Lists,
+// maps and bytes are copied out of the interpreter, so changing one here
+// does not change it for scripts, and copied back in as new ones.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Nil,
+    Boolean(bool),
+    Number(f64),
+    String(String),
+    List(Vec<Value>),
+    // In the order the keys were first inserted, as scripts see them.
+    Map(Vec<(String, Value)>),
+    Bytes(Vec<u8>),
+    Handle(Handle),
+}
+
+// A value the host can hold and give back to scripts but not look inside:
+// a function, namespace, thread or channel end. A list or map met again
+// inside itself is one too, so that copying it out comes to an end.
+#[derive(Clone)]
+pub struct Handle(LiteralValue);
+
+impl Value {
+    pub fn from_literal(value: &LiteralValue) -> Value {
+        Value::copy(value, &mut HashSet::new())
+    }
+
+    // `parents` holds the lists and maps being copied around this value.
+    fn copy(value: &LiteralValue, parents: &mut HashSet<*const ()>) -> Value {
+        match value {
+            LiteralValue::Nil => Value::Nil,
+            LiteralValue::Boolean(b) => Value::Boolean(*b),
+            LiteralValue::Number(n) => Value::Number(*n),
+            LiteralValue::String(s) => Value::String(s.to_string()),
+            LiteralValue::Buffer(buffer) => Value::String(buffer.borrow().clone()),
+            LiteralValue::Bytes(bytes) => Value::Bytes(bytes.borrow().clone()),
+            LiteralValue::List(items) => {
+                let pointer = Rc::as_ptr(items) as *const ();
+                if !parents.insert(pointer) {
+                    return Value::Handle(Handle(value.clone()));
+                }
+                let items = items
+                    .borrow()
+                    .iter()
+                    .map(|item| stack::descend(|| Value::copy(item, parents)))
+                    .collect();
+                parents.remove(&pointer);
+                Value::List(items)
+            }
+            LiteralValue::Map(map) => {
+                let pointer = Rc::as_ptr(map) as *const ();
+                if !parents.insert(pointer) {
+                    return Value::Handle(Handle(value.clone()));
+                }
+                let entries = map
+                    .borrow()
+                    .iter()
+                    .map(|(key, value)| {
+                        (
+                            key.to_string(),
+                            stack::descend(|| Value::copy(value, parents)),
+                        )
+                    })
+                    .collect();
+                parents.remove(&pointer);
+                Value::Map(entries)
+            }
+            LiteralValue::NativeFunction(_)
+            | LiteralValue::Function(_)
+            | LiteralValue::Closure(_)
+            | LiteralValue::Namespace(_)
+            | LiteralValue::Thread(_)
+            | LiteralValue::Endpoint(_) => Value::Handle(Handle(value.clone())),
+        }
+    }
+
+    pub fn into_literal(self) -> LiteralValue {
+        match self {
+            Value::Nil => LiteralValue::Nil,
+            Value::Boolean(b) => LiteralValue::Boolean(b),
+            Value::Number(n) => LiteralValue::Number(n),
+            Value::String(s) => LiteralValue::String(s.into()),
+            Value::List(items) => {
+                let items = items
+                    .into_iter()
+                    .map(|item| stack::descend(|| item.into_literal()))
+                    .collect();
+                LiteralValue::List(Rc::new(RefCell::new(items)))
+            }
+            Value::Map(entries) => {
+                let mut map = Map::new();
+                for (key, value) in entries {
+                    map.insert(key.into(), stack::descend(|| value.into_literal()));
+                }
+                LiteralValue::Map(Rc::new(RefCell::new(map)))
+            }
+            Value::Bytes(bytes) => LiteralValue::Bytes(Rc::new(RefCell::new(bytes))),
+            Value::Handle(Handle(value)) => value,
+        }
+    }
+
+    // What kind of value this is, for error messages: "a string".
+    fn description(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Boolean(_) => "a boolean",
+            Value::Number(_) => "a number",
+            Value::String(_) => "a string",
+            Value::List(_) => "a list",
+            Value::Map(_) => "a map",
+            Value::Bytes(_) => "bytes",
+            Value::Handle(_) => "a function, namespace, thread or channel end",
+        }
+    }
+}
+
+// Why a `Value` could not be converted to a Rust type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConversionError {
+    // What was asked for: "a number".
+    pub expected: &'static str,
+    pub value: Value,
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Expected {} but got {}.",
+            self.expected,
+            self.value.description()
+        )
+    }
+}
+
+impl std::error::Error for ConversionError {}
+
+impl From<f64> for Value {
+    fn from(n: f64) -> Value {
+        Value::Number(n)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Value {
+        Value::Boolean(b)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Value {
+        Value::String(s)
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(items: Vec<Value>) -> Value {
+        Value::List(items)
+    }
+}
+
+impl TryFrom<Value> for f64 {
+    type Error = ConversionError;
+
+    fn try_from(value: Value) -> Result<f64, ConversionError> {
+        match value {
+            Value::Number(n) => Ok(n),
+            value => Err(ConversionError {
+                expected: "a number",
+                value,
+            }),
+        }
+    }
+}
+
+impl TryFrom<Value> for bool {
+    type Error = ConversionError;
+
+    fn try_from(value: Value) -> Result<bool, ConversionError> {
+        match value {
+            Value::Boolean(b) => Ok(b),
+            value => Err(ConversionError {
+                expected: "a boolean",
+                value,
+            }),
+        }
+    }
+}
+
+impl TryFrom<Value> for String {
+    type Error = ConversionError;
+
+    fn try_from(value: Value) -> Result<String, ConversionError> {
+        match value {
+            Value::String(s) => Ok(s),
+            value => Err(ConversionError {
+                expected: "a string",
+                value,
+            }),
+        }
+    }
+}
+
+impl TryFrom<Value> for Vec<Value> {
+    type Error = ConversionError;
+
+    fn try_from(value: Value) -> Result<Vec<Value>, ConversionError> {
+        match value {
+            Value::List(items) => Ok(items),
+            value => Err(ConversionError {
+                expected: "a list",
+                value,
+            }),
+        }
+    }
+}
+
+impl Handle {
+    // What the handle refers to, to tell whether two handles are the same.
+    fn pointer(&self) -> *const () {
+        match &self.0 {
+            LiteralValue::NativeFunction(function) => Rc::as_ptr(function) as *const (),
+            LiteralValue::Function(function) => Rc::as_ptr(function) as *const (),
+            LiteralValue::Closure(closure) => Rc::as_ptr(closure) as *const (),
+            LiteralValue::Namespace(namespace) => Rc::as_ptr(namespace) as *const (),
+            LiteralValue::Thread(thread) => Rc::as_ptr(thread) as *const (),
+            LiteralValue::Endpoint(endpoint) => Rc::as_ptr(endpoint) as *const (),
+            LiteralValue::List(items) => Rc::as_ptr(items) as *const (),
+            LiteralValue::Map(map) => Rc::as_ptr(map) as *const (),
+            _ => unreachable!("only shared values are handles"),
+        }
+    }
+}
+
+impl PartialEq for Handle {
+    fn eq(&self, other: &Handle) -> bool {
+        self.pointer() == other.pointer()
+    }
+}
+
+// As scripts print the value.
+impl fmt::Debug for Handle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            LiteralValue::NativeFunction(function) => write!(f, "<native fn {}>", function.name),
+            LiteralValue::Function(function) => write!(f, "<fn {}>", function.name.lexeme),
+            LiteralValue::Closure(closure) => write!(f, "{:?}", closure),
+            LiteralValue::Namespace(namespace) => write!(f, "<namespace {}>", namespace.name),
+            LiteralValue::Thread(thread) => write!(f, "<thread {}>", thread.name),
+            LiteralValue::Endpoint(endpoint) => write!(f, "{}", endpoint),
+            LiteralValue::List(_) => write!(f, "[...]"),
+            LiteralValue::Map(_) => write!(f, "{{...}}"),
+            _ => unreachable!("only shared values are handles"),
+        }
+    }
+}
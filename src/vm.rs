@@ -0,0 +1,509 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::{
+    ast::{Ast, Program},
+    compiler::{self, Capture, Function, Op},
+    diagnostic::{self, Span},
+    engine::Engine,
+    expr::ExprId,
+    interpreter::{Interpreter, RuntimeError},
+    scanner::{LiteralValue, Token, TokenType},
+    stack, InterpreterError,
+};
+
+// The stack machine that runs what `compiler` makes of a script, for
+// `--backend=vm`. Calls between compiled functions push frames on a list
+// rather than recursing, and the operands of each instruction are on one
+// stack shared by the frames, with each call's arguments and locals at the
+// bottom of its part.
+//
+// Globals, natives and printing are the interpreter's, so compiled code
+// sees the same globals as the prelude and natives can call back into
+// compiled functions through `Interpreter::call_value`. Calls, and loops
+// going round, count toward the interpreter's limits as its statements and
+// calls do, across runs that natives start too. `--trace`, `--coverage`,
+// hooks and metrics only follow the tree-walker, and `spawn` only takes
+// its functions.
+
+// What the VM keeps on its stack: values as the rest of the interpreter
+// has them, or, in builds with the `nanbox` feature, packed into eight
+// bytes; see `nanbox`. They become values again wherever they leave the
+// stack, as for a global, a cell or a native.
+#[cfg(not(feature = "nanbox"))]
+type Slot = LiteralValue;
+#[cfg(feature = "nanbox")]
+type Slot = crate::nanbox::Boxed;
+
+pub(crate) trait StackValue: Clone {
+    fn from_literal(value: LiteralValue) -> Self;
+    fn number(n: f64) -> Self;
+    fn boolean(b: bool) -> Self;
+    fn nil() -> Self;
+    fn as_number(&self) -> Option<f64>;
+    fn as_closure(&self) -> Option<&Rc<Closure>>;
+    // As `Interpreter::is_truthy`.
+    fn is_truthy(&self) -> bool;
+    fn into_literal(self) -> LiteralValue;
+    fn to_literal(&self) -> LiteralValue;
+
+    // Values for what is on the stack, copied only if they are packed.
+    fn literals(slots: &[Self]) -> Cow<'_, [LiteralValue]> {
+        Cow::Owned(slots.iter().map(Self::to_literal).collect())
+    }
+}
+
+impl StackValue for LiteralValue {
+    fn from_literal(value: LiteralValue) -> Self {
+        value
+    }
+
+    fn number(n: f64) -> Self {
+        LiteralValue::Number(n)
+    }
+
+    fn boolean(b: bool) -> Self {
+        LiteralValue::Boolean(b)
+    }
+
+    fn nil() -> Self {
+        LiteralValue::Nil
+    }
+
+    fn as_number(&self) -> Option<f64> {
+        match self {
+            LiteralValue::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    fn as_closure(&self) -> Option<&Rc<Closure>> {
+        match self {
+            LiteralValue::Closure(closure) => Some(closure),
+            _ => None,
+        }
+    }
+
+    fn is_truthy(&self) -> bool {
+        match self {
+            LiteralValue::Nil => false,
+            LiteralValue::Boolean(b) => *b,
+            _ => true,
+        }
+    }
+
+    fn into_literal(self) -> LiteralValue {
+        self
+    }
+
+    fn to_literal(&self) -> LiteralValue {
+        self.clone()
+    }
+
+    fn literals(slots: &[Self]) -> Cow<'_, [LiteralValue]> {
+        Cow::Borrowed(slots)
+    }
+}
+
+// A compiled function with the variables it captured when it was made.
+pub struct Closure {
+    pub function: Rc<Function>,
+    upvalues: Vec<Rc<RefCell<LiteralValue>>>,
+}
+
+impl Closure {
+    // The cells of the variables it captured.
+    pub(crate) fn upvalues(&self) -> &[Rc<RefCell<LiteralValue>>] {
+        &self.upvalues
+    }
+}
+
+impl fmt::Debug for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<fn {}>", self.function.name)
+    }
+}
+
+struct Frame {
+    closure: Rc<Closure>,
+    // Where to carry on in the caller once this call returns.
+    ip: usize,
+    // Where the call's arguments start on the stack.
+    base: usize,
+    cells: Vec<Rc<RefCell<LiteralValue>>>,
+}
+
+impl Frame {
+    fn new(closure: Rc<Closure>, base: usize) -> Frame {
+        let cells = (0..closure.function.cells)
+            .map(|_| Rc::new(RefCell::new(LiteralValue::Nil)))
+            .collect();
+        Frame {
+            closure,
+            ip: 0,
+            base,
+            cells,
+        }
+    }
+}
+
+// Runs some calls of compiled functions quicker than the VM would, as the
+// JIT in `jit` does.
+pub trait Accelerator {
+    // What a call gives, or none to leave the call to the VM. A call is only
+    // taken if it cannot fail or do anything the VM would not.
+    fn call(
+        &mut self,
+        interpreter: &Interpreter,
+        closure: &Rc<Closure>,
+        arguments: &[LiteralValue],
+    ) -> Option<LiteralValue>;
+}
+
+// The VM as an engine. Scripts are compiled as they are given, and run
+// with the globals, natives and output of the interpreter.
+pub struct Vm {
+    interpreter: Interpreter,
+    accelerator: Option<Box<dyn Accelerator>>,
+}
+
+impl Vm {
+    pub fn new(interpreter: Interpreter, accelerator: Option<Box<dyn Accelerator>>) -> Vm {
+        Vm {
+            interpreter,
+            accelerator,
+        }
+    }
+}
+
+impl Engine for Vm {
+    fn interpret(&mut self, program: &Program) -> Result<(), InterpreterError> {
+        let script = compiler::compile(program)?;
+        let accelerator = self.accelerator.as_mut().map(|a| a.as_mut() as _);
+        run(&mut self.interpreter, script, accelerator)?;
+        Ok(())
+    }
+
+    fn evaluate(
+        &mut self,
+        ast: &Arc<Ast>,
+        expression: ExprId,
+    ) -> Result<LiteralValue, InterpreterError> {
+        let function = compiler::compile_expression(ast, expression)?;
+        let accelerator = self.accelerator.as_mut().map(|a| a.as_mut() as _);
+        Ok(run(&mut self.interpreter, function, accelerator)?)
+    }
+
+    fn define_global(&mut self, name: &str, value: LiteralValue) {
+        self.interpreter.define_global(name, value);
+    }
+
+    fn call(
+        &mut self,
+        callee: LiteralValue,
+        paren: &Token,
+        arguments: Vec<LiteralValue>,
+    ) -> Result<LiteralValue, RuntimeError> {
+        self.interpreter.call_value(callee, paren, arguments)
+    }
+
+    fn interpreter(&self) -> &Interpreter {
+        &self.interpreter
+    }
+
+    fn interpreter_mut(&mut self) -> &mut Interpreter {
+        &mut self.interpreter
+    }
+}
+
+// Runs compiled code, giving the value it returns.
+fn run(
+    interpreter: &mut Interpreter,
+    script: Rc<Function>,
+    accelerator: Option<&mut dyn Accelerator>,
+) -> Result<LiteralValue, RuntimeError> {
+    let closure = Rc::new(Closure {
+        function: script,
+        upvalues: Vec::new(),
+    });
+    execute(interpreter, closure, Vec::new(), accelerator)
+}
+
+// Calls a compiled function from outside compiled code, as natives do.
+pub fn call(
+    interpreter: &mut Interpreter,
+    closure: &Rc<Closure>,
+    paren: &Token,
+    arguments: Vec<LiteralValue>,
+) -> Result<LiteralValue, RuntimeError> {
+    if arguments.len() != closure.function.arity {
+        return Err(RuntimeError::ArityMismatch {
+            token: paren.clone(),
+            expected: closure.function.arity,
+            got: arguments.len(),
+        });
+    }
+    interpreter.enter_frame(paren)?;
+    // A native calling back into compiled code recurses in Rust.
+    let result = stack::descend(|| execute(interpreter, Rc::clone(closure), arguments, None));
+    interpreter.leave_frames(1);
+    result.map_err(|error| {
+        error.within([diagnostic::Frame {
+            function: closure.function.name.clone(),
+            span: Span::of(paren),
+        }])
+    })
+}
+
+fn execute(
+    interpreter: &mut Interpreter,
+    closure: Rc<Closure>,
+    arguments: Vec<LiteralValue>,
+    accelerator: Option<&mut dyn Accelerator>,
+) -> Result<LiteralValue, RuntimeError> {
+    let mut frames = vec![Frame::new(closure, 0)];
+    dispatch(interpreter, &mut frames, arguments, accelerator).map_err(|error| {
+        // The calls the error stopped are no longer running.
+        interpreter.leave_frames(frames.len() - 1);
+        error.within(trace(&frames))
+    })
+}
+
+// The calls `frames` were making, innermost first, for an error to be
+// traced in. Who called the first frame is not known here.
+fn trace(frames: &[Frame]) -> Vec<diagnostic::Frame> {
+    frames
+        .windows(2)
+        .rev()
+        .filter_map(|pair| {
+            let caller = &pair[0].closure.function;
+            // The caller stopped just after the call.
+            match caller.chunk.code[pair[0].ip - 1] {
+                Op::Call { token, .. } => Some(diagnostic::Frame {
+                    function: pair[1].closure.function.name.clone(),
+                    span: Span::of(&caller.chunk.tokens[token as usize]),
+                }),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+fn dispatch(
+    interpreter: &mut Interpreter,
+    frames: &mut Vec<Frame>,
+    arguments: Vec<LiteralValue>,
+    mut accelerator: Option<&mut dyn Accelerator>,
+) -> Result<LiteralValue, RuntimeError> {
+    let mut stack: Vec<Slot> = arguments.into_iter().map(Slot::from_literal).collect();
+    // The running function, where it is in it, and where its part of the
+    // stack starts, kept out of the frame for speed.
+    let mut function = Rc::clone(&frames[0].closure.function);
+    let mut ip = 0;
+    let mut base = 0;
+
+    macro_rules! pop {
+        () => {
+            stack.pop().expect("the compiler balances the stack")
+        };
+    }
+    macro_rules! peek {
+        () => {
+            stack.last().expect("the compiler balances the stack")
+        };
+    }
+    macro_rules! frame {
+        () => {
+            frames.last().expect("a function is running")
+        };
+    }
+
+    loop {
+        let op = function.chunk.code[ip];
+        ip += 1;
+        let token = |index: u32| &function.chunk.tokens[index as usize];
+        match op {
+            Op::Constant(index) => {
+                let constant = function.chunk.constants[index as usize].clone();
+                stack.push(Slot::from_literal(constant));
+            }
+            Op::Nil => stack.push(Slot::nil()),
+            Op::True => stack.push(Slot::boolean(true)),
+            Op::False => stack.push(Slot::boolean(false)),
+            Op::Pop => {
+                pop!();
+            }
+            Op::GetLocal(slot) => stack.push(stack[base + slot as usize].clone()),
+            Op::SetLocal(slot) => stack[base + slot as usize] = peek!().clone(),
+            Op::GetCell(cell) => {
+                let value = frame!().cells[cell as usize].borrow().clone();
+                stack.push(Slot::from_literal(value));
+            }
+            Op::SetCell(cell) => {
+                *frame!().cells[cell as usize].borrow_mut() = peek!().to_literal();
+            }
+            Op::MakeCell(cell) => {
+                let value = pop!().into_literal();
+                frames.last_mut().expect("a function is running").cells[cell as usize] =
+                    Rc::new(RefCell::new(value));
+            }
+            Op::GetUpvalue(index) => {
+                let upvalue = &frame!().closure.upvalues[index as usize];
+                stack.push(Slot::from_literal(upvalue.borrow().clone()));
+            }
+            Op::SetUpvalue(index) => {
+                *frame!().closure.upvalues[index as usize].borrow_mut() = peek!().to_literal();
+            }
+            Op::GetGlobal(name) => stack.push(Slot::from_literal(interpreter.global(token(name))?)),
+            Op::DefineGlobal(name) => {
+                let value = pop!().into_literal();
+                interpreter.define_global(&token(name).lexeme, value);
+            }
+            Op::SetGlobal(name) => interpreter.assign_global(token(name), peek!().to_literal())?,
+            Op::GetProperty(name) => {
+                let object = pop!().into_literal();
+                let value = interpreter.get_property(object, token(name))?;
+                stack.push(Slot::from_literal(value));
+            }
+            Op::SetProperty(name) => {
+                let value = pop!().into_literal();
+                let object = pop!().into_literal();
+                let value = interpreter.set_property(object, token(name), value)?;
+                stack.push(Slot::from_literal(value));
+            }
+            Op::Add(operator)
+            | Op::Subtract(operator)
+            | Op::Multiply(operator)
+            | Op::Less(operator)
+            | Op::Greater(operator)
+            | Op::Binary(operator) => {
+                let right = pop!();
+                let left = pop!();
+                let result = match (op, left.as_number(), right.as_number()) {
+                    (Op::Add(_), Some(a), Some(b)) => Slot::number(a + b),
+                    (Op::Subtract(_), Some(a), Some(b)) => Slot::number(a - b),
+                    (Op::Multiply(_), Some(a), Some(b)) => Slot::number(a * b),
+                    (Op::Less(_), Some(a), Some(b)) => Slot::boolean(a < b),
+                    (Op::Greater(_), Some(a), Some(b)) => Slot::boolean(a > b),
+                    _ => {
+                        let (left, right) = (left.into_literal(), right.into_literal());
+                        let value = interpreter.binary(token(operator), left, right)?;
+                        if let LiteralValue::String(s) = &value {
+                            interpreter.charge(s.len(), token(operator))?;
+                        }
+                        Slot::from_literal(value)
+                    }
+                };
+                stack.push(result);
+            }
+            Op::Negate(operator) => {
+                let value = pop!().into_literal();
+                let value = interpreter.unary(token(operator), value)?;
+                stack.push(Slot::from_literal(value));
+            }
+            Op::Not => {
+                let value = pop!();
+                stack.push(Slot::boolean(!value.is_truthy()));
+            }
+            Op::Print => {
+                let text = interpreter.stringify(pop!().into_literal());
+                interpreter.write_output(&format!("{}\n", text));
+            }
+            Op::Jump(target) => {
+                // Only loops jump back.
+                if (target as usize) < ip {
+                    interpreter.step(|| Token::new(TokenType::Eof, String::new(), None, 0, 0))?;
+                }
+                ip = target as usize;
+            }
+            Op::JumpIfFalse(target) => {
+                if !peek!().is_truthy() {
+                    ip = target as usize;
+                }
+            }
+            Op::JumpIfTrue(target) => {
+                if peek!().is_truthy() {
+                    ip = target as usize;
+                }
+            }
+            Op::Call {
+                token: paren,
+                arguments,
+            } => {
+                interpreter.step(|| token(paren).clone())?;
+                let start = stack.len() - arguments as usize;
+                match stack[start - 1].as_closure() {
+                    Some(closure) => {
+                        let closure = Rc::clone(closure);
+                        if arguments as usize != closure.function.arity {
+                            return Err(RuntimeError::ArityMismatch {
+                                token: token(paren).clone(),
+                                expected: closure.function.arity,
+                                got: arguments as usize,
+                            });
+                        }
+                        if let Some(accelerator) = accelerator.as_deref_mut() {
+                            let arguments = Slot::literals(&stack[start..]);
+                            let result = accelerator.call(interpreter, &closure, &arguments);
+                            if let Some(result) = result {
+                                stack.truncate(start - 1);
+                                stack.push(Slot::from_literal(result));
+                                continue;
+                            }
+                        }
+                        interpreter.enter_frame(token(paren))?;
+                        frames.last_mut().expect("a function is running").ip = ip;
+                        function = Rc::clone(&closure.function);
+                        ip = 0;
+                        base = start;
+                        frames.push(Frame::new(closure, start));
+                    }
+                    None => {
+                        let arguments = stack.split_off(start);
+                        let arguments = arguments.into_iter().map(Slot::into_literal).collect();
+                        let callee = pop!().into_literal();
+                        let result = interpreter.call_value(callee, token(paren), arguments)?;
+                        stack.push(Slot::from_literal(result));
+                    }
+                }
+            }
+            Op::Closure(index) => {
+                let nested = Rc::clone(&function.chunk.functions[index as usize]);
+                let frame = frame!();
+                let upvalues = nested
+                    .captures
+                    .iter()
+                    .map(|capture| match *capture {
+                        Capture::Cell(cell) => Rc::clone(&frame.cells[cell as usize]),
+                        Capture::Upvalue(index) => {
+                            Rc::clone(&frame.closure.upvalues[index as usize])
+                        }
+                    })
+                    .collect();
+                let closure = LiteralValue::Closure(Rc::new(Closure {
+                    function: nested,
+                    upvalues,
+                }));
+                interpreter.track(&closure);
+                stack.push(Slot::from_literal(closure));
+            }
+            Op::Return => {
+                let result = pop!();
+                let finished = frames.pop().expect("a function is running");
+                let Some(caller) = frames.last() else {
+                    return Ok(result.into_literal());
+                };
+                interpreter.leave_frames(1);
+                // The callee goes too.
+                stack.truncate(finished.base - 1);
+                stack.push(result);
+                function = Rc::clone(&caller.closure.function);
+                ip = caller.ip;
+                base = caller.base;
+            }
+        }
+    }
+}
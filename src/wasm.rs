@@ -0,0 +1,65 @@
+use wasm_bindgen::prelude::*;
+
+use crate::{capability::Capability, interpreter::Setup};
+
+// The interpreter as a WebAssembly module, for playgrounds that run scripts
+// in the browser rather than on a server like `playground`'s. It is built
+// with
+//
+//     cargo build --release --target wasm32-unknown-unknown
+//     wasm-bindgen --target web --out-dir pkg \
+//         target/wasm32-unknown-unknown/release/interprete.wasm
+//
+// and called from JavaScript:
+//
+//     import init, { run } from "./pkg/interprete.js";
+//     await init();
+//     const { output, errors } = run("print 1 + 2;");
+//
+// A page has no files, environment or processes to give scripts, so they
+// run with every capability denied and no plugins, and what they print is
+// collected rather than written out. Each call runs in a fresh interpreter.
+
+#[wasm_bindgen(getter_with_clone)]
+pub struct Outcome {
+    // What the script printed.
+    pub output: String,
+    // Why the script failed, if it did, as the command line would show it.
+    pub errors: Vec<String>,
+}
+
+#[wasm_bindgen]
+pub fn run(source: String) -> Outcome {
+    let mut setup = Setup::default();
+    for capability in Capability::ALL {
+        setup.capabilities.deny(capability);
+    }
+    let mut engine = match setup.engine() {
+        Ok(engine) => engine,
+        Err(error) => {
+            return Outcome {
+                output: String::new(),
+                errors: vec![error],
+            }
+        }
+    };
+    engine.interpreter_mut().capture_output();
+    let errors = match crate::run_in(engine.as_mut(), source.clone()) {
+        Ok(()) => Vec::new(),
+        Err(error) => vec![error.diagnostic().render("<script>", &source)],
+    };
+    Outcome {
+        output: engine.interpreter_mut().take_output(),
+        errors,
+    }
+}
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_namespace = Date, js_name = now)]
+    fn date_now() -> f64;
+}
+
+pub fn seconds_since_epoch() -> f64 {
+    date_now() / 1000.0
+}
@@ -0,0 +1,154 @@
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+
+use crate::{
+    interpreter::{Interpreter, InterruptHandle},
+    thread::Message,
+    value::Value,
+};
+
+// An interpreter on a thread of its own, for hosts with several threads.
+// An `Interpreter` holds its values in `Rc`s and cannot leave the thread
+// that made it, but a `Worker` can be moved to another thread or shared
+// between them, and runs what they ask of it one request at a time:
+//
+//     let worker = Worker::new(|| Interpreter::builder().sandbox(true).build())?;
+//     let handle = thread::spawn(move || worker.eval("1 + 2"));
+//
+// As with `spawn`, only plain data crosses between threads: values are
+// copied, and a function, namespace or thread cannot be passed in or given
+// back. Hosts that need more run a closure on the worker's thread with
+// `run`, which sees the interpreter itself.
+pub struct Worker {
+    requests: Option<Sender<Request>>,
+    thread: Option<JoinHandle<()>>,
+    interrupt: InterruptHandle,
+}
+
+type Request = Box<dyn FnOnce(&mut Interpreter) + Send>;
+
+impl Worker {
+    // Makes the interpreter on the new thread with `make`, which is where
+    // natives registered with `register_fn` must be registered, and fails
+    // as it does.
+    pub fn new<F>(make: F) -> Result<Worker, String>
+    where
+        F: FnOnce() -> Result<Interpreter, String> + Send + 'static,
+    {
+        let (requests, received) = mpsc::channel::<Request>();
+        let (made, outcome) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            let mut interpreter = match make() {
+                Ok(interpreter) => interpreter,
+                Err(error) => {
+                    made.send(Err(error)).ok();
+                    return;
+                }
+            };
+            made.send(Ok(interpreter.interrupt_handle())).ok();
+            for request in received {
+                request(&mut interpreter);
+            }
+        });
+        let interrupt = outcome.recv().unwrap_or_else(|_| Err(stopped()))?;
+        Ok(Worker {
+            requests: Some(requests),
+            thread: Some(thread),
+            interrupt,
+        })
+    }
+
+    // As `Interpreter::interrupt_handle`. Cancelling stops the request
+    // running, or the next one if none is, and those after run as usual.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
+    // Runs `task` with the interpreter on its thread, and gives what it
+    // gives, or an error if the thread has stopped because a task panicked.
+    pub fn run<R, F>(&self, task: F) -> Result<R, String>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut Interpreter) -> R + Send + 'static,
+    {
+        let (reply, result) = mpsc::channel();
+        let request: Request = Box::new(move |interpreter| {
+            reply.send(task(interpreter)).ok();
+        });
+        let requests = self
+            .requests
+            .as_ref()
+            .expect("only dropping takes the sender");
+        requests.send(request).map_err(|_| stopped())?;
+        result.recv().map_err(|_| stopped())
+    }
+
+    // As `Interpreter::eval`.
+    pub fn eval(&self, source: &str) -> Result<Value, String> {
+        let source = source.to_string();
+        let result = self.run(move |interpreter| {
+            let value = interpreter.eval(&source).map_err(|e| e.to_string())?;
+            Message::from_value(&value.into_literal())
+        });
+        Ok(receive(result??))
+    }
+
+    // As `Interpreter::call`.
+    pub fn call(&self, name: &str, arguments: &[Value]) -> Result<Value, String> {
+        let name = name.to_string();
+        let arguments = send_all(arguments)?;
+        let result = self.run(move |interpreter| {
+            let arguments: Vec<Value> = arguments.into_iter().map(receive).collect();
+            let value = interpreter
+                .call(&name, &arguments)
+                .map_err(|e| e.to_string())?;
+            Message::from_value(&value.into_literal())
+        });
+        Ok(receive(result??))
+    }
+
+    // As `Interpreter::set_global`.
+    pub fn set_global(&self, name: &str, value: Value) -> Result<(), String> {
+        let name = name.to_string();
+        let value = send(value)?;
+        self.run(move |interpreter| interpreter.set_global(&name, receive(value)))
+    }
+
+    // As `Interpreter::get_global`, for any value.
+    pub fn get_global(&self, name: &str) -> Result<Option<Value>, String> {
+        let name = name.to_string();
+        let result = self.run(move |interpreter| {
+            interpreter
+                .get_global::<Value>(&name)
+                .map(|value| Message::from_value(&value.into_literal()))
+                .transpose()
+        });
+        Ok(result??.map(receive))
+    }
+}
+
+impl Drop for Worker {
+    // Lets the thread finish what it was asked, and waits for it.
+    fn drop(&mut self) {
+        drop(self.requests.take());
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+fn stopped() -> String {
+    "The interpreter's thread has stopped.".to_string()
+}
+
+fn send(value: Value) -> Result<Message, String> {
+    Message::from_value(&value.into_literal())
+}
+
+fn send_all(values: &[Value]) -> Result<Vec<Message>, String> {
+    values.iter().cloned().map(send).collect()
+}
+
+fn receive(message: Message) -> Value {
+    Value::from_literal(&message.into_value())
+}
@@ -0,0 +1,124 @@
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+
+// Just enough of ZMTP 3.0, the wire protocol of ZeroMQ, to serve the peers
+// of a Jupyter kernel over TCP: the greeting and handshake with the NULL
+// mechanism, then multipart messages in both directions. Each connection
+// is a socket of its own, so a reply goes back on the connection its
+// request came in on and the routing that ZeroMQ does between connections
+// is left to the caller.
+
+pub struct Socket {
+    stream: TcpStream,
+}
+
+// A frame whose flags have this bit has more frames after it in the same
+// message.
+const MORE: u8 = 0x01;
+// The frame's size takes eight bytes rather than one.
+const LONG: u8 = 0x02;
+// The frame is a command, such as `READY`, rather than part of a message.
+const COMMAND: u8 = 0x04;
+// The largest frame accepted. The size comes from the peer, so is checked
+// before room is made for the frame.
+const MAX_FRAME: usize = 64 * 1024 * 1024;
+
+impl Socket {
+    // Completes the handshake with a peer that has just connected, telling
+    // it what type of socket this is, such as "ROUTER" or "PUB".
+    pub fn accept(mut stream: TcpStream, socket_type: &str) -> io::Result<Socket> {
+        let mut greeting = vec![0xff, 0, 0, 0, 0, 0, 0, 0, 1, 0x7f, 3, 0];
+        let mut mechanism = b"NULL".to_vec();
+        mechanism.resize(20, 0);
+        greeting.extend(mechanism);
+        // Not the server in the handshake's sense, then filler.
+        greeting.resize(64, 0);
+        stream.write_all(&greeting)?;
+        let mut theirs = [0u8; 64];
+        stream.read_exact(&mut theirs)?;
+        if theirs[0] != 0xff || theirs[9] != 0x7f || theirs[10] < 3 {
+            return Err(invalid("The peer does not speak ZMTP 3."));
+        }
+        if !theirs[12..32].starts_with(b"NULL\0") {
+            return Err(invalid("The peer wants security other than NULL."));
+        }
+
+        let mut ready = b"\x05READY".to_vec();
+        ready.push(b"Socket-Type".len() as u8);
+        ready.extend_from_slice(b"Socket-Type");
+        ready.extend_from_slice(&(socket_type.len() as u32).to_be_bytes());
+        ready.extend_from_slice(socket_type.as_bytes());
+        let mut socket = Socket { stream };
+        socket.write_frame(COMMAND, &ready)?;
+        // The peer's `READY` names its own socket type, which is taken on
+        // trust.
+        socket.read_frame()?;
+        Ok(socket)
+    }
+
+    pub fn try_clone(&self) -> io::Result<Socket> {
+        Ok(Socket {
+            stream: self.stream.try_clone()?,
+        })
+    }
+
+    // Reads the next message, as its frames, skipping any commands.
+    pub fn receive(&mut self) -> io::Result<Vec<Vec<u8>>> {
+        let mut frames = Vec::new();
+        loop {
+            let (flags, body) = self.read_frame()?;
+            if flags & COMMAND != 0 {
+                continue;
+            }
+            frames.push(body);
+            if flags & MORE == 0 {
+                return Ok(frames);
+            }
+        }
+    }
+
+    pub fn send(&mut self, frames: &[Vec<u8>]) -> io::Result<()> {
+        for (index, frame) in frames.iter().enumerate() {
+            let more = if index + 1 < frames.len() { MORE } else { 0 };
+            self.write_frame(more, frame)?;
+        }
+        self.stream.flush()
+    }
+
+    fn read_frame(&mut self) -> io::Result<(u8, Vec<u8>)> {
+        let mut flags = [0u8; 1];
+        self.stream.read_exact(&mut flags)?;
+        let size = if flags[0] & LONG != 0 {
+            let mut size = [0u8; 8];
+            self.stream.read_exact(&mut size)?;
+            usize::try_from(u64::from_be_bytes(size)).unwrap_or(usize::MAX)
+        } else {
+            let mut size = [0u8; 1];
+            self.stream.read_exact(&mut size)?;
+            size[0] as usize
+        };
+        if size > MAX_FRAME {
+            return Err(invalid("The peer sent a frame that is too large."));
+        }
+        let mut body = vec![0; size];
+        self.stream.read_exact(&mut body)?;
+        Ok((flags[0], body))
+    }
+
+    fn write_frame(&mut self, flags: u8, body: &[u8]) -> io::Result<()> {
+        let mut frame = Vec::with_capacity(body.len() + 9);
+        if body.len() > 255 {
+            frame.push(flags | LONG);
+            frame.extend_from_slice(&(body.len() as u64).to_be_bytes());
+        } else {
+            frame.push(flags);
+            frame.push(body.len() as u8);
+        }
+        frame.extend_from_slice(body);
+        self.stream.write_all(&frame)
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
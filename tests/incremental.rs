@@ -0,0 +1,131 @@
+use interprete::{
+    incremental::{Document, TextEdit},
+    parser::Parser,
+    scanner::{Scanner, Token},
+};
+
+const SOURCE: &str = "// A script with a little of everything the scanner knows.
+var greeting = \"hello,
+world\";
+fun add(a, b) { return a + b; }
+var n = 1.5 + add(2, 3.25);
+if (n >= 7 and !false) print greeting; else print \"é\";
+while (n > 0) n = n - 1;
+";
+
+// What may be typed in an edit, including text that opens or closes a
+// string or a comment, or runs into a number next to it.
+const SNIPPETS: [&str; 12] = [
+    "",
+    " ",
+    "\n",
+    "x",
+    "1",
+    ".5",
+    "\"",
+    "\"a\nb\"",
+    "// note\n",
+    "é",
+    "var ",
+    ";",
+];
+
+// A token as the scanner gives it, to compare with another.
+fn shown(token: &Token) -> (String, String, usize, usize) {
+    let kind = format!("{:?} {:?}", token.token_type, token.literal);
+    (kind, token.lexeme.clone(), token.line, token.column)
+}
+
+// Checks that a document holds the tokens and program that scanning and
+// parsing its source from scratch would give.
+fn assert_rescanned(document: &Document) {
+    let source = document.source().to_string();
+    let Ok(tokens) = Scanner::new(source.clone()).scan_tokens() else {
+        assert!(!document.scans(), "{:?} does not scan", source);
+        return;
+    };
+    assert!(document.scans(), "{:?} scans", source);
+    let ours: Vec<_> = document.tokens().iter().map(shown).collect();
+    let theirs: Vec<_> = tokens.iter().map(shown).collect();
+    assert_eq!(ours, theirs, "tokens of {:?}", source);
+    let ours = format!("{:?}", document.parse());
+    let theirs = format!("{:?}", Parser::new(tokens).parse());
+    assert_eq!(ours, theirs, "program of {:?}", source);
+}
+
+// A generator of numbers that is the same on every run, so a failure can
+// be repeated.
+struct Random(u64);
+
+impl Random {
+    fn below(&mut self, n: usize) -> usize {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        ((self.0 >> 33) % n as u64) as usize
+    }
+
+    // A byte offset in `source` on a character boundary.
+    fn offset(&mut self, source: &str) -> usize {
+        let mut offset = self.below(source.len() + 1);
+        while !source.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        offset
+    }
+}
+
+#[test]
+fn edits_give_what_a_full_rescan_gives() {
+    let mut random = Random(1);
+    for _ in 0..200 {
+        let mut document = Document::new(SOURCE.to_string());
+        for _ in 0..10 {
+            let source = document.source();
+            let start = random.offset(source);
+            // Mostly short edits, as typing makes.
+            let mut end = (start + random.below(4)).min(source.len());
+            while !source.is_char_boundary(end) {
+                end += 1;
+            }
+            let edit = TextEdit {
+                start,
+                end,
+                text: SNIPPETS[random.below(SNIPPETS.len())].to_string(),
+            };
+            let _ = document.edit(&edit);
+            assert_rescanned(&document);
+        }
+    }
+}
+
+#[test]
+fn typing_a_line_a_character_at_a_time() {
+    let mut document = Document::new(SOURCE.to_string());
+    let line = "var s = \"a\" + 12.5; // done\n";
+    let mut at = SOURCE.find("fun").unwrap();
+    for c in line.chars() {
+        let edit = TextEdit {
+            start: at,
+            end: at,
+            text: c.to_string(),
+        };
+        let _ = document.edit(&edit);
+        assert_rescanned(&document);
+        at += c.len_utf8();
+    }
+}
+
+#[test]
+fn an_edit_outside_the_source_is_refused() {
+    let mut document = Document::new("print 1;".to_string());
+    let edit = TextEdit {
+        start: 4,
+        end: 20,
+        text: String::new(),
+    };
+    assert!(document.edit(&edit).is_err());
+    assert_eq!(document.source(), "print 1;");
+    assert_rescanned(&document);
+}
@@ -0,0 +1,214 @@
+use interprete::{
+    engine::Engine,
+    interpreter::{Backend, Limits, Setup},
+};
+
+// Scripts that ask for more than they may have, of memory or of the stack,
+// must fail with an error on both backends, rather than abort the process.
+
+fn engine(backend: Backend, limits: Option<Limits>) -> Box<dyn Engine> {
+    let setup = Setup {
+        backend,
+        ..Setup::default()
+    };
+    let mut engine = setup.engine().expect("an engine is made");
+    let interpreter = engine.interpreter_mut();
+    interpreter.capture_output();
+    if let Some(limits) = limits {
+        interpreter.limit(limits);
+    }
+    engine
+}
+
+// The code of the error that running `source` on `backend` gives.
+fn error(backend: Backend, limits: Option<Limits>, source: &str) -> &'static str {
+    let mut engine = engine(backend, limits);
+    match interprete::run_in(engine.as_mut(), source.to_string()) {
+        Ok(()) => panic!("{:?} ran {:?}", backend, source),
+        Err(error) => error.diagnostic().code,
+    }
+}
+
+// Checks that `source` fails with `code` on each backend.
+fn assert_fails(limits: Option<Limits>, source: &str, code: &str) {
+    for backend in [Backend::Tree, Backend::Vm] {
+        assert_eq!(error(backend, limits, source), code, "{:?}", source);
+    }
+}
+
+const LIMITS: Limits = Limits {
+    steps: 1_000_000,
+    depth: 200,
+    memory: 16 * 1024 * 1024,
+};
+
+#[test]
+fn bytes_of_a_huge_length() {
+    assert_fails(None, "print len(bytes(100000000000000));", "E1008");
+    assert_fails(
+        None,
+        "print len(bytes(100000000000000 * 100000000000000));",
+        "E1008",
+    );
+    assert_fails(Some(LIMITS), "print len(bytes(100000000));", "E1013");
+}
+
+#[test]
+fn format_fields_too_wide() {
+    assert_fails(None, r#"print format("%.99999999999f", 1);"#, "E1008");
+    assert_fails(None, r#"print format("%999999999999d", 1);"#, "E1008");
+    assert_fails(
+        None,
+        r#"print format("%99999999999999999999999s", 1);"#,
+        "E1008",
+    );
+    assert_fails(None, r#"printf("%-1025s", "a");"#, "E1008");
+}
+
+#[test]
+fn format_fields_within_bounds() {
+    for backend in [Backend::Tree, Backend::Vm] {
+        let mut engine = engine(backend, None);
+        let source = r#"print len(format("%1024d|%.1024f", 1, 1));"#;
+        interprete::run_in(engine.as_mut(), source.to_string()).unwrap();
+        assert_eq!(engine.interpreter_mut().take_output(), "2051\n");
+    }
+}
+
+// JSON nested `depth` deep, as a string in a script.
+fn nested_json(depth: usize) -> String {
+    format!("\"{}{}\"", "[".repeat(depth), "]".repeat(depth))
+}
+
+#[test]
+fn json_nested_too_deep() {
+    let source = format!("print len(jsonParse({}));", nested_json(262_144));
+    assert_fails(None, &source, "E1008");
+    for backend in [Backend::Tree, Backend::Vm] {
+        let mut engine = engine(backend, None);
+        let source = format!("print len(jsonParse({}));", nested_json(1000));
+        interprete::run_in(engine.as_mut(), source).unwrap();
+        assert_eq!(engine.interpreter_mut().take_output(), "1\n");
+    }
+}
+
+// Lists nested far deeper than calls may be, which a script builds in a
+// loop, can be printed, converted, sent and dropped.
+#[test]
+fn deeply_nested_values() {
+    let source = "var a = list();
+        for (var i = 0; i < 30000; i = i + 1) a = list(a);
+        print len(toString(a));
+        print len(jsonStringify(a));
+        var ends = channel();
+        send(get(ends, 0), a);
+        print len(jsonStringify(receive(get(ends, 1))));
+        print len(jsonStringify(deepCopy(a)));
+        print deepEquals(a, deepCopy(a));
+        a = nil;";
+    for backend in [Backend::Tree, Backend::Vm] {
+        let mut engine = engine(backend, None);
+        interprete::run_in(engine.as_mut(), source.to_string()).unwrap();
+        let output = engine.interpreter_mut().take_output();
+        assert_eq!(
+            output, "60002\n60002\n60002\n60002\ntrue\n",
+            "{:?}",
+            backend
+        );
+    }
+}
+
+// A copy of lists that hold one another holds its copies the same way.
+#[test]
+fn copies_keep_what_is_shared() {
+    let source = "var a = list(); push(a, a);
+        var b = list(a, a, list(a));
+        var c = deepCopy(b);
+        print get(c, 0) == get(c, 1);
+        print get(get(c, 0), 0) == get(c, 0);
+        print get(get(c, 2), 0) == get(c, 0);
+        print get(c, 0) == a;";
+    for backend in [Backend::Tree, Backend::Vm] {
+        let mut engine = engine(backend, None);
+        interprete::run_in(engine.as_mut(), source.to_string()).unwrap();
+        let output = engine.interpreter_mut().take_output();
+        assert_eq!(output, "true\ntrue\ntrue\nfalse\n", "{:?}", backend);
+    }
+}
+
+// Calls nest as deep on the VM as on the tree-walker, counting those that
+// natives make back into scripts, and as deep as the limits allow.
+#[test]
+fn calls_through_natives_too_deep() {
+    let source = "fun f(n) { return map(list(n), f); } f(1);";
+    assert_fails(None, source, "E1011");
+    assert_fails(Some(LIMITS), source, "E1011");
+    let source = "fun f(n) { if (n == 0) return 0; return f(n - 1) + 1; } print f(500);";
+    assert_fails(Some(LIMITS), source, "E1011");
+}
+
+// A comparator that sorts again recurses through the prelude and `sort`.
+#[test]
+fn comparators_that_sort_too_deep() {
+    let source = "var xs = list(2, 1);
+        fun by(a, b) { sort(xs, by); return a < b; }
+        sort(xs, by);";
+    assert_fails(None, source, "E1011");
+    assert_fails(Some(LIMITS), source, "E1011");
+}
+
+// Calls a stack overflow stopped no longer count, so the engine can call
+// as deep again afterwards.
+#[test]
+fn depth_is_given_back_after_an_overflow() {
+    let deep = "fun f(n) { if (n == 0) return 0; return f(n - 1) + 1; }
+        print len(map(list(150), f));";
+    for backend in [Backend::Tree, Backend::Vm] {
+        let mut engine = engine(backend, Some(LIMITS));
+        for _ in 0..3 {
+            let source = "fun g(n) { return map(list(g(n + 1)), g); } g(1);".to_string();
+            let error = interprete::run_in(engine.as_mut(), source).unwrap_err();
+            assert_eq!(error.diagnostic().code, "E1011", "{:?}", backend);
+            interprete::run_in(engine.as_mut(), deep.to_string()).unwrap();
+        }
+        let output = engine.interpreter_mut().take_output();
+        assert_eq!(output, "1\n1\n1\n", "{:?}", backend);
+    }
+}
+
+// Steps and memory are counted in functions natives call, and in strings
+// compiled code builds.
+#[test]
+fn steps_and_memory_on_both_backends() {
+    assert_fails(
+        Some(LIMITS),
+        "fun spin(x) { while (true) {} } map(list(1), spin);",
+        "E1009",
+    );
+    assert_fails(Some(LIMITS), "fun spin() { spin(); } spin();", "E1011");
+    assert_fails(
+        Some(LIMITS),
+        "var s = \"ab\"; while (true) s = s + s;",
+        "E1013",
+    );
+}
+
+// Threads are held to the limits of the interpreter that starts them, and
+// stop when it is asked to, rather than running on after it.
+#[test]
+fn threads_keep_the_limits_they_start_with() {
+    let source = "fun spin() { while (true) {} } join(spawn(spin));";
+    let mut limited = engine(Backend::Tree, Some(LIMITS));
+    let error = interprete::run_in(limited.as_mut(), source.to_string()).unwrap_err();
+    assert!(error.to_string().contains("out of fuel"), "{}", error);
+
+    let mut stopped = engine(Backend::Tree, None);
+    let interrupt = stopped.interpreter().interrupt_handle();
+    let watchdog = std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        interrupt.cancel();
+    });
+    let error = interprete::run_in(stopped.as_mut(), source.to_string()).unwrap_err();
+    assert!(error.to_string().contains("interrupted"), "{}", error);
+    watchdog.join().unwrap();
+}
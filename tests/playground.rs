@@ -0,0 +1,111 @@
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Runs `interprete serve` on a free port, stopping it when dropped.
+struct Server {
+    process: Child,
+    port: u16,
+}
+
+impl Server {
+    fn start() -> Server {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .expect("a free port")
+            .port();
+        let process = Command::new(env!("CARGO_BIN_EXE_interprete"))
+            .args(["serve", "--port", &port.to_string()])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("the server starts");
+        let server = Server { process, port };
+        let started = Instant::now();
+        while TcpStream::connect(("127.0.0.1", port)).is_err() {
+            assert!(started.elapsed() < Duration::from_secs(10), "no answer");
+            thread::sleep(Duration::from_millis(20));
+        }
+        server
+    }
+
+    // Posts a script to `/run`, and gives the status and body of the
+    // response.
+    fn run(&self, source: &str) -> (u16, String) {
+        let mut stream = TcpStream::connect(("127.0.0.1", self.port)).unwrap();
+        let request = format!(
+            "POST /run HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}",
+            source.len(),
+            source
+        );
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let status = response
+            .split(' ')
+            .nth(1)
+            .and_then(|status| status.parse().ok())
+            .unwrap_or_else(|| panic!("a status in {:?}", response));
+        let body = response.split_once("\r\n\r\n").map_or("", |(_, body)| body);
+        (status, body.to_string())
+    }
+
+    // Checks that the server still runs scripts.
+    fn assert_answers(&self) {
+        let (status, body) = self.run("print 1 + 2;");
+        assert_eq!(status, 200, "{}", body);
+        assert!(body.contains(r#""stdout":"3\n""#), "{}", body);
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.process.kill().ok();
+        self.process.wait().ok();
+    }
+}
+
+// Scripts that ask natives for more memory than there is, or give them
+// data nested too deep to walk on the stack, fail or finish on their own.
+#[test]
+fn scripts_asking_too_much_of_natives_stop_alone() {
+    let server = Server::start();
+    server.assert_answers();
+    let deep_json = "var b = buffer();
+        for (var i = 0; i < 20000; i = i + 1) append(b, \"[[[[[[[[[[\");
+        for (var i = 0; i < 20000; i = i + 1) append(b, \"]]]]]]]]]]\");
+        print len(jsonParse(toString(b)));";
+    let deep_list = "var a = list();
+        for (var i = 0; i < 200000; i = i + 1) a = list(a);
+        print len(jsonStringify(a));";
+    for (source, expected) in [
+        ("print len(bytes(100000000000000));", r#""code":"E1013""#),
+        (r#"print format("%.99999999999f", 1);"#, r#""code":"E1008""#),
+        (r#"print format("%999999999999d", 1);"#, r#""code":"E1008""#),
+        (deep_json, r#""code":"E1008""#),
+        (deep_list, r#""stdout":"400002\n""#),
+    ] {
+        let (status, body) = server.run(source);
+        assert_eq!(status, 200, "{}", body);
+        assert!(body.contains(expected), "{}", body);
+        server.assert_answers();
+    }
+}
+
+// A script stuck in a native past the deadline, here printing a list that
+// holds the same list twice at each of 40 levels, is killed, and the
+// server carries on.
+#[test]
+fn a_script_stuck_in_a_native_is_killed() {
+    let server = Server::start();
+    let source = "var a = list();
+        for (var i = 0; i < 40; i = i + 1) a = list(a, a);
+        print a;";
+    let (status, body) = server.run(source);
+    assert_eq!(status, 500, "{}", body);
+    assert!(body.contains("killed"), "{}", body);
+    server.assert_answers();
+}
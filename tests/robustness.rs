@@ -0,0 +1,293 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use interprete::{
+    ast::Program,
+    capability::Capability,
+    engine::Engine,
+    interpreter::{Backend, Limits, Setup},
+    parser::Parser,
+    scanner::{Literal, Scanner, Token, TokenType},
+    serialize,
+};
+
+// Malformed scripts, token streams and programs must give errors, never
+// panic. These tests break up valid scripts in many ways, and run whatever
+// still parses on both the tree-walker and the VM.
+
+const SEEDS: [&str; 4] = [
+    "var a = 1; var b = \"two\"; print a + 2 * (3 - 4) / 5; print b + \"!\";
+     if (a >= 1 and !nil or false) print a; else print b;",
+    "fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }
+     var i = 0; while (i < 10) { print fib(i); i = i + 1; }",
+    "fun counter() { var n = 0; fun next() { n = n + 1; return n; } return next; }
+     var c = counter(); c(); print c();
+     for (var i = 0; i < 3; i = i + 1) { var j = i; print j; }",
+    "var xs = list(1, 2, 3); set(xs, 0, get(xs, 1) + get(xs, 2)); print len(xs);
+     var m = jsonParse(\"{}\"); set(m, \"k\", substring(\"hello\", 1, 3)); print m;
+     print toUpper(\"é\") + toString(1.5); print ord(\"a\") - 1;",
+];
+
+// A generator of numbers that is the same on every run, so a failure can
+// be repeated.
+struct Random(u64);
+
+impl Random {
+    fn below(&mut self, n: usize) -> usize {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        ((self.0 >> 33) % n.max(1) as u64) as usize
+    }
+}
+
+// How long a script may run before it is stopped, for those that a change
+// has made loop forever.
+const DEADLINE: Duration = Duration::from_millis(200);
+
+fn engine(backend: Backend) -> Box<dyn Engine> {
+    let mut setup = Setup {
+        backend,
+        ..Setup::default()
+    };
+    for capability in Capability::ALL {
+        setup.capabilities.deny(capability);
+    }
+    let mut engine = setup.engine().expect("an engine is made");
+    let interpreter = engine.interpreter_mut();
+    interpreter.capture_output();
+    interpreter.limit(Limits {
+        steps: 100_000,
+        depth: 200,
+        memory: 16 * 1024 * 1024,
+    });
+    engine
+}
+
+// Runs a program on a fresh engine, stopping it at the deadline.
+fn run(backend: Backend, program: &Program) {
+    let mut engine = engine(backend);
+    let interrupt = engine.interpreter().interrupt_handle();
+    let (finished, done) = mpsc::channel::<()>();
+    let watchdog = thread::spawn(move || {
+        if done.recv_timeout(DEADLINE) == Err(mpsc::RecvTimeoutError::Timeout) {
+            interrupt.cancel();
+        }
+    });
+    let _ = engine.interpret(program);
+    drop(finished);
+    watchdog.join().unwrap();
+}
+
+// Parses tokens and runs the program, if there is one, on each backend.
+fn parse_and_run(tokens: Vec<Token>) {
+    let Ok(program) = Parser::new(tokens).parse() else {
+        return;
+    };
+    run(Backend::Tree, &program);
+    run(Backend::Vm, &program);
+}
+
+fn scan_and_run(source: &str) {
+    if let Ok(tokens) = Scanner::new(source.to_string()).scan_tokens() {
+        parse_and_run(tokens);
+    }
+    // Scanning everything there is to report must not panic either.
+    let (tokens, _) = Scanner::new(source.to_string()).scan_all();
+    let _ = Parser::new(tokens).parse_all();
+}
+
+// Fails the test, naming the input, if `f` panics.
+fn assert_no_panic(input: impl std::fmt::Debug, f: impl FnOnce()) {
+    if panic::catch_unwind(AssertUnwindSafe(f)).is_err() {
+        panic!("panicked on {:?}", input);
+    }
+}
+
+// A character boundary in `source` at or before `offset`.
+fn boundary(source: &str, mut offset: usize) -> usize {
+    while !source.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}
+
+#[test]
+fn seeds_run() {
+    for seed in SEEDS {
+        let tokens = Scanner::new(seed.to_string()).scan_tokens().unwrap();
+        Parser::new(tokens).parse().unwrap();
+        assert_no_panic(seed, || scan_and_run(seed));
+    }
+}
+
+#[test]
+fn truncated_sources() {
+    for seed in SEEDS {
+        for end in (0..seed.len()).filter(|&end| seed.is_char_boundary(end)) {
+            let source = &seed[..end];
+            assert_no_panic(source, || scan_and_run(source));
+        }
+    }
+}
+
+#[test]
+fn sources_with_characters_changed() {
+    const CHARACTERS: [&str; 16] = [
+        "\"", "/", "//", ".", "1", "é", "\n", "(", ")", "{", "}", ",", "!", ";", "=", "x",
+    ];
+    let mut random = Random(2);
+    for seed in SEEDS {
+        for _ in 0..150 {
+            let mut source = seed.to_string();
+            for _ in 0..1 + random.below(3) {
+                let start = boundary(&source, random.below(source.len() + 1));
+                let end = boundary(&source, (start + random.below(3)).min(source.len()));
+                let end = end.max(start);
+                let text = CHARACTERS[random.below(CHARACTERS.len())];
+                source.replace_range(start..end, text);
+            }
+            assert_no_panic(&source, || scan_and_run(&source));
+        }
+    }
+}
+
+#[test]
+fn token_streams_with_tokens_moved() {
+    let mut random = Random(3);
+    for seed in SEEDS {
+        let tokens = Scanner::new(seed.to_string()).scan_tokens().unwrap();
+        for _ in 0..150 {
+            let mut tokens = tokens.clone();
+            for _ in 0..1 + random.below(3) {
+                if tokens.is_empty() {
+                    break;
+                }
+                let i = random.below(tokens.len());
+                let j = random.below(tokens.len());
+                match random.below(4) {
+                    0 => {
+                        tokens.remove(i);
+                    }
+                    1 => tokens.insert(i, tokens[j].clone()),
+                    2 => tokens.swap(i, j),
+                    _ => tokens.truncate(i),
+                }
+            }
+            assert_no_panic(&tokens, || parse_and_run(tokens.clone()));
+        }
+    }
+}
+
+// Token streams the scanner would never give: any token in any order,
+// numbers and strings without their values, and no end of file.
+#[test]
+fn arbitrary_token_streams() {
+    let vocabulary = "( ) { } , . - + ; / * ! != = == > >= < <= x \"s\" 1.5 \
+                      and class else false fun for if nil or print return super this \
+                      true var while";
+    let mut pool = Scanner::new(vocabulary.to_string()).scan_tokens().unwrap();
+    pool.pop();
+    pool.push(Token::new(TokenType::Number, "7".to_string(), None, 1, 1));
+    pool.push(Token::new(
+        TokenType::String,
+        "\"t\"".to_string(),
+        None,
+        1,
+        1,
+    ));
+    let text = Some(Literal::String("u".to_string()));
+    pool.push(Token::new(TokenType::Number, "8".to_string(), text, 1, 1));
+    pool.push(Token::new(TokenType::Eof, String::new(), None, 1, 1));
+    let mut random = Random(4);
+    for _ in 0..2000 {
+        let mut tokens: Vec<Token> = (0..random.below(24))
+            .map(|_| pool[random.below(pool.len())].clone())
+            .collect();
+        if random.below(4) > 0 {
+            tokens.push(Token::new(TokenType::Eof, String::new(), None, 1, 1));
+        }
+        assert_no_panic(&tokens, || parse_and_run(tokens.clone()));
+    }
+}
+
+// Programs in the JSON form of `--emit-ast`, cut short or with a part
+// changed, as a tool might give `--run-ast`.
+#[test]
+fn malformed_programs() {
+    let mut random = Random(5);
+    for seed in SEEDS {
+        let tokens = Scanner::new(seed.to_string()).scan_tokens().unwrap();
+        let json = serialize::serialize_program(&Parser::new(tokens).parse().unwrap());
+        for _ in 0..150 {
+            let mut json = json.clone();
+            let start = boundary(&json, random.below(json.len()));
+            match random.below(3) {
+                0 => json.truncate(start),
+                1 => {
+                    let end = boundary(&json, (start + 1 + random.below(8)).min(json.len()));
+                    json.replace_range(start..end.max(start), "");
+                }
+                _ => json.insert_str(start, ["0", "-1", "\"Var\"", "[]", "{}"][random.below(5)]),
+            }
+            assert_no_panic(&json, || {
+                if let Ok(program) = serialize::deserialize_program(&json) {
+                    run(Backend::Tree, &program);
+                    run(Backend::Vm, &program);
+                }
+            });
+        }
+    }
+}
+
+// Arguments to natives that ask for far more than there is, or that are
+// not what the native takes.
+#[test]
+fn hostile_native_arguments() {
+    let sources = [
+        "print len(bytes(100000000000000));",
+        "print len(bytes(100000000000000 * 100000000000000));",
+        "print len(bytes(-1)); print len(bytes(0.5)); print len(bytes(nil));",
+        r#"print format("%.99999999999f", 1);"#,
+        r#"print format("%999999999999d", 1);"#,
+        r#"print format("%99999999999999999999999s", 1);"#,
+        r#"print format("%", 1); print format("%d"); print format("%q", 1);"#,
+        r#"print substring("héllo", 2, 1); print substring("a", -1, 99);"#,
+        r#"print repeat("ab", 100000000); print chr(-1); print chr(1114112);"#,
+        r#"print jsonParse("{\"a\":"); print jsonParse("1e999999");"#,
+        "print sort(list(1, \"a\")); print sort(list(2, 1), len);",
+        "print map(list(1), nil); print join(1); print get(list(), 99);",
+    ];
+    for source in sources {
+        assert_no_panic(source, || scan_and_run(source));
+    }
+}
+
+// Sources and data nested far deeper than calls may be.
+#[test]
+fn deeply_nested_sources_and_data() {
+    let depth = 100_000;
+    let sources = [
+        format!("print {}1{};", "(".repeat(depth), ")".repeat(depth)),
+        format!("print {}1;", "-".repeat(depth)),
+        format!("{}{}", "{".repeat(depth), "}".repeat(depth)),
+        format!(
+            "print len(jsonParse(\"{}{}\"));",
+            "[".repeat(depth),
+            "]".repeat(depth)
+        ),
+        "var a = list(); for (var i = 0; i < 20000; i = i + 1) a = list(a);
+         print len(toString(a)); print deepEquals(a, deepCopy(a));"
+            .to_string(),
+        "var m = jsonParse(\"{}\");
+         for (var i = 0; i < 20000; i = i + 1) { var n = jsonParse(\"{}\"); set(n, \"m\", m); m = n; }
+         print len(jsonStringify(m));"
+            .to_string(),
+    ];
+    for source in &sources {
+        assert_no_panic(&source[..40], || scan_and_run(source));
+    }
+}
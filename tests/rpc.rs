@@ -0,0 +1,68 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+// Runs `interprete rpc` on the given requests, one per line, and gives the
+// lines it answers with.
+fn serve(requests: &[&str]) -> Vec<String> {
+    let mut server = Command::new(env!("CARGO_BIN_EXE_interprete"))
+        .arg("rpc")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("the server starts");
+    let mut input = server.stdin.take().unwrap();
+    for request in requests {
+        writeln!(input, "{}", request).unwrap();
+    }
+    drop(input);
+    let output = server.wait_with_output().unwrap();
+    let output = String::from_utf8(output.stdout).unwrap();
+    output.lines().map(str::to_string).collect()
+}
+
+#[test]
+fn scripts_cannot_read_the_requests() {
+    let responses = serve(&[
+        r#"{"jsonrpc":"2.0","id":1,"method":"evaluate","params":{"source":"var x = readLine();"}}"#,
+        r#"{"jsonrpc":"2.0","id":2,"method":"evaluate","params":{"source":"1 + 2"}}"#,
+    ]);
+    assert_eq!(responses.len(), 2, "{:?}", responses);
+    assert!(responses[0].contains(r#""id":1,"error""#));
+    assert!(responses[0].contains("'console' capability"));
+    assert_eq!(
+        responses[1],
+        r#"{"jsonrpc":"2.0","id":2,"result":{"value":"3","output":""}}"#
+    );
+}
+
+#[test]
+fn scripts_cannot_write_between_the_responses() {
+    let responses = serve(&[
+        r#"{"jsonrpc":"2.0","id":1,"method":"evaluate","params":{"source":"breakpoint();"}}"#,
+        r#"{"jsonrpc":"2.0","id":2,"method":"evaluate","params":{"source":"print 4;"}}"#,
+    ]);
+    assert_eq!(responses.len(), 2, "{:?}", responses);
+    assert!(responses[0].contains(r#""id":1,"error""#));
+    assert_eq!(
+        responses[1],
+        r#"{"jsonrpc":"2.0","id":2,"result":{"value":null,"output":"4\n"}}"#
+    );
+}
+
+#[test]
+fn threads_print_into_the_output_of_the_response() {
+    let responses = serve(&[
+        r#"{"jsonrpc":"2.0","id":1,"method":"evaluate","params":{"source":"fun f() { print 42; } join(spawn(f));"}}"#,
+        r#"{"jsonrpc":"2.0","id":2,"method":"evaluate","params":{"source":"print 4;"}}"#,
+    ]);
+    assert_eq!(responses.len(), 2, "{:?}", responses);
+    assert_eq!(
+        responses[0],
+        r#"{"jsonrpc":"2.0","id":1,"result":{"value":null,"output":"42\n"}}"#
+    );
+    assert_eq!(
+        responses[1],
+        r#"{"jsonrpc":"2.0","id":2,"result":{"value":null,"output":"4\n"}}"#
+    );
+}
@@ -0,0 +1,52 @@
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use interprete::interpreter::Setup;
+
+// What threads print goes where the interpreter that started them prints,
+// once they are joined or it next prints itself.
+
+const SOURCE: &str = "fun greet(n) { print \"thread \" + toString(n); }
+    fun nest() {
+        fun inner() { print \"thread 2\"; }
+        join(spawn(inner));
+        print \"nest\";
+    }
+    print \"before\";
+    join(spawn(greet, 1));
+    join(spawn(nest));
+    print \"after\";";
+
+const EXPECTED: &str = "before\nthread 1\nthread 2\nnest\nafter\n";
+
+#[test]
+fn captured_output_holds_what_threads_print() {
+    let mut engine = Setup::default().engine().unwrap();
+    engine.interpreter_mut().capture_output();
+    interprete::run_in(engine.as_mut(), SOURCE.to_string()).unwrap();
+    assert_eq!(engine.interpreter_mut().take_output(), EXPECTED);
+}
+
+// A writer the test keeps a handle to.
+#[derive(Clone, Default)]
+struct Shared(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn a_writer_gets_what_threads_print() {
+    let shared = Shared::default();
+    let mut engine = Setup::default().engine().unwrap();
+    engine.interpreter_mut().set_output(shared.clone());
+    interprete::run_in(engine.as_mut(), SOURCE.to_string()).unwrap();
+    assert_eq!(String::from_utf8(shared.0.take()).unwrap(), EXPECTED);
+}